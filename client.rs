@@ -0,0 +1,386 @@
+// client.rs - сборка ArbitrageParams на стороне клиента (feature "client")
+//
+// Главная ошибка интеграции - неверный accounts_count. Здесь он считается из тех же
+// правил, по которым билдеры ищут аккаунты в срезе ноги.
+use crate::*;
+
+/// Объемы ноги (все рассчитано ботом)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LegAmounts {
+    pub amount_in: u64,
+    pub tokens_to_buy: u64,
+    pub max_sol_cost: u64,
+    pub tokens_to_sell: u64,
+    pub min_wsol_out: u64,
+}
+
+/// Аккаунты среза, специфичные для venue (без mint и токен-аккаунтов пользователя)
+fn venue_accounts(dex: &DexType) -> Result<usize> {
+    Ok(match dex {
+        // program, global, fee_recipient, bonding curve, associated bonding curve, event authority
+        DexType::PumpFun => 6,
+        // program, market, market authority, bids, asks, event heap, base/quote vault (оракулы - extra_accounts)
+        DexType::OpenBookV2 => 8,
+        // program, authority, amm, 2 vault-а, pool mint, fee account, 3 оракула
+        DexType::Lifinity => 10,
+        // program, log authority, market, base/quote vault
+        DexType::Phoenix => 5,
+        // program, amm config, pool, 2 vault-а, observation, token-2022, memo, quote mint
+        // (tick array-и и bitmap extension - extra_accounts)
+        DexType::RaydiumClmm => 9,
+        // program, swap, authority, 2 vault-а, pool mint, fee account
+        DexType::OrcaV1 => 7,
+        // program, swap info, authority, 2 reserves, 2 admin fee аккаунта, clock
+        DexType::StableSwap => 8,
+        // program, state, pool, tickmap, 2 reserve, program authority, quote mint
+        // (tick-и пересекаемых tick-ов - extra_accounts)
+        DexType::Invariant => 8,
+        // program, clmm config, pool, 2 vault-а, tick array map, quote mint
+        // (tick array-и - extra_accounts)
+        DexType::Crema => 7,
+        // program, whirlpool, 2 vault-а, oracle (tick array-и - extra_accounts)
+        DexType::WhirlpoolLike { .. } => 5,
+        // Raw занимает весь срез ноги - считать нечего
+        DexType::Raw { .. } => return Err(MyErrorCode::InvalidDexType.into()),
+        // У Meteora нет билдера
+        DexType::Meteora => return Err(MyErrorCode::NotImplemented.into()),
+    })
+}
+
+/// accounts_count для ноги: venue-аккаунты каждого DEX-а (одинаковые DEX-ы - один набор),
+/// user token account token_mint, mint для Pump.fun / oracle / create_ata и опциональные аккаунты.
+/// `extra_accounts` - то, что роутер не может знать заранее (например оракулы OpenBook маркета).
+pub fn leg_accounts_count(arbitrage: &ArbitrageParams, extra_accounts: usize) -> Result<u8> {
+    let mut dexes = vec![&arbitrage.buy_dex];
+    if arbitrage.sell_dex != arbitrage.buy_dex {
+        dexes.push(&arbitrage.sell_dex);
+    }
+
+    let mut count = 1; // user token account token_mint
+    let mut needs_mint = arbitrage.oracle.is_some() || arbitrage.create_ata;
+
+    if let Some(bridge) = &arbitrage.bridge {
+        if !dexes.contains(&&bridge.dex) {
+            dexes.push(&bridge.dex);
+        }
+        count += 1; // user token account output_mint
+    }
+
+    // Partner у Crema - это referral_account плюс его ATA обоих токенов пула
+    let has_crema = dexes.contains(&&DexType::Crema);
+
+    for dex in dexes {
+        count += venue_accounts(dex)?;
+        needs_mint |= *dex == DexType::PumpFun
+            || *dex == DexType::RaydiumClmm
+            || *dex == DexType::Invariant
+            || *dex == DexType::Crema;
+    }
+
+    if needs_mint {
+        count += 1;
+    }
+    if arbitrage.create_ata {
+        count += 1; // associated token program
+    }
+    if arbitrage.token_program.is_some() {
+        count += 1;
+    }
+    if arbitrage.oracle.is_some() {
+        count += 1;
+    }
+    if arbitrage.funding_account.is_some() {
+        count += 1;
+    }
+    if arbitrage.referral_account.is_some() {
+        count += 1;
+        if has_crema {
+            count += 2;
+        }
+    }
+    if arbitrage.auto_claim_seat {
+        count += 4; // seat manager program, seat manager, deposit collector, seat
+    }
+
+    u8::try_from(count + extra_accounts).map_err(|_| MyErrorCode::AccountCountMismatch.into())
+}
+
+impl ArbitrageParams {
+    /// Классическая нога BUY на `buy_dex` -> SELL на `sell_dex` с посчитанным accounts_count
+    pub fn cross_venue(
+        buy_dex: DexType,
+        sell_dex: DexType,
+        token_mint: Pubkey,
+        amounts: LegAmounts,
+        extra_accounts: usize,
+    ) -> Result<Self> {
+        require!(
+            amounts.tokens_to_buy > 0 && amounts.tokens_to_sell > 0 && amounts.max_sol_cost > 0,
+            MyErrorCode::InvalidConfigValue
+        );
+        // Продать больше купленного нельзя (fee-on-transfer только уменьшает полученное)
+        require!(amounts.tokens_to_sell <= amounts.tokens_to_buy, MyErrorCode::InvalidConfigValue);
+
+        let mut arbitrage = ArbitrageParams {
+            token_mint,
+            amount_in: amounts.amount_in,
+            min_wsol_out: amounts.min_wsol_out,
+            buy_dex,
+            sell_dex,
+            accounts_count: 0,
+            tokens_to_buy: amounts.tokens_to_buy,
+            max_sol_cost: amounts.max_sol_cost,
+            tokens_to_sell: amounts.tokens_to_sell,
+            bridge: None,
+            token_program: None,
+            allow_same_venue: false,
+            reverse: false,
+            create_ata: false,
+            fee_recipient: None,
+            oracle: None,
+            max_oracle_deviation_bps: 0,
+            funding_account: None,
+            data_prefix: Vec::new(),
+            layout: Vec::new(),
+            cu_cap: 0,
+            referral_account: None,
+            auto_claim_seat: false,
+        };
+        arbitrage.accounts_count = leg_accounts_count(&arbitrage, extra_accounts)?;
+        Ok(arbitrage)
+    }
+
+    /// Pump.fun -> Pump.fun по одной bonding curve (намеренный same-venue round trip)
+    pub fn pump_round_trip(token_mint: Pubkey, amounts: LegAmounts) -> Result<Self> {
+        let mut arbitrage = Self::cross_venue(DexType::PumpFun, DexType::PumpFun, token_mint, amounts, 0)?;
+        arbitrage.allow_same_venue = true;
+        Ok(arbitrage)
+    }
+
+    /// Pump.fun -> OpenBook v2; `oracle_count` - ненулевые оракулы маркета (0..=2)
+    pub fn pump_to_openbook(token_mint: Pubkey, amounts: LegAmounts, oracle_count: usize) -> Result<Self> {
+        require!(oracle_count <= 2, MyErrorCode::InvalidConfigValue);
+        Self::cross_venue(DexType::PumpFun, DexType::OpenBookV2, token_mint, amounts, oracle_count)
+    }
+
+    /// Pump.fun -> Lifinity v2
+    pub fn pump_to_lifinity(token_mint: Pubkey, amounts: LegAmounts) -> Result<Self> {
+        Self::cross_venue(DexType::PumpFun, DexType::Lifinity, token_mint, amounts, 0)
+    }
+
+    /// Pump.fun -> Phoenix
+    pub fn pump_to_phoenix(token_mint: Pubkey, amounts: LegAmounts) -> Result<Self> {
+        Self::cross_venue(DexType::PumpFun, DexType::Phoenix, token_mint, amounts, 0)
+    }
+
+    /// Pump.fun -> Orca legacy Token Swap
+    pub fn pump_to_orca_v1(token_mint: Pubkey, amounts: LegAmounts) -> Result<Self> {
+        Self::cross_venue(DexType::PumpFun, DexType::OrcaV1, token_mint, amounts, 0)
+    }
+
+    /// Pump.fun -> StableSwap (LST / стейбл пул к quote)
+    pub fn pump_to_stable_swap(token_mint: Pubkey, amounts: LegAmounts) -> Result<Self> {
+        Self::cross_venue(DexType::PumpFun, DexType::StableSwap, token_mint, amounts, 0)
+    }
+
+    /// Pump.fun -> Invariant CLMM; `tick_count` - tick-и, которые пересечет SELL
+    pub fn pump_to_invariant(token_mint: Pubkey, amounts: LegAmounts, tick_count: usize) -> Result<Self> {
+        Self::cross_venue(DexType::PumpFun, DexType::Invariant, token_mint, amounts, tick_count)
+    }
+
+    /// Pump.fun -> Crema CLMM; `tick_array_count` - tick array-и, которые пересечет SELL (1+)
+    pub fn pump_to_crema(token_mint: Pubkey, amounts: LegAmounts, tick_array_count: usize) -> Result<Self> {
+        require!(tick_array_count > 0, MyErrorCode::InvalidConfigValue);
+        Self::cross_venue(DexType::PumpFun, DexType::Crema, token_mint, amounts, tick_array_count)
+    }
+
+    /// Pump.fun -> Whirlpool или его клон `program_id`; `tick_array_count` - tick array-и SELL (1..=3)
+    pub fn pump_to_whirlpool_like(
+        token_mint: Pubkey,
+        amounts: LegAmounts,
+        program_id: Pubkey,
+        tick_array_count: usize,
+    ) -> Result<Self> {
+        require!((1..=3).contains(&tick_array_count), MyErrorCode::InvalidConfigValue);
+        Self::cross_venue(
+            DexType::PumpFun,
+            DexType::WhirlpoolLike { program_id },
+            token_mint,
+            amounts,
+            tick_array_count,
+        )
+    }
+
+    /// Пересчитать accounts_count после изменения опциональных полей (oracle, create_ata, bridge...)
+    pub fn with_recounted_accounts(mut self, extra_accounts: usize) -> Result<Self> {
+        self.accounts_count = leg_accounts_count(&self, extra_accounts)?;
+        Ok(self)
+    }
+}
+
+/// Декодирует отладочный return data упавшего батча (программа собрана с "failure-debug").
+/// `return_program` / `data` - returnData из simulateTransaction или meta транзакции;
+/// None - данные записал не роутер (например, DEX в CPI) или записи не было.
+pub fn decode_failure_debug(return_program: &Pubkey, data: &[u8]) -> Option<LegFailureDebug> {
+    if *return_program != crate::ID || data.is_empty() {
+        return None;
+    }
+    LegFailureDebug::try_from_slice(data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMOUNTS: LegAmounts = LegAmounts {
+        amount_in: 1_000_000,
+        tokens_to_buy: 5_000,
+        max_sol_cost: 1_000_000,
+        tokens_to_sell: 5_000,
+        min_wsol_out: 1_010_000,
+    };
+
+    #[track_caller]
+    fn assert_error<T: std::fmt::Debug>(result: Result<T>, expected: MyErrorCode) {
+        let error = result.unwrap_err();
+        assert_eq!(error_code_of(&error), error_code_of(&expected.into()));
+    }
+
+    #[test]
+    fn builders_count_venue_accounts_user_token_and_mint() {
+        let mint = Pubkey::new_unique();
+        // user token + mint (Pump.fun) + 6 Pump.fun + venue продажи + extra_accounts
+        let counts = [
+            (ArbitrageParams::pump_round_trip(mint, AMOUNTS).unwrap(), 1 + 1 + 6),
+            (ArbitrageParams::pump_to_openbook(mint, AMOUNTS, 2).unwrap(), 1 + 1 + 6 + 8 + 2),
+            (ArbitrageParams::pump_to_lifinity(mint, AMOUNTS).unwrap(), 1 + 1 + 6 + 10),
+            (ArbitrageParams::pump_to_phoenix(mint, AMOUNTS).unwrap(), 1 + 1 + 6 + 5),
+            (ArbitrageParams::pump_to_orca_v1(mint, AMOUNTS).unwrap(), 1 + 1 + 6 + 7),
+            (ArbitrageParams::pump_to_stable_swap(mint, AMOUNTS).unwrap(), 1 + 1 + 6 + 8),
+            (ArbitrageParams::pump_to_invariant(mint, AMOUNTS, 3).unwrap(), 1 + 1 + 6 + 8 + 3),
+            (ArbitrageParams::pump_to_crema(mint, AMOUNTS, 2).unwrap(), 1 + 1 + 6 + 7 + 2),
+            (
+                ArbitrageParams::pump_to_whirlpool_like(mint, AMOUNTS, Pubkey::new_unique(), 3).unwrap(),
+                1 + 1 + 6 + 5 + 3,
+            ),
+        ];
+        for (arbitrage, expected) in counts {
+            assert_eq!(arbitrage.accounts_count, expected, "{:?}", arbitrage.sell_dex);
+            assert_eq!(arbitrage.token_mint, mint);
+        }
+        assert!(ArbitrageParams::pump_round_trip(mint, AMOUNTS).unwrap().allow_same_venue);
+    }
+
+    #[test]
+    fn builders_reject_invalid_amounts_and_extra_account_ranges() {
+        let mint = Pubkey::new_unique();
+        let oversold = LegAmounts {
+            tokens_to_sell: AMOUNTS.tokens_to_buy + 1,
+            ..AMOUNTS
+        };
+        assert_error(ArbitrageParams::pump_to_lifinity(mint, oversold), MyErrorCode::InvalidConfigValue);
+        let no_cost = LegAmounts {
+            max_sol_cost: 0,
+            ..AMOUNTS
+        };
+        assert_error(ArbitrageParams::pump_to_phoenix(mint, no_cost), MyErrorCode::InvalidConfigValue);
+
+        assert_error(ArbitrageParams::pump_to_openbook(mint, AMOUNTS, 3), MyErrorCode::InvalidConfigValue);
+        assert_error(ArbitrageParams::pump_to_crema(mint, AMOUNTS, 0), MyErrorCode::InvalidConfigValue);
+        let whirlpool = Pubkey::new_unique();
+        for tick_array_count in [0, 4] {
+            let result = ArbitrageParams::pump_to_whirlpool_like(mint, AMOUNTS, whirlpool, tick_array_count);
+            assert_error(result, MyErrorCode::InvalidConfigValue);
+        }
+    }
+
+    #[test]
+    fn leg_accounts_count_adds_optional_accounts() {
+        let mint = Pubkey::new_unique();
+        let base = ArbitrageParams::cross_venue(DexType::Lifinity, DexType::Phoenix, mint, AMOUNTS, 0).unwrap();
+        // user token + 10 Lifinity + 5 Phoenix, mint не нужен
+        assert_eq!(base.accounts_count, 1 + 10 + 5);
+
+        let count = |arbitrage: ArbitrageParams| leg_accounts_count(&arbitrage, 0).unwrap();
+        // oracle и create_ata тянут mint; create_ata еще и ATA program
+        assert_eq!(count(ArbitrageParams { oracle: Some(Pubkey::new_unique()), ..base.clone() }), 16 + 2);
+        assert_eq!(count(ArbitrageParams { create_ata: true, ..base.clone() }), 16 + 2);
+        assert_eq!(
+            count(ArbitrageParams {
+                oracle: Some(Pubkey::new_unique()),
+                create_ata: true,
+                ..base.clone()
+            }),
+            16 + 3
+        );
+        assert_eq!(count(ArbitrageParams { token_program: Some(anchor_spl::token_2022::ID), ..base.clone() }), 16 + 1);
+        assert_eq!(count(ArbitrageParams { funding_account: Some(Pubkey::new_unique()), ..base.clone() }), 16 + 1);
+        assert_eq!(count(ArbitrageParams { auto_claim_seat: true, ..base.clone() }), 16 + 4);
+        assert_eq!(count(ArbitrageParams { referral_account: Some(Pubkey::new_unique()), ..base.clone() }), 16 + 1);
+
+        // Бридж: его venue (если новый DEX) и token account output_mint
+        let bridged = ArbitrageParams {
+            bridge: Some(SwapLeg {
+                dex: DexType::OrcaV1,
+                output_mint: Pubkey::new_unique(),
+                min_amount_out: 1,
+            }),
+            ..base.clone()
+        };
+        assert_eq!(count(bridged), 16 + 7 + 1);
+        let bridged_on_buy_venue = ArbitrageParams {
+            bridge: Some(SwapLeg {
+                dex: DexType::Lifinity,
+                output_mint: Pubkey::new_unique(),
+                min_amount_out: 1,
+            }),
+            ..base.clone()
+        };
+        assert_eq!(count(bridged_on_buy_venue), 16 + 1);
+
+        // Referral у Crema - еще две ATA partner-а
+        let crema = ArbitrageParams::pump_to_crema(Pubkey::new_unique(), AMOUNTS, 1).unwrap();
+        let crema_count = crema.accounts_count;
+        let with_partner = ArbitrageParams {
+            referral_account: Some(Pubkey::new_unique()),
+            ..crema
+        };
+        assert_eq!(leg_accounts_count(&with_partner, 1).unwrap(), crema_count + 3);
+    }
+
+    #[test]
+    fn leg_accounts_count_rejects_raw_meteora_and_overflow() {
+        let mint = Pubkey::new_unique();
+        let raw = DexType::Raw {
+            program_id: Pubkey::new_unique(),
+            data: Vec::new(),
+            is_writable: Vec::new(),
+        };
+        assert_error(
+            ArbitrageParams::cross_venue(raw, DexType::PumpFun, mint, AMOUNTS, 0),
+            MyErrorCode::InvalidDexType,
+        );
+        assert_error(
+            ArbitrageParams::cross_venue(DexType::PumpFun, DexType::Meteora, mint, AMOUNTS, 0),
+            MyErrorCode::NotImplemented,
+        );
+        assert_error(
+            ArbitrageParams::cross_venue(DexType::PumpFun, DexType::Lifinity, mint, AMOUNTS, 255),
+            MyErrorCode::AccountCountMismatch,
+        );
+    }
+
+    #[test]
+    fn with_recounted_accounts_picks_up_changed_options() {
+        let arbitrage = ArbitrageParams::pump_to_phoenix(Pubkey::new_unique(), AMOUNTS).unwrap();
+        let before = arbitrage.accounts_count;
+        // Mint у Pump.fun уже в срезе - create_ata добавляет только ATA program
+        let recounted = ArbitrageParams {
+            create_ata: true,
+            ..arbitrage
+        }
+        .with_recounted_accounts(0)
+        .unwrap();
+        assert_eq!(recounted.accounts_count, before + 1);
+    }
+}
@@ -1,7 +1,14 @@
 // lib.rs - HFT Arbitrage Router: FULL INLINE (NO LIBSECP256K1 ISSUES)
 use anchor_lang::prelude::*;
-use anchor_spl::token::{Token, TokenAccount};
-use anchor_spl::associated_token::get_associated_token_address;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::token::{self, SyncNative, Token, TokenAccount};
+use anchor_spl::token_interface::{Mint as InterfaceMint, TokenAccount as InterfaceTokenAccount};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+};
+use anchor_spl::associated_token::{self, get_associated_token_address_with_program_id};
 use anchor_lang::solana_program::{
     instruction::{AccountMeta, Instruction},
 };
@@ -9,294 +16,5164 @@ use std::str::FromStr;
 
 declare_id!("4xVUrp3J6t6FKrS61uWN6UZRCrvfMU97qa8uJJxncaP1");
 
+/// Off-chain сборка ArbitrageParams для Rust-клиентов
+#[cfg(feature = "client")]
+pub mod client;
+
+/// Тесты роутера (моки CPI и sysvar-ов через program_stubs)
+#[cfg(test)]
+mod tests;
+
 #[program]
 pub mod dex_arbitrage_router {
     use super::*;
 
-    /// Инициализация роутера (вызывается один раз)
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+    /// Инициализация роутера (вызывается один раз).
+    /// quote_mint - mint quote-аккаунта прибыли (wSOL на mainnet, свой mint на localnet/devnet).
+    pub fn initialize(ctx: Context<Initialize>, quote_mint: Pubkey) -> Result<()> {
         let router_state = &mut ctx.accounts.router_state;
+        router_state.version = ROUTER_STATE_VERSION;
         router_state.owner = ctx.accounts.owner.key();
         router_state.is_paused = false;
         router_state.bump = ctx.bumps.router_state;
+        router_state.last_nonce = 0;
+        router_state.max_slippage_bps = 0;
+        router_state.profit_fee_bps = 0;
+        router_state.treasury = Pubkey::default();
+        router_state.pump_buy_discriminator = PUMP_BUY_DISCRIMINATOR;
+        router_state.pump_sell_discriminator = PUMP_SELL_DISCRIMINATOR;
+        router_state.allow_raw = false;
+        router_state.min_slots_between_batches = 0;
+        router_state.last_batch_slot = 0;
+        router_state.max_accounts_per_leg = DEFAULT_MAX_ACCOUNTS_PER_LEG;
+        router_state.enforce_distinct_venues = false;
+        router_state.total_batches = 0;
+        router_state.cumulative_profit = 0;
+        router_state.suggested_cu_price_microlamports = 0;
+        router_state.fee_recipient = Pubkey::from_str(PUMP_FEE_RECIPIENT).map_err(|_| MyErrorCode::InvalidConfigValue)?;
+        router_state.is_shutdown = false;
+        router_state.strict_leg_accounts = false;
+        router_state.pending_owner = Pubkey::default();
+        router_state.quote_mint = quote_mint;
+        router_state.quote_has_transfer_fee = false;
+        router_state.consecutive_failures = 0;
+        router_state.failure_threshold = 0;
+        router_state.failure_reporter = Pubkey::default();
+        router_state.denied_mints = [Pubkey::default(); MAX_DENIED_MINTS];
+        router_state.denied_count = 0;
+        router_state.recent_batch_tags = [[0u8; 32]; BATCH_TAG_HISTORY];
+        router_state.batch_tag_cursor = 0;
+        router_state.auto_slippage_relief_bps = 0;
+        router_state.reserved_cu = 0;
+        router_state.seed = [0u8; 8];
+        router_state.min_liquidity = 0;
+        router_state.max_mints_per_batch = 0;
+        router_state.dex_discriminators = [DexDiscriminator::default(); MAX_DISCRIMINATOR_OVERRIDES];
+        router_state.total_legs_executed = 0;
+        router_state.profit_mint = Pubkey::default();
+        router_state.profit_oracle = Pubkey::default();
+        router_state.min_profit = 0;
+        router_state.max_scan_passes = 0;
         
         msg!("HFT Arbitrage Router initialized. Owner: {}", router_state.owner);
         Ok(())
     }
 
-    /// 🚀 ГЛАВНАЯ ФУНКЦИЯ: ANCHOR 0.29 COMPATIBLE (EXPLICIT LIFETIMES)
+    /// 🚀 ГЛАВНАЯ ФУНКЦИЯ: батч из 4 ног (основной hot path)
     pub fn execute_arbitrage_batch<'info>(
         ctx: Context<'_, '_, 'info, 'info, ExecuteArbitrageBatch<'info>>,
         arbitrages: [ArbitrageParams; 4],
+        options: BatchOptions,
+    ) -> Result<()> {
+        execute_batch(ctx, arbitrages, options)
+    }
+
+    /// 🚀 Батч из 1 ноги: тот же execute_batch, массив на стеке без лишних слотов
+    pub fn execute_batch_1<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteArbitrageBatch<'info>>,
+        arbitrages: [ArbitrageParams; 1],
+        options: BatchOptions,
+    ) -> Result<()> {
+        execute_batch(ctx, arbitrages, options)
+    }
+
+    /// 🚀 Батч из 2 ног
+    pub fn execute_batch_2<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteArbitrageBatch<'info>>,
+        arbitrages: [ArbitrageParams; 2],
+        options: BatchOptions,
+    ) -> Result<()> {
+        execute_batch(ctx, arbitrages, options)
+    }
+
+    /// 🚀 Батч из 8 ног (аккаунты ног - через lookup table)
+    pub fn execute_batch_8<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteArbitrageBatch<'info>>,
+        arbitrages: [ArbitrageParams; 8],
+        options: BatchOptions,
+    ) -> Result<()> {
+        execute_batch(ctx, arbitrages, options)
+    }
+
+    /// ⚡ Одна нога с минимумом instruction data: без окна, порядка, batch_tag и лимитов батча.
+    /// Тот же execute_batch (pre-flight, билдеры, проверка прибыли), remaining_accounts - только
+    /// срез этой ноги. Нога с Pump.fun платит / получает нативные SOL - они входят в результат
+    pub fn execute_single_arbitrage<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteArbitrageBatch<'info>>,
+        arbitrage: ArbitrageParams,
+        nonce: u64,
+        wrap_amount: u64,
+        tx_cost_lamports: u64,
     ) -> Result<()> {
-        // 1. Проверка паузы (первая линия защиты)
-        require!(!ctx.accounts.router_state.is_paused, MyErrorCode::ContractIsPaused);
+        let settle_native = arbitrage.buy_dex == DexType::PumpFun || arbitrage.sell_dex == DexType::PumpFun;
+        let options = BatchOptions {
+            wrap_amount,
+            nonce,
+            settle_native,
+            tx_cost_lamports,
+            ..BatchOptions::single_leg()
+        };
+        execute_batch(ctx, [arbitrage], options)
+    }
+
+    /// Emergency stop: только owner может поставить на паузу/снять с паузы
+    pub fn toggle_pause(ctx: Context<TogglePause>) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
         
-        msg!("🚀 Starting INLINE HFT arbitrage batch execution with 4 trades");
-
-        // 🎯 КЛЮЧЕВОЕ РЕШЕНИЕ: ИЗВЛЕКАЕМ ВСЕ ССЫЛКИ ДО ЦИКЛА (РЕШАЕТ LIFETIME ПРОБЛЕМЫ)
-        let user = &ctx.accounts.user;
-        let system_program = &ctx.accounts.system_program;
-        let token_program = &ctx.accounts.token_program;
-        let rent = &ctx.accounts.rent;
-        let user_key = user.key();
-        let system_program_key = system_program.key();
-        let token_program_key = token_program.key();
-        let rent_key = rent.key();
-
-        // 🔧 СОЗДАЕМ КОНСТАНТЫ ОДИН РАЗ (МИНИМИЗИРУЕМ CRYPTO ОПЕРАЦИИ)
-        let pump_program_id = Pubkey::from_str("6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P").unwrap();
-        let fee_recipient = Pubkey::from_str("CebN5WGQ4jvEPvsVU4EoHEpgzq1VV7AbicfhtW4xC9iM").unwrap();
-
-        // 2. Гибкая нарезка аккаунтов на основе accounts_count
-        let mut account_offset = 0;
+        router_state.is_paused = !router_state.is_paused;
         
-        // 3. ПОЛНОСТЬЮ INLINE ЦИКЛ: ВСЯ ЛОГИКА ПРЯМО ЗДЕСЬ
+        msg!("🛑 Router pause status changed to: {}", router_state.is_paused);
+        Ok(())
+    }
+
+    /// 📐 Оценка CU батча без исполнения: Go-бот ставит точный set_compute_unit_limit
+    /// и по разбивке на ноги решает, какие выкинуть, чтобы уложиться в бюджет.
+    /// Результат (BatchCuEstimate) возвращается через return data.
+    pub fn compute_estimate(_ctx: Context<ComputeEstimate>, arbitrages: [ArbitrageParams; 4]) -> Result<BatchCuEstimate> {
+        let mut total_cu = BATCH_BASE_CU;
+        let mut legs = Vec::with_capacity(arbitrages.len());
+
+        for (index, arbitrage) in arbitrages.iter().enumerate() {
+            // Пустой слот (accounts_count = 0) не исполняется - CU на него не закладываем
+            if arbitrage.accounts_count == 0 {
+                continue;
+            }
+            let cu_estimate = leg_cu_estimate(arbitrage)?;
+            total_cu = total_cu.checked_add(cu_estimate).ok_or(MyErrorCode::ArithmeticError)?;
+            legs.push(LegCuEstimate {
+                index: index as u8,
+                cu_estimate,
+            });
+        }
+        // Батч, не влезающий в лимит транзакции, не исполнится ни с каким set_compute_unit_limit
+        require!(total_cu <= MAX_TRANSACTION_CU, MyErrorCode::InsufficientCompute);
+
+        msg!("📐 Estimated batch compute: {} CU", total_cu);
+        Ok(BatchCuEstimate {
+            base_cu: BATCH_BASE_CU,
+            legs,
+            total_cu,
+        })
+    }
+
+    /// 🔮 Прогноз прибыли батча без исполнения. Pump.fun стороны ног считаются по текущим резервам
+    /// bonding curve, остальные venue - по худшему случаю бота (max_sol_cost / min_wsol_out).
+    /// remaining_accounts - срезы ног как в execute_arbitrage_batch; ноги с accounts_count = 0 пустые.
+    pub fn preview_batch(ctx: Context<PreviewBatch>, arbitrages: [ArbitrageParams; 4]) -> Result<i64> {
+        let pump_program_id = parse_program_id(PUMP_FUN_PROGRAM_ID)?;
+        let mut account_offset = 0;
+        let mut predicted_profit: i64 = 0;
+
         for (index, arbitrage) in arbitrages.iter().enumerate() {
-            msg!("⚡ Executing arbitrage #{} (FULL INLINE MODE)", index + 1);
-            msg!("📊 Accounts needed: {}", arbitrage.accounts_count);
-            
-            // Вычисляем границы среза для этого арбитража
+            if arbitrage.accounts_count == 0 {
+                continue;
+            }
+
             let start = account_offset;
             let end = start + arbitrage.accounts_count as usize;
-            
-            // Проверяем что у нас достаточно аккаунтов
-            require!(
-                ctx.remaining_accounts.len() >= end,
-                MyErrorCode::InsufficientAccounts
-            );
-            
+            require!(ctx.remaining_accounts.len() >= end, MyErrorCode::InsufficientAccounts);
             let arbitrage_accounts_slice = &ctx.remaining_accounts[start..end];
-            
-            msg!("🔧 Using accounts slice [{}, {})", start, end);
-            
-            msg!("🧠 Go-bot parameters: buy {} tokens (max {} SOL), sell {} tokens (min {} wSOL)", 
-                 arbitrage.tokens_to_buy, arbitrage.max_sol_cost, 
-                 arbitrage.tokens_to_sell, arbitrage.min_wsol_out);
-
-            // ====================================================================
-            // 🔥 INLINE BUY INSTRUCTION CREATION
-            // ====================================================================
-            
-            let buy_instruction = match arbitrage.buy_dex {
-                DexType::PumpFun => {
-                    msg!("🔧 Creating Pump.fun BUY instruction inline...");
-                    
-                    // Поиск аккаунтов inline (БЕЗ CRYPTO ЗАВИСИМОСТЕЙ)
-                    let mut pump_program_account = None;
-                    let mut global_account = None;
-                    let mut fee_recipient_account = None;
-                    let mut mint_account = None;
-                    let mut bonding_curve_account = None;
-                    let mut user_token_account = None;
-                    let mut event_authority_account = None;
-                    
-                    // Inline поиск всех нужных аккаунтов (COMPILE-TIME PUBKEYS)
-                    for acc_info in arbitrage_accounts_slice {
-                        // Pump program
-                        if acc_info.key() == pump_program_id {
-                            pump_program_account = Some(acc_info);
-                        }
-                        // Global PDA
-                        let (expected_global, _) = Pubkey::find_program_address(&[b"global"], &pump_program_id);
-                        if acc_info.key() == expected_global {
-                            global_account = Some(acc_info);
-                        }
-                        // Fee recipient
-                        if acc_info.key() == fee_recipient {
-                            fee_recipient_account = Some(acc_info);
-                        }
-                        // Mint
-                        if acc_info.key() == arbitrage.token_mint {
-                            mint_account = Some(acc_info);
-                        }
-                        // Bonding curve PDA
-                        let (expected_bonding_curve, _) = Pubkey::find_program_address(&[b"bonding-curve", arbitrage.token_mint.as_ref()], &pump_program_id);
-                        if acc_info.key() == expected_bonding_curve {
-                            bonding_curve_account = Some(acc_info);
-                        }
-                        // User token account
-                        if acc_info.owner == &anchor_spl::token::ID && acc_info.data_len() == TokenAccount::LEN {
-                            if let Ok(token_account) = TokenAccount::try_deserialize(&mut acc_info.data.borrow().as_ref()) {
-                                if token_account.owner == user_key && token_account.mint == arbitrage.token_mint {
-                                    user_token_account = Some(acc_info);
-                                }
-                            }
-                        }
-                        // Event authority PDA
-                        let (expected_event_authority, _) = Pubkey::find_program_address(&[b"__event_authority"], &pump_program_id);
-                        if acc_info.key() == expected_event_authority {
-                            event_authority_account = Some(acc_info);
-                        }
-                    }
-                    
-                    // Проверяем что все аккаунты найдены
-                    let pump_program_account = pump_program_account.ok_or(MyErrorCode::AccountNotFound)?;
-                    let global_account = global_account.ok_or(MyErrorCode::PDAAccountNotFound)?;
-                    let fee_recipient_account = fee_recipient_account.ok_or(MyErrorCode::AccountNotFound)?;
-                    let mint_account = mint_account.ok_or(MyErrorCode::MintAccountNotFound)?;
-                    let bonding_curve_account = bonding_curve_account.ok_or(MyErrorCode::PDAAccountNotFound)?;
-                    let user_token_account = user_token_account.ok_or(MyErrorCode::TokenAccountNotFound)?;
-                    let event_authority_account = event_authority_account.ok_or(MyErrorCode::PDAAccountNotFound)?;
-                    
-                    // Находим associated bonding curve (ATA)
-                    let expected_ata = get_associated_token_address(&bonding_curve_account.key(), &arbitrage.token_mint);
-                    let mut associated_bonding_curve_account = None;
-                    for acc_info in arbitrage_accounts_slice {
-                        if acc_info.key() == expected_ata {
-                            associated_bonding_curve_account = Some(acc_info);
-                            break;
-                        }
-                    }
-                    let associated_bonding_curve_account = associated_bonding_curve_account.ok_or(MyErrorCode::AccountNotFound)?;
-                    
-                    // Создаем instruction data
-                    let mut instruction_data = Vec::new();
-                    instruction_data.extend_from_slice(&[0x66, 0x06, 0x3d, 0x12, 0x01, 0xda, 0xeb, 0xea]); // buy discriminator
-                    instruction_data.extend_from_slice(&arbitrage.tokens_to_buy.to_le_bytes());
-                    instruction_data.extend_from_slice(&arbitrage.max_sol_cost.to_le_bytes());
-                    
-                    // Создаем instruction
-                    Instruction {
-                        program_id: pump_program_id,
-                        accounts: vec![
-                            AccountMeta::new_readonly(global_account.key(), false),
-                            AccountMeta::new(fee_recipient_account.key(), false),
-                            AccountMeta::new_readonly(mint_account.key(), false),
-                            AccountMeta::new(bonding_curve_account.key(), false),
-                            AccountMeta::new(associated_bonding_curve_account.key(), false),
-                            AccountMeta::new(user_token_account.key(), false),
-                            AccountMeta::new(user_key, true),
-                            AccountMeta::new_readonly(system_program_key, false),
-                            AccountMeta::new_readonly(token_program_key, false),
-                            AccountMeta::new_readonly(rent_key, false),
-                            AccountMeta::new_readonly(event_authority_account.key(), false),
-                            AccountMeta::new_readonly(pump_program_account.key(), false),
-                        ],
-                        data: instruction_data,
-                    }
-                },
-                DexType::Meteora => {
-                    msg!("🚧 Meteora not implemented yet");
-                    return Err(MyErrorCode::InvalidDexType.into());
-                },
-            };
+            account_offset = end;
 
-            // Создаем accounts для buy invoke
-            let buy_accounts = match arbitrage.buy_dex {
-                DexType::PumpFun => {
-                    let mut accounts = Vec::new();
-                    
-                    // Те же аккаунты что в instruction, но как AccountInfo
-                    for acc_info in arbitrage_accounts_slice {
-                        let (expected_global, _) = Pubkey::find_program_address(&[b"global"], &pump_program_id);
-                        let (expected_bonding_curve, _) = Pubkey::find_program_address(&[b"bonding-curve", arbitrage.token_mint.as_ref()], &pump_program_id);
-                        let expected_ata = get_associated_token_address(&expected_bonding_curve, &arbitrage.token_mint);
-                        let (expected_event_authority, _) = Pubkey::find_program_address(&[b"__event_authority"], &pump_program_id);
-                        
-                        if acc_info.key() == expected_global ||
-                           acc_info.key() == fee_recipient ||
-                           acc_info.key() == arbitrage.token_mint ||
-                           acc_info.key() == expected_bonding_curve ||
-                           acc_info.key() == expected_ata ||
-                           acc_info.key() == expected_event_authority ||
-                           acc_info.key() == pump_program_id ||
-                           (acc_info.owner == &anchor_spl::token::ID && acc_info.data_len() == TokenAccount::LEN) {
-                            accounts.push(acc_info.clone());
-                        }
-                    }
-                    
-                    // Добавляем основные аккаунты из контекста
-                    accounts.push(user.to_account_info());
-                    accounts.push(system_program.to_account_info());
-                    accounts.push(token_program.to_account_info());
-                    accounts.push(rent.to_account_info());
-                    
-                    accounts
-                },
-                DexType::Meteora => vec![],
+            // Reverse считает прибыль в token_mint, треугольник идет через output_mint - не прогнозируем
+            if arbitrage.reverse || arbitrage.bridge.is_some() {
+                msg!("🔮 Arbitrage #{}: reverse / bridge leg not previewed", index + 1);
+                continue;
+            }
+
+            let mut pump_curve = if arbitrage.buy_dex == DexType::PumpFun || arbitrage.sell_dex == DexType::PumpFun {
+                Some(read_pump_curve(arbitrage_accounts_slice, &arbitrage.token_mint, &pump_program_id)?)
+            } else {
+                None
             };
 
-            // ====================================================================
-            // 🔥 INLINE SELL INSTRUCTION CREATION
-            // ====================================================================
-            
-            let sell_instruction = match arbitrage.sell_dex {
-                DexType::PumpFun => {
-                    msg!("🔧 Creating Pump.fun SELL instruction inline...");
-                    
-                    // Создаем instruction data для sell
-                    let mut instruction_data = Vec::new();
-                    instruction_data.extend_from_slice(&[0x33, 0xe6, 0x85, 0xa4, 0x01, 0x7f, 0x83, 0xad]); // sell discriminator
-                    instruction_data.extend_from_slice(&arbitrage.tokens_to_sell.to_le_bytes());
-                    instruction_data.extend_from_slice(&arbitrage.min_wsol_out.to_le_bytes());
-                    
-                    // Те же аккаунты что и для buy (Pump.fun использует одинаковые)
-                    Instruction {
-                        program_id: pump_program_id,
-                        accounts: buy_instruction.accounts.clone(), // Переиспользуем аккаунты
-                        data: instruction_data,
-                    }
-                },
-                DexType::Meteora => {
-                    msg!("🚧 Meteora not implemented yet");
-                    return Err(MyErrorCode::InvalidDexType.into());
+            let buy_cost = match pump_curve.as_mut() {
+                Some((reserves, fee_bps)) if arbitrage.buy_dex == DexType::PumpFun => {
+                    let cost = reserves
+                        .buy_cost(arbitrage.tokens_to_buy, *fee_bps)
+                        .ok_or(MyErrorCode::ArithmeticError)?;
+                    // Round trip по той же кривой продает уже по сдвинутым резервам
+                    *reserves = reserves.after_buy(arbitrage.tokens_to_buy).ok_or(MyErrorCode::ArithmeticError)?;
+                    cost
                 },
+                _ => arbitrage.max_sol_cost,
+            };
+            let sell_out = match pump_curve {
+                Some((reserves, fee_bps)) if arbitrage.sell_dex == DexType::PumpFun => reserves
+                    .sell_out(arbitrage.tokens_to_sell, fee_bps)
+                    .ok_or(MyErrorCode::ArithmeticError)?,
+                _ => arbitrage.min_wsol_out,
             };
 
-            let sell_accounts = buy_accounts.clone(); // Для Pump.fun те же аккаунты
-
-            // ====================================================================
-            // 🚀 АТОМАРНОЕ ИСПОЛНЕНИЕ: BUY -> SELL
-            // ====================================================================
-            
-            msg!("🚀 Executing BUY -> SELL atomically (INLINE)...");
-            
-            // Выполняем BUY
-            anchor_lang::solana_program::program::invoke(&buy_instruction, &buy_accounts)?;
-            msg!("✅ BUY completed");
-            
-            // Выполняем SELL
-            anchor_lang::solana_program::program::invoke(&sell_instruction, &sell_accounts)?;
-            msg!("✅ SELL completed");
-            
-            msg!("🎉 Arbitrage #{} completed successfully (INLINE)", index + 1);
-            
-            // Обновляем offset для следующего арбитража
-            account_offset = end;
+            let leg_profit = i64::try_from(sell_out as i128 - buy_cost as i128).map_err(|_| MyErrorCode::ArithmeticError)?;
+            msg!("🔮 Arbitrage #{}: cost {}, out {}, predicted {}", index + 1, buy_cost, sell_out, leg_profit);
+            predicted_profit = predicted_profit.checked_add(leg_profit).ok_or(MyErrorCode::ArithmeticError)?;
+        }
+
+        msg!("🔮 Predicted batch profit: {}", predicted_profit);
+        Ok(predicted_profit)
+    }
+
+    /// 🧭 PDA Pump.fun для mint: [global, bonding-curve, associated-bonding-curve, __event_authority].
+    /// Возвращается через return data - Go-бот строит remaining_accounts без своей копии деривации.
+    pub fn derive_pump_accounts(_ctx: Context<DerivePumpAccounts>, mint: Pubkey) -> Result<[Pubkey; 4]> {
+        let pump_program_id = parse_program_id(PUMP_FUN_PROGRAM_ID)?;
+        let pump_accounts = derive_pump_fun_pdas(&mint, &pump_program_id, &anchor_spl::token::ID);
+
+        msg!("🧭 Pump.fun accounts for {}: {:?}", mint, pump_accounts);
+        Ok(pump_accounts)
+    }
+
+    /// 📖 Полный конфиг роутера через return data в стабильном Borsh layout-е (RouterStateView),
+    /// без Anchor discriminator - версионированный путь чтения для Go-бота.
+    pub fn get_state(ctx: Context<GetState>) -> Result<RouterStateView> {
+        let router_state = &ctx.accounts.router_state;
+
+        Ok(RouterStateView {
+            version: router_state.version,
+            owner: router_state.owner,
+            is_paused: router_state.is_paused,
+            last_nonce: router_state.last_nonce,
+            max_slippage_bps: router_state.max_slippage_bps,
+            profit_fee_bps: router_state.profit_fee_bps,
+            treasury: router_state.treasury,
+            pump_buy_discriminator: router_state.pump_buy_discriminator,
+            pump_sell_discriminator: router_state.pump_sell_discriminator,
+            allow_raw: router_state.allow_raw,
+            min_slots_between_batches: router_state.min_slots_between_batches,
+            last_batch_slot: router_state.last_batch_slot,
+            max_accounts_per_leg: router_state.max_accounts_per_leg,
+            enforce_distinct_venues: router_state.enforce_distinct_venues,
+            total_batches: router_state.total_batches,
+            cumulative_profit: router_state.cumulative_profit,
+            suggested_cu_price_microlamports: router_state.suggested_cu_price_microlamports,
+            fee_recipient: router_state.fee_recipient,
+            is_shutdown: router_state.is_shutdown,
+            strict_leg_accounts: router_state.strict_leg_accounts,
+            pending_owner: router_state.pending_owner,
+            quote_mint: router_state.effective_quote_mint(),
+            quote_has_transfer_fee: router_state.quote_has_transfer_fee,
+            consecutive_failures: router_state.consecutive_failures,
+            failure_threshold: router_state.failure_threshold,
+            failure_reporter: router_state.failure_reporter,
+            denied_mints: router_state.active_denied_mints().to_vec(),
+            batch_tag_cursor: router_state.batch_tag_cursor,
+            auto_slippage_relief_bps: router_state.auto_slippage_relief_bps,
+            reserved_cu: router_state.reserved_cu,
+            seed: router_state.seed,
+            min_liquidity: router_state.min_liquidity,
+            max_mints_per_batch: router_state.max_mints_per_batch,
+            dex_discriminators: router_state.dex_discriminators,
+            total_legs_executed: router_state.total_legs_executed,
+            profit_mint: router_state.profit_mint,
+            profit_oracle: router_state.profit_oracle,
+            min_profit: router_state.min_profit,
+            max_scan_passes: router_state.max_scan_passes,
+        })
+    }
+
+    /// 🧾 Какие DexType исполняет этот деплой (бит i = вариант DexType с индексом i).
+    /// Результат (u32) возвращается через return data - клиент проверяет ноги заранее.
+    pub fn supported_dexes(ctx: Context<SupportedDexes>) -> Result<u32> {
+        let mask = supported_dex_mask(ctx.accounts.router_state.allow_raw);
+
+        msg!("🧾 Supported DEX mask: {:#010b}", mask);
+        Ok(mask)
+    }
+
+    /// ⚙️ Глобальный потолок slippage в bps для каждой ноги (0 = выключен)
+    pub fn set_max_slippage_bps(ctx: Context<UpdateConfig>, max_slippage_bps: u16) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        require!(max_slippage_bps <= 10_000, MyErrorCode::InvalidConfigValue);
+
+        router_state.max_slippage_bps = max_slippage_bps;
+
+        msg!("⚙️ Max slippage cap set to {} bps", max_slippage_bps);
+        Ok(())
+    }
+
+    /// 🏦 Protocol fee с реализованной прибыли и treasury-владелец wSOL аккаунта для него
+    pub fn set_profit_fee(ctx: Context<UpdateConfig>, profit_fee_bps: u16, treasury: Pubkey) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        require!(profit_fee_bps <= 10_000, MyErrorCode::InvalidConfigValue);
+
+        router_state.profit_fee_bps = profit_fee_bps;
+        router_state.treasury = treasury;
+
+        msg!("🏦 Profit fee set to {} bps, treasury: {}", profit_fee_bps, treasury);
+        Ok(())
+    }
+
+    /// 🧬 Миграция RouterState на текущий layout (ROUTER_STATE_VERSION).
+    /// v1 (без version) разбирается вручную; v2+ только дорастает до нового размера -
+    /// новые поля всегда добавляются в конец и инициализируются нулями. Исключение - v17..v27:
+    /// их denied_mints (Vec) перекладывается в фиксированный массив v28.
+    pub fn migrate_router(ctx: Context<MigrateRouter>) -> Result<()> {
+        let router_info = ctx.accounts.router_state.to_account_info();
+        require!(router_info.owner == ctx.program_id, MyErrorCode::InvalidAccountData);
+
+        let from_version = {
+            let data = router_info.try_borrow_data()?;
+            require!(
+                data.len() > 8 && data[..8] == RouterState::DISCRIMINATOR,
+                MyErrorCode::InvalidAccountData
+            );
+            if data.len() == ROUTER_STATE_V1_LEN { 1 } else { data[8] }
+        };
+        require!(from_version < ROUTER_STATE_VERSION, MyErrorCode::AlreadyMigrated);
+
+        let mut router_state = if from_version == 1 {
+            // v1: owner + is_paused + bump без поля version - переносим вручную
+            let data = router_info.try_borrow_data()?;
+            let legacy = RouterStateV1::deserialize(&mut &data[8..])
+                .map_err(|_| MyErrorCode::InvalidAccountData)?;
+            RouterState {
+                owner: legacy.owner,
+                is_paused: legacy.is_paused,
+                bump: legacy.bump,
+                ..RouterState::default()
+            }
+        } else {
+            // v2+: новые поля в хвосте - дочитываем их нулями (аккаунт дорастет при записи)
+            let mut data = router_info.try_borrow_data()?.to_vec();
+            if (17..28).contains(&from_version) {
+                data = widen_legacy_denied_mints(&data)?;
+            }
+            if data.len() < RouterState::LEN {
+                data.resize(RouterState::LEN, 0);
+            }
+            RouterState::try_deserialize(&mut &data[..])?
+        };
+        // До v28 за концом denied_mints (Vec) в резерве могли лежать байты удаленных из denylist
+        // mint-ов, а не нули - поля новее версии аккаунта сбрасываем явно
+        if from_version < 18 {
+            router_state.recent_batch_tags = [[0u8; 32]; BATCH_TAG_HISTORY];
+            router_state.batch_tag_cursor = 0;
+        }
+        if from_version < 19 {
+            router_state.auto_slippage_relief_bps = 0;
+        }
+        if from_version < 20 {
+            router_state.reserved_cu = 0;
+        }
+        if from_version < 21 {
+            // До reinitialize был только основной PDA
+            router_state.seed = [0u8; 8];
+        }
+        if from_version < 22 {
+            router_state.min_liquidity = 0;
+        }
+        if from_version < 23 {
+            router_state.max_mints_per_batch = 0;
+        }
+        if from_version < 24 {
+            router_state.dex_discriminators = [DexDiscriminator::default(); MAX_DISCRIMINATOR_OVERRIDES];
+        }
+        if from_version < 25 {
+            router_state.total_legs_executed = 0;
+        }
+        if from_version < 26 {
+            // Без оракула и порога - прежняя проверка неубыточности в quote
+            router_state.profit_mint = Pubkey::default();
+            router_state.profit_oracle = Pubkey::default();
+            router_state.min_profit = 0;
+        }
+        if from_version < 27 {
+            router_state.max_scan_passes = 0;
+        }
+        router_state.version = ROUTER_STATE_VERSION;
+
+        // Проверяем права владельца
+        require!(
+            ctx.accounts.owner.key() == router_state.owner,
+            MyErrorCode::UnauthorizedAccess
+        );
+
+        // Доплачиваем rent за новый размер
+        let rent_minimum = Rent::get()?.minimum_balance(RouterState::LEN);
+        let rent_shortfall = rent_minimum.saturating_sub(router_info.lamports());
+        if rent_shortfall > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.owner.to_account_info(),
+                        to: router_info.clone(),
+                    },
+                ),
+                rent_shortfall,
+            )?;
+        }
+
+        if router_info.data_len() != RouterState::LEN {
+            router_info.realloc(RouterState::LEN, true)?;
+        }
+        let mut data = router_info.try_borrow_mut_data()?;
+        router_state.try_serialize(&mut &mut data[..])?;
+
+        msg!("🧬 Router state migrated v{} -> v{}", from_version, ROUTER_STATE_VERSION);
+        Ok(())
+    }
+
+    /// 🔁 Discriminator-ы Pump.fun buy/sell: реагируем на апгрейд Pump.fun без редеплоя
+    pub fn set_pump_discriminators(
+        ctx: Context<UpdateConfig>,
+        buy_discriminator: [u8; 8],
+        sell_discriminator: [u8; 8],
+    ) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.pump_buy_discriminator = buy_discriminator;
+        router_state.pump_sell_discriminator = sell_discriminator;
+
+        msg!("🔁 Pump.fun discriminators updated: buy {:?}, sell {:?}", buy_discriminator, sell_discriminator);
+        Ok(())
+    }
+
+    /// 🔁 Discriminator инструкции любого Anchor-DEX-а для стороны сделки: реагируем на rename
+    /// без редеплоя. Нулевой discriminator снимает переопределение (билдер берет compiled default).
+    /// Pump.fun - через set_pump_discriminators
+    pub fn set_dex_discriminator(
+        ctx: Context<UpdateConfig>,
+        dex: DexType,
+        side: TradeSide,
+        discriminator: [u8; 8],
+    ) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        require!(has_anchor_discriminator(&dex), MyErrorCode::InvalidDexType);
+
+        let entry = DexDiscriminator {
+            dex: dex_index(&dex),
+            side: side as u8,
+            discriminator,
+        };
+        let slot = router_state
+            .dex_discriminators
+            .iter()
+            .position(|existing| existing.is_set() && existing.dex == entry.dex && existing.side == entry.side)
+            .or_else(|| router_state.dex_discriminators.iter().position(|existing| !existing.is_set()))
+            .ok_or(MyErrorCode::InvalidConfigValue)?;
+        router_state.dex_discriminators[slot] = entry;
+
+        msg!("🔁 {:?} {:?} discriminator set to {:?}", dex, side, discriminator);
+        Ok(())
+    }
+
+    /// 🧩 Разрешить/запретить DexType::Raw ноги (произвольный CPI без апгрейда программы)
+    pub fn set_allow_raw(ctx: Context<UpdateConfig>, allow_raw: bool) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.allow_raw = allow_raw;
+
+        msg!("🧩 Raw CPI legs {}", if allow_raw { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// 🐢 Минимальный интервал в слотах между батчами (0 = без ограничения)
+    pub fn set_min_slots_between_batches(ctx: Context<UpdateConfig>, min_slots_between_batches: u64) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.min_slots_between_batches = min_slots_between_batches;
+
+        msg!("🐢 Min slots between batches set to {}", min_slots_between_batches);
+        Ok(())
+    }
+
+    /// 📊 Максимум accounts_count на одну ногу (0 = дефолт DEFAULT_MAX_ACCOUNTS_PER_LEG)
+    pub fn set_max_accounts_per_leg(ctx: Context<UpdateConfig>, max_accounts_per_leg: u8) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.max_accounts_per_leg = max_accounts_per_leg;
+
+        msg!("📊 Max accounts per leg set to {}", max_accounts_per_leg);
+        Ok(())
+    }
+
+    /// 🔁 Требовать buy_dex != sell_dex (кроме ног с allow_same_venue); по умолчанию только предупреждение
+    pub fn set_enforce_distinct_venues(ctx: Context<UpdateConfig>, enforce_distinct_venues: bool) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.enforce_distinct_venues = enforce_distinct_venues;
+
+        msg!("🔁 Distinct venues {}", if enforce_distinct_venues { "enforced" } else { "not enforced" });
+        Ok(())
+    }
+
+    /// 💡 Рекомендованная цена CU (microlamports) для priority fee всех ботов - только подсказка
+    pub fn set_suggested_cu_price(ctx: Context<UpdateConfig>, suggested_cu_price_microlamports: u64) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.suggested_cu_price_microlamports = suggested_cu_price_microlamports;
+
+        msg!("💡 Suggested CU price set to {} microlamports", suggested_cu_price_microlamports);
+        Ok(())
+    }
+
+    /// 💸 Fee recipient Pump.fun по умолчанию для всех ног (нога может переопределить свой)
+    pub fn set_fee_recipient(ctx: Context<UpdateConfig>, fee_recipient: Pubkey) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.fee_recipient = fee_recipient;
+
+        msg!("💸 Default Pump.fun fee recipient set to {}", fee_recipient);
+        Ok(())
+    }
+
+    /// 🧷 Strict mode: ноги с разными token_mint не должны делить mint-специфичные аккаунты
+    pub fn set_strict_leg_accounts(ctx: Context<UpdateConfig>, strict_leg_accounts: bool) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.strict_leg_accounts = strict_leg_accounts;
+
+        msg!("🧷 Strict leg accounts {}", if strict_leg_accounts { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// 🧾 Quote mint берет transfer fee (Token-2022): min-out ног сверяется за вычетом комиссии
+    pub fn set_quote_has_transfer_fee(ctx: Context<UpdateConfig>, quote_has_transfer_fee: bool) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.quote_has_transfer_fee = quote_has_transfer_fee;
+
+        msg!("🧾 Quote transfer fee handling {}", if quote_has_transfer_fee { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// 🔌 Circuit breaker: порог подряд проваленных батчей до авто-паузы (0 = выключен)
+    /// и ключ, которому разрешено сообщать о провалах (default = только owner)
+    pub fn set_failure_threshold(ctx: Context<UpdateConfig>, failure_threshold: u8, failure_reporter: Pubkey) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.failure_threshold = failure_threshold;
+        router_state.failure_reporter = failure_reporter;
+        router_state.consecutive_failures = 0;
+
+        msg!("🔌 Failure threshold set to {} (reporter {})", failure_threshold, failure_reporter);
+        Ok(())
+    }
+
+    /// 🔌 Откаченная транзакция не может ничего записать в стейт, поэтому провал батча бот
+    /// сообщает отдельной инструкцией. Успешный батч сбрасывает счетчик сам.
+    /// При достижении failure_threshold роутер ставится на паузу.
+    pub fn record_failure(ctx: Context<RecordFailure>) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        let reporter = ctx.accounts.reporter.key();
+        require!(
+            reporter == router_state.owner
+                || (router_state.failure_reporter != Pubkey::default() && reporter == router_state.failure_reporter),
+            MyErrorCode::UnauthorizedAccess
+        );
+
+        router_state.consecutive_failures = router_state.consecutive_failures.saturating_add(1);
+        msg!("🔌 Batch failure recorded: {} in a row", router_state.consecutive_failures);
+
+        if router_state.failure_threshold > 0 && router_state.consecutive_failures >= router_state.failure_threshold {
+            router_state.is_paused = true;
+            msg!("🛑 Circuit breaker tripped - router paused");
+        }
+        Ok(())
+    }
+
+    /// ⏳ Резерв CU под финальную проверку прибыли: при меньшем остатке оставшиеся ноги пропускаются
+    /// (0 = выключен)
+    pub fn set_reserved_cu(ctx: Context<UpdateConfig>, reserved_cu: u32) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        require!(reserved_cu <= MAX_TRANSACTION_CU, MyErrorCode::InvalidConfigValue);
+
+        router_state.reserved_cu = reserved_cu;
+
+        msg!("⏳ Reserved CU set to {}", reserved_cu);
+        Ok(())
+    }
+
+    /// 🏜️ Минимальный SOL-резерв bonding curve Pump.fun: ноги по более мелким кривым
+    /// отклоняются (в BestEffort - пропускаются). 0 = проверяются только пустые резервы
+    pub fn set_min_liquidity(ctx: Context<UpdateConfig>, min_liquidity: u64) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.min_liquidity = min_liquidity;
+
+        msg!("🏜️ Min liquidity set to {}", min_liquidity);
+        Ok(())
+    }
+
+    /// 🎯 Порог прибыли батча: min_profit в общей единице. С оракулом прибыль в profit_mint
+    /// нормализуется по его цене (USDC- / LST-quoted стратегии), без оракула сравнивается
+    /// как есть в единицах profit_mint. profit_mint должен совпадать с mint quote-аккаунта батча
+    pub fn set_profit_config(
+        ctx: Context<UpdateConfig>,
+        profit_mint: Pubkey,
+        profit_oracle: Option<Pubkey>,
+        min_profit: u64,
+    ) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.profit_mint = profit_mint;
+        router_state.profit_oracle = profit_oracle.unwrap_or_default();
+        router_state.min_profit = min_profit;
+
+        msg!("🎯 Profit config: mint {}, oracle {:?}, min profit {}", profit_mint, profit_oracle, min_profit);
+        Ok(())
+    }
+
+    /// ⛽ Проходов по срезу ноги на разбор ее аккаунтов: бюджет сравнений = accounts_count * passes.
+    /// 0 = DEFAULT_SCAN_PASSES
+    pub fn set_max_scan_passes(ctx: Context<UpdateConfig>, max_scan_passes: u8) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.max_scan_passes = max_scan_passes;
+
+        msg!("⛽ Max scan passes set to {}", max_scan_passes);
+        Ok(())
+    }
+
+    /// 🧺 Потолок различных token_mint в одном батче (0 = без ограничения)
+    pub fn set_max_mints_per_batch(ctx: Context<UpdateConfig>, max_mints_per_batch: u8) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.max_mints_per_batch = max_mints_per_batch;
+
+        msg!("🧺 Max mints per batch set to {}", max_mints_per_batch);
+        Ok(())
+    }
+
+    /// 🚫 Добавить mint в denylist: ноги с этим token_mint отклоняются до исполнения
+    pub fn add_denied_mint(ctx: Context<UpdateConfig>, mint: Pubkey) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        if router_state.active_denied_mints().contains(&mint) {
+            msg!("🚫 Mint {} already denied", mint);
+            return Ok(());
         }
+        let count = usize::from(router_state.denied_count);
+        require!(count < MAX_DENIED_MINTS, MyErrorCode::InvalidConfigValue);
+        router_state.denied_mints[count] = mint;
+        router_state.denied_count += 1;
+
+        msg!("🚫 Mint {} denied ({} / {})", mint, router_state.denied_count, MAX_DENIED_MINTS);
+        Ok(())
+    }
+
+    /// 🚫 Убрать mint из denylist
+    pub fn remove_denied_mint(ctx: Context<UpdateConfig>, mint: Pubkey) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        let position = router_state
+            .active_denied_mints()
+            .iter()
+            .position(|denied| *denied == mint)
+            .ok_or(MyErrorCode::InvalidConfigValue)?;
+        // swap_remove внутри массива: последний занятый слот на место удаленного, хвост - нули
+        let last = usize::from(router_state.denied_count) - 1;
+        router_state.denied_mints.swap(position, last);
+        router_state.denied_mints[last] = Pubkey::default();
+        router_state.denied_count -= 1;
+
+        msg!("🚫 Mint {} removed from denylist", mint);
+        Ok(())
+    }
+
+    /// 🩹 Допуск в bps, на который роутер сам снижает min_wsol_out каждой ноги (не ниже amount_in).
+    /// Итоговая прибыль батча проверяется как раньше (0 = выключен)
+    pub fn set_auto_slippage_relief_bps(ctx: Context<UpdateConfig>, auto_slippage_relief_bps: u16) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        require!(auto_slippage_relief_bps <= 10_000, MyErrorCode::InvalidConfigValue);
+
+        router_state.auto_slippage_relief_bps = auto_slippage_relief_bps;
+
+        msg!("🩹 Auto slippage relief set to {} bps", auto_slippage_relief_bps);
+        Ok(())
+    }
+
+    /// 👑 Шаг 1 передачи прав: текущий owner предлагает нового (кошелек или PDA, например vault Squads).
+    /// Pubkey::default() отменяет предложение.
+    pub fn propose_new_owner(ctx: Context<UpdateConfig>, new_owner: Pubkey) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.pending_owner = new_owner;
+
+        msg!("👑 Ownership proposed to {}", new_owner);
+        Ok(())
+    }
+
+    /// 👑 Шаг 2: новый owner подтверждает подписью. PDA подписывает через invoke_signed из своей
+    /// программы (multisig) - для Signer это неотличимо от обычной подписи, как и во всех owner-only инструкциях.
+    pub fn accept_ownership(ctx: Context<AcceptOwnership>) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        require!(router_state.pending_owner != Pubkey::default(), MyErrorCode::UnauthorizedAccess);
+        require!(
+            ctx.accounts.new_owner.key() == router_state.pending_owner,
+            MyErrorCode::UnauthorizedAccess
+        );
+
+        router_state.owner = router_state.pending_owner;
+        router_state.pending_owner = Pubkey::default();
+
+        msg!("👑 Ownership accepted by {}", router_state.owner);
+        Ok(())
+    }
+
+    /// ☠️ Необратимый shutdown (end-of-life / миграция на новую программу): торговля больше невозможна
+    pub fn shutdown(ctx: Context<UpdateConfig>) -> Result<()> {
+        let router_state = &mut ctx.accounts.router_state;
+
+        router_state.is_shutdown = true;
+
+        msg!("☠️ Router permanently shut down by {}", router_state.owner);
+        Ok(())
+    }
+
+    /// 🔑 Blue/green: новый RouterState под [b"router_state", new_seed] с конфигом старого.
+    /// Старый должен стоять на паузе и на ней остается; счетчики и история нового начинаются с нуля.
+    pub fn reinitialize(ctx: Context<Reinitialize>, new_seed: [u8; 8]) -> Result<()> {
+        let old_router_state = &ctx.accounts.old_router_state;
+
+        require!(old_router_state.is_paused, MyErrorCode::ContractNotPaused);
+        // Нулевой seed зарезервирован за основным PDA
+        require!(new_seed != [0u8; 8], MyErrorCode::InvalidConfigValue);
+
+        let new_router_state = RouterState {
+            version: ROUTER_STATE_VERSION,
+            is_paused: false,
+            bump: ctx.bumps.new_router_state,
+            seed: new_seed,
+            // Runtime-состояние не переносим
+            last_nonce: 0,
+            last_batch_slot: 0,
+            total_batches: 0,
+            cumulative_profit: 0,
+            total_legs_executed: 0,
+            pending_owner: Pubkey::default(),
+            consecutive_failures: 0,
+            recent_batch_tags: [[0u8; 32]; BATCH_TAG_HISTORY],
+            batch_tag_cursor: 0,
+            ..(**old_router_state).clone()
+        };
+        ctx.accounts.new_router_state.set_inner(new_router_state);
+
+        msg!("🔑 Router re-keyed: {} -> {}", old_router_state.key(), ctx.accounts.new_router_state.key());
+        Ok(())
+    }
+
+    /// 🛟 Rescue: вывод застрявших SPL токенов с аккаунта PDA роутера владельцу (только на паузе)
+    pub fn rescue_tokens(ctx: Context<RescueTokens>, amount: u64) -> Result<()> {
+        let router_state = &ctx.accounts.router_state;
+
+        // Safety valve работает только при остановленном роутере
+        require!(router_state.is_paused, MyErrorCode::ContractNotPaused);
+
+        // Подписываем перевод сидами PDA роутера
+        let signer_seeds: &[&[&[u8]]] = &[&[b"router_state", router_state.seed_suffix(), &[router_state.bump]]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.source_token_account.to_account_info(),
+                    to: ctx.accounts.destination_token_account.to_account_info(),
+                    authority: router_state.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+        )?;
+
+        msg!("🛟 Rescued {} tokens of mint {} to owner", amount, ctx.accounts.source_token_account.mint);
+        Ok(())
+    }
+
+    /// 🧯 Break-glass: весь баланс каждого SPL токен-аккаунта PDA роутера из remaining_accounts
+    /// уходит на токен-аккаунт owner-а того же mint (тоже в remaining_accounts). Только на паузе.
+    pub fn emergency_withdraw_all<'info>(ctx: Context<'_, '_, 'info, 'info, EmergencyWithdrawAll<'info>>) -> Result<()> {
+        let router_state = &ctx.accounts.router_state;
+
+        // Safety valve работает только при остановленном роутере
+        require!(router_state.is_paused, MyErrorCode::ContractNotPaused);
+
+        let router_key = router_state.key();
+        let owner_key = ctx.accounts.owner.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[b"router_state", router_state.seed_suffix(), &[router_state.bump]]];
+        let mut swept_count: u32 = 0;
+
+        for source_info in ctx.remaining_accounts.iter() {
+            if source_info.owner != &anchor_spl::token::ID {
+                continue;
+            }
+            let Some(source) = parse_token_account(source_info)? else {
+                continue;
+            };
+            // Аккаунты owner-а - это destination-ы, их пропускаем
+            if source.owner != router_key || source.amount == 0 {
+                continue;
+            }
+
+            let destination_info = find_user_token_account(ctx.remaining_accounts, &owner_key, &source.mint)?;
+
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: source_info.clone(),
+                        to: destination_info.clone(),
+                        authority: router_state.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                source.amount,
+            )?;
+            swept_count += 1;
+            msg!("🧯 Swept {} tokens of mint {} to owner", source.amount, source.mint);
+        }
+
+        msg!("🧯 Emergency withdraw complete: {} token accounts swept", swept_count);
+        Ok(())
+    }
+
+    /// 🧽 Закрыть пустые токен-аккаунты user-а из remaining_accounts и вернуть их rent.
+    /// Непустой или чужой аккаунт откатывает весь вызов. Роутер в этом не участвует - подписывает user.
+    pub fn close_empty_atas<'info>(ctx: Context<'_, '_, 'info, 'info, CloseEmptyAtas<'info>>) -> Result<()> {
+        let user = ctx.accounts.user.to_account_info();
+        let rent_before = user.lamports();
+
+        for token_account in ctx.remaining_accounts.iter() {
+            // CPI уходит в программу-владельца аккаунта: SPL Token или Token-2022 из контекста
+            let token_program = if token_account.owner == &anchor_spl::token::ID {
+                ctx.accounts.token_program.to_account_info()
+            } else if token_account.owner == &anchor_spl::token_2022::ID {
+                ctx.accounts
+                    .token_2022_program
+                    .as_ref()
+                    .ok_or(MyErrorCode::AccountNotFound)?
+                    .to_account_info()
+            } else {
+                return Err(MyErrorCode::InvalidTokenAccount.into());
+            };
+
+            let state = InterfaceTokenAccount::try_deserialize(&mut token_account.data.borrow().as_ref())
+                .map_err(|_| MyErrorCode::InvalidTokenAccount)?;
+            require!(state.owner == user.key(), MyErrorCode::InvalidTokenAccount);
+            require!(state.amount == 0, MyErrorCode::TokenAccountNotEmpty);
+
+            anchor_spl::token_interface::close_account(CpiContext::new(
+                token_program,
+                anchor_spl::token_interface::CloseAccount {
+                    account: token_account.clone(),
+                    destination: user.clone(),
+                    authority: user.clone(),
+                },
+            ))?;
+        }
+
+        msg!(
+            "🧽 Closed {} empty token accounts, reclaimed {} lamports",
+            ctx.remaining_accounts.len(),
+            user.lamports().saturating_sub(rent_before)
+        );
+        Ok(())
+    }
+
+    /// 🆕 Подготовка ротации токенов: ATA user-а для каждого mint одним вызовом (create_idempotent,
+    /// payer = user; существующие пропускаются). remaining_accounts - mint и ATA каждого mint-а.
+    pub fn create_user_atas<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateUserAtas<'info>>,
+        mints: Vec<Pubkey>,
+    ) -> Result<()> {
+        // Каждый create стоит десятки тысяч CU - список ограничен, чтобы влезть в транзакцию
+        require!(!mints.is_empty() && mints.len() <= MAX_CREATE_ATAS, MyErrorCode::InvalidConfigValue);
+
+        let user = ctx.accounts.user.to_account_info();
+        let mut created_count = 0;
+
+        for mint in mints.iter() {
+            let mint_account = find_account(ctx.remaining_accounts, mint).ok_or(MyErrorCode::MintAccountNotFound)?;
+
+            // ATA деривируется от программы-владельца mint-а: SPL Token или Token-2022 из контекста
+            let token_program = if mint_account.owner == &anchor_spl::token::ID {
+                ctx.accounts.token_program.to_account_info()
+            } else if mint_account.owner == &anchor_spl::token_2022::ID {
+                ctx.accounts
+                    .token_2022_program
+                    .as_ref()
+                    .ok_or(MyErrorCode::AccountNotFound)?
+                    .to_account_info()
+            } else {
+                return Err(MyErrorCode::InvalidProgramId.into());
+            };
+
+            let ata = get_associated_token_address_with_program_id(&user.key(), mint, &token_program.key());
+            let ata_account = find_account(ctx.remaining_accounts, &ata).ok_or(MyErrorCode::TokenAccountNotFound)?;
+            if !is_uninitialized_account(ata_account) {
+                continue;
+            }
+
+            associated_token::create_idempotent(CpiContext::new(
+                ctx.accounts.associated_token_program.to_account_info(),
+                associated_token::Create {
+                    payer: user.clone(),
+                    associated_token: ata_account.clone(),
+                    authority: user.clone(),
+                    mint: mint_account.clone(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    token_program,
+                },
+            ))?;
+            created_count += 1;
+        }
+
+        msg!("🆕 Created {} of {} user ATAs", created_count, mints.len());
+        Ok(())
+    }
+
+    /// 🚰 Вывод ошибочно присланных SOL с PDA роутера владельцу (только излишек сверх rent-exemption).
+    /// Аккаунт принадлежит программе, поэтому lamports списываются напрямую - system CPI не нужен.
+    pub fn drain_lamports(ctx: Context<DrainLamports>, amount: u64) -> Result<()> {
+        let router_info = ctx.accounts.router_state.to_account_info();
+        let rent_minimum = Rent::get()?.minimum_balance(router_info.data_len());
+        let remaining = router_info
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(MyErrorCode::RentExemptionViolation)?;
+        require!(remaining >= rent_minimum, MyErrorCode::RentExemptionViolation);
+
+        let owner_info = ctx.accounts.owner.to_account_info();
+        **router_info.try_borrow_mut_lamports()? = remaining;
+        **owner_info.try_borrow_mut_lamports()? = owner_info
+            .lamports()
+            .checked_add(amount)
+            .ok_or(MyErrorCode::ArithmeticError)?;
+
+        msg!("🚰 Drained {} lamports from router PDA to owner", amount);
+        Ok(())
+    }
+}
+
+// ============================================================================
+// 🚀 ИСПОЛНЕНИЕ БАТЧА
+// ============================================================================
+
+/// Тело execute_arbitrage_batch / execute_batch_N: Anchor-инструкции не бывают generic,
+/// поэтому число ног N - const generic здесь, а инструкции - тонкие обертки с фиксированным N.
+/// Статистика RouterState (total_batches, total_legs_executed, cumulative_profit) пишется только
+/// успешным батчем: откат транзакции отбрасывает любые записи, поэтому по ноге ее не ведем -
+/// P&L каждой исполненной ноги есть в событии ArbitrageExecuted.
+/// ANCHOR 0.29 COMPATIBLE (EXPLICIT LIFETIMES)
+fn execute_batch<'info, const N: usize>(
+    ctx: Context<'_, '_, 'info, 'info, ExecuteArbitrageBatch<'info>>,
+    arbitrages: [ArbitrageParams; N],
+    options: BatchOptions,
+) -> Result<()> {
+    let BatchOptions {
+        wrap_amount,
+        nonce,
+        max_total_sol,
+        mode,
+        start_index,
+        count,
+        unwrap_all,
+        settle_native,
+        batch_tag,
+        order,
+        flash_loan,
+        tx_cost_lamports,
+    } = options;
+
+    // 📏 Остаток CU на входе - для фактического расхода в BatchCompleted
+    let cu_at_entry = remaining_compute_units();
+
+    // 1. Проверка shutdown и паузы (первая линия защиты)
+    require!(!ctx.accounts.router_state.is_shutdown, MyErrorCode::RouterShutdown);
+    require!(!ctx.accounts.router_state.is_paused, MyErrorCode::ContractIsPaused);
+
+    // 🔁 Идемпотентность: каждый батч несет строго возрастающий nonce (at-most-once на роутер).
+    // Счетчик один на весь роутер - несколько ботов должны делить общую последовательность
+    // (например, брать nonce из слота/времени), иначе их батчи будут отклоняться друг другом.
+    // Ненулевой batch_tag (например, hash от recent blockhash + ноги) заменяет nonce:
+    // повтор отклоняется по последним BATCH_TAG_HISTORY тегам, монотонность не нужна.
+    if batch_tag != [0u8; 32] {
+        let router_state = &mut ctx.accounts.router_state;
+        require!(!router_state.recent_batch_tags.contains(&batch_tag), MyErrorCode::DuplicateBatchTag);
+        let cursor = router_state.batch_tag_cursor as usize % BATCH_TAG_HISTORY;
+        router_state.recent_batch_tags[cursor] = batch_tag;
+        router_state.batch_tag_cursor = ((cursor + 1) % BATCH_TAG_HISTORY) as u8;
+    } else {
+        require!(nonce > ctx.accounts.router_state.last_nonce, MyErrorCode::StaleNonce);
+        ctx.accounts.router_state.last_nonce = nonce;
+    }
+
+    // 🐢 Грубый throttle против разогнавшегося бота (0 = выключен)
+    let current_slot = Clock::get()?.slot;
+    let min_slots = ctx.accounts.router_state.min_slots_between_batches;
+    require!(
+        min_slots == 0
+            || current_slot.saturating_sub(ctx.accounts.router_state.last_batch_slot) >= min_slots,
+        MyErrorCode::RateLimited
+    );
+
+    // 🪟 Окно исполнения: только arbitrages[start_index..start_index + count].
+    // remaining_accounts содержат аккаунты ТОЛЬКО ног окна, поэтому нарезка начинается с 0.
+    let window_start = start_index as usize;
+    let window_end = window_start + count as usize;
+    require!(count > 0 && window_end <= arbitrages.len(), MyErrorCode::InvalidBatchWindow);
+
+    // 🔀 Порядок исполнения ног окна: order[k] = индекс ноги, исполняемой k-й (пусто = по порядку).
+    // Нарезка remaining_accounts остается по исходным индексам.
+    let mut execution_rank: [usize; N] = core::array::from_fn(|index| index);
+    if !order.is_empty() {
+        require!(order.len() == count as usize, MyErrorCode::InvalidLegOrder);
+        let mut seen = [false; N];
+        for (rank, &leg_index) in order.iter().enumerate() {
+            let leg_index = leg_index as usize;
+            require!(
+                (window_start..window_end).contains(&leg_index) && !seen[leg_index],
+                MyErrorCode::InvalidLegOrder
+            );
+            seen[leg_index] = true;
+            execution_rank[leg_index] = rank;
+        }
+    }
+    let window = &arbitrages[window_start..window_end];
+
+    // 🏦 Flash loan: заем и возврат - соседние top-level инструкции транзакции вокруг батча
+    if let Some(flash_loan) = &flash_loan {
+        // Возврат списывается с user_wsol_account после батча - закрывать его нельзя
+        require!(!unwrap_all, MyErrorCode::InvalidConfigValue);
+        let instructions_sysvar = ctx
+            .accounts
+            .instructions_sysvar
+            .as_ref()
+            .ok_or(MyErrorCode::AccountNotFound)?;
+        verify_flash_loan(
+            flash_loan,
+            &instructions_sysvar.to_account_info(),
+            &ctx.accounts.user.key(),
+            &ctx.accounts.user_wsol_account.key(),
+        )?;
+        msg!("🏦 Flash loan of {} from reserve {} verified (fee up to {})",
+             flash_loan.amount, flash_loan.reserve, flash_loan.max_fee);
+    }
+
+    // 🧾 tx_cost_lamports вычитается из прибыли в quote - это одна единица только при quote = wSOL
+    if tx_cost_lamports > 0 {
+        require!(
+            ctx.accounts.user_wsol_account.mint == anchor_spl::token::spl_token::native_mint::ID,
+            MyErrorCode::InvalidConfigValue
+        );
+    }
+
+    // 💸 Потолок суммарных трат SOL на весь батч (0 = без ограничения)
+    if max_total_sol > 0 {
+        // u128 + checked_add: ноги с max_sol_cost около u64::MAX не должны переполнять сумму
+        let total_sol_cost = window.iter().try_fold(0u128, |total, arbitrage| {
+            total.checked_add(arbitrage.max_sol_cost as u128).ok_or(MyErrorCode::ArithmeticError)
+        })?;
+        require!(total_sol_cost <= max_total_sol as u128, MyErrorCode::BatchSpendExceeded);
+    }
+
+    // 🧺 Потолок различных token_mint в окне (0 = без ограничения): набор на стеке, ног не больше N
+    let max_mints_per_batch = ctx.accounts.router_state.max_mints_per_batch;
+    if max_mints_per_batch > 0 {
+        let mut distinct_mints = [Pubkey::default(); N];
+        let mut distinct_count = 0;
+        for arbitrage in window {
+            if !distinct_mints[..distinct_count].contains(&arbitrage.token_mint) {
+                distinct_mints[distinct_count] = arbitrage.token_mint;
+                distinct_count += 1;
+            }
+        }
+        require!(distinct_count <= max_mints_per_batch as usize, MyErrorCode::TooManyMints);
+    }
+    
+    msg!("🚀 Starting INLINE HFT arbitrage batch execution with {} trades (window [{}, {}))",
+         count, window_start, window_end);
+
+    // 🎯 КЛЮЧЕВОЕ РЕШЕНИЕ: ИЗВЛЕКАЕМ ВСЕ ССЫЛКИ ДО ЦИКЛА (РЕШАЕТ LIFETIME ПРОБЛЕМЫ)
+    let user = &ctx.accounts.user;
+    let system_program = &ctx.accounts.system_program;
+    let token_program = &ctx.accounts.token_program;
+    let rent = &ctx.accounts.rent;
+
+    // 💸 Потребность окна: Pump.fun платит нативными SOL, остальные DEX-ы - wSOL
+    // (вход BUY-инструкций = max_sol_cost; amount_in ноги информационный)
+    let (native_cost, wsol_cost) = window.iter().fold((0u128, 0u128), |(native, wsol), arbitrage| {
+        match arbitrage.buy_dex {
+            DexType::PumpFun => (native + arbitrage.max_sol_cost as u128, wsol),
+            _ if arbitrage.funding_account.is_some() => (native, wsol),
+            _ => (native, wsol + arbitrage.max_sol_cost as u128),
+        }
+    });
+
+    // 🧮 AUTO_WRAP_AMOUNT: оборачиваем ровно недостающее до wSOL-потребности всего окна - один
+    // transfer + sync_native на батч; остаток возвращается одним закрытием при unwrap_all
+    let wrap_amount = if wrap_amount == AUTO_WRAP_AMOUNT {
+        let wsol_balance = token_account_amount(&ctx.accounts.user_wsol_account.to_account_info())? as u128;
+        u64::try_from(wsol_cost.saturating_sub(wsol_balance)).map_err(|_| MyErrorCode::ArithmeticError)?
+    } else {
+        wrap_amount
+    };
+
+    // 💧 WRAP SOL -> wSOL ДО ЦИКЛА (0 = не оборачиваем)
+    if wrap_amount > 0 {
+        let wsol_info = ctx.accounts.user_wsol_account.to_account_info();
+
+        // sync_native работает только с native-аккаунтом классического SPL Token: иначе лампорты
+        // ушли бы на аккаунт без учета в балансе, а сбой всплыл бы невнятно в CPI свопа.
+        // Mint == quote_mint уже проверен constraint-ом user_wsol_account
+        require!(
+            ctx.accounts.user_wsol_account.is_native() && wsol_info.owner == token_program.key,
+            MyErrorCode::WsolWrapFailed
+        );
+
+        // Если аккаунт не покрывает rent-exempt минимум, доплачиваем недостающее сверху
+        let rent_minimum = rent.minimum_balance(wsol_info.data_len());
+        let rent_shortfall = rent_minimum.saturating_sub(wsol_info.lamports());
+        let lamports_to_send = wrap_amount
+            .checked_add(rent_shortfall)
+            .ok_or(MyErrorCode::ArithmeticError)?;
+        // Нативные SOL должны покрыть и wrap, и Pump.fun BUY-и окна
+        require!(
+            user.lamports() as u128 >= lamports_to_send as u128 + native_cost + USER_LAMPORTS_RESERVE as u128,
+            MyErrorCode::InsufficientFunds
+        );
+
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: user.to_account_info(),
+                    to: wsol_info.clone(),
+                },
+            ),
+            lamports_to_send,
+        )?;
+        token::sync_native(CpiContext::new(
+            token_program.to_account_info(),
+            SyncNative { account: wsol_info },
+        ))
+        .map_err(|_| MyErrorCode::WsolWrapFailed)?;
+
+        msg!("💧 Wrapped {} lamports into wSOL (rent top-up: {})", wrap_amount, rent_shortfall);
+    }
+
+    // 💰 Хватает ли средств на все BUY окна: понятная ошибка здесь вместо падения глубоко в CPI.
+    // Ноги с funding_account проверяются в prepare_leg.
+    require!(
+        user.lamports() as u128 >= native_cost + USER_LAMPORTS_RESERVE as u128,
+        MyErrorCode::InsufficientFunds
+    );
+    require!(
+        token_account_amount(&ctx.accounts.user_wsol_account.to_account_info())? as u128 >= wsol_cost,
+        MyErrorCode::InsufficientFunds
+    );
+
+    // 🔧 СОЗДАЕМ КОНСТАНТЫ ОДИН РАЗ (МИНИМИЗИРУЕМ CRYPTO ОПЕРАЦИИ)
+    let pump_program_id = parse_program_id(PUMP_FUN_PROGRAM_ID)?;
+    // Fee recipient Pump.fun по умолчанию для роутера (default после migrate_router -> compiled default)
+    let fee_recipient = match ctx.accounts.router_state.fee_recipient {
+        configured if configured != Pubkey::default() => configured,
+        _ => Pubkey::from_str(PUMP_FEE_RECIPIENT).map_err(|_| MyErrorCode::InvalidConfigValue)?,
+    };
+
+    // Общий контекст для билдеров (один раз до цикла)
+    let build_ctx = BuildContext {
+        user: user.to_account_info(),
+        user_quote_account: ctx.accounts.user_wsol_account.to_account_info(),
+        system_program: system_program.to_account_info(),
+        token_program: token_program.to_account_info(),
+        rent: rent.to_account_info(),
+        pump_program_id,
+        fee_recipient,
+        pump_buy_discriminator: configured_or_default(
+            ctx.accounts.router_state.pump_buy_discriminator,
+            PUMP_BUY_DISCRIMINATOR,
+        ),
+        pump_sell_discriminator: configured_or_default(
+            ctx.accounts.router_state.pump_sell_discriminator,
+            PUMP_SELL_DISCRIMINATOR,
+        ),
+        allow_raw: ctx.accounts.router_state.allow_raw,
+        enforce_distinct_venues: ctx.accounts.router_state.enforce_distinct_venues,
+        min_liquidity: ctx.accounts.router_state.min_liquidity,
+        dex_discriminators: ctx.accounts.router_state.dex_discriminators,
+        scan_passes: match ctx.accounts.router_state.max_scan_passes {
+            0 => DEFAULT_SCAN_PASSES,
+            max_scan_passes => max_scan_passes,
+        } as usize,
+    };
+
+    // Сумма P&L ног в quote: i128, чтобы убыточная нога не переполняла беззнаковый счетчик
+    let mut batch_profit: i128 = 0;
+
+    // settle_native: нативные SOL user-а тоже часть результата (Pump.fun и пр. расчеты в SOL).
+    // Комиссия транзакции списана до исполнения и в дельту не попадает; rent созданных ATA - шум.
+    let native_before = build_ctx.user.lamports();
+    let mut ata_rent_paid: u64 = 0;
+
+    // Глобальный потолок slippage (0 = выключен)
+    let max_slippage_bps = ctx.accounts.router_state.max_slippage_bps;
+    // Автоматический допуск к min_wsol_out каждой ноги (0 = выключен)
+    let slippage_relief_bps = ctx.accounts.router_state.auto_slippage_relief_bps;
+    // Резерв CU под финальную проверку прибыли (0 = выключен)
+    let reserved_cu = ctx.accounts.router_state.reserved_cu;
+
+    // Потолок accounts_count на ногу (0 после migrate_router -> дефолт)
+    let max_accounts_per_leg = match ctx.accounts.router_state.max_accounts_per_leg {
+        0 => DEFAULT_MAX_ACCOUNTS_PER_LEG,
+        configured => configured,
+    };
+
+    // Strict mode: (token_mint, bonding-curve PDA) уже обработанных ног
+    let strict_leg_accounts = ctx.accounts.router_state.strict_leg_accounts;
+    let mut seen_leg_accounts: Vec<(Pubkey, Pubkey)> = Vec::new();
+
+    // Quote с transfer fee (Token-2022): min_wsol_out задан до комиссии, а мы меряем после
+    let quote_fee_mint = if ctx.accounts.router_state.quote_has_transfer_fee {
+        Some(ctx.accounts.quote_mint.as_ref().ok_or(MyErrorCode::MintAccountNotFound)?.to_account_info())
+    } else {
+        None
+    };
+    let current_epoch = Clock::get()?.epoch;
+
+    // 2. ✈️ PRE-FLIGHT: нарезка срезов и подготовка ВСЕХ ног окна до первого swap CPI.
+    // AllOrNothing падает здесь, не исполнив ни одной ноги; BestEffort заранее отсеивает пропуски.
+    let mut account_offset = 0;
+    let mut skipped_count: u8 = 0;
+    let mut legs = Vec::with_capacity(count as usize);
+
+    for (index, arbitrage) in arbitrages.iter().enumerate().skip(window_start).take(count as usize) {
+        msg!("🔎 Pre-flight arbitrage #{}", index + 1);
+        msg!("📊 Accounts needed: {}", arbitrage.accounts_count);
+
+        // 🚫 Compliance denylist: token_mint (и bridge output_mint) ноги
+        let denied_mints = ctx.accounts.router_state.active_denied_mints();
+        require!(
+            !denied_mints.contains(&arbitrage.token_mint)
+                && arbitrage.bridge.as_ref().map_or(true, |bridge| !denied_mints.contains(&bridge.output_mint)),
+            MyErrorCode::MintDenied
+        );
+
+        // Ограничиваем худший случай сканов среза
+        require!(
+            arbitrage.accounts_count <= max_accounts_per_leg,
+            MyErrorCode::AccountCountMismatch
+        );
+        // И снизу: недосчет ловим до сканов среза
+        require!(
+            arbitrage.accounts_count >= min_accounts(&arbitrage.buy_dex),
+            MyErrorCode::InsufficientAccounts
+        );
+        
+        // Вычисляем границы среза для этого арбитража
+        let start = account_offset;
+        let end = start + arbitrage.accounts_count as usize;
+        
+        // Проверяем что у нас достаточно аккаунтов
+        require!(
+            ctx.remaining_accounts.len() >= end,
+            MyErrorCode::InsufficientAccounts
+        );
+        
+        let arbitrage_accounts_slice = &ctx.remaining_accounts[start..end];
+        set_failure_debug(index, FAILURE_STAGE_PREFLIGHT, arbitrage_accounts_slice);
+        
+        msg!("🔧 Using accounts slice [{}, {})", start, end);
+
+        // Обновляем offset для следующего арбитража (до возможного пропуска ноги)
+        account_offset = end;
+
+        // 🧷 Срез ноги не должен содержать mint / bonding curve другой ноги - типичный сдвиг границ срезов
+        if strict_leg_accounts {
+            let (bonding_curve, _) = Pubkey::find_program_address(
+                &[b"bonding-curve", arbitrage.token_mint.as_ref()],
+                &build_ctx.pump_program_id,
+            );
+            for (other_mint, other_curve) in seen_leg_accounts.iter() {
+                if *other_mint == arbitrage.token_mint {
+                    continue;
+                }
+                require!(
+                    find_account(arbitrage_accounts_slice, other_mint).is_none()
+                        && find_account(arbitrage_accounts_slice, other_curve).is_none(),
+                    MyErrorCode::AccountCountMismatch
+                );
+            }
+            seen_leg_accounts.push((arbitrage.token_mint, bonding_curve));
+        }
+
+        // 🩹 Допуск к min_wsol_out ноги на движение цены: прибыль все равно проверяется по батчу
+        let arbitrage = ArbitrageParams {
+            min_wsol_out: apply_slippage_relief(arbitrage.min_wsol_out, arbitrage.amount_in, slippage_relief_bps),
+            ..arbitrage.clone()
+        };
+
+        // ====================================================================
+        // 🔥 BUY / SELL INSTRUCTION CREATION (все проверки до первого CPI)
+        // ====================================================================
+
+        let prepared = match prepare_leg(&arbitrage, arbitrage_accounts_slice, &build_ctx, max_slippage_bps) {
+            Ok(prepared) => prepared,
+            // Нога еще ничего не исполнила - в BestEffort режиме просто пропускаем
+            Err(error) if mode == ExecutionMode::BestEffort => {
+                let reason_code = error_code_of(&error);
+                msg!("⏭️ Arbitrage #{} skipped (reason code {})", index + 1, reason_code);
+                emit!(ArbitrageSkipped {
+                    index: index as u8,
+                    reason_code,
+                });
+                skipped_count += 1;
+                continue;
+            },
+            Err(error) => return Err(error),
+        };
+        legs.push((index, arbitrage, arbitrage_accounts_slice, prepared));
+    }
+    msg!("✈️ Pre-flight passed: {} legs ready, {} skipped", legs.len(), skipped_count);
+    legs.sort_by_key(|(index, ..)| execution_rank[*index]);
+
+    // 3. ИСПОЛНЕНИЕ: аккаунты всех ног уже подтверждены, инструкции собраны
+    let mut executed_count: u8 = 0;
+    // Сумма cu_cap еще не исполненных ног - их бюджет не отдаем текущей
+    let mut pending_cu_caps: u64 = legs.iter().map(|(_, arbitrage, ..)| arbitrage.cu_cap as u64).sum();
+
+    for (index, arbitrage, arbitrage_accounts_slice, prepared) in legs {
+        let arbitrage = &arbitrage;
+        pending_cu_caps -= arbitrage.cu_cap as u64;
+
+        // ⏳ CU на исходе: оставшиеся ноги пропускаем, чтобы проверка прибыли и статистика
+        // отработали по уже исполненным
+        let remaining_cu = remaining_compute_units();
+        if reserved_cu > 0 && remaining_cu < reserved_cu as u64 {
+            let reason_code = error_code_of(&anchor_lang::error::Error::from(MyErrorCode::InsufficientCompute));
+            msg!("⏳ Arbitrage #{} skipped: {} CU left, {} reserved", index + 1, remaining_cu, reserved_cu);
+            emit!(ArbitrageSkipped {
+                index: index as u8,
+                reason_code,
+            });
+            skipped_count += 1;
+            continue;
+        }
+
+        // 🧮 Бюджет CU: нога с cu_cap не должна съесть то, что нужно следующим ногам и резерву
+        if arbitrage.cu_cap > 0 {
+            let needed_cu = arbitrage.cu_cap as u64 + pending_cu_caps + reserved_cu as u64;
+            if remaining_cu < needed_cu {
+                msg!("🧮 Arbitrage #{}: cap {} CU, {} left, {} needed with later legs",
+                     index + 1, arbitrage.cu_cap, remaining_cu, needed_cu);
+                require!(mode == ExecutionMode::BestEffort, MyErrorCode::InsufficientCompute);
+                emit!(ArbitrageSkipped {
+                    index: index as u8,
+                    reason_code: error_code_of(&anchor_lang::error::Error::from(MyErrorCode::InsufficientCompute)),
+                });
+                skipped_count += 1;
+                continue;
+            }
+        }
+        msg!("⚡ Executing arbitrage #{} (FULL INLINE MODE)", index + 1);
+        set_failure_debug(index, FAILURE_STAGE_EXECUTION, arbitrage_accounts_slice);
+        msg!("🧠 Go-bot parameters: buy {} tokens (max {} SOL), sell {} tokens (min {} wSOL)", 
+             arbitrage.tokens_to_buy, arbitrage.max_sol_cost, 
+             arbitrage.tokens_to_sell, arbitrage.min_wsol_out);
+
+        let (buy_instruction, buy_accounts) = &prepared.buy;
+
+        // 🆕 ATA свежего токена создаем прямо перед свопами ноги (аккаунты проверены в pre-flight)
+        if !prepared.ata_creations.is_empty() {
+            let lamports_before_ata = build_ctx.user.lamports();
+            create_atas(&prepared.ata_creations, &build_ctx)?;
+            ata_rent_paid += lamports_before_ata.saturating_sub(build_ctx.user.lamports());
+        }
+
+        // 💺 Seat Phoenix занимаем до первого свопа ноги (собран в pre-flight)
+        if let Some(seat_claim) = &prepared.seat_claim {
+            invoke_phoenix_seat_claim(seat_claim)?;
+        }
+
+        // Снимок quote до ноги (ее funding-аккаунта, если задан) - P&L ноги = разница после исполнения
+        let leg_quote_before = token_account_amount(&prepared.build_ctx.user_quote_account)?;
+        let leg_native_before = build_ctx.user.lamports();
+        // Amount out, о котором SELL-программа сообщила через return data (если сообщила)
+        let mut sell_amount_out: Option<u64> = None;
+
+        match &prepared.sell {
+            Some((sell_instruction, sell_accounts)) if arbitrage.reverse => {
+                // ============================================================
+                // 🔄 REVERSE: SELL -> BUY, прибыль считается в token_mint
+                // ============================================================
+
+                msg!("🔄 Executing SELL -> BUY (reverse)...");
+
+                let tokens_before = token_account_amount(&prepared.token_account)?;
+                anchor_lang::solana_program::program::invoke(sell_instruction, sell_accounts)?;
+                msg!("✅ SELL completed");
+
+                anchor_lang::solana_program::program::invoke(buy_instruction, buy_accounts)?;
+                msg!("✅ BUY completed");
+
+                let tokens_after = token_account_amount(&prepared.token_account)?;
+                require!(tokens_after >= tokens_before, MyErrorCode::NotProfitable);
+                msg!("🔄 Reverse leg gained {} tokens", tokens_after - tokens_before);
+            },
+            Some((sell_instruction, sell_accounts)) => {
+                // ============================================================
+                // 🚀 АТОМАРНОЕ ИСПОЛНЕНИЕ: BUY -> SELL
+                // ============================================================
+
+                msg!("🚀 Executing BUY -> SELL atomically (INLINE)...");
+
+                // Выполняем BUY
+                let tokens_before = token_account_amount(&prepared.token_account)?;
+                anchor_lang::solana_program::program::invoke(buy_instruction, buy_accounts)?;
+                let tokens_received = token_account_amount(&prepared.token_account)?
+                    .checked_sub(tokens_before)
+                    .ok_or(MyErrorCode::ArithmeticError)?;
+                require!(tokens_received > 0, MyErrorCode::InsufficientTokensReceived);
+                msg!("✅ BUY completed: {} tokens received", tokens_received);
+                let quote_before_sell = token_account_amount(&prepared.build_ctx.user_quote_account)?;
+
+                // Partial fill / fee-on-transfer: продаем только то, что реально пришло
+                if tokens_received < arbitrage.tokens_to_sell {
+                    msg!("⚠️ Received {} < tokens_to_sell {}, clamping SELL", tokens_received, arbitrage.tokens_to_sell);
+                    let clamped_params = ArbitrageParams {
+                        tokens_to_sell: tokens_received,
+                        ..arbitrage.clone()
+                    };
+                    let (clamped_instruction, clamped_accounts) = build_sell_instruction(
+                        &clamped_params,
+                        arbitrage_accounts_slice,
+                        &prepared.build_ctx,
+                        &prepared.buy,
+                    )?;
+                    anchor_lang::solana_program::program::invoke(&clamped_instruction, &clamped_accounts)?;
+                } else {
+                    anchor_lang::solana_program::program::invoke(sell_instruction, sell_accounts)?;
+                }
+                sell_amount_out = returned_amount(&sell_instruction.program_id);
+
+                // Min-out по фактически пришедшему quote (Pump.fun продает в нативные SOL - не меряем)
+                if arbitrage.sell_dex != DexType::PumpFun {
+                    let quote_received = token_account_amount(&prepared.build_ctx.user_quote_account)?
+                        .saturating_sub(quote_before_sell);
+                    let expected_out = min_out_after_transfer_fee(
+                        arbitrage.min_wsol_out,
+                        quote_fee_mint.as_ref(),
+                        current_epoch,
+                    )?;
+                    require!(quote_received >= expected_out, MyErrorCode::MinOutNotMet);
+                    if let Some(reported) = sell_amount_out {
+                        msg!("📨 SELL reported {} out, measured {}", reported, quote_received);
+                    }
+                }
+                msg!("✅ SELL completed");
+            },
+            None => {
+                // 🔺 ТРЕУГОЛЬНИК: BUY -> BRIDGE -> SELL, выход каждого шага питает следующий
+                let bridge = arbitrage.bridge.as_ref().ok_or(MyErrorCode::InvalidConfigValue)?;
+                execute_triangular_arbitrage(
+                    bridge,
+                    arbitrage_accounts_slice,
+                    arbitrage,
+                    &prepared.build_ctx,
+                    buy_instruction,
+                    buy_accounts,
+                )?;
+            },
+        }
+        executed_count += 1;
+
+        let leg_profit = token_account_amount(&prepared.build_ctx.user_quote_account)? as i128 - leg_quote_before as i128;
+        // Reverse нога уже проверена в token_mint - ее quote-дельта в агрегат не входит
+        if !arbitrage.reverse {
+            batch_profit += leg_profit;
+        }
+
+        // 🧮 PerLegProfit: каждая нога обязана быть в плюсе сама - жирная нога не маскирует убыточные
+        if mode == ExecutionMode::PerLegProfit && !arbitrage.reverse {
+            let leg_native_delta = if settle_native {
+                build_ctx.user.lamports() as i128 - leg_native_before as i128
+            } else {
+                0
+            };
+            require!(leg_profit + leg_native_delta >= 0, MyErrorCode::NotProfitable);
+        }
+        
+        emit!(ArbitrageExecuted {
+            index: index as u8,
+            leg_profit: i64::try_from(leg_profit).map_err(|_| MyErrorCode::ArithmeticError)?,
+            sell_amount_out,
+        });
+        
+        msg!("🎉 Arbitrage #{} completed successfully (INLINE), leg P&L {}", index + 1, leg_profit);
+    }
+
+    // ====================================================================
+    // 💰 ФИНАЛЬНАЯ ПРОВЕРКА ПРИБЫЛИ + PROTOCOL FEE
+    // ====================================================================
+
+    // Нативная часть результата: дельта lamports без rent созданных ATA (он возвратный)
+    let mut native_delta: i128 = 0;
+    if settle_native {
+        native_delta = build_ctx.user.lamports() as i128 - native_before as i128 + ata_rent_paid as i128;
+        msg!("🪙 Native SOL delta {} (ATA rent excluded: {})", native_delta, ata_rent_paid);
+        batch_profit += native_delta;
+    }
+    // P&L ног и нативная часть - то, что уходит в cumulative_profit (без займа и комиссий транзакции)
+    let settled_profit = batch_profit;
+
+    // 🏦 Займ возвращается следующей инструкцией с комиссией сверху - прибыль считаем после нее
+    // (сам заем пришел до батча, а возврат уйдет после, поэтому в дельты ног они не попадают)
+    if let Some(flash_loan) = &flash_loan {
+        batch_profit -= flash_loan.max_fee as i128;
+    }
+
+    // 🧾 Сама транзакция тоже стоит: base fee + priority fee (оценка бота в lamports; quote - wSOL,
+    // проверено на входе) уже списаны до исполнения и в дельты не попадают - батч, прибыльный
+    // только до комиссии, откатываем
+    if tx_cost_lamports > 0 {
+        msg!("🧾 Gross profit {}, tx cost {}", batch_profit, tx_cost_lamports);
+        batch_profit -= tx_cost_lamports as i128;
+    }
+
+    // Откат только по агрегату: убыток одной ноги может покрываться прибылью другой
+    require!(batch_profit >= 0, MyErrorCode::NotProfitable);
+    let profit = u64::try_from(batch_profit).map_err(|_| MyErrorCode::ArithmeticError)?;
+
+    // 🎯 Порог прибыли в общей единице: через оракул profit_mint, если задан, иначе в сырых единицах
+    let min_profit = ctx.accounts.router_state.min_profit;
+    if min_profit > 0 {
+        require!(
+            ctx.accounts.router_state.effective_profit_mint() == ctx.accounts.user_wsol_account.mint,
+            MyErrorCode::InvalidTokenAccount
+        );
+        let normalized_profit = if ctx.accounts.router_state.profit_oracle != Pubkey::default() {
+            let oracle = ctx.accounts.profit_oracle.as_ref().ok_or(MyErrorCode::AccountNotFound)?;
+            let mint = ctx.accounts.quote_mint.as_ref().ok_or(MyErrorCode::MintAccountNotFound)?.to_account_info();
+            let profit_decimals = mint_decimals(&mint)?;
+            normalize_profit(profit, &oracle.to_account_info(), profit_decimals, current_slot)?
+        } else {
+            profit
+        };
+        msg!("🎯 Profit {} -> {} normalized (min {})", profit, normalized_profit, min_profit);
+        require!(normalized_profit >= min_profit, MyErrorCode::NotProfitable);
+    }
+
+    let profit_fee = calculate_profit_fee(profit, ctx.accounts.router_state.profit_fee_bps);
+    if profit_fee > 0 {
+        let treasury_token_account = ctx
+            .accounts
+            .treasury_token_account
+            .as_ref()
+            .ok_or(MyErrorCode::AccountNotFound)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.user_wsol_account.to_account_info(),
+                    to: treasury_token_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            profit_fee,
+        )?;
+        msg!("🏦 Protocol fee {} wSOL sent to treasury (profit {})", profit_fee, profit);
+    }
+
+    // 🧾 wSOL аккаунт остается для следующих батчей: ниже rent-exemption он непригоден, поэтому
+    // недостачу доплачиваем нативными SOL user-а (lamports без sync_native - в rent-резерв, не в баланс),
+    // а если их не хватает (user сам должен остаться rent-exempt) - откат. При unwrap_all аккаунт
+    // закрывается, проверять нечего
+    if !unwrap_all {
+        let wsol_info = ctx.accounts.user_wsol_account.to_account_info();
+        let rent_minimum = ctx.accounts.rent.minimum_balance(wsol_info.data_len());
+        let rent_shortfall = rent_minimum.saturating_sub(wsol_info.lamports());
+        if rent_shortfall > 0 {
+            let user_rent_minimum = ctx.accounts.rent.minimum_balance(0);
+            require!(
+                ctx.accounts.user.lamports() >= rent_shortfall.saturating_add(user_rent_minimum),
+                MyErrorCode::WsolNotRentExempt
+            );
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.user.to_account_info(),
+                        to: wsol_info,
+                    },
+                ),
+                rent_shortfall,
+            )?;
+            msg!("🧾 wSOL account topped up to rent-exemption: {} lamports from user", rent_shortfall);
+        }
+    }
+
+    // 🧹 Unwrap: закрываем wSOL аккаунт - весь баланс и его rent возвращаются user-у нативными SOL
+    // в этой же транзакции. Следующему батчу аккаунт нужно создать заново (Go-бот, wrap_amount).
+    if unwrap_all {
+        require!(
+            ctx.accounts.user_wsol_account.mint == anchor_spl::token::spl_token::native_mint::ID,
+            MyErrorCode::InvalidTokenAccount
+        );
+        token::close_account(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            token::CloseAccount {
+                account: ctx.accounts.user_wsol_account.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            },
+        ))?;
+        msg!("🧹 wSOL account closed, lamports returned to user");
+    }
+
+    // Слот успешного батча - точка отсчета для rate limit
+    ctx.accounts.router_state.last_batch_slot = current_slot;
+
+    // 📈 Статистика - одной записью в конце (накопленная прибыль не уходит ниже нуля)
+    let router_state = &mut ctx.accounts.router_state;
+    router_state.total_batches = router_state.total_batches.saturating_add(1);
+    router_state.total_legs_executed = router_state.total_legs_executed.saturating_add(executed_count as u64);
+    router_state.cumulative_profit = accumulate_profit(router_state.cumulative_profit, settled_profit);
+    // Успешный батч сбрасывает серию провалов circuit breaker-а
+    router_state.consecutive_failures = 0;
+
+    // 💵 Отчетная прибыль в USDC (на исполнение не влияет): нет оракула или цена устарела -> 0 и флаг
+    let profit_usdc = ctx
+        .accounts
+        .usdc_price_oracle
+        .as_ref()
+        .and_then(|oracle| profit_in_usdc(profit, &oracle.to_account_info(), current_slot));
+
+    // 📏 Метрика расхода CU - под feature "cu-metrics" (включен по умолчанию), сам syscall есть всегда
+    let cu_consumed = if cfg!(feature = "cu-metrics") {
+        cu_at_entry.saturating_sub(remaining_compute_units())
+    } else {
+        0
+    };
+
+    emit!(BatchCompleted {
+        executed_count,
+        skipped_count,
+        profit,
+        profit_fee,
+        cu_consumed,
+        profit_usdc: profit_usdc.unwrap_or(0),
+        usdc_price_stale: profit_usdc.is_none(),
+    });
+
+    clear_failure_debug();
+    msg!("🏆 INLINE HFT arbitrage batch completed successfully - MAXIMUM SPEED!");
+    Ok(())
+}
+
+// ============================================================================
+// 🔧 БИЛДЕРЫ DEX-ИНСТРУКЦИЙ
+// ============================================================================
+
+/// Pump.fun program
+const PUMP_FUN_PROGRAM_ID: &str = "6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P";
+
+/// Meteora DLMM program (Anchor, #[event_cpi])
+const METEORA_DLMM_PROGRAM_ID: &str = "LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo";
+
+/// Fee recipient Pump.fun по умолчанию (если в RouterState не задан иной)
+const PUMP_FEE_RECIPIENT: &str = "CebN5WGQ4jvEPvsVU4EoHEpgzq1VV7AbicfhtW4xC9iM";
+
+/// Текущие discriminator-ы Pump.fun buy/sell (дефолт, если в RouterState не задано иное)
+const PUMP_BUY_DISCRIMINATOR: [u8; 8] = [0x66, 0x06, 0x3d, 0x12, 0x01, 0xda, 0xeb, 0xea];
+const PUMP_SELL_DISCRIMINATOR: [u8; 8] = [0x33, 0xe6, 0x85, 0xa4, 0x01, 0x7f, 0x83, 0xad];
+
+/// Смещение флага `complete` в аккаунте Pump.fun BondingCurve
+/// (discriminator + 5 x u64: virtual/real reserves и total supply)
+const PUMP_BONDING_CURVE_COMPLETE_OFFSET: usize = 48;
+
+// Смещения резервов в аккаунте Pump.fun BondingCurve
+const PUMP_BONDING_CURVE_VIRTUAL_TOKEN_OFFSET: usize = 8;
+const PUMP_BONDING_CURVE_VIRTUAL_SOL_OFFSET: usize = 16;
+const PUMP_BONDING_CURVE_REAL_TOKEN_OFFSET: usize = 24;
+const PUMP_BONDING_CURVE_REAL_SOL_OFFSET: usize = 32;
+
+/// Смещение fee_basis_points в аккаунте Pump.fun Global
+/// (discriminator, initialized, authority, fee_recipient, 4 x u64 initial-параметров)
+const PUMP_GLOBAL_FEE_BPS_OFFSET: usize = 105;
+
+// Роли аккаунтов среза в ArbitrageParams.layout (байт на аккаунт, по позиции в срезе)
+pub const LAYOUT_ROLE_PUMP_GLOBAL: u8 = 0;
+pub const LAYOUT_ROLE_PUMP_FEE_RECIPIENT: u8 = 1;
+pub const LAYOUT_ROLE_PUMP_BONDING_CURVE: u8 = 2;
+pub const LAYOUT_ROLE_PUMP_ASSOCIATED_BONDING_CURVE: u8 = 3;
+pub const LAYOUT_ROLE_PUMP_EVENT_AUTHORITY: u8 = 4;
+pub const LAYOUT_ROLE_TOKEN_MINT: u8 = 5;
+pub const LAYOUT_ROLE_USER_TOKEN_ACCOUNT: u8 = 6;
+pub const LAYOUT_ROLE_PUMP_PROGRAM: u8 = 7;
+/// Аккаунт другого DEX-а: роутер его не проверяет, билдер ищет как обычно
+pub const LAYOUT_ROLE_OTHER: u8 = 0xff;
+
+/// OpenBook v2 program
+const OPENBOOK_V2_PROGRAM_ID: &str = "opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb";
+
+/// Anchor discriminator аккаунта `Market` (sha256("account:Market")[..8])
+const OPENBOOK_MARKET_DISCRIMINATOR: [u8; 8] = [0xdb, 0xbe, 0xd5, 0x37, 0x00, 0xe3, 0xc6, 0x9a];
+
+// Смещения полей в аккаунте OpenBook v2 Market (включая 8 байт discriminator)
+const OPENBOOK_MARKET_AUTHORITY_OFFSET: usize = 16;
+const OPENBOOK_MARKET_BIDS_OFFSET: usize = 200;
+const OPENBOOK_MARKET_ASKS_OFFSET: usize = 232;
+const OPENBOOK_MARKET_EVENT_HEAP_OFFSET: usize = 264;
+const OPENBOOK_MARKET_ORACLE_A_OFFSET: usize = 296;
+const OPENBOOK_MARKET_ORACLE_B_OFFSET: usize = 328;
+const OPENBOOK_MARKET_QUOTE_LOT_SIZE_OFFSET: usize = 448;
+const OPENBOOK_MARKET_BASE_LOT_SIZE_OFFSET: usize = 456;
+const OPENBOOK_MARKET_BASE_MINT_OFFSET: usize = 576;
+const OPENBOOK_MARKET_BASE_VAULT_OFFSET: usize = 640;
+const OPENBOOK_MARKET_QUOTE_VAULT_OFFSET: usize = 680;
+
+/// Lifinity v2 program
+const LIFINITY_V2_PROGRAM_ID: &str = "2wT8Yq49kHgDzXuPxZSaeLaH1qbmGXtEyPy64bL7aD3c";
+
+/// Anchor discriminator аккаунта `Amm` (sha256("account:Amm")[..8])
+const LIFINITY_AMM_DISCRIMINATOR: [u8; 8] = [0x8f, 0xf5, 0xc8, 0x11, 0x4a, 0xd6, 0xc4, 0x87];
+
+// Смещения полей в аккаунте Lifinity v2 Amm (включая 8 байт discriminator)
+const LIFINITY_AMM_TOKEN_A_ACCOUNT_OFFSET: usize = 158;
+const LIFINITY_AMM_TOKEN_B_ACCOUNT_OFFSET: usize = 190;
+const LIFINITY_AMM_POOL_MINT_OFFSET: usize = 222;
+const LIFINITY_AMM_TOKEN_A_MINT_OFFSET: usize = 254;
+const LIFINITY_AMM_TOKEN_B_MINT_OFFSET: usize = 286;
+const LIFINITY_AMM_FEE_ACCOUNT_OFFSET: usize = 318;
+const LIFINITY_AMM_ORACLE_MAIN_OFFSET: usize = 350;
+const LIFINITY_AMM_ORACLE_SUB_OFFSET: usize = 382;
+const LIFINITY_AMM_ORACLE_PC_OFFSET: usize = 414;
+
+/// Phoenix v1 program
+const PHOENIX_PROGRAM_ID: &str = "PhoeNiXZ8ByJGLkxNfZRnkUfjvmuYqLR89jjFHGqdXY";
+
+/// Размер MarketHeader; кроме маркетов Phoenix владеет только Seat-ами (128 байт)
+const PHOENIX_MARKET_HEADER_LEN: usize = 576;
+
+/// Phoenix Seat Manager program (владеет authority маркетов, раздает seat-ы)
+const PHOENIX_SEAT_MANAGER_PROGRAM_ID: &str = "PSMxQbAoDWDbvd9ezQJgARyq6R9L5kJAasaLDVcZwf1";
+/// Тег SeatManagerInstruction::ClaimSeat
+const PHOENIX_SEAT_MANAGER_CLAIM_SEAT_TAG: u8 = 2;
+
+// Seat: discriminant (u64), market, trader, approval_status (u64: 0 NotApproved, 1 Approved, 2 Retired)
+const PHOENIX_SEAT_APPROVAL_STATUS_OFFSET: usize = 72;
+const PHOENIX_SEAT_STATUS_APPROVED: u64 = 1;
+
+// Смещения полей в Phoenix MarketHeader (без Anchor discriminator, свой u64 discriminant)
+const PHOENIX_MARKET_BASE_MINT_OFFSET: usize = 48;
+const PHOENIX_MARKET_BASE_VAULT_OFFSET: usize = 80;
+const PHOENIX_MARKET_BASE_LOT_SIZE_OFFSET: usize = 112;
+const PHOENIX_MARKET_QUOTE_MINT_OFFSET: usize = 128;
+const PHOENIX_MARKET_QUOTE_VAULT_OFFSET: usize = 160;
+const PHOENIX_MARKET_QUOTE_LOT_SIZE_OFFSET: usize = 192;
+
+/// Raydium CLMM program
+const RAYDIUM_CLMM_PROGRAM_ID: &str = "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK";
+/// SPL Memo program (обязательный аккаунт swap_v2)
+const MEMO_PROGRAM_ID: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+
+/// Anchor discriminator-ы аккаунтов `PoolState` и `TickArrayState`
+const RAYDIUM_CLMM_POOL_DISCRIMINATOR: [u8; 8] = [0xf7, 0xed, 0xe3, 0xf5, 0xd7, 0xc3, 0xde, 0x46];
+const RAYDIUM_CLMM_TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [0xc0, 0x9b, 0x55, 0xcd, 0x31, 0xf9, 0x81, 0x2a];
+
+// Смещения полей в аккаунте Raydium CLMM PoolState (включая 8 байт discriminator)
+const RAYDIUM_CLMM_POOL_AMM_CONFIG_OFFSET: usize = 9;
+const RAYDIUM_CLMM_POOL_TOKEN_MINT_0_OFFSET: usize = 73;
+const RAYDIUM_CLMM_POOL_TOKEN_MINT_1_OFFSET: usize = 105;
+const RAYDIUM_CLMM_POOL_TOKEN_VAULT_0_OFFSET: usize = 137;
+const RAYDIUM_CLMM_POOL_TOKEN_VAULT_1_OFFSET: usize = 169;
+const RAYDIUM_CLMM_POOL_OBSERVATION_OFFSET: usize = 201;
+
+/// Максимум tick array-ев, которые может пересечь один swap
+const RAYDIUM_CLMM_MAX_TICK_ARRAYS: usize = 3;
+
+/// Orca legacy Token Swap (форк SPL Token Swap, пулы до Whirlpools)
+const ORCA_TOKEN_SWAP_PROGRAM_ID: &str = "9W959DqEETiGZocYWCQPaJ6sBmUzgfxXfqGeTzdp3aP";
+
+/// Размер аккаунта SwapV1 (1 байт версии + 323 байта состояния); discriminator-а нет
+const ORCA_SWAP_LEN: usize = 324;
+/// Instruction tag `Swap` в SwapInstruction
+const ORCA_SWAP_INSTRUCTION_TAG: u8 = 1;
+
+// Смещения полей в аккаунте SwapV1 (включая байт версии)
+const ORCA_SWAP_IS_INITIALIZED_OFFSET: usize = 1;
+const ORCA_SWAP_BUMP_SEED_OFFSET: usize = 2;
+const ORCA_SWAP_TOKEN_PROGRAM_OFFSET: usize = 3;
+const ORCA_SWAP_TOKEN_A_OFFSET: usize = 35;
+const ORCA_SWAP_TOKEN_B_OFFSET: usize = 67;
+const ORCA_SWAP_POOL_MINT_OFFSET: usize = 99;
+const ORCA_SWAP_TOKEN_A_MINT_OFFSET: usize = 131;
+const ORCA_SWAP_TOKEN_B_MINT_OFFSET: usize = 163;
+const ORCA_SWAP_POOL_FEE_ACCOUNT_OFFSET: usize = 195;
+
+/// Saber StableSwap (stableswap-инвариант: wSOL/LST и стейблкоин пары)
+const STABLE_SWAP_PROGRAM_ID: &str = "SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg1UZ";
+
+/// Размер аккаунта SwapInfo; discriminator-а нет
+const STABLE_SWAP_INFO_LEN: usize = 395;
+/// Instruction tag `Swap` в SwapInstruction
+const STABLE_SWAP_INSTRUCTION_TAG: u8 = 1;
+
+// Смещения полей в аккаунте SwapInfo
+const STABLE_SWAP_IS_INITIALIZED_OFFSET: usize = 0;
+const STABLE_SWAP_IS_PAUSED_OFFSET: usize = 1;
+const STABLE_SWAP_NONCE_OFFSET: usize = 2;
+const STABLE_SWAP_TARGET_AMP_OFFSET: usize = 11;
+const STABLE_SWAP_TOKEN_A_RESERVES_OFFSET: usize = 107;
+const STABLE_SWAP_TOKEN_A_MINT_OFFSET: usize = 139;
+const STABLE_SWAP_TOKEN_A_ADMIN_FEES_OFFSET: usize = 171;
+const STABLE_SWAP_TOKEN_B_RESERVES_OFFSET: usize = 204;
+const STABLE_SWAP_TOKEN_B_MINT_OFFSET: usize = 236;
+const STABLE_SWAP_TOKEN_B_ADMIN_FEES_OFFSET: usize = 268;
+
+/// Invariant CLMM program
+const INVARIANT_PROGRAM_ID: &str = "HyaB3W9q6XdA5xwpU4XnSZV94htfmbmqJXZcEbRaJutt";
+
+/// Anchor discriminator-ы аккаунтов `Pool` и `Tick`
+const INVARIANT_POOL_DISCRIMINATOR: [u8; 8] = [0xf1, 0x9a, 0x6d, 0x04, 0x11, 0xb1, 0x6d, 0xbc];
+const INVARIANT_TICK_DISCRIMINATOR: [u8; 8] = [0xb0, 0x5e, 0x43, 0xf7, 0x85, 0xad, 0x07, 0x73];
+
+// Смещения полей в аккаунте Invariant Pool (zero-copy, packed; включая 8 байт discriminator)
+const INVARIANT_POOL_TOKEN_X_OFFSET: usize = 8;
+const INVARIANT_POOL_TOKEN_Y_OFFSET: usize = 40;
+const INVARIANT_POOL_RESERVE_X_OFFSET: usize = 72;
+const INVARIANT_POOL_RESERVE_Y_OFFSET: usize = 104;
+const INVARIANT_POOL_TICKMAP_OFFSET: usize = 222;
+
+/// Границы sqrt price Invariant (Decimal с 24 знаками) - лимит "без ограничения" по направлению
+const INVARIANT_MIN_SQRT_PRICE: u128 = 15_258_932_000_000_000_000;
+const INVARIANT_MAX_SQRT_PRICE: u128 = 65_535_383_934_512_647_000_000_000_000;
+
+/// Максимум tick-аккаунтов, которые может пересечь один swap (ограничение размера транзакции)
+const INVARIANT_MAX_TICKS: usize = 8;
+
+/// Crema Finance CLMM program
+const CREMA_PROGRAM_ID: &str = "CLMM9tUoggJu2wagPkkqs9eFG4BWhVBZWkP1qv3Sp7tR";
+
+/// Anchor discriminator-ы аккаунтов `Clmmpool` и `TickArray`
+const CREMA_POOL_DISCRIMINATOR: [u8; 8] = [0xaa, 0xa0, 0x21, 0x7a, 0x95, 0xd9, 0xb7, 0xf4];
+const CREMA_TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [0x45, 0x61, 0xbd, 0xbe, 0x6e, 0x07, 0x42, 0xbb];
+
+/// Anchor discriminator-ы инструкций `swap` и `swap_with_partner`
+const CREMA_SWAP_DISCRIMINATOR: [u8; 8] = [0xf8, 0xc6, 0x9e, 0x91, 0xe1, 0x75, 0x87, 0xc8];
+const CREMA_SWAP_WITH_PARTNER_DISCRIMINATOR: [u8; 8] = [0x85, 0xd7, 0xbf, 0xd6, 0x66, 0xf3, 0x37, 0x19];
+
+// Смещения полей в аккаунте Crema Clmmpool (включая 8 байт discriminator)
+const CREMA_POOL_CLMM_CONFIG_OFFSET: usize = 8;
+const CREMA_POOL_TOKEN_A_OFFSET: usize = 40;
+const CREMA_POOL_TOKEN_B_OFFSET: usize = 72;
+const CREMA_POOL_TOKEN_A_VAULT_OFFSET: usize = 104;
+const CREMA_POOL_TOKEN_B_VAULT_OFFSET: usize = 136;
+
+/// Границы sqrt price Crema (Q64.64) - лимит "без ограничения" по направлению
+const CREMA_MIN_SQRT_PRICE: u128 = 4_295_048_016;
+const CREMA_MAX_SQRT_PRICE: u128 = 79_226_673_515_401_279_992_447_579_055;
+
+/// Максимум tick array-ев, которые может пересечь один swap (ограничение размера транзакции)
+const CREMA_MAX_TICK_ARRAYS: usize = 8;
+
+/// Anchor discriminator аккаунта `Whirlpool` (пул) - sha256("account:Whirlpool")[..8]
+const WHIRLPOOL_DISCRIMINATOR: [u8; 8] = [0x3f, 0x95, 0xd1, 0x0c, 0xe1, 0x80, 0x63, 0x09];
+/// Anchor discriminator аккаунта `TickArray` Whirlpool
+const WHIRLPOOL_TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [0x45, 0x61, 0xbd, 0xbe, 0x6e, 0x07, 0x42, 0xbb];
+
+// Смещения полей в аккаунте Whirlpool (включая 8 байт discriminator)
+const WHIRLPOOL_TOKEN_MINT_A_OFFSET: usize = 101;
+const WHIRLPOOL_TOKEN_VAULT_A_OFFSET: usize = 133;
+const WHIRLPOOL_TOKEN_MINT_B_OFFSET: usize = 181;
+const WHIRLPOOL_TOKEN_VAULT_B_OFFSET: usize = 213;
+
+/// Границы sqrt price Whirlpool (Q64.64) - лимит "без ограничения" по направлению
+const WHIRLPOOL_MIN_SQRT_PRICE: u128 = 4_295_048_016;
+const WHIRLPOOL_MAX_SQRT_PRICE: u128 = 79_226_673_515_401_279_992_447_579_055;
+
+/// Tick array-ев в swap Whirlpool (фиксированно; недостающие повторяют последний)
+const WHIRLPOOL_TICK_ARRAYS: usize = 3;
+
+/// Максимальная длина data_prefix ноги (discriminator обертки + запас)
+const MAX_DATA_PREFIX_LEN: usize = 16;
+
+/// Сторона сделки внутри одного арбитража
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+/// Общий контекст для всех билдеров (собирается один раз до цикла)
+#[derive(Clone)]
+struct BuildContext<'info> {
+    user: AccountInfo<'info>,
+    user_quote_account: AccountInfo<'info>, // wSOL аккаунт (или output_mint на bridge-шаге)
+    system_program: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    rent: AccountInfo<'info>,
+    pump_program_id: Pubkey,
+    fee_recipient: Pubkey,
+    pump_buy_discriminator: [u8; 8],
+    pump_sell_discriminator: [u8; 8],
+    allow_raw: bool,
+    enforce_distinct_venues: bool,
+    min_liquidity: u64,
+    dex_discriminators: [DexDiscriminator; MAX_DISCRIMINATOR_OVERRIDES],
+    scan_passes: usize, // max_scan_passes роутера (0 уже заменен на DEFAULT_SCAN_PASSES)
+}
+
+/// Диспетчер: строит инструкцию и список AccountInfo для одной стороны арбитража
+fn build_dex_instruction<'info>(
+    dex: &DexType,
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    let (instruction, accounts) = match dex {
+        DexType::PumpFun => build_pump_fun_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::OpenBookV2 => build_openbook_v2_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::Lifinity => build_lifinity_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::Phoenix => build_phoenix_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::RaydiumClmm => build_raydium_clmm_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::OrcaV1 => build_orca_v1_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::StableSwap => build_stable_swap_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::Invariant => build_invariant_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::Crema => build_crema_instruction(side, accounts_slice, arbitrage, build_ctx),
+        DexType::WhirlpoolLike { program_id } => {
+            build_whirlpool_like_instruction(program_id, side, accounts_slice, arbitrage, build_ctx)
+        },
+        DexType::Raw { program_id, data, is_writable } => {
+            build_raw_instruction(program_id, data, is_writable, accounts_slice, build_ctx)
+        },
+        DexType::Meteora => {
+            // Event authority проверяем уже сейчас: Anchor DEX ноги - только свой PDA, не Pump.fun-овский
+            let meteora_program_id = parse_program_id(METEORA_DLMM_PROGRAM_ID)?;
+            find_event_authority(accounts_slice, &meteora_program_id)?;
+            msg!("🚧 Meteora not implemented yet");
+            Err(MyErrorCode::NotImplemented.into())
+        },
+    }?;
+
+    let instruction = finalize_instruction(dex, side, arbitrage, build_ctx, instruction)?;
+    Ok((instruction, accounts))
+}
+
+/// Общая постобработка инструкции ноги: override discriminator-а и `data_prefix`.
+/// Через нее проходят и билдеры диспетчера, и inline Pump.fun SELL (в т.ч. clamped)
+fn finalize_instruction(
+    dex: &DexType,
+    side: TradeSide,
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext,
+    mut instruction: Instruction,
+) -> Result<Instruction> {
+    // 🔁 Discriminator, переопределенный owner-ом, поверх compiled default билдера
+    if has_anchor_discriminator(dex) {
+        let entry = dex_index(dex);
+        if let Some(dex_discriminator) = build_ctx
+            .dex_discriminators
+            .iter()
+            .find(|existing| existing.is_set() && existing.dex == entry && existing.side == side as u8)
+        {
+            require!(instruction.data.len() >= 8, MyErrorCode::InvalidAccountData);
+            instruction.data[..8].copy_from_slice(&dex_discriminator.discriminator);
+        }
+    }
+
+    // 🎁 Обертка-роутер (Anchor-программа вокруг swap-а) ждет свой discriminator перед payload-ом.
+    // Префикс меняет семантику вызова так же, как Raw нога, поэтому доступен только при allow_raw
+    if !arbitrage.data_prefix.is_empty() {
+        require!(build_ctx.allow_raw, MyErrorCode::RawDexDisabled);
+        require!(arbitrage.data_prefix.len() <= MAX_DATA_PREFIX_LEN, MyErrorCode::InvalidRawLeg);
+        instruction.data.splice(0..0, arbitrage.data_prefix.iter().copied());
+    }
+
+    Ok(instruction)
+}
+
+/// 📏 Минимальный срез ноги для DEX-а BUY: venue-аккаунты билдера + user token account
+/// (+ mint, где билдер его ищет). Дешевая проверка недосчета до сканов среза.
+const fn min_accounts(dex: &DexType) -> u8 {
+    match dex {
+        // program, global, fee_recipient, bonding curve, associated bonding curve, event authority, mint, user ATA
+        DexType::PumpFun => 8,
+        // program, market, market authority, bids, asks, event heap, base/quote vault, user ATA
+        DexType::OpenBookV2 => 9,
+        // program, authority, amm, 2 vault-а, pool mint, fee account, 3 оракула, user ATA
+        DexType::Lifinity => 11,
+        // program, log authority, market, base/quote vault, user ATA
+        DexType::Phoenix => 6,
+        // program, amm config, pool, 2 vault-а, observation, token-2022, memo, 2 mint-а, user ATA, tick array
+        DexType::RaydiumClmm => 12,
+        // program, swap, authority, 2 vault-а, pool mint, fee account, user ATA
+        DexType::OrcaV1 => 8,
+        // program, swap info, authority, 2 reserves, admin fee выходного токена, clock, user ATA
+        DexType::StableSwap => 8,
+        // program, state, pool, tickmap, 2 mint-а, 2 reserve, program authority, user ATA
+        DexType::Invariant => 10,
+        // program, clmm config, pool, 2 mint-а, 2 vault-а, tick array map, user ATA, tick array
+        DexType::Crema => 10,
+        // program, whirlpool, 2 vault-а, oracle, user ATA, tick array
+        DexType::WhirlpoolLike { .. } => 7,
+        // Как минимум сама вызываемая программа
+        DexType::Raw { .. } => 1,
+        // Билдера нет - отклоняется в build_dex_instruction
+        DexType::Meteora => 0,
+    }
+}
+
+/// 🤝 DEX-ы с referral / host fee аккаунтом в swap-инструкции (у Crema - partner). Bonding curve Pump.fun
+/// referral-а не принимает - нога с ним отклоняется, а не молча теряет рибейт
+const fn supports_referral(dex: &DexType) -> bool {
+    matches!(dex, DexType::OrcaV1 | DexType::Crema)
+}
+
+/// 🔺 Треугольник BUY(token_mint) -> BRIDGE(token_mint -> output_mint) -> SELL(output_mint).
+/// Bridge исполняется как SELL token_mint на маркете base = token_mint / quote = output_mint;
+/// вход bridge и SELL = фактически полученный на предыдущем шаге объем (а не оценка Go-бота).
+fn execute_triangular_arbitrage<'info>(
+    bridge: &SwapLeg,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+    buy_instruction: &Instruction,
+    buy_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let user_key = build_ctx.user.key();
+    let token_account = find_user_token_account(accounts_slice, &user_key, &arbitrage.token_mint)?;
+    let output_account = find_user_token_account(accounts_slice, &user_key, &bridge.output_mint)?;
+
+    let quote_before = token_account_amount(&build_ctx.user_quote_account)?;
+
+    // 1. BUY token_mint
+    msg!("🔺 Executing triangle BUY -> BRIDGE -> SELL...");
+    let tokens_before = token_account_amount(token_account)?;
+    anchor_lang::solana_program::program::invoke(buy_instruction, buy_accounts)?;
+    let tokens_received = token_account_amount(token_account)?
+        .checked_sub(tokens_before)
+        .ok_or(MyErrorCode::ArithmeticError)?;
+    msg!("✅ BUY completed: {} tokens received", tokens_received);
+
+    // 2. BRIDGE token_mint -> output_mint (quote-аккаунт на этом шаге = аккаунт output_mint)
+    let bridge_params = ArbitrageParams {
+        tokens_to_sell: tokens_received,
+        min_wsol_out: bridge.min_amount_out,
+        ..arbitrage.clone()
+    };
+    let bridge_ctx = BuildContext {
+        user_quote_account: output_account.clone(),
+        ..build_ctx.clone()
+    };
+    let (bridge_instruction, bridge_accounts) =
+        build_dex_instruction(&bridge.dex, TradeSide::Sell, accounts_slice, &bridge_params, &bridge_ctx)?;
+
+    let output_before = token_account_amount(output_account)?;
+    anchor_lang::solana_program::program::invoke(&bridge_instruction, &bridge_accounts)?;
+    let output_received = token_account_amount(output_account)?
+        .checked_sub(output_before)
+        .ok_or(MyErrorCode::ArithmeticError)?;
+    msg!("✅ BRIDGE completed: {} of {} received", output_received, bridge.output_mint);
+
+    // 3. SELL output_mint обратно в quote
+    let sell_params = ArbitrageParams {
+        token_mint: bridge.output_mint,
+        tokens_to_sell: output_received,
+        // Layout сверен для token_mint ноги - для output_mint аккаунты ищутся обычным сканом
+        layout: Vec::new(),
+        ..arbitrage.clone()
+    };
+    let (sell_instruction, sell_accounts) =
+        build_dex_instruction(&arbitrage.sell_dex, TradeSide::Sell, accounts_slice, &sell_params, build_ctx)?;
+    anchor_lang::solana_program::program::invoke(&sell_instruction, &sell_accounts)?;
+    msg!("✅ SELL completed");
+
+    // Финальный баланс quote не должен быть меньше начального
+    let quote_after = token_account_amount(&build_ctx.user_quote_account)?;
+    require!(quote_after >= quote_before, MyErrorCode::NotProfitable);
+
+    Ok(())
+}
+
+/// Нога, полностью собранная до первого CPI (все проверки пройдены)
+struct PreparedLeg<'info> {
+    build_ctx: BuildContext<'info>,
+    token_account: AccountInfo<'info>, // Аккаунт token_mint пользователя
+    buy: (Instruction, Vec<AccountInfo<'info>>),
+    sell: Option<(Instruction, Vec<AccountInfo<'info>>)>, // None для треугольника: SELL собирается после BRIDGE
+    seat_claim: Option<PhoenixSeatClaim<'info>>,          // auto_claim_seat, seat еще не одобрен
+    ata_creations: Vec<AtaCreation<'info>>,               // create_ata: еще не созданные ATA ноги
+}
+
+/// 🧪 Подготовка ноги: проверки параметров и сборка инструкций без единого CPI.
+/// Ошибка здесь ничего не исполнила, поэтому в BestEffort режиме ногу можно пропустить.
+/// Вызывается в pre-flight проходе для всех ног окна - до исполнения первой из них.
+fn prepare_leg<'info>(
+    arbitrage: &ArbitrageParams,
+    accounts_slice: &[AccountInfo<'info>],
+    build_ctx: &BuildContext<'info>,
+    max_slippage_bps: u16,
+) -> Result<PreparedLeg<'info>> {
+    // 🛡️ Глобальный cap на slippage относительно amount_in (независимо от Go-бота)
+    check_slippage_cap(arbitrage, max_slippage_bps)?;
+
+    // 🔮 Пул не должен быть "слишком выгодным" относительно оракула (манипуляция)
+    check_oracle_deviation(arbitrage, accounts_slice)?;
+
+    // 🔁 Round trip на одном venue почти всегда баг бота: предупреждаем или (по флагу owner-а) отклоняем
+    if arbitrage.bridge.is_none() && arbitrage.buy_dex == arbitrage.sell_dex {
+        require!(
+            !build_ctx.enforce_distinct_venues || arbitrage.allow_same_venue,
+            MyErrorCode::SameVenue
+        );
+        msg!("⚠️ Same-venue round trip on {:?}", arbitrage.buy_dex);
+    }
+
+    // 🏜️ Слитый пул (типично для свежего rug) уронил бы весь батч уже на CPI
+    check_pool_liquidity(arbitrage, accounts_slice, build_ctx)?;
+
+    // 🪙 Токен-программа ноги: явная из параметров (например Token-2022) или из контекста.
+    // Явная - только SPL Token / Token-2022: подмененная программа получила бы подпись user-а
+    if let Some(leg_token_program) = arbitrage.token_program {
+        require!(is_token_program(&leg_token_program), MyErrorCode::InvalidProgramId);
+    }
+    let mut leg_build_ctx = match arbitrage.token_program {
+        Some(leg_token_program) => BuildContext {
+            token_program: find_account(accounts_slice, &leg_token_program)
+                .ok_or(MyErrorCode::AccountNotFound)?
+                .clone(),
+            ..build_ctx.clone()
+        },
+        None => build_ctx.clone(),
+    };
+
+    // 🆕 create_ata: только проверка аккаунтов, сами ATA создаются в цикле исполнения перед свопом
+    let ata_creations = if arbitrage.create_ata {
+        prepare_ata_creations(arbitrage, accounts_slice, &leg_build_ctx)?
+    } else {
+        Vec::new()
+    };
+
+    // Токен-аккаунт пользователя: по нему проверяем, сколько реально пришло после BUY
+    let token_account = find_leg_token_account(accounts_slice, arbitrage, &leg_build_ctx)?.clone();
+
+    // 🪙 Смешанный батч (классические и Token-2022 ноги): токен-программа каждой ноги - программа-владелец
+    // ее токен-аккаунта. Без явного token_program берем Token-2022 из среза, если аккаунт его
+    // (еще не созданный ATA деривирован от программы ноги - ее и оставляем)
+    if token_account.owner != leg_build_ctx.token_program.key && !is_uninitialized_account(&token_account) {
+        require!(arbitrage.token_program.is_none(), MyErrorCode::InvalidTokenAccount);
+        leg_build_ctx.token_program = find_account(accounts_slice, token_account.owner)
+            .filter(|acc_info| is_token_program(acc_info.key))
+            .ok_or(MyErrorCode::AccountNotFound)?
+            .clone();
+        msg!("🪙 Leg token program resolved to {}", token_account.owner);
+    }
+
+    // 💸 Fee recipient Pump.fun ноги: явный (ротация / конкретный для токена) или дефолт роутера
+    if let Some(leg_fee_recipient) = arbitrage.fee_recipient {
+        find_account(accounts_slice, &leg_fee_recipient).ok_or(MyErrorCode::AccountNotFound)?;
+        leg_build_ctx.fee_recipient = leg_fee_recipient;
+    }
+
+    // 🤝 Referral аккаунт имеет смысл только для DEX-ов, которые его принимают
+    if arbitrage.referral_account.is_some() {
+        require!(
+            supports_referral(&arbitrage.buy_dex) || supports_referral(&arbitrage.sell_dex),
+            MyErrorCode::InvalidConfigValue
+        );
+    }
+
+    // 💺 Seat есть только у Phoenix
+    if arbitrage.auto_claim_seat {
+        require!(
+            arbitrage.buy_dex == DexType::Phoenix || arbitrage.sell_dex == DexType::Phoenix,
+            MyErrorCode::InvalidConfigValue
+        );
+    }
+
+    // 🗺️ Layout от Go-бота: каждая позиция сверяется с ожидаемым для роли ключом до сборки
+    validate_leg_layout(arbitrage, accounts_slice, &leg_build_ctx)?;
+
+    // 🏧 Pre-funded quote-аккаунт ноги: тот же mint, что у user_wsol_account, владелец - user
+    if let Some(funding_account) = arbitrage.funding_account {
+        let funding_info = find_account(accounts_slice, &funding_account).ok_or(MyErrorCode::TokenAccountNotFound)?;
+        let quote_mint = quote_mint_of(build_ctx)?;
+        let funding = InterfaceTokenAccount::try_deserialize(&mut funding_info.data.borrow().as_ref())
+            .map_err(|_| MyErrorCode::InvalidTokenAccount)?;
+        require!(
+            funding.owner == build_ctx.user.key() && funding.mint == quote_mint,
+            MyErrorCode::InvalidTokenAccount
+        );
+        // Pump.fun платит нативными SOL - funding-аккаунт на BUY не расходуется
+        if arbitrage.buy_dex != DexType::PumpFun {
+            require!(funding.amount >= arbitrage.max_sol_cost, MyErrorCode::InsufficientFunds);
+        }
+        leg_build_ctx.user_quote_account = funding_info.clone();
+    }
+
+    let buy = build_dex_instruction(&arbitrage.buy_dex, TradeSide::Buy, accounts_slice, arbitrage, &leg_build_ctx)?;
+
+    // 🔄 Reverse: SELL идет первым, поэтому токены должны быть у пользователя заранее
+    if arbitrage.reverse {
+        require!(arbitrage.bridge.is_none(), MyErrorCode::InvalidConfigValue);
+        require!(
+            token_account_amount(&token_account)? >= arbitrage.tokens_to_sell,
+            MyErrorCode::InsufficientTokenBalance
+        );
+    }
+
+    let sell = if arbitrage.bridge.is_some() {
+        None
+    } else {
+        Some(build_sell_instruction(arbitrage, accounts_slice, &leg_build_ctx, &buy)?)
+    };
+
+    // 💺 Seat Phoenix: только сборка, CPI - в цикле исполнения прямо перед свопом ноги
+    let seat_claim = if arbitrage.auto_claim_seat {
+        build_phoenix_seat_claim(accounts_slice, &leg_build_ctx)?
+    } else {
+        None
+    };
+
+    Ok(PreparedLeg {
+        build_ctx: leg_build_ctx,
+        token_account,
+        buy,
+        sell,
+        seat_claim,
+        ata_creations,
+    })
+}
+
+/// Ликвидность bonding curve Pump.fun ноги: BUY нужны токены в кривой, SELL - SOL, не меньше min_liquidity.
+/// Остальные venue свои резервы проверяют сами при CPI
+fn check_pool_liquidity(arbitrage: &ArbitrageParams, accounts_slice: &[AccountInfo], build_ctx: &BuildContext) -> Result<()> {
+    let pump_buy = arbitrage.buy_dex == DexType::PumpFun;
+    let pump_sell = arbitrage.sell_dex == DexType::PumpFun;
+    if !pump_buy && !pump_sell {
+        return Ok(());
+    }
+
+    let (bonding_curve, _) = Pubkey::find_program_address(
+        &[b"bonding-curve", arbitrage.token_mint.as_ref()],
+        &build_ctx.pump_program_id,
+    );
+    // Кривой нет в срезе - это отловит билдер своей ошибкой
+    let bonding_curve_account = match find_account(accounts_slice, &bonding_curve) {
+        Some(acc_info) => acc_info,
+        None => return Ok(()),
+    };
+
+    let data = bonding_curve_account.try_borrow_data()?;
+    let real_token_reserves = read_u64(&data, PUMP_BONDING_CURVE_REAL_TOKEN_OFFSET)?;
+    let real_sol_reserves = read_u64(&data, PUMP_BONDING_CURVE_REAL_SOL_OFFSET)?;
+
+    let drained = (pump_buy && real_token_reserves == 0)
+        || (pump_sell && real_sol_reserves == 0)
+        || real_sol_reserves < build_ctx.min_liquidity;
+    if drained {
+        msg!("🏜️ Bonding curve {} has {} tokens / {} SOL left", bonding_curve, real_token_reserves, real_sol_reserves);
+        return Err(MyErrorCode::InsufficientLiquidity.into());
+    }
+    Ok(())
+}
+
+/// SELL-инструкция ноги (вызывается и повторно после BUY, если пришло меньше `tokens_to_sell`)
+fn build_sell_instruction<'info>(
+    arbitrage: &ArbitrageParams,
+    accounts_slice: &[AccountInfo<'info>],
+    build_ctx: &BuildContext<'info>,
+    buy: &(Instruction, Vec<AccountInfo<'info>>),
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    match (&arbitrage.buy_dex, &arbitrage.sell_dex) {
+        // Pump.fun -> Pump.fun: те же аккаунты, меняется только data (без повторного скана)
+        (DexType::PumpFun, DexType::PumpFun) => {
+            msg!("🔧 Creating Pump.fun SELL instruction inline...");
+            let sell_instruction = Instruction {
+                program_id: build_ctx.pump_program_id,
+                accounts: buy.0.accounts.clone(), // Переиспользуем аккаунты
+                data: pump_fun_instruction_data(TradeSide::Sell, arbitrage, build_ctx),
+            };
+            let sell_instruction =
+                finalize_instruction(&arbitrage.sell_dex, TradeSide::Sell, arbitrage, build_ctx, sell_instruction)?;
+            Ok((sell_instruction, buy.1.clone()))
+        },
+        _ => build_dex_instruction(&arbitrage.sell_dex, TradeSide::Sell, accounts_slice, arbitrage, build_ctx),
+    }
+}
+
+/// 🆕 ATA, который create_ata ноги создаст перед ее свопом (create_idempotent, payer = user)
+struct AtaCreation<'info> {
+    associated_token_program: AccountInfo<'info>,
+    ata: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+}
+
+/// 🆕 Еще не созданные ATA ноги: пользователя для token_mint и, для Pump.fun ног, associated
+/// bonding curve свежего токена. Только проверка аккаунтов среза - без CPI.
+fn prepare_ata_creations<'info>(
+    arbitrage: &ArbitrageParams,
+    accounts_slice: &[AccountInfo<'info>],
+    build_ctx: &BuildContext<'info>,
+) -> Result<Vec<AtaCreation<'info>>> {
+    let mut ata_creations = Vec::new();
+    if let Some(ata_creation) = ata_creation_if_missing(&build_ctx.user, arbitrage, accounts_slice, build_ctx)? {
+        ata_creations.push(ata_creation);
+    }
+
+    if arbitrage.buy_dex == DexType::PumpFun || arbitrage.sell_dex == DexType::PumpFun {
+        let (bonding_curve, _) = Pubkey::find_program_address(
+            &[b"bonding-curve", arbitrage.token_mint.as_ref()],
+            &build_ctx.pump_program_id,
+        );
+        let bonding_curve_account = find_account(accounts_slice, &bonding_curve).ok_or(MyErrorCode::PDAAccountNotFound)?;
+        if let Some(ata_creation) = ata_creation_if_missing(bonding_curve_account, arbitrage, accounts_slice, build_ctx)? {
+            ata_creations.push(ata_creation);
+        }
+    }
+
+    Ok(ata_creations)
+}
+
+/// ATA (authority, token_mint) токен-программы ноги из среза. Уже существующий ATA - None,
+/// для несуществующего в срезе должны быть mint и associated token program.
+fn ata_creation_if_missing<'info>(
+    authority: &AccountInfo<'info>,
+    arbitrage: &ArbitrageParams,
+    accounts_slice: &[AccountInfo<'info>],
+    build_ctx: &BuildContext<'info>,
+) -> Result<Option<AtaCreation<'info>>> {
+    let ata = get_associated_token_address_with_program_id(
+        &authority.key(),
+        &arbitrage.token_mint,
+        &build_ctx.token_program.key(),
+    );
+    let ata_account = find_account(accounts_slice, &ata).ok_or(MyErrorCode::TokenAccountNotFound)?;
+    if !is_uninitialized_account(ata_account) {
+        return Ok(None);
+    }
+
+    let mint_account = find_account(accounts_slice, &arbitrage.token_mint).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let associated_token_program = find_account(accounts_slice, &associated_token::ID)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+
+    Ok(Some(AtaCreation {
+        associated_token_program: associated_token_program.clone(),
+        ata: ata_account.clone(),
+        authority: authority.clone(),
+        mint: mint_account.clone(),
+        token_program: build_ctx.token_program.clone(),
+    }))
+}
+
+/// 🆕 CPI create_idempotent для собранных в pre-flight ATA ноги
+fn create_atas<'info>(ata_creations: &[AtaCreation<'info>], build_ctx: &BuildContext<'info>) -> Result<()> {
+    for ata_creation in ata_creations {
+        associated_token::create_idempotent(CpiContext::new(
+            ata_creation.associated_token_program.clone(),
+            associated_token::Create {
+                payer: build_ctx.user.clone(),
+                associated_token: ata_creation.ata.clone(),
+                authority: ata_creation.authority.clone(),
+                mint: ata_creation.mint.clone(),
+                system_program: build_ctx.system_program.clone(),
+                token_program: ata_creation.token_program.clone(),
+            },
+        ))?;
+        msg!("🆕 Created ATA {} of {} for mint {}", ata_creation.ata.key(), ata_creation.authority.key(), ata_creation.mint.key());
+    }
+    Ok(())
+}
+
+/// ATA ноги с create_ata, который еще не создан: цикл исполнения создаст его перед свопом,
+/// поэтому сборка инструкций принимает его вместо инициализированного token account-а
+fn is_pending_ata(acc_info: &AccountInfo, authority: &Pubkey, arbitrage: &ArbitrageParams, build_ctx: &BuildContext) -> bool {
+    arbitrage.create_ata
+        && is_uninitialized_account(acc_info)
+        && acc_info.key()
+            == get_associated_token_address_with_program_id(authority, &arbitrage.token_mint, build_ctx.token_program.key)
+}
+
+/// Аккаунт token_mint пользователя для ноги: инициализированный из среза или еще не созданный ATA create_ata
+fn find_leg_token_account<'a, 'info>(
+    accounts_slice: &'a [AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<&'a AccountInfo<'info>> {
+    let user_key = build_ctx.user.key();
+    match find_user_token_account(accounts_slice, &user_key, &arbitrage.token_mint) {
+        Err(error) if arbitrage.create_ata => accounts_slice
+            .iter()
+            .find(|acc_info| is_pending_ata(acc_info, &user_key, arbitrage, build_ctx))
+            .ok_or(error),
+        result => result,
+    }
+}
+
+/// Anchor `#[event_cpi]` authority: PDA [b"__event_authority"] программы DEX-а (от mint не зависит).
+/// Общая конвенция для Anchor DEX-ов - всегда деривируем от program id конкретной ноги.
+fn derive_event_authority(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"__event_authority"], program_id).0
+}
+
+/// Event authority Anchor DEX-а ноги в срезе. Authority другой программы (например, Pump.fun-овский
+/// в ноге Meteora) не подходит: по ней CPI упал бы уже внутри DEX-а - PDAAccountNotFound здесь
+fn find_event_authority<'a, 'info>(
+    accounts_slice: &'a [AccountInfo<'info>],
+    program_id: &Pubkey,
+) -> Result<&'a AccountInfo<'info>> {
+    let event_authority = derive_event_authority(program_id);
+    find_account(accounts_slice, &event_authority).ok_or(MyErrorCode::PDAAccountNotFound.into())
+}
+
+/// PDA Pump.fun для mint: [global, bonding-curve, associated-bonding-curve (ATA), __event_authority].
+/// ATA деривируется от токен-программы mint-а (SPL Token или Token-2022)
+fn derive_pump_fun_pdas(mint: &Pubkey, pump_program_id: &Pubkey, token_program_id: &Pubkey) -> [Pubkey; 4] {
+    let (global, _) = Pubkey::find_program_address(&[b"global"], pump_program_id);
+    let (bonding_curve, _) = Pubkey::find_program_address(&[b"bonding-curve", mint.as_ref()], pump_program_id);
+    let associated_bonding_curve = get_associated_token_address_with_program_id(&bonding_curve, mint, token_program_id);
+    let event_authority = derive_event_authority(pump_program_id);
+    [global, bonding_curve, associated_bonding_curve, event_authority]
+}
+
+/// Сверка ArbitrageParams.layout со срезом: роль каждой позиции против derive / ожидаемого ключа.
+/// Пустой layout - обычный поиск по срезу.
+fn validate_leg_layout(
+    arbitrage: &ArbitrageParams,
+    accounts_slice: &[AccountInfo],
+    build_ctx: &BuildContext,
+) -> Result<()> {
+    if arbitrage.layout.is_empty() {
+        return Ok(());
+    }
+    require!(arbitrage.layout.len() == accounts_slice.len(), MyErrorCode::LayoutMismatch);
+
+    let [global, bonding_curve, associated_bonding_curve, event_authority] =
+        derive_pump_fun_pdas(&arbitrage.token_mint, &build_ctx.pump_program_id, build_ctx.token_program.key);
+    let user_key = build_ctx.user.key();
+
+    for (position, (acc_info, role)) in accounts_slice.iter().zip(arbitrage.layout.iter()).enumerate() {
+        let matches = match *role {
+            LAYOUT_ROLE_PUMP_GLOBAL => acc_info.key() == global,
+            LAYOUT_ROLE_PUMP_FEE_RECIPIENT => acc_info.key() == build_ctx.fee_recipient,
+            LAYOUT_ROLE_PUMP_BONDING_CURVE => acc_info.key() == bonding_curve,
+            LAYOUT_ROLE_PUMP_ASSOCIATED_BONDING_CURVE => acc_info.key() == associated_bonding_curve,
+            LAYOUT_ROLE_PUMP_EVENT_AUTHORITY => acc_info.key() == event_authority,
+            LAYOUT_ROLE_TOKEN_MINT => acc_info.key() == arbitrage.token_mint,
+            LAYOUT_ROLE_PUMP_PROGRAM => acc_info.key() == build_ctx.pump_program_id,
+            LAYOUT_ROLE_USER_TOKEN_ACCOUNT => (is_token_program(acc_info.owner)
+                && InterfaceTokenAccount::try_deserialize(&mut acc_info.data.borrow().as_ref())
+                    .map(|token_account| token_account.owner == user_key && token_account.mint == arbitrage.token_mint)
+                    .unwrap_or(false))
+                || is_pending_ata(acc_info, &user_key, arbitrage, build_ctx),
+            LAYOUT_ROLE_OTHER => true,
+            _ => false,
+        };
+        if !matches {
+            msg!("🗺️ Layout mismatch at position {} (role {})", position, role);
+            return Err(MyErrorCode::LayoutMismatch.into());
+        }
+    }
+    Ok(())
+}
+
+/// Instruction data для Pump.fun: discriminator + amount + sol limit
+fn pump_fun_instruction_data(side: TradeSide, arbitrage: &ArbitrageParams, build_ctx: &BuildContext) -> Vec<u8> {
+    let mut instruction_data = Vec::with_capacity(24);
+    match side {
+        TradeSide::Buy => {
+            instruction_data.extend_from_slice(&build_ctx.pump_buy_discriminator);
+            instruction_data.extend_from_slice(&arbitrage.tokens_to_buy.to_le_bytes());
+            instruction_data.extend_from_slice(&arbitrage.max_sol_cost.to_le_bytes());
+        },
+        TradeSide::Sell => {
+            instruction_data.extend_from_slice(&build_ctx.pump_sell_discriminator);
+            instruction_data.extend_from_slice(&arbitrage.tokens_to_sell.to_le_bytes());
+            instruction_data.extend_from_slice(&arbitrage.min_wsol_out.to_le_bytes());
+        },
+    }
+    instruction_data
+}
+
+/// Виртуальные резервы bonding curve Pump.fun: цена - constant product по ним
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PumpCurveReserves {
+    pub virtual_token_reserves: u64,
+    pub virtual_sol_reserves: u64,
+}
+
+impl PumpCurveReserves {
+    /// SOL за покупку `tokens` с комиссией - та же формула, что в программе Pump.fun
+    /// (None = кривая столько не отдаст)
+    pub fn buy_cost(&self, tokens: u64, fee_bps: u64) -> Option<u64> {
+        let virtual_token = self.virtual_token_reserves as u128;
+        let tokens = tokens as u128;
+        if tokens >= virtual_token {
+            return None;
+        }
+        let sol_cost = tokens * self.virtual_sol_reserves as u128 / (virtual_token - tokens) + 1;
+        let fee = sol_cost * fee_bps as u128 / 10_000;
+        u64::try_from(sol_cost + fee).ok()
+    }
+
+    /// SOL за продажу `tokens` за вычетом комиссии
+    pub fn sell_out(&self, tokens: u64, fee_bps: u64) -> Option<u64> {
+        let denominator = self.virtual_token_reserves as u128 + tokens as u128;
+        if denominator == 0 {
+            return None;
+        }
+        let sol_out = tokens as u128 * self.virtual_sol_reserves as u128 / denominator;
+        let fee = sol_out * fee_bps as u128 / 10_000;
+        u64::try_from(sol_out - fee).ok()
+    }
+
+    /// Резервы после покупки `tokens` (комиссия уходит fee recipient-у, а не в кривую)
+    pub fn after_buy(&self, tokens: u64) -> Option<Self> {
+        let sol_in = self.buy_cost(tokens, 0)?;
+        Some(Self {
+            virtual_token_reserves: self.virtual_token_reserves.checked_sub(tokens)?,
+            virtual_sol_reserves: self.virtual_sol_reserves.checked_add(sol_in)?,
+        })
+    }
+}
+
+/// Резервы bonding curve и комиссия из Global для mint ноги (оба аккаунта - в срезе)
+fn read_pump_curve(
+    arbitrage_accounts_slice: &[AccountInfo],
+    token_mint: &Pubkey,
+    pump_program_id: &Pubkey,
+) -> Result<(PumpCurveReserves, u64)> {
+    let [global, bonding_curve, _, _] = derive_pump_fun_pdas(token_mint, pump_program_id, &anchor_spl::token::ID);
+
+    let global_account = find_account(arbitrage_accounts_slice, &global).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let bonding_curve_account =
+        find_account(arbitrage_accounts_slice, &bonding_curve).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    require!(global_account.owner == pump_program_id, MyErrorCode::InvalidAccountOwner);
+    require!(bonding_curve_account.owner == pump_program_id, MyErrorCode::InvalidAccountOwner);
+
+    let fee_bps = read_u64(&global_account.try_borrow_data()?, PUMP_GLOBAL_FEE_BPS_OFFSET)?;
+
+    let data = bonding_curve_account.try_borrow_data()?;
+    let complete = data.get(PUMP_BONDING_CURVE_COMPLETE_OFFSET).copied().ok_or(MyErrorCode::InvalidAccountData)?;
+    require!(complete == 0, MyErrorCode::BondingCurveComplete);
+
+    let reserves = PumpCurveReserves {
+        virtual_token_reserves: read_u64(&data, PUMP_BONDING_CURVE_VIRTUAL_TOKEN_OFFSET)?,
+        virtual_sol_reserves: read_u64(&data, PUMP_BONDING_CURVE_VIRTUAL_SOL_OFFSET)?,
+    };
+    Ok((reserves, fee_bps))
+}
+
+/// Pump.fun: поиск аккаунтов в срезе + сборка buy/sell инструкции
+fn build_pump_fun_instruction<'info>(
+    side: TradeSide,
+    arbitrage_accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Pump.fun {:?} instruction inline...", side);
+
+    let pump_program_id = build_ctx.pump_program_id;
+    let fee_recipient = build_ctx.fee_recipient;
+    let user_key = build_ctx.user.key();
+
+    // ⛽ Детерминированный потолок сравнений аккаунтов на разбор ноги
+    let mut scan_budget = ScanBudget::new(arbitrage_accounts_slice.len(), build_ctx.scan_passes);
+
+    // Поиск аккаунтов inline (БЕЗ CRYPTO ЗАВИСИМОСТЕЙ)
+    let mut pump_program_account = None;
+    let mut global_account = None;
+    let mut fee_recipient_account = None;
+    let mut mint_account = None;
+    let mut bonding_curve_account = None;
+    let mut user_token_account = None;
+    let mut event_authority_account = None;
+
+    if !arbitrage.layout.is_empty() {
+        // 🗺️ Layout уже сверен в prepare_leg - берем аккаунты по позициям, без скана и derive PDA
+        scan_budget.charge(arbitrage_accounts_slice.len())?;
+        for (acc_info, role) in arbitrage_accounts_slice.iter().zip(arbitrage.layout.iter()) {
+            match *role {
+                LAYOUT_ROLE_PUMP_PROGRAM => pump_program_account = Some(acc_info),
+                LAYOUT_ROLE_PUMP_GLOBAL => global_account = Some(acc_info),
+                LAYOUT_ROLE_PUMP_FEE_RECIPIENT => fee_recipient_account = Some(acc_info),
+                LAYOUT_ROLE_TOKEN_MINT => mint_account = Some(acc_info),
+                LAYOUT_ROLE_PUMP_BONDING_CURVE => bonding_curve_account = Some(acc_info),
+                LAYOUT_ROLE_USER_TOKEN_ACCOUNT => user_token_account = Some(acc_info),
+                LAYOUT_ROLE_PUMP_EVENT_AUTHORITY => event_authority_account = Some(acc_info),
+                _ => {},
+            }
+        }
+    } else {
+        // PDA выводим один раз до прохода, а не на каждый аккаунт среза
+        let (expected_global, _) = Pubkey::find_program_address(&[b"global"], &pump_program_id);
+        let (expected_bonding_curve, _) = Pubkey::find_program_address(&[b"bonding-curve", arbitrage.token_mint.as_ref()], &pump_program_id);
+        let expected_event_authority = derive_event_authority(&pump_program_id);
+
+        // Inline поиск всех нужных аккаунтов (COMPILE-TIME PUBKEYS)
+        scan_budget.charge(arbitrage_accounts_slice.len())?;
+        for acc_info in arbitrage_accounts_slice {
+            // Pump program
+            if acc_info.key() == pump_program_id {
+                pump_program_account = Some(acc_info);
+            }
+            // Global PDA
+            if acc_info.key() == expected_global {
+                global_account = Some(acc_info);
+            }
+            // Fee recipient
+            if acc_info.key() == fee_recipient {
+                fee_recipient_account = Some(acc_info);
+            }
+            // Mint
+            if acc_info.key() == arbitrage.token_mint {
+                mint_account = Some(acc_info);
+            }
+            // Bonding curve PDA
+            if acc_info.key() == expected_bonding_curve {
+                bonding_curve_account = Some(acc_info);
+            }
+            // User token account (токен-программы ноги: классический SPL Token или Token-2022)
+            if acc_info.owner == build_ctx.token_program.key {
+                if let Some(token_account) = parse_token_account(acc_info)? {
+                    if token_account.owner == user_key && token_account.mint == arbitrage.token_mint {
+                        // Первое совпадение; второй другой аккаунт того же mint - неоднозначность
+                        match user_token_account {
+                            None => user_token_account = Some(acc_info),
+                            Some(first) if first.key != acc_info.key => {
+                                return Err(MyErrorCode::InvalidTokenAccount.into());
+                            },
+                            Some(_) => {},
+                        }
+                    }
+                }
+            }
+            // Event authority PDA
+            if acc_info.key() == expected_event_authority {
+                event_authority_account = Some(acc_info);
+            }
+        }
+        // 🆕 create_ata: ATA пользователя создастся перед свопом
+        if user_token_account.is_none() && arbitrage.create_ata {
+            let pending_ata = get_associated_token_address_with_program_id(
+                &user_key,
+                &arbitrage.token_mint,
+                build_ctx.token_program.key,
+            );
+            user_token_account = scan_budget
+                .find(arbitrage_accounts_slice, &pending_ata)?
+                .filter(|acc_info| is_pending_ata(acc_info, &user_key, arbitrage, build_ctx));
+        }
+    }
+
+    // Проверяем что все аккаунты найдены
+    let pump_program_account = pump_program_account.ok_or(MyErrorCode::AccountNotFound)?;
+    let global_account = global_account.ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let fee_recipient_account = fee_recipient_account.ok_or(MyErrorCode::AccountNotFound)?;
+    let mint_account = mint_account.ok_or(MyErrorCode::MintAccountNotFound)?;
+    let bonding_curve_account = bonding_curve_account.ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let user_token_account = user_token_account.ok_or(MyErrorCode::TokenAccountNotFound)?;
+    let event_authority_account = event_authority_account.ok_or(MyErrorCode::PDAAccountNotFound)?;
+
+    // Ключ совпадает, но CPI возможен только в исполняемый аккаунт
+    require!(pump_program_account.executable, MyErrorCode::InvalidProgramId);
+
+    // 🛡️ Defense-in-depth против подмены: PDA Pump.fun принадлежат программе,
+    // mint и токен-аккаунты - токен-программе ноги (event authority - пустой PDA, не проверяем)
+    require!(global_account.owner == &pump_program_id, MyErrorCode::InvalidAccountOwner);
+    require!(bonding_curve_account.owner == &pump_program_id, MyErrorCode::InvalidAccountOwner);
+    require!(mint_account.owner == build_ctx.token_program.key, MyErrorCode::InvalidAccountOwner);
+    require!(
+        user_token_account.owner == build_ctx.token_program.key
+            || is_pending_ata(user_token_account, &user_key, arbitrage, build_ctx),
+        MyErrorCode::InvalidAccountOwner
+    );
+
+    // 🏁 Завершенная bonding curve (миграция в AMM) больше не торгуется - падаем рано и понятно
+    let curve_complete = bonding_curve_account
+        .try_borrow_data()?
+        .get(PUMP_BONDING_CURVE_COMPLETE_OFFSET)
+        .copied()
+        .ok_or(MyErrorCode::InvalidAccountData)?;
+    require!(curve_complete == 0, MyErrorCode::BondingCurveComplete);
+
+    // Находим associated bonding curve (ATA). У совсем свежего токена его может еще не быть:
+    // отдельная ошибка, чтобы бот мог подождать / пропустить (или передать create_ata -
+    // тогда ATA создастся перед свопом ноги)
+    let expected_ata = get_associated_token_address_with_program_id(
+        &bonding_curve_account.key(),
+        &arbitrage.token_mint,
+        build_ctx.token_program.key,
+    );
+    let associated_bonding_curve_pending = |acc_info: &AccountInfo| {
+        is_pending_ata(acc_info, bonding_curve_account.key, arbitrage, build_ctx)
+    };
+    let associated_bonding_curve_account = scan_budget
+        .find(arbitrage_accounts_slice, &expected_ata)?
+        .filter(|acc_info| !is_uninitialized_account(acc_info) || associated_bonding_curve_pending(acc_info))
+        .ok_or(MyErrorCode::AssociatedBondingCurveMissing)?;
+    require!(
+        associated_bonding_curve_account.owner == build_ctx.token_program.key
+            || associated_bonding_curve_pending(associated_bonding_curve_account),
+        MyErrorCode::InvalidAccountOwner
+    );
+
+    // Создаем instruction
+    let instruction = Instruction {
+        program_id: pump_program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(global_account.key(), false),
+            AccountMeta::new(fee_recipient_account.key(), false),
+            AccountMeta::new_readonly(mint_account.key(), false),
+            AccountMeta::new(bonding_curve_account.key(), false),
+            AccountMeta::new(associated_bonding_curve_account.key(), false),
+            AccountMeta::new(user_token_account.key(), false),
+            AccountMeta::new(user_key, true),
+            AccountMeta::new_readonly(build_ctx.system_program.key(), false),
+            AccountMeta::new_readonly(build_ctx.token_program.key(), false),
+            AccountMeta::new_readonly(build_ctx.rent.key(), false),
+            AccountMeta::new_readonly(event_authority_account.key(), false),
+            AccountMeta::new_readonly(pump_program_account.key(), false),
+        ],
+        data: pump_fun_instruction_data(side, arbitrage, build_ctx),
+    };
+
+    // Те же аккаунты что в instruction, но как AccountInfo - ровно найденные по ключу
+    // (токен-аккаунты ноги могут быть и Token-2022, и еще не созданными ATA create_ata)
+    let accounts = vec![
+        global_account.clone(),
+        fee_recipient_account.clone(),
+        mint_account.clone(),
+        bonding_curve_account.clone(),
+        associated_bonding_curve_account.clone(),
+        user_token_account.clone(),
+        build_ctx.user.clone(),
+        build_ctx.system_program.clone(),
+        build_ctx.token_program.clone(),
+        build_ctx.rent.clone(),
+        event_authority_account.clone(),
+        pump_program_account.clone(),
+    ];
+
+    Ok((instruction, accounts))
+}
+
+/// OpenBook v2: `place_take_order` (IOC) по маркету base = token_mint, quote = wSOL.
+/// BUY -> Bid на tokens_to_buy с потолком max_sol_cost, SELL -> Ask на tokens_to_sell с полом min_wsol_out.
+fn build_openbook_v2_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating OpenBook v2 {:?} take order...", side);
+
+    let openbook_program_id = parse_program_id(OPENBOOK_V2_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &openbook_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+
+    // Маркет определяем по владельцу и discriminator (bids/asks тоже принадлежат OpenBook)
+    let market_account = find_program_account(
+        accounts_slice,
+        &openbook_program_id,
+        &OPENBOOK_MARKET_DISCRIMINATOR,
+        OPENBOOK_MARKET_QUOTE_VAULT_OFFSET + 32,
+    )
+    .ok_or(MyErrorCode::AccountNotFound)?;
+
+    // Все остальные ключи берем из самого маркета
+    let (market_authority, bids, asks, event_heap, oracle_a, oracle_b, base_vault, quote_vault, base_mint, quote_lot_size, base_lot_size) = {
+        let data = market_account.try_borrow_data()?;
+        (
+            read_pubkey(&data, OPENBOOK_MARKET_AUTHORITY_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_BIDS_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_ASKS_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_EVENT_HEAP_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_ORACLE_A_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_ORACLE_B_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_BASE_VAULT_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_QUOTE_VAULT_OFFSET)?,
+            read_pubkey(&data, OPENBOOK_MARKET_BASE_MINT_OFFSET)?,
+            read_u64(&data, OPENBOOK_MARKET_QUOTE_LOT_SIZE_OFFSET)?,
+            read_u64(&data, OPENBOOK_MARKET_BASE_LOT_SIZE_OFFSET)?,
+        )
+    };
+
+    // Маркет должен торговать именно нашим токеном
+    require!(base_mint == arbitrage.token_mint, MyErrorCode::PoolMintMismatch);
+    require!(quote_lot_size > 0 && base_lot_size > 0, MyErrorCode::InvalidAccountData);
+
+    let market_authority_account = find_account(accounts_slice, &market_authority).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let bids_account = find_account(accounts_slice, &bids).ok_or(MyErrorCode::AccountNotFound)?;
+    let asks_account = find_account(accounts_slice, &asks).ok_or(MyErrorCode::AccountNotFound)?;
+    let event_heap_account = find_account(accounts_slice, &event_heap).ok_or(MyErrorCode::AccountNotFound)?;
+    let base_vault_account = find_account(accounts_slice, &base_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let quote_vault_account = find_account(accounts_slice, &quote_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_base_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    // Лоты: base в base_lot_size, quote в quote_lot_size, цена = quote lots за один base lot
+    let base_lot_size = base_lot_size as u128;
+    let quote_lot_size = quote_lot_size as u128;
+    let (side_byte, price_lots, max_base_lots, max_quote_lots) = match side {
+        TradeSide::Buy => {
+            // Bid: лимит цены округляем вверх, чтобы не обрезать собственный потолок max_sol_cost
+            let numerator = (arbitrage.max_sol_cost as u128)
+                .checked_mul(base_lot_size)
+                .ok_or(MyErrorCode::ArithmeticError)?;
+            let denominator = (arbitrage.tokens_to_buy as u128)
+                .checked_mul(quote_lot_size)
+                .filter(|d| *d > 0)
+                .ok_or(MyErrorCode::ArithmeticError)?;
+            let price_lots = (numerator + denominator - 1) / denominator;
+            (
+                0u8,
+                price_lots,
+                arbitrage.tokens_to_buy as u128 / base_lot_size,
+                arbitrage.max_sol_cost as u128 / quote_lot_size,
+            )
+        },
+        TradeSide::Sell => {
+            // Ask: минимальная цена = min_wsol_out / tokens_to_sell (округляем вниз, но не ниже 1 лота)
+            let numerator = (arbitrage.min_wsol_out as u128)
+                .checked_mul(base_lot_size)
+                .ok_or(MyErrorCode::ArithmeticError)?;
+            let denominator = (arbitrage.tokens_to_sell as u128)
+                .checked_mul(quote_lot_size)
+                .filter(|d| *d > 0)
+                .ok_or(MyErrorCode::ArithmeticError)?;
+            (
+                1u8,
+                (numerator / denominator).max(1),
+                arbitrage.tokens_to_sell as u128 / base_lot_size,
+                i64::MAX as u128,
+            )
+        },
+    };
+    let price_lots = i64::try_from(price_lots).map_err(|_| MyErrorCode::ArithmeticError)?;
+    let max_base_lots = i64::try_from(max_base_lots).map_err(|_| MyErrorCode::ArithmeticError)?;
+    let max_quote_lots = i64::try_from(max_quote_lots).map_err(|_| MyErrorCode::ArithmeticError)?;
+
+    // PlaceTakeOrderArgs { side, price_lots, max_base_lots, max_quote_lots_including_fees, order_type, limit }
+    let mut instruction_data = Vec::with_capacity(35);
+    instruction_data.extend_from_slice(&[0x03, 0x2c, 0x47, 0x03, 0x1a, 0xc7, 0xcb, 0x55]); // place_take_order discriminator
+    instruction_data.push(side_byte);
+    instruction_data.extend_from_slice(&price_lots.to_le_bytes());
+    instruction_data.extend_from_slice(&max_base_lots.to_le_bytes());
+    instruction_data.extend_from_slice(&max_quote_lots.to_le_bytes());
+    instruction_data.push(1); // PlaceOrderType::ImmediateOrCancel
+    instruction_data.push(10); // limit: максимум матчей за один вызов
+
+    let mut accounts = vec![
+        program_account.clone(),
+        market_account.clone(),
+        market_authority_account.clone(),
+        bids_account.clone(),
+        asks_account.clone(),
+        base_vault_account.clone(),
+        quote_vault_account.clone(),
+        event_heap_account.clone(),
+        user_base_account.clone(),
+        build_ctx.user_quote_account.clone(),
+        build_ctx.user.clone(),
+        build_ctx.token_program.clone(),
+        build_ctx.system_program.clone(),
+    ];
+
+    // Опциональные оракулы: отсутствующий Anchor Option-аккаунт передается как program id
+    let mut oracle_meta = |oracle: Pubkey| -> Result<AccountMeta> {
+        if oracle == Pubkey::default() {
+            return Ok(AccountMeta::new_readonly(openbook_program_id, false));
+        }
+        let oracle_account = find_account(accounts_slice, &oracle).ok_or(MyErrorCode::AccountNotFound)?;
+        accounts.push(oracle_account.clone());
+        Ok(AccountMeta::new_readonly(oracle, false))
+    };
+    let oracle_a_meta = oracle_meta(oracle_a)?;
+    let oracle_b_meta = oracle_meta(oracle_b)?;
+
+    let instruction = Instruction {
+        program_id: openbook_program_id,
+        accounts: vec![
+            AccountMeta::new(user_key, true),                  // signer
+            AccountMeta::new(user_key, true),                  // penalty_payer
+            AccountMeta::new(market_account.key(), false),
+            AccountMeta::new_readonly(market_authority, false),
+            AccountMeta::new(bids, false),
+            AccountMeta::new(asks, false),
+            AccountMeta::new(base_vault, false),
+            AccountMeta::new(quote_vault, false),
+            AccountMeta::new(event_heap, false),
+            AccountMeta::new(user_base_account.key(), false),
+            AccountMeta::new(build_ctx.user_quote_account.key(), false),
+            oracle_a_meta,
+            oracle_b_meta,
+            AccountMeta::new_readonly(build_ctx.token_program.key(), false),
+            AccountMeta::new_readonly(build_ctx.system_program.key(), false),
+            AccountMeta::new_readonly(openbook_program_id, false), // open_orders_admin: None
+        ],
+        data: instruction_data,
+    };
+
+    Ok((instruction, accounts))
+}
+
+/// Lifinity v2: `swap` (amount_in, minimum_amount_out) по пулу token_mint / wSOL.
+/// BUY: wSOL -> token (amount_in = max_sol_cost, min_out = tokens_to_buy),
+/// SELL: token -> wSOL (amount_in = tokens_to_sell, min_out = min_wsol_out).
+fn build_lifinity_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Lifinity v2 {:?} swap...", side);
+
+    let lifinity_program_id = parse_program_id(LIFINITY_V2_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &lifinity_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let amm_account = find_program_account(
+        accounts_slice,
+        &lifinity_program_id,
+        &LIFINITY_AMM_DISCRIMINATOR,
+        LIFINITY_AMM_ORACLE_PC_OFFSET + 32,
+    )
+    .ok_or(MyErrorCode::AccountNotFound)?;
+
+    // Vaults, mints, fee и oracle берем из конфигурации пула
+    let (token_a_account, token_b_account, pool_mint, token_a_mint, token_b_mint, fee_account, oracle_main, oracle_sub, oracle_pc) = {
+        let data = amm_account.try_borrow_data()?;
+        (
+            read_pubkey(&data, LIFINITY_AMM_TOKEN_A_ACCOUNT_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_TOKEN_B_ACCOUNT_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_POOL_MINT_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_TOKEN_A_MINT_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_TOKEN_B_MINT_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_FEE_ACCOUNT_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_ORACLE_MAIN_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_ORACLE_SUB_OFFSET)?,
+            read_pubkey(&data, LIFINITY_AMM_ORACLE_PC_OFFSET)?,
+        )
+    };
+
+    // Одна из сторон пула обязана быть нашим токеном
+    let (token_vault, quote_vault) = if token_a_mint == arbitrage.token_mint {
+        (token_a_account, token_b_account)
+    } else if token_b_mint == arbitrage.token_mint {
+        (token_b_account, token_a_account)
+    } else {
+        return Err(MyErrorCode::PoolMintMismatch.into());
+    };
+
+    // Authority пула: PDA [amm] от программы Lifinity
+    let (authority, _) = Pubkey::find_program_address(&[amm_account.key.as_ref()], &lifinity_program_id);
+    let authority_account = find_account(accounts_slice, &authority).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let token_vault_account = find_account(accounts_slice, &token_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let quote_vault_account = find_account(accounts_slice, &quote_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let pool_mint_account = find_account(accounts_slice, &pool_mint).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let fee_account_info = find_account(accounts_slice, &fee_account).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_token_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    // 🔮 Lifinity прайсит по Pyth: принимаем только оракулы, сконфигурированные в самом пуле
+    let oracle_main_account = find_account(accounts_slice, &oracle_main).ok_or(MyErrorCode::OracleMismatch)?;
+    let oracle_sub_account = find_account(accounts_slice, &oracle_sub).ok_or(MyErrorCode::OracleMismatch)?;
+    let oracle_pc_account = find_account(accounts_slice, &oracle_pc).ok_or(MyErrorCode::OracleMismatch)?;
+
+    let (source, destination, swap_source, swap_destination, amount_in, minimum_amount_out) = match side {
+        TradeSide::Buy => (
+            &build_ctx.user_quote_account,
+            user_token_account,
+            quote_vault,
+            token_vault,
+            arbitrage.max_sol_cost,
+            arbitrage.tokens_to_buy,
+        ),
+        TradeSide::Sell => (
+            user_token_account,
+            &build_ctx.user_quote_account,
+            token_vault,
+            quote_vault,
+            arbitrage.tokens_to_sell,
+            arbitrage.min_wsol_out,
+        ),
+    };
+
+    let mut instruction_data = Vec::with_capacity(24);
+    instruction_data.extend_from_slice(&[0xf8, 0xc6, 0x9e, 0x91, 0xe1, 0x75, 0x87, 0xc8]); // swap discriminator
+    instruction_data.extend_from_slice(&amount_in.to_le_bytes());
+    instruction_data.extend_from_slice(&minimum_amount_out.to_le_bytes());
+
+    let instruction = Instruction {
+        program_id: lifinity_program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new(amm_account.key(), false),
+            AccountMeta::new_readonly(user_key, true), // user_transfer_authority
+            AccountMeta::new(source.key(), false),
+            AccountMeta::new(destination.key(), false),
+            AccountMeta::new(swap_source, false),
+            AccountMeta::new(swap_destination, false),
+            AccountMeta::new(pool_mint, false),
+            AccountMeta::new(fee_account, false),
+            AccountMeta::new_readonly(build_ctx.token_program.key(), false),
+            AccountMeta::new_readonly(oracle_main, false),
+            AccountMeta::new_readonly(oracle_sub, false),
+            AccountMeta::new_readonly(oracle_pc, false),
+        ],
+        data: instruction_data,
+    };
+
+    let accounts = vec![
+        program_account.clone(),
+        authority_account.clone(),
+        amm_account.clone(),
+        build_ctx.user.clone(),
+        user_token_account.clone(),
+        build_ctx.user_quote_account.clone(),
+        token_vault_account.clone(),
+        quote_vault_account.clone(),
+        pool_mint_account.clone(),
+        fee_account_info.clone(),
+        build_ctx.token_program.clone(),
+        oracle_main_account.clone(),
+        oracle_sub_account.clone(),
+        oracle_pc_account.clone(),
+    ];
+
+    Ok((instruction, accounts))
+}
+
+/// Phoenix v1: `Swap` с `OrderPacket::ImmediateOrCancel` по маркету base = token_mint / quote = wSOL.
+/// SELL: num_base_lots = tokens_to_sell, min_quote_lots_to_fill = min_wsol_out (вверх до лота),
+/// BUY: num_quote_lots = max_sol_cost, min_base_lots_to_fill = tokens_to_buy (вверх до лота).
+/// Seat для Swap не нужен (он только у SwapWithFreeFunds / лимитных ордеров).
+fn build_phoenix_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Phoenix {:?} IOC swap...", side);
+
+    let phoenix_program_id = parse_program_id(PHOENIX_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &phoenix_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let market_account = accounts_slice
+        .iter()
+        .find(|acc_info| acc_info.owner == &phoenix_program_id && acc_info.data_len() >= PHOENIX_MARKET_HEADER_LEN)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let (base_mint, base_vault, base_lot_size, quote_mint, quote_vault, quote_lot_size) = {
+        let data = market_account.try_borrow_data()?;
+        (
+            read_pubkey(&data, PHOENIX_MARKET_BASE_MINT_OFFSET)?,
+            read_pubkey(&data, PHOENIX_MARKET_BASE_VAULT_OFFSET)?,
+            read_u64(&data, PHOENIX_MARKET_BASE_LOT_SIZE_OFFSET)?,
+            read_pubkey(&data, PHOENIX_MARKET_QUOTE_MINT_OFFSET)?,
+            read_pubkey(&data, PHOENIX_MARKET_QUOTE_VAULT_OFFSET)?,
+            read_u64(&data, PHOENIX_MARKET_QUOTE_LOT_SIZE_OFFSET)?,
+        )
+    };
+
+    // Маркет должен торговать именно нашим токеном против нашего quote
+    require!(base_mint == arbitrage.token_mint, MyErrorCode::PoolMintMismatch);
+    let user_quote_mint = quote_mint_of(build_ctx)?;
+    require!(quote_mint == user_quote_mint, MyErrorCode::PoolMintMismatch);
+
+    let (log_authority, _) = Pubkey::find_program_address(&[b"log"], &phoenix_program_id);
+    let log_authority_account = find_account(accounts_slice, &log_authority).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let base_vault_account = find_account(accounts_slice, &base_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let quote_vault_account = find_account(accounts_slice, &quote_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_base_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    let instruction_data = phoenix_ioc_swap_data(side, arbitrage, base_lot_size, quote_lot_size)?;
+
+    let instruction = Instruction {
+        program_id: phoenix_program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(phoenix_program_id, false),
+            AccountMeta::new_readonly(log_authority, false),
+            AccountMeta::new(market_account.key(), false),
+            AccountMeta::new_readonly(user_key, true), // trader
+            AccountMeta::new(user_base_account.key(), false),
+            AccountMeta::new(build_ctx.user_quote_account.key(), false),
+            AccountMeta::new(base_vault, false),
+            AccountMeta::new(quote_vault, false),
+            AccountMeta::new_readonly(build_ctx.token_program.key(), false),
+        ],
+        data: instruction_data,
+    };
+
+    let accounts = vec![
+        program_account.clone(),
+        log_authority_account.clone(),
+        market_account.clone(),
+        build_ctx.user.clone(),
+        user_base_account.clone(),
+        build_ctx.user_quote_account.clone(),
+        base_vault_account.clone(),
+        quote_vault_account.clone(),
+        build_ctx.token_program.clone(),
+    ];
+
+    Ok((instruction, accounts))
+}
+
+/// 💺 ClaimSeat ноги, собранный в pre-flight: исполняется циклом прямо перед ее свопом
+struct PhoenixSeatClaim<'info> {
+    seat: AccountInfo<'info>,
+    phoenix_program_id: Pubkey,
+    instruction: Instruction,
+    accounts: Vec<AccountInfo<'info>>,
+}
+
+/// Seat трейдера создан программой Phoenix и в статусе Approved
+fn phoenix_seat_approved(seat_account: &AccountInfo, phoenix_program_id: &Pubkey) -> Result<bool> {
+    Ok(seat_account.owner == phoenix_program_id
+        && read_u64(&seat_account.try_borrow_data()?, PHOENIX_SEAT_APPROVAL_STATUS_OFFSET)
+            .map_or(false, |status| status == PHOENIX_SEAT_STATUS_APPROVED))
+}
+
+/// 💺 ClaimSeat через Phoenix Seat Manager (он сам делает RequestSeat и одобряет seat), если seat
+/// трейдера [b"seat", market, user] еще не создан или не в статусе Approved. Только сборка -
+/// None, если seat уже одобрен; CPI делает `invoke_phoenix_seat_claim` в цикле исполнения.
+fn build_phoenix_seat_claim<'info>(
+    accounts_slice: &[AccountInfo<'info>],
+    build_ctx: &BuildContext<'info>,
+) -> Result<Option<PhoenixSeatClaim<'info>>> {
+    let phoenix_program_id = parse_program_id(PHOENIX_PROGRAM_ID)?;
+    let seat_manager_program_id = parse_program_id(PHOENIX_SEAT_MANAGER_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let phoenix_program_account = find_account(accounts_slice, &phoenix_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let market_account = accounts_slice
+        .iter()
+        .find(|acc_info| acc_info.owner == &phoenix_program_id && acc_info.data_len() >= PHOENIX_MARKET_HEADER_LEN)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let market = market_account.key();
+
+    let (seat, _) = Pubkey::find_program_address(&[b"seat", market.as_ref(), user_key.as_ref()], &phoenix_program_id);
+    let seat_account = find_account(accounts_slice, &seat).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    if phoenix_seat_approved(seat_account, &phoenix_program_id)? {
+        return Ok(None);
+    }
+
+    let (log_authority, _) = Pubkey::find_program_address(&[b"log"], &phoenix_program_id);
+    let log_authority_account = find_account(accounts_slice, &log_authority).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let seat_manager_program_account = find_account(accounts_slice, &seat_manager_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let (seat_manager, _) = Pubkey::find_program_address(&[market.as_ref()], &seat_manager_program_id);
+    let seat_manager_account = find_account(accounts_slice, &seat_manager).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let (deposit_collector, _) =
+        Pubkey::find_program_address(&[market.as_ref(), b"deposit"], &seat_manager_program_id);
+    let deposit_collector_account =
+        find_account(accounts_slice, &deposit_collector).ok_or(MyErrorCode::PDAAccountNotFound)?;
+
+    let instruction = Instruction {
+        program_id: seat_manager_program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(phoenix_program_id, false),
+            AccountMeta::new_readonly(log_authority, false),
+            AccountMeta::new(market, false),
+            AccountMeta::new(seat_manager, false),
+            AccountMeta::new(deposit_collector, false),
+            AccountMeta::new_readonly(user_key, true), // trader
+            AccountMeta::new(user_key, true),          // payer
+            AccountMeta::new(seat, false),
+            AccountMeta::new_readonly(build_ctx.system_program.key(), false),
+        ],
+        data: vec![PHOENIX_SEAT_MANAGER_CLAIM_SEAT_TAG],
+    };
+
+    let accounts = vec![
+        seat_manager_program_account.clone(),
+        phoenix_program_account.clone(),
+        log_authority_account.clone(),
+        market_account.clone(),
+        seat_manager_account.clone(),
+        deposit_collector_account.clone(),
+        build_ctx.user.clone(),
+        seat_account.clone(),
+        build_ctx.system_program.clone(),
+    ];
+
+    Ok(Some(PhoenixSeatClaim {
+        seat: seat_account.clone(),
+        phoenix_program_id,
+        instruction,
+        accounts,
+    }))
+}
+
+/// 💺 CPI ClaimSeat перед свопом ноги. Seat того же маркета могла уже занять предыдущая нога
+/// батча - тогда повторный вызов ничего не делает.
+fn invoke_phoenix_seat_claim(claim: &PhoenixSeatClaim) -> Result<()> {
+    if phoenix_seat_approved(&claim.seat, &claim.phoenix_program_id)? {
+        return Ok(());
+    }
+    msg!("💺 Claiming Phoenix seat {}", claim.seat.key());
+    anchor_lang::solana_program::program::invoke(&claim.instruction, &claim.accounts)?;
+    Ok(())
+}
+
+/// Instruction data Phoenix `Swap` (tag 0) + borsh `OrderPacket::ImmediateOrCancel` (variant 2)
+fn phoenix_ioc_swap_data(
+    side: TradeSide,
+    arbitrage: &ArbitrageParams,
+    base_lot_size: u64,
+    quote_lot_size: u64,
+) -> Result<Vec<u8>> {
+    require!(base_lot_size > 0 && quote_lot_size > 0, MyErrorCode::InvalidAccountData);
+
+    // Минимумы округляем вверх: лоты не должны ослабить порог Go-бота
+    let (side_byte, num_base_lots, num_quote_lots, min_base_lots_to_fill, min_quote_lots_to_fill) = match side {
+        TradeSide::Buy => (
+            0u8, // Side::Bid
+            0,
+            arbitrage.max_sol_cost / quote_lot_size,
+            ceil_div(arbitrage.tokens_to_buy, base_lot_size)?,
+            0,
+        ),
+        TradeSide::Sell => (
+            1u8, // Side::Ask
+            arbitrage.tokens_to_sell / base_lot_size,
+            0,
+            0,
+            ceil_div(arbitrage.min_wsol_out, quote_lot_size)?,
+        ),
+    };
+    require!(num_base_lots > 0 || num_quote_lots > 0, MyErrorCode::ArithmeticError);
+
+    let mut instruction_data = Vec::with_capacity(64);
+    instruction_data.push(0); // PhoenixInstruction::Swap
+    instruction_data.push(2); // OrderPacket::ImmediateOrCancel
+    instruction_data.push(side_byte);
+    instruction_data.push(0); // price_in_ticks: None (рыночный)
+    instruction_data.extend_from_slice(&num_base_lots.to_le_bytes());
+    instruction_data.extend_from_slice(&num_quote_lots.to_le_bytes());
+    instruction_data.extend_from_slice(&min_base_lots_to_fill.to_le_bytes());
+    instruction_data.extend_from_slice(&min_quote_lots_to_fill.to_le_bytes());
+    instruction_data.push(0); // self_trade_behavior: Abort
+    instruction_data.push(0); // match_limit: None
+    instruction_data.extend_from_slice(&0u128.to_le_bytes()); // client_order_id
+    instruction_data.push(0); // use_only_deposited_funds: false
+    instruction_data.push(0); // last_valid_slot: None
+    instruction_data.push(0); // last_valid_unix_timestamp_in_seconds: None
+
+    Ok(instruction_data)
+}
+
+/// Деление с округлением вверх (для перевода порогов в лоты)
+fn ceil_div(amount: u64, lot_size: u64) -> Result<u64> {
+    let rounded = amount.checked_add(lot_size - 1).ok_or(MyErrorCode::ArithmeticError)?;
+    Ok(rounded / lot_size)
+}
+
+/// Raydium CLMM: `swap_v2` (amount, other_amount_threshold, sqrt_price_limit_x64, is_base_input).
+/// BUY: quote -> token (amount = max_sol_cost, threshold = tokens_to_buy),
+/// SELL: token -> quote (amount = tokens_to_sell, threshold = min_wsol_out).
+/// Tick array-и (до трех) и bitmap extension передаются в срезе в порядке обхода цены.
+fn build_raydium_clmm_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Raydium CLMM {:?} swap_v2...", side);
+
+    let clmm_program_id = parse_program_id(RAYDIUM_CLMM_PROGRAM_ID)?;
+    let memo_program_id = parse_program_id(MEMO_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &clmm_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let pool_account = find_program_account(
+        accounts_slice,
+        &clmm_program_id,
+        &RAYDIUM_CLMM_POOL_DISCRIMINATOR,
+        RAYDIUM_CLMM_POOL_OBSERVATION_OFFSET + 32,
+    )
+    .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let (amm_config, mint_0, mint_1, vault_0, vault_1, observation) = {
+        let data = pool_account.try_borrow_data()?;
+        (
+            read_pubkey(&data, RAYDIUM_CLMM_POOL_AMM_CONFIG_OFFSET)?,
+            read_pubkey(&data, RAYDIUM_CLMM_POOL_TOKEN_MINT_0_OFFSET)?,
+            read_pubkey(&data, RAYDIUM_CLMM_POOL_TOKEN_MINT_1_OFFSET)?,
+            read_pubkey(&data, RAYDIUM_CLMM_POOL_TOKEN_VAULT_0_OFFSET)?,
+            read_pubkey(&data, RAYDIUM_CLMM_POOL_TOKEN_VAULT_1_OFFSET)?,
+            read_pubkey(&data, RAYDIUM_CLMM_POOL_OBSERVATION_OFFSET)?,
+        )
+    };
+
+    // Одна из сторон пула - наш токен, другая - quote
+    let (token_vault, quote_vault, quote_mint) = if mint_0 == arbitrage.token_mint {
+        (vault_0, vault_1, mint_1)
+    } else if mint_1 == arbitrage.token_mint {
+        (vault_1, vault_0, mint_0)
+    } else {
+        return Err(MyErrorCode::PoolMintMismatch.into());
+    };
+    let user_quote_mint = quote_mint_of(build_ctx)?;
+    require!(quote_mint == user_quote_mint, MyErrorCode::PoolMintMismatch);
+
+    let amm_config_account = find_account(accounts_slice, &amm_config).ok_or(MyErrorCode::AccountNotFound)?;
+    let observation_account = find_account(accounts_slice, &observation).ok_or(MyErrorCode::AccountNotFound)?;
+    let token_vault_account = find_account(accounts_slice, &token_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let quote_vault_account = find_account(accounts_slice, &quote_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let token_mint_account = find_account(accounts_slice, &arbitrage.token_mint).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let quote_mint_account = find_account(accounts_slice, &quote_mint).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let token_2022_program_account = find_account(accounts_slice, &anchor_spl::token_2022::ID)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    // swap_v2 принимает обе токен-программы фиксированными позициями: в слот token_program всегда
+    // SPL Token (у Token-2022 ноги он берется из среза), программу mint-а CLMM выбирает сам
+    let token_program_account = if build_ctx.token_program.key == &anchor_spl::token::ID {
+        &build_ctx.token_program
+    } else {
+        find_account(accounts_slice, &anchor_spl::token::ID).ok_or(MyErrorCode::AccountNotFound)?
+    };
+    let memo_program_account = find_account(accounts_slice, &memo_program_id).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_token_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    // Bitmap extension (если есть) + tick array-и пула в порядке среза
+    let (bitmap_extension, _) = Pubkey::find_program_address(
+        &[b"pool_tick_array_bitmap_extension", pool_account.key().as_ref()],
+        &clmm_program_id,
+    );
+    let bitmap_extension_account = find_account(accounts_slice, &bitmap_extension);
+    let tick_array_accounts: Vec<&AccountInfo<'info>> = accounts_slice
+        .iter()
+        .filter(|acc_info| {
+            acc_info.owner == &clmm_program_id
+                && acc_info.data_len() >= 8
+                && acc_info.data.borrow()[..8] == RAYDIUM_CLMM_TICK_ARRAY_DISCRIMINATOR[..]
+        })
+        .take(RAYDIUM_CLMM_MAX_TICK_ARRAYS)
+        .collect();
+    require!(!tick_array_accounts.is_empty(), MyErrorCode::AccountNotFound);
+
+    let (amount, other_amount_threshold, input_account, output_account, input_vault, output_vault, input_mint, output_mint) =
+        match side {
+            TradeSide::Buy => (
+                arbitrage.max_sol_cost,
+                arbitrage.tokens_to_buy,
+                &build_ctx.user_quote_account,
+                user_token_account,
+                quote_vault_account,
+                token_vault_account,
+                quote_mint_account,
+                token_mint_account,
+            ),
+            TradeSide::Sell => (
+                arbitrage.tokens_to_sell,
+                arbitrage.min_wsol_out,
+                user_token_account,
+                &build_ctx.user_quote_account,
+                token_vault_account,
+                quote_vault_account,
+                token_mint_account,
+                quote_mint_account,
+            ),
+        };
+
+    let mut instruction_data = Vec::with_capacity(41);
+    instruction_data.extend_from_slice(&[0x2b, 0x04, 0xed, 0x0b, 0x1a, 0xc9, 0x1e, 0x62]); // swap_v2 discriminator
+    instruction_data.extend_from_slice(&amount.to_le_bytes());
+    instruction_data.extend_from_slice(&other_amount_threshold.to_le_bytes());
+    instruction_data.extend_from_slice(&0u128.to_le_bytes()); // sqrt_price_limit_x64: 0 = без лимита
+    instruction_data.push(1); // is_base_input: amount - это вход
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(user_key, true), // payer
+        AccountMeta::new_readonly(amm_config, false),
+        AccountMeta::new(pool_account.key(), false),
+        AccountMeta::new(input_account.key(), false),
+        AccountMeta::new(output_account.key(), false),
+        AccountMeta::new(input_vault.key(), false),
+        AccountMeta::new(output_vault.key(), false),
+        AccountMeta::new(observation, false),
+        AccountMeta::new_readonly(anchor_spl::token::ID, false),
+        AccountMeta::new_readonly(anchor_spl::token_2022::ID, false),
+        AccountMeta::new_readonly(memo_program_id, false),
+        AccountMeta::new_readonly(input_mint.key(), false),
+        AccountMeta::new_readonly(output_mint.key(), false),
+    ];
+    let mut accounts = vec![
+        program_account.clone(),
+        build_ctx.user.clone(),
+        amm_config_account.clone(),
+        pool_account.clone(),
+        input_account.clone(),
+        output_account.clone(),
+        input_vault.clone(),
+        output_vault.clone(),
+        observation_account.clone(),
+        token_program_account.clone(),
+        token_2022_program_account.clone(),
+        memo_program_account.clone(),
+        input_mint.clone(),
+        output_mint.clone(),
+    ];
+
+    if let Some(bitmap_extension_account) = bitmap_extension_account {
+        metas.push(AccountMeta::new_readonly(bitmap_extension, false));
+        accounts.push(bitmap_extension_account.clone());
+    }
+    for tick_array_account in tick_array_accounts {
+        metas.push(AccountMeta::new(tick_array_account.key(), false));
+        accounts.push(tick_array_account.clone());
+    }
+
+    let instruction = Instruction {
+        program_id: clmm_program_id,
+        accounts: metas,
+        data: instruction_data,
+    };
+
+    Ok((instruction, accounts))
+}
+
+/// Orca legacy Token Swap: `Swap` (tag 1, amount_in, minimum_amount_out) по пулу token_mint / wSOL.
+/// BUY: wSOL -> token (amount_in = max_sol_cost, min_out = tokens_to_buy),
+/// SELL: token -> wSOL (amount_in = tokens_to_sell, min_out = min_wsol_out).
+fn build_orca_v1_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Orca Token Swap {:?} swap...", side);
+
+    let orca_program_id = parse_program_id(ORCA_TOKEN_SWAP_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &orca_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    // Discriminator-а нет: swap-аккаунт узнаем по владельцу, размеру и флагу инициализации
+    let swap_account = accounts_slice
+        .iter()
+        .find(|acc_info| {
+            acc_info.owner == &orca_program_id
+                && acc_info.data_len() == ORCA_SWAP_LEN
+                && acc_info.data.borrow()[ORCA_SWAP_IS_INITIALIZED_OFFSET] == 1
+        })
+        .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let (bump_seed, pool_token_program, token_a_account, token_b_account, pool_mint, token_a_mint, token_b_mint, fee_account) = {
+        let data = swap_account.try_borrow_data()?;
+        (
+            data[ORCA_SWAP_BUMP_SEED_OFFSET],
+            read_pubkey(&data, ORCA_SWAP_TOKEN_PROGRAM_OFFSET)?,
+            read_pubkey(&data, ORCA_SWAP_TOKEN_A_OFFSET)?,
+            read_pubkey(&data, ORCA_SWAP_TOKEN_B_OFFSET)?,
+            read_pubkey(&data, ORCA_SWAP_POOL_MINT_OFFSET)?,
+            read_pubkey(&data, ORCA_SWAP_TOKEN_A_MINT_OFFSET)?,
+            read_pubkey(&data, ORCA_SWAP_TOKEN_B_MINT_OFFSET)?,
+            read_pubkey(&data, ORCA_SWAP_POOL_FEE_ACCOUNT_OFFSET)?,
+        )
+    };
+    // Пул проверяет token program сам, но понятная ошибка лучше падения внутри CPI
+    require!(pool_token_program == build_ctx.token_program.key(), MyErrorCode::InvalidProgramId);
+
+    // Одна из сторон пула обязана быть нашим токеном
+    let (token_vault, quote_vault) = if token_a_mint == arbitrage.token_mint {
+        (token_a_account, token_b_account)
+    } else if token_b_mint == arbitrage.token_mint {
+        (token_b_account, token_a_account)
+    } else {
+        return Err(MyErrorCode::PoolMintMismatch.into());
+    };
+
+    // Authority пула: PDA [swap] с bump-ом из состояния пула
+    let authority = Pubkey::create_program_address(&[swap_account.key.as_ref(), &[bump_seed]], &orca_program_id)
+        .map_err(|_| MyErrorCode::PDAAccountNotFound)?;
+    let authority_account = find_account(accounts_slice, &authority).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let token_vault_account = find_account(accounts_slice, &token_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let quote_vault_account = find_account(accounts_slice, &quote_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let pool_mint_account = find_account(accounts_slice, &pool_mint).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let fee_account_info = find_account(accounts_slice, &fee_account).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_token_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    let (source, destination, swap_source, swap_destination, amount_in, minimum_amount_out) = match side {
+        TradeSide::Buy => (
+            &build_ctx.user_quote_account,
+            user_token_account,
+            quote_vault,
+            token_vault,
+            arbitrage.max_sol_cost,
+            arbitrage.tokens_to_buy,
+        ),
+        TradeSide::Sell => (
+            user_token_account,
+            &build_ctx.user_quote_account,
+            token_vault,
+            quote_vault,
+            arbitrage.tokens_to_sell,
+            arbitrage.min_wsol_out,
+        ),
+    };
+
+    let mut instruction_data = Vec::with_capacity(17);
+    instruction_data.push(ORCA_SWAP_INSTRUCTION_TAG);
+    instruction_data.extend_from_slice(&amount_in.to_le_bytes());
+    instruction_data.extend_from_slice(&minimum_amount_out.to_le_bytes());
+
+    let mut instruction = Instruction {
+        program_id: orca_program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(swap_account.key(), false),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new_readonly(user_key, true), // user_transfer_authority
+            AccountMeta::new(source.key(), false),
+            AccountMeta::new(swap_source, false),
+            AccountMeta::new(swap_destination, false),
+            AccountMeta::new(destination.key(), false),
+            AccountMeta::new(pool_mint, false),
+            AccountMeta::new(fee_account, false),
+            AccountMeta::new_readonly(build_ctx.token_program.key(), false),
+        ],
+        data: instruction_data,
+    };
+
+    let mut accounts = vec![
+        program_account.clone(),
+        swap_account.clone(),
+        authority_account.clone(),
+        build_ctx.user.clone(),
+        user_token_account.clone(),
+        build_ctx.user_quote_account.clone(),
+        token_vault_account.clone(),
+        quote_vault_account.clone(),
+        pool_mint_account.clone(),
+        fee_account_info.clone(),
+        build_ctx.token_program.clone(),
+    ];
+
+    // 🤝 Host fee аккаунт (опциональный последний аккаунт Swap): LP-токены пула, часть комиссии - нам
+    if let Some(referral_account) = arbitrage.referral_account {
+        let referral_info = find_account(accounts_slice, &referral_account).ok_or(MyErrorCode::AccountNotFound)?;
+        let referral = InterfaceTokenAccount::try_deserialize(&mut referral_info.data.borrow().as_ref())
+            .map_err(|_| MyErrorCode::InvalidTokenAccount)?;
+        require!(referral.mint == pool_mint, MyErrorCode::InvalidTokenAccount);
+        instruction.accounts.push(AccountMeta::new(referral_account, false));
+        accounts.push(referral_info.clone());
+    }
+
+    Ok((instruction, accounts))
+}
+
+/// Saber StableSwap: `Swap` (tag 1, amount_in, minimum_amount_out) по пулу token_mint / quote.
+/// BUY: quote -> token (amount_in = max_sol_cost, min_out = tokens_to_buy),
+/// SELL: token -> quote (amount_in = tokens_to_sell, min_out = min_wsol_out).
+/// Admin fee берется с выходной стороны, поэтому нужен admin fee аккаунт выходного токена.
+fn build_stable_swap_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating StableSwap {:?} swap...", side);
+
+    let stable_swap_program_id = parse_program_id(STABLE_SWAP_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &stable_swap_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    // Discriminator-а нет: SwapInfo узнаем по владельцу, размеру и флагу инициализации
+    let swap_info = accounts_slice
+        .iter()
+        .find(|acc_info| {
+            acc_info.owner == &stable_swap_program_id
+                && acc_info.data_len() == STABLE_SWAP_INFO_LEN
+                && acc_info.data.borrow()[STABLE_SWAP_IS_INITIALIZED_OFFSET] == 1
+        })
+        .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let (is_paused, nonce, target_amp, token_a_reserves, token_a_mint, token_a_admin_fees, token_b_reserves, token_b_mint, token_b_admin_fees) = {
+        let data = swap_info.try_borrow_data()?;
+        (
+            data[STABLE_SWAP_IS_PAUSED_OFFSET] != 0,
+            data[STABLE_SWAP_NONCE_OFFSET],
+            read_u64(&data, STABLE_SWAP_TARGET_AMP_OFFSET)?,
+            read_pubkey(&data, STABLE_SWAP_TOKEN_A_RESERVES_OFFSET)?,
+            read_pubkey(&data, STABLE_SWAP_TOKEN_A_MINT_OFFSET)?,
+            read_pubkey(&data, STABLE_SWAP_TOKEN_A_ADMIN_FEES_OFFSET)?,
+            read_pubkey(&data, STABLE_SWAP_TOKEN_B_RESERVES_OFFSET)?,
+            read_pubkey(&data, STABLE_SWAP_TOKEN_B_MINT_OFFSET)?,
+            read_pubkey(&data, STABLE_SWAP_TOKEN_B_ADMIN_FEES_OFFSET)?,
+        )
+    };
+    require!(!is_paused, MyErrorCode::InvalidAccountData);
+
+    // Обе стороны пула сверяем с ногой: token_mint и mint quote-аккаунта
+    let quote_mint = quote_mint_of(build_ctx)?;
+    let (token_reserves, token_admin_fees, quote_reserves, quote_admin_fees) =
+        if token_a_mint == arbitrage.token_mint && token_b_mint == quote_mint {
+            (token_a_reserves, token_a_admin_fees, token_b_reserves, token_b_admin_fees)
+        } else if token_b_mint == arbitrage.token_mint && token_a_mint == quote_mint {
+            (token_b_reserves, token_b_admin_fees, token_a_reserves, token_a_admin_fees)
+        } else {
+            return Err(MyErrorCode::PoolMintMismatch.into());
+        };
+
+    // Authority пула: PDA [swap_info] с nonce из состояния пула
+    let authority = Pubkey::create_program_address(&[swap_info.key.as_ref(), &[nonce]], &stable_swap_program_id)
+        .map_err(|_| MyErrorCode::PDAAccountNotFound)?;
+    let authority_account = find_account(accounts_slice, &authority).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let token_reserves_account = find_account(accounts_slice, &token_reserves).ok_or(MyErrorCode::AccountNotFound)?;
+    let quote_reserves_account = find_account(accounts_slice, &quote_reserves).ok_or(MyErrorCode::AccountNotFound)?;
+    let clock_account = find_account(accounts_slice, &anchor_lang::solana_program::sysvar::clock::ID)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let user_token_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    let (source, destination, swap_source, swap_destination, admin_fees, amount_in, minimum_amount_out) = match side {
+        TradeSide::Buy => (
+            &build_ctx.user_quote_account,
+            user_token_account,
+            quote_reserves_account,
+            token_reserves_account,
+            token_admin_fees,
+            arbitrage.max_sol_cost,
+            arbitrage.tokens_to_buy,
+        ),
+        TradeSide::Sell => (
+            user_token_account,
+            &build_ctx.user_quote_account,
+            token_reserves_account,
+            quote_reserves_account,
+            quote_admin_fees,
+            arbitrage.tokens_to_sell,
+            arbitrage.min_wsol_out,
+        ),
+    };
+    let admin_fees_account = find_account(accounts_slice, &admin_fees).ok_or(MyErrorCode::AccountNotFound)?;
+
+    msg!("📈 StableSwap amp factor {}", target_amp);
+
+    let mut instruction_data = Vec::with_capacity(17);
+    instruction_data.push(STABLE_SWAP_INSTRUCTION_TAG);
+    instruction_data.extend_from_slice(&amount_in.to_le_bytes());
+    instruction_data.extend_from_slice(&minimum_amount_out.to_le_bytes());
+
+    let instruction = Instruction {
+        program_id: stable_swap_program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(swap_info.key(), false),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new_readonly(user_key, true), // user_authority
+            AccountMeta::new_readonly(clock_account.key(), false),
+            AccountMeta::new(source.key(), false),
+            AccountMeta::new(swap_source.key(), false),
+            AccountMeta::new(swap_destination.key(), false),
+            AccountMeta::new(destination.key(), false),
+            AccountMeta::new(admin_fees, false),
+            AccountMeta::new_readonly(build_ctx.token_program.key(), false),
+        ],
+        data: instruction_data,
+    };
+
+    let accounts = vec![
+        program_account.clone(),
+        swap_info.clone(),
+        authority_account.clone(),
+        build_ctx.user.clone(),
+        clock_account.clone(),
+        user_token_account.clone(),
+        build_ctx.user_quote_account.clone(),
+        token_reserves_account.clone(),
+        quote_reserves_account.clone(),
+        admin_fees_account.clone(),
+        build_ctx.token_program.clone(),
+    ];
+
+    Ok((instruction, accounts))
+}
+
+/// Invariant CLMM: `swap` (x_to_y, amount, by_amount_in, sqrt_price_limit). Min-out у swap-а нет -
+/// проскальзывание ограничивает исполнение по входу; SELL сверяется с min_wsol_out после CPI.
+/// BUY: quote -> token (amount = max_sol_cost), SELL: token -> quote (amount = tokens_to_sell).
+/// Tick-аккаунты пересекаемых tick-ов передаются в срезе в порядке обхода цены.
+fn build_invariant_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Invariant {:?} swap...", side);
+
+    let invariant_program_id = parse_program_id(INVARIANT_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &invariant_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let pool_account = find_program_account(
+        accounts_slice,
+        &invariant_program_id,
+        &INVARIANT_POOL_DISCRIMINATOR,
+        INVARIANT_POOL_TICKMAP_OFFSET + 32,
+    )
+    .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let (token_x, token_y, reserve_x, reserve_y, tickmap) = {
+        let data = pool_account.try_borrow_data()?;
+        (
+            read_pubkey(&data, INVARIANT_POOL_TOKEN_X_OFFSET)?,
+            read_pubkey(&data, INVARIANT_POOL_TOKEN_Y_OFFSET)?,
+            read_pubkey(&data, INVARIANT_POOL_RESERVE_X_OFFSET)?,
+            read_pubkey(&data, INVARIANT_POOL_RESERVE_Y_OFFSET)?,
+            read_pubkey(&data, INVARIANT_POOL_TICKMAP_OFFSET)?,
+        )
+    };
+
+    // Обе стороны пула сверяем с ногой: token_mint и mint quote-аккаунта
+    let quote_mint = quote_mint_of(build_ctx)?;
+    let token_is_x = if token_x == arbitrage.token_mint && token_y == quote_mint {
+        true
+    } else if token_y == arbitrage.token_mint && token_x == quote_mint {
+        false
+    } else {
+        return Err(MyErrorCode::PoolMintMismatch.into());
+    };
+
+    let (state, _) = Pubkey::find_program_address(&[b"statev1"], &invariant_program_id);
+    let (program_authority, _) = Pubkey::find_program_address(&[b"Invariant"], &invariant_program_id);
+    let state_account = find_account(accounts_slice, &state).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let program_authority_account =
+        find_account(accounts_slice, &program_authority).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let tickmap_account = find_account(accounts_slice, &tickmap).ok_or(MyErrorCode::AccountNotFound)?;
+    let token_x_account = find_account(accounts_slice, &token_x).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let token_y_account = find_account(accounts_slice, &token_y).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let reserve_x_account = find_account(accounts_slice, &reserve_x).ok_or(MyErrorCode::AccountNotFound)?;
+    let reserve_y_account = find_account(accounts_slice, &reserve_y).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_token_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    // Токен-программа каждой стороны - владелец ее mint-а (quote обычно классический SPL Token)
+    let token_program_of = |mint_account: &AccountInfo<'info>| -> Result<AccountInfo<'info>> {
+        if mint_account.owner == build_ctx.token_program.key {
+            Ok(build_ctx.token_program.clone())
+        } else {
+            find_account(accounts_slice, mint_account.owner)
+                .filter(|acc_info| is_token_program(acc_info.key))
+                .cloned()
+                .ok_or(MyErrorCode::AccountNotFound.into())
+        }
+    };
+    let token_x_program = token_program_of(token_x_account)?;
+    let token_y_program = token_program_of(token_y_account)?;
+
+    let (account_x, account_y) = if token_is_x {
+        (user_token_account, &build_ctx.user_quote_account)
+    } else {
+        (&build_ctx.user_quote_account, user_token_account)
+    };
+
+    // BUY отдает quote, SELL - токен; цена x/y падает при x -> y
+    let (x_to_y, amount) = match side {
+        TradeSide::Buy => (!token_is_x, arbitrage.max_sol_cost),
+        TradeSide::Sell => (token_is_x, arbitrage.tokens_to_sell),
+    };
+    let sqrt_price_limit = if x_to_y { INVARIANT_MIN_SQRT_PRICE } else { INVARIANT_MAX_SQRT_PRICE };
+
+    // Tick-и пула в порядке среза
+    let tick_accounts: Vec<&AccountInfo<'info>> = accounts_slice
+        .iter()
+        .filter(|acc_info| {
+            acc_info.owner == &invariant_program_id
+                && acc_info.data_len() >= 8
+                && acc_info.data.borrow()[..8] == INVARIANT_TICK_DISCRIMINATOR[..]
+        })
+        .take(INVARIANT_MAX_TICKS)
+        .collect();
+
+    let mut instruction_data = Vec::with_capacity(34);
+    instruction_data.extend_from_slice(&[0xf8, 0xc6, 0x9e, 0x91, 0xe1, 0x75, 0x87, 0xc8]); // swap discriminator
+    instruction_data.push(x_to_y as u8);
+    instruction_data.extend_from_slice(&amount.to_le_bytes());
+    instruction_data.push(1); // by_amount_in: amount - это вход
+    instruction_data.extend_from_slice(&sqrt_price_limit.to_le_bytes());
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(state, false),
+        AccountMeta::new(pool_account.key(), false),
+        AccountMeta::new(tickmap, false),
+        AccountMeta::new_readonly(token_x, false),
+        AccountMeta::new_readonly(token_y, false),
+        AccountMeta::new(account_x.key(), false),
+        AccountMeta::new(account_y.key(), false),
+        AccountMeta::new(reserve_x, false),
+        AccountMeta::new(reserve_y, false),
+        AccountMeta::new_readonly(user_key, true), // owner
+        AccountMeta::new_readonly(program_authority, false),
+        AccountMeta::new_readonly(token_x_program.key(), false),
+        AccountMeta::new_readonly(token_y_program.key(), false),
+    ];
+    let mut accounts = vec![
+        program_account.clone(),
+        state_account.clone(),
+        pool_account.clone(),
+        tickmap_account.clone(),
+        token_x_account.clone(),
+        token_y_account.clone(),
+        account_x.clone(),
+        account_y.clone(),
+        reserve_x_account.clone(),
+        reserve_y_account.clone(),
+        build_ctx.user.clone(),
+        program_authority_account.clone(),
+        token_x_program,
+        token_y_program,
+    ];
+
+    for tick_account in tick_accounts {
+        metas.push(AccountMeta::new(tick_account.key(), false));
+        accounts.push(tick_account.clone());
+    }
+
+    let instruction = Instruction {
+        program_id: invariant_program_id,
+        accounts: metas,
+        data: instruction_data,
+    };
+
+    Ok((instruction, accounts))
+}
+
+/// Crema Finance CLMM: `swap` (amount, amount_specified_is_input, a_to_b, sqrt_price_limit).
+/// Как у Invariant, min-out у swap-а нет - SELL сверяется с min_wsol_out после CPI.
+/// BUY: quote -> token (amount = max_sol_cost), SELL: token -> quote (amount = tokens_to_sell).
+/// Tick array-и пересекаемых диапазонов - в срезе в порядке обхода цены; referral_account ноги -
+/// partner (swap_with_partner, комиссия partner-а на его ATA обоих токенов пула).
+fn build_crema_instruction<'info>(
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Crema {:?} swap...", side);
+
+    let crema_program_id = parse_program_id(CREMA_PROGRAM_ID)?;
+    let user_key = build_ctx.user.key();
+
+    let program_account = find_account(accounts_slice, &crema_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    let pool_account = find_program_account(
+        accounts_slice,
+        &crema_program_id,
+        &CREMA_POOL_DISCRIMINATOR,
+        CREMA_POOL_TOKEN_B_VAULT_OFFSET + 32,
+    )
+    .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let (clmm_config, token_a, token_b, token_a_vault, token_b_vault) = {
+        let data = pool_account.try_borrow_data()?;
+        (
+            read_pubkey(&data, CREMA_POOL_CLMM_CONFIG_OFFSET)?,
+            read_pubkey(&data, CREMA_POOL_TOKEN_A_OFFSET)?,
+            read_pubkey(&data, CREMA_POOL_TOKEN_B_OFFSET)?,
+            read_pubkey(&data, CREMA_POOL_TOKEN_A_VAULT_OFFSET)?,
+            read_pubkey(&data, CREMA_POOL_TOKEN_B_VAULT_OFFSET)?,
+        )
+    };
+
+    // Обе стороны пула сверяем с ногой: token_mint и mint quote-аккаунта
+    let quote_mint = quote_mint_of(build_ctx)?;
+    let token_is_a = if token_a == arbitrage.token_mint && token_b == quote_mint {
+        true
+    } else if token_b == arbitrage.token_mint && token_a == quote_mint {
+        false
+    } else {
+        return Err(MyErrorCode::PoolMintMismatch.into());
+    };
+
+    let pool_key = pool_account.key();
+    let (tick_array_map, _) = Pubkey::find_program_address(&[b"tick_array_map", pool_key.as_ref()], &crema_program_id);
+    let clmm_config_account = find_account(accounts_slice, &clmm_config).ok_or(MyErrorCode::AccountNotFound)?;
+    let tick_array_map_account =
+        find_account(accounts_slice, &tick_array_map).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let token_a_account = find_account(accounts_slice, &token_a).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let token_b_account = find_account(accounts_slice, &token_b).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let token_a_vault_account = find_account(accounts_slice, &token_a_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let token_b_vault_account = find_account(accounts_slice, &token_b_vault).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_token_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    // Токен-программа каждой стороны - владелец ее mint-а (quote обычно классический SPL Token)
+    let token_program_of = |mint_account: &AccountInfo<'info>| -> Result<AccountInfo<'info>> {
+        if mint_account.owner == build_ctx.token_program.key {
+            Ok(build_ctx.token_program.clone())
+        } else {
+            find_account(accounts_slice, mint_account.owner)
+                .filter(|acc_info| is_token_program(acc_info.key))
+                .cloned()
+                .ok_or(MyErrorCode::AccountNotFound.into())
+        }
+    };
+    let token_a_program = token_program_of(token_a_account)?;
+    let token_b_program = token_program_of(token_b_account)?;
+
+    let (account_a, account_b) = if token_is_a {
+        (user_token_account, &build_ctx.user_quote_account)
+    } else {
+        (&build_ctx.user_quote_account, user_token_account)
+    };
+
+    // BUY отдает quote, SELL - токен; цена a/b падает при a -> b
+    let (a_to_b, amount) = match side {
+        TradeSide::Buy => (!token_is_a, arbitrage.max_sol_cost),
+        TradeSide::Sell => (token_is_a, arbitrage.tokens_to_sell),
+    };
+    let sqrt_price_limit = if a_to_b { CREMA_MIN_SQRT_PRICE } else { CREMA_MAX_SQRT_PRICE };
+
+    // Tick array-и пула в порядке среза (несколько - swap пересекает несколько диапазонов)
+    let tick_arrays: Vec<&AccountInfo<'info>> = accounts_slice
+        .iter()
+        .filter(|acc_info| {
+            acc_info.owner == &crema_program_id
+                && acc_info.data_len() >= 8
+                && acc_info.data.borrow()[..8] == CREMA_TICK_ARRAY_DISCRIMINATOR[..]
+        })
+        .take(CREMA_MAX_TICK_ARRAYS)
+        .collect();
+    require!(!tick_arrays.is_empty(), MyErrorCode::AccountNotFound);
+
+    let discriminator = if arbitrage.referral_account.is_some() {
+        CREMA_SWAP_WITH_PARTNER_DISCRIMINATOR
+    } else {
+        CREMA_SWAP_DISCRIMINATOR
+    };
+    let mut instruction_data = Vec::with_capacity(34);
+    instruction_data.extend_from_slice(&discriminator);
+    instruction_data.extend_from_slice(&amount.to_le_bytes());
+    instruction_data.push(1); // amount_specified_is_input: amount - это вход
+    instruction_data.push(a_to_b as u8);
+    instruction_data.extend_from_slice(&sqrt_price_limit.to_le_bytes());
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(clmm_config, false),
+        AccountMeta::new(pool_key, false),
+        AccountMeta::new_readonly(token_a, false),
+        AccountMeta::new_readonly(token_b, false),
+        AccountMeta::new(account_a.key(), false),
+        AccountMeta::new(account_b.key(), false),
+        AccountMeta::new(token_a_vault, false),
+        AccountMeta::new(token_b_vault, false),
+        AccountMeta::new(tick_array_map, false),
+        AccountMeta::new_readonly(user_key, true), // owner
+    ];
+    let mut accounts = vec![
+        program_account.clone(),
+        clmm_config_account.clone(),
+        pool_account.clone(),
+        token_a_account.clone(),
+        token_b_account.clone(),
+        account_a.clone(),
+        account_b.clone(),
+        token_a_vault_account.clone(),
+        token_b_vault_account.clone(),
+        tick_array_map_account.clone(),
+        build_ctx.user.clone(),
+    ];
+
+    // 🤝 Partner: сам аккаунт и его ATA обоих токенов пула
+    if let Some(partner) = arbitrage.referral_account {
+        let partner_account = find_account(accounts_slice, &partner).ok_or(MyErrorCode::AccountNotFound)?;
+        let partner_ata_a = get_associated_token_address_with_program_id(&partner, &token_a, &token_a_program.key());
+        let partner_ata_b = get_associated_token_address_with_program_id(&partner, &token_b, &token_b_program.key());
+        let partner_ata_a_account = find_account(accounts_slice, &partner_ata_a).ok_or(MyErrorCode::TokenAccountNotFound)?;
+        let partner_ata_b_account = find_account(accounts_slice, &partner_ata_b).ok_or(MyErrorCode::TokenAccountNotFound)?;
+        metas.push(AccountMeta::new_readonly(partner, false));
+        metas.push(AccountMeta::new(partner_ata_a, false));
+        metas.push(AccountMeta::new(partner_ata_b, false));
+        accounts.push(partner_account.clone());
+        accounts.push(partner_ata_a_account.clone());
+        accounts.push(partner_ata_b_account.clone());
+    }
+
+    metas.push(AccountMeta::new_readonly(token_a_program.key(), false));
+    metas.push(AccountMeta::new_readonly(token_b_program.key(), false));
+    accounts.push(token_a_program);
+    accounts.push(token_b_program);
+
+    for tick_array in tick_arrays {
+        metas.push(AccountMeta::new(tick_array.key(), false));
+        accounts.push(tick_array.clone());
+    }
+
+    let instruction = Instruction {
+        program_id: crema_program_id,
+        accounts: metas,
+        data: instruction_data,
+    };
+
+    Ok((instruction, accounts))
+}
+
+/// Whirlpool и клоны с тем же layout-ом: `swap` (amount, other_amount_threshold, sqrt_price_limit,
+/// amount_specified_is_input, a_to_b). program_id ноги - и цель CPI, и программа PDA (oracle);
+/// пул и tick array-и должны принадлежать ей же. Min-out контролирует сам DEX через threshold.
+/// BUY: quote -> token (max_sol_cost -> не меньше tokens_to_buy), SELL: token -> quote.
+/// Program id от бота делает ногу CPI в произвольную программу, поэтому она доступна только при `allow_raw`.
+fn build_whirlpool_like_instruction<'info>(
+    whirlpool_program_id: &Pubkey,
+    side: TradeSide,
+    accounts_slice: &[AccountInfo<'info>],
+    arbitrage: &ArbitrageParams,
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    msg!("🔧 Creating Whirlpool-like {:?} swap on {}...", side, whirlpool_program_id);
+
+    require!(build_ctx.allow_raw, MyErrorCode::RawDexDisabled);
+    let user_key = build_ctx.user.key();
+
+    // Program id приходит от бота: подпись user-а не должна уйти в роутер или токен-программу
+    require!(
+        *whirlpool_program_id != crate::ID && !is_token_program(whirlpool_program_id),
+        MyErrorCode::InvalidProgramId
+    );
+    let program_account = find_account(accounts_slice, whirlpool_program_id)
+        .ok_or(MyErrorCode::AccountNotFound)?;
+    require!(program_account.executable, MyErrorCode::InvalidProgramId);
+
+    // Пул - аккаунт программы с discriminator-ом Whirlpool (форк сохраняет имя аккаунта, а с ним и
+    // discriminator); чужой аккаунт подходящей длины (config, position) под разбор не попадет
+    let whirlpool_account = find_program_account(
+        accounts_slice,
+        whirlpool_program_id,
+        &WHIRLPOOL_DISCRIMINATOR,
+        WHIRLPOOL_TOKEN_VAULT_B_OFFSET + 32,
+    )
+    .ok_or(MyErrorCode::AccountNotFound)?;
+
+    let (token_mint_a, token_vault_a, token_mint_b, token_vault_b) = {
+        let data = whirlpool_account.try_borrow_data()?;
+        (
+            read_pubkey(&data, WHIRLPOOL_TOKEN_MINT_A_OFFSET)?,
+            read_pubkey(&data, WHIRLPOOL_TOKEN_VAULT_A_OFFSET)?,
+            read_pubkey(&data, WHIRLPOOL_TOKEN_MINT_B_OFFSET)?,
+            read_pubkey(&data, WHIRLPOOL_TOKEN_VAULT_B_OFFSET)?,
+        )
+    };
+
+    // Обе стороны пула сверяем с ногой: token_mint и mint quote-аккаунта
+    let quote_mint = quote_mint_of(build_ctx)?;
+    let token_is_a = if token_mint_a == arbitrage.token_mint && token_mint_b == quote_mint {
+        true
+    } else if token_mint_b == arbitrage.token_mint && token_mint_a == quote_mint {
+        false
+    } else {
+        return Err(MyErrorCode::PoolMintMismatch.into());
+    };
+
+    let whirlpool = whirlpool_account.key();
+    let (oracle, _) = Pubkey::find_program_address(&[b"oracle", whirlpool.as_ref()], whirlpool_program_id);
+    let oracle_account = find_account(accounts_slice, &oracle).ok_or(MyErrorCode::PDAAccountNotFound)?;
+    let token_vault_a_account = find_account(accounts_slice, &token_vault_a).ok_or(MyErrorCode::AccountNotFound)?;
+    let token_vault_b_account = find_account(accounts_slice, &token_vault_b).ok_or(MyErrorCode::AccountNotFound)?;
+    let user_token_account = find_leg_token_account(accounts_slice, arbitrage, build_ctx)?;
+
+    let (owner_account_a, owner_account_b) = if token_is_a {
+        (user_token_account, &build_ctx.user_quote_account)
+    } else {
+        (&build_ctx.user_quote_account, user_token_account)
+    };
+
+    // BUY отдает quote, SELL - токен; цена a/b падает при a -> b
+    let (a_to_b, amount, other_amount_threshold) = match side {
+        TradeSide::Buy => (!token_is_a, arbitrage.max_sol_cost, arbitrage.tokens_to_buy),
+        TradeSide::Sell => (token_is_a, arbitrage.tokens_to_sell, arbitrage.min_wsol_out),
+    };
+    let sqrt_price_limit = if a_to_b { WHIRLPOOL_MIN_SQRT_PRICE } else { WHIRLPOOL_MAX_SQRT_PRICE };
+
+    // Tick array-и пула в порядке обхода цены; swap всегда принимает три - недостающие повторяем
+    let mut tick_arrays: Vec<&AccountInfo<'info>> = accounts_slice
+        .iter()
+        .filter(|acc_info| {
+            acc_info.owner == whirlpool_program_id
+                && acc_info.data_len() >= 8
+                && acc_info.data.borrow()[..8] == WHIRLPOOL_TICK_ARRAY_DISCRIMINATOR[..]
+        })
+        .take(WHIRLPOOL_TICK_ARRAYS)
+        .collect();
+    let last_tick_array = *tick_arrays.last().ok_or(MyErrorCode::AccountNotFound)?;
+    tick_arrays.resize(WHIRLPOOL_TICK_ARRAYS, last_tick_array);
+
+    let mut instruction_data = Vec::with_capacity(42);
+    instruction_data.extend_from_slice(&[0xf8, 0xc6, 0x9e, 0x91, 0xe1, 0x75, 0x87, 0xc8]); // swap discriminator
+    instruction_data.extend_from_slice(&amount.to_le_bytes());
+    instruction_data.extend_from_slice(&other_amount_threshold.to_le_bytes());
+    instruction_data.extend_from_slice(&sqrt_price_limit.to_le_bytes());
+    instruction_data.push(1); // amount_specified_is_input: amount - это вход
+    instruction_data.push(a_to_b as u8);
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(build_ctx.token_program.key(), false),
+        AccountMeta::new_readonly(user_key, true), // token_authority
+        AccountMeta::new(whirlpool, false),
+        AccountMeta::new(owner_account_a.key(), false),
+        AccountMeta::new(token_vault_a, false),
+        AccountMeta::new(owner_account_b.key(), false),
+        AccountMeta::new(token_vault_b, false),
+    ];
+    let mut accounts = vec![
+        program_account.clone(),
+        build_ctx.token_program.clone(),
+        build_ctx.user.clone(),
+        whirlpool_account.clone(),
+        owner_account_a.clone(),
+        token_vault_a_account.clone(),
+        owner_account_b.clone(),
+        token_vault_b_account.clone(),
+    ];
+    for tick_array in tick_arrays {
+        metas.push(AccountMeta::new(tick_array.key(), false));
+        accounts.push(tick_array.clone());
+    }
+    metas.push(AccountMeta::new_readonly(oracle, false));
+    accounts.push(oracle_account.clone());
+
+    let instruction = Instruction {
+        program_id: *whirlpool_program_id,
+        accounts: metas,
+        data: instruction_data,
+    };
+
+    Ok((instruction, accounts))
+}
+
+/// 🧩 Raw CPI: program_id и data целиком от Go-бота, metas = весь срез ноги по порядку.
+/// Роутер не проверяет семантику инструкции, поэтому доступно только при `allow_raw`.
+fn build_raw_instruction<'info>(
+    program_id: &Pubkey,
+    data: &[u8],
+    is_writable: &[bool],
+    accounts_slice: &[AccountInfo<'info>],
+    build_ctx: &BuildContext<'info>,
+) -> Result<(Instruction, Vec<AccountInfo<'info>>)> {
+    require!(build_ctx.allow_raw, MyErrorCode::RawDexDisabled);
+    require!(is_writable.len() == accounts_slice.len(), MyErrorCode::InvalidRawLeg);
+    require!(*program_id != ID, MyErrorCode::InvalidRawLeg);
+
+    // Программа должна быть в срезе, иначе CPI не найдет ее AccountInfo
+    find_account(accounts_slice, program_id).ok_or(MyErrorCode::AccountNotFound)?;
+
+    msg!("🧩 Raw CPI to {} ({} accounts, {} bytes)", program_id, accounts_slice.len(), data.len());
+
+    let metas = accounts_slice
+        .iter()
+        .zip(is_writable.iter())
+        .map(|(acc_info, &writable)| AccountMeta {
+            pubkey: acc_info.key(),
+            is_signer: acc_info.is_signer,
+            is_writable: writable,
+        })
+        .collect();
+
+    let instruction = Instruction {
+        program_id: *program_id,
+        accounts: metas,
+        data: data.to_vec(),
+    };
+
+    Ok((instruction, accounts_slice.to_vec()))
+}
+
+// ============================================================================
+// 📐 CU-БЮДЖЕТ (подстраиваемые константы для compute_estimate)
+// ============================================================================
+
+/// Максимум CU на транзакцию (set_compute_unit_limit выше не поднять)
+const MAX_TRANSACTION_CU: u32 = 1_400_000;
+/// Фиксированная стоимость батча: проверки, wrap, логи
+const BATCH_BASE_CU: u32 = 15_000;
+/// Скан одного аккаунта среза (включая derive PDA для Pump.fun)
+const ACCOUNT_SCAN_CU: u32 = 4_500;
+/// Один CPI в Pump.fun buy/sell
+const PUMP_FUN_SWAP_CU: u32 = 40_000;
+/// Один CPI в OpenBook v2 place_take_order
+const OPENBOOK_V2_SWAP_CU: u32 = 70_000;
+/// Один CPI в Lifinity v2 swap (включая чтение Pyth)
+const LIFINITY_SWAP_CU: u32 = 55_000;
+/// Один CPI в Phoenix swap
+const PHOENIX_SWAP_CU: u32 = 60_000;
+/// Один CPI в Raydium CLMM swap_v2 (до трех tick array-ев)
+const RAYDIUM_CLMM_SWAP_CU: u32 = 90_000;
+/// Один CPI в Orca legacy Token Swap
+const ORCA_V1_SWAP_CU: u32 = 35_000;
+/// Один CPI в StableSwap (итеративный расчет инварианта)
+const STABLE_SWAP_CU: u32 = 45_000;
+/// Один CPI в Invariant swap (без пересечения tick-ов)
+const INVARIANT_SWAP_CU: u32 = 80_000;
+/// Один CPI в Crema swap (до одного tick array-я)
+const CREMA_SWAP_CU: u32 = 85_000;
+/// Один CPI в Whirlpool swap (три tick array-я)
+const WHIRLPOOL_SWAP_CU: u32 = 80_000;
+/// Один raw CPI (семантика неизвестна - берем с запасом)
+const RAW_CPI_CU: u32 = 80_000;
+/// Sentinel для DEX-ов без билдера (исполнить такую ногу нельзя)
+pub const UNSUPPORTED_DEX_CU: u32 = 0;
+
+/// Стоимость одного CPI в DEX - единственное место для перекалибровки
+pub const fn dex_cu_cost(dex: &DexType) -> u32 {
+    match dex {
+        DexType::PumpFun => PUMP_FUN_SWAP_CU,
+        DexType::OpenBookV2 => OPENBOOK_V2_SWAP_CU,
+        DexType::Lifinity => LIFINITY_SWAP_CU,
+        DexType::Phoenix => PHOENIX_SWAP_CU,
+        DexType::RaydiumClmm => RAYDIUM_CLMM_SWAP_CU,
+        DexType::OrcaV1 => ORCA_V1_SWAP_CU,
+        DexType::StableSwap => STABLE_SWAP_CU,
+        DexType::Invariant => INVARIANT_SWAP_CU,
+        DexType::Crema => CREMA_SWAP_CU,
+        DexType::WhirlpoolLike { .. } => WHIRLPOOL_SWAP_CU,
+        DexType::Raw { .. } => RAW_CPI_CU,
+        DexType::Meteora => UNSUPPORTED_DEX_CU,
+    }
+}
+
+/// Доплата за каждый tick array Raydium CLMM / Crema / tick Invariant сверх заложенных в стоимость swap-а
+const TICK_ARRAY_CROSSING_CU: u32 = 15_000;
+
+/// Оценка CU одной ноги: скан среза + CPI каждого DEX-а (по таблице dex_cu_cost).
+/// Аккаунты сверх min_accounts у CLMM-ноги - дополнительные tick array-и / tick-и, каждый со своим пересечением
+fn leg_cu_estimate(arbitrage: &ArbitrageParams) -> Result<u32> {
+    let mut dexes = vec![&arbitrage.buy_dex, &arbitrage.sell_dex];
+    if let Some(bridge) = &arbitrage.bridge {
+        dexes.push(&bridge.dex);
+    }
+
+    let mut leg_cu = (arbitrage.accounts_count as u32) * ACCOUNT_SCAN_CU;
+    for dex in dexes.iter() {
+        let dex_cu = dex_cu_cost(dex);
+        require!(dex_cu != UNSUPPORTED_DEX_CU, MyErrorCode::NotImplemented);
+        leg_cu += dex_cu;
+    }
+
+    let has_clmm = dexes.iter().any(|dex| {
+        matches!(dex, DexType::RaydiumClmm | DexType::Invariant | DexType::Crema | DexType::WhirlpoolLike { .. })
+    });
+    if has_clmm {
+        let mut distinct_dexes: Vec<&DexType> = Vec::with_capacity(dexes.len());
+        for dex in dexes {
+            if !distinct_dexes.contains(&dex) {
+                distinct_dexes.push(dex);
+            }
+        }
+        let base_accounts: u32 = distinct_dexes.iter().map(|dex| min_accounts(dex) as u32).sum();
+        let extra_tick_arrays = (arbitrage.accounts_count as u32).saturating_sub(base_accounts);
+        leg_cu += extra_tick_arrays * TICK_ARRAY_CROSSING_CU;
+    }
+
+    Ok(leg_cu)
+}
+
+/// Остаток CU транзакции (syscall sol_remaining_compute_units): на нем держатся reserved_cu и cu_cap,
+/// поэтому он есть в любой сборке
+fn remaining_compute_units() -> u64 {
+    anchor_lang::solana_program::compute_units::sol_remaining_compute_units()
+}
+
+/// Номер варианта DexType в порядке объявления (бит в supported_dex_mask, ключ в dex_discriminators)
+pub const fn dex_index(dex: &DexType) -> u8 {
+    match dex {
+        DexType::Meteora => 0,
+        DexType::PumpFun => 1,
+        DexType::OpenBookV2 => 2,
+        DexType::Lifinity => 3,
+        DexType::Raw { .. } => 4,
+        DexType::Phoenix => 5,
+        DexType::RaydiumClmm => 6,
+        DexType::OrcaV1 => 7,
+        DexType::StableSwap => 8,
+        DexType::Invariant => 9,
+        DexType::Crema => 10,
+        DexType::WhirlpoolLike { .. } => 11,
+    }
+}
+
+/// DEX-ы, чьи инструкции начинаются с 8-байтного Anchor discriminator-а, переопределяемого
+/// через dex_discriminators (у Phoenix, Orca и StableSwap - однобайтный tag, у Raw data целиком
+/// от бота). Pump.fun сюда не входит: его discriminator-ы - только set_pump_discriminators.
+/// WhirlpoolLike тоже: запись dex_discriminators ключуется dex_index и задела бы все клоны сразу
+pub const fn has_anchor_discriminator(dex: &DexType) -> bool {
+    matches!(
+        dex,
+        DexType::Meteora
+            | DexType::OpenBookV2
+            | DexType::Lifinity
+            | DexType::RaydiumClmm
+            | DexType::Invariant
+            | DexType::Crema
+    )
+}
+
+/// Битовая маска исполнимых DexType: бит i = вариант с Borsh-индексом i.
+/// Поддержка выводится из dex_cu_cost (единственный match с sentinel-ом для DEX-ов без билдера);
+/// Raw и WhirlpoolLike (CPI в program id от бота) исполнимы только при allow_raw.
+pub fn supported_dex_mask(allow_raw: bool) -> u32 {
+    // Все варианты в порядке объявления DexType - новый вариант добавляется и сюда
+    let variants = [
+        DexType::Meteora,
+        DexType::PumpFun,
+        DexType::OpenBookV2,
+        DexType::Lifinity,
+        DexType::Raw { program_id: Pubkey::default(), data: Vec::new(), is_writable: Vec::new() },
+        DexType::Phoenix,
+        DexType::RaydiumClmm,
+        DexType::OrcaV1,
+        DexType::StableSwap,
+        DexType::Invariant,
+        DexType::Crema,
+        DexType::WhirlpoolLike { program_id: Pubkey::default() },
+    ];
+
+    variants.iter().enumerate().fold(0u32, |mask, (bit, dex)| {
+        let executable = dex_cu_cost(dex) != UNSUPPORTED_DEX_CU
+            && (allow_raw || !matches!(dex, DexType::Raw { .. } | DexType::WhirlpoolLike { .. }));
+        if executable { mask | (1 << bit) } else { mask }
+    })
+}
+
+// ============================================================================
+// 🛡️ ПРОВЕРКИ ПАРАМЕТРОВ
+// ============================================================================
+
+/// max_sol_cost <= amount_in * (1 + cap) и min_wsol_out >= amount_in * (1 - cap)
+fn check_slippage_cap(arbitrage: &ArbitrageParams, max_slippage_bps: u16) -> Result<()> {
+    if max_slippage_bps == 0 {
+        return Ok(());
+    }
+
+    let amount_in = arbitrage.amount_in as u128;
+    let bps = max_slippage_bps as u128;
+    let max_cost = amount_in * (10_000 + bps) / 10_000;
+    let min_out = amount_in * 10_000u128.saturating_sub(bps) / 10_000;
+
+    require!(arbitrage.max_sol_cost as u128 <= max_cost, MyErrorCode::SlippageExceedsCap);
+    require!(arbitrage.min_wsol_out as u128 >= min_out, MyErrorCode::SlippageExceedsCap);
+    Ok(())
+}
+
+/// min_wsol_out - relief_bps, но не ниже amount_in: нога может уйти в ноль, но не в убыток
+/// сверх заложенного Go-ботом (если сам min_wsol_out ниже amount_in - он не меняется)
+fn apply_slippage_relief(min_wsol_out: u64, amount_in: u64, relief_bps: u16) -> u64 {
+    if relief_bps == 0 {
+        return min_wsol_out;
+    }
+    let relief = (min_wsol_out as u128 * relief_bps as u128 / 10_000) as u64;
+    min_wsol_out.saturating_sub(relief).max(min_wsol_out.min(amount_in))
+}
+
+/// Kamino Lending program
+const KAMINO_LENDING_PROGRAM_ID: &str = "KLend2g3cP87fffoy8q1mQqGKjrxjC8boSyAYavgmjD";
+/// Anchor discriminator-ы `flash_borrow_reserve_liquidity` / `flash_repay_reserve_liquidity`
+const KAMINO_FLASH_BORROW_DISCRIMINATOR: [u8; 8] = [0x87, 0xe7, 0x34, 0xa7, 0x07, 0x34, 0xd4, 0xc1];
+const KAMINO_FLASH_REPAY_DISCRIMINATOR: [u8; 8] = [0xb9, 0x75, 0x00, 0xcb, 0x60, 0xf5, 0xb4, 0xba];
+
+// Позиции аккаунтов в flash borrow / repay Kamino (у обеих инструкций одинаковые)
+const KAMINO_FLASH_USER_AUTHORITY_INDEX: usize = 0;
+const KAMINO_FLASH_RESERVE_INDEX: usize = 3;
+const KAMINO_FLASH_USER_LIQUIDITY_INDEX: usize = 6;
+
+/// 🏦 Flash loan батча. Kamino (как MarginFi и Solend) отказывает во flash-заеме через CPI -
+/// borrow и repay обязаны быть top-level инструкциями. Поэтому роутер не вызывает их сам, а
+/// сверяет по sysvar Instructions: непосредственно перед батчем - borrow amount из reserve на
+/// user_wsol_account, непосредственно после - repay того же amount со ссылкой на этот borrow.
+/// Откат батча откатывает и заем; прибыль батча считается за вычетом комиссии займа.
+fn verify_flash_loan(
+    flash_loan: &FlashLoanParams,
+    instructions_sysvar: &AccountInfo,
+    user: &Pubkey,
+    user_wsol_account: &Pubkey,
+) -> Result<()> {
+    use anchor_lang::solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked};
+
+    require!(
+        flash_loan.lending_program == parse_program_id(KAMINO_LENDING_PROGRAM_ID)?,
+        MyErrorCode::InvalidProgramId
+    );
+    require!(flash_loan.amount > 0, MyErrorCode::InvalidConfigValue);
+
+    let current_index = load_current_index_checked(instructions_sysvar)? as usize;
+    let borrow_index = current_index.checked_sub(1).ok_or(MyErrorCode::FlashLoanMismatch)?;
+    let borrow = load_instruction_at_checked(borrow_index, instructions_sysvar)
+        .map_err(|_| MyErrorCode::FlashLoanMismatch)?;
+    let repay = load_instruction_at_checked(current_index + 1, instructions_sysvar)
+        .map_err(|_| MyErrorCode::FlashLoanMismatch)?;
+
+    // Заем и возврат: та же программа и reserve, наш user и наш quote-аккаунт, та же сумма
+    let matches_loan = |instruction: &Instruction, discriminator: &[u8; 8]| -> bool {
+        let account_is = |index: usize, key: &Pubkey| instruction.accounts.get(index).map_or(false, |meta| meta.pubkey == *key);
+        instruction.program_id == flash_loan.lending_program
+            && instruction.data.get(..8) == Some(&discriminator[..])
+            && instruction.data.get(8..16) == Some(&flash_loan.amount.to_le_bytes()[..])
+            && account_is(KAMINO_FLASH_USER_AUTHORITY_INDEX, user)
+            && account_is(KAMINO_FLASH_RESERVE_INDEX, &flash_loan.reserve)
+            && account_is(KAMINO_FLASH_USER_LIQUIDITY_INDEX, user_wsol_account)
+    };
+    require!(matches_loan(&borrow, &KAMINO_FLASH_BORROW_DISCRIMINATOR), MyErrorCode::FlashLoanMismatch);
+    require!(matches_loan(&repay, &KAMINO_FLASH_REPAY_DISCRIMINATOR), MyErrorCode::FlashLoanMismatch);
+    // repay ссылается на индекс своего borrow
+    require!(repay.data.get(16) == Some(&(borrow_index as u8)), MyErrorCode::FlashLoanMismatch);
+    Ok(())
+}
+
+/// Pyth v2 (push) oracle program
+const PYTH_ORACLE_PROGRAM_ID: &str = "FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH";
+/// Switchboard v2 program
+const SWITCHBOARD_V2_PROGRAM_ID: &str = "SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f";
+
+// Pyth PriceAccount: magic, expo и агрегированная цена
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_EXPO_OFFSET: usize = 20;
+const PYTH_AGG_PRICE_OFFSET: usize = 208;
+const PYTH_AGG_STATUS_OFFSET: usize = 224;
+const PYTH_AGG_PUB_SLOT_OFFSET: usize = 232;
+const PYTH_STATUS_TRADING: u32 = 1;
+
+// Switchboard AggregatorAccountData (packed, включая 8 байт discriminator):
+// latest_confirmed_round.result = SwitchboardDecimal { mantissa: i128, scale: u32 }
+const SWITCHBOARD_ROUND_OPEN_SLOT_OFFSET: usize = 350;
+const SWITCHBOARD_RESULT_MANTISSA_OFFSET: usize = 366;
+const SWITCHBOARD_RESULT_SCALE_OFFSET: usize = 382;
+
+/// Цена SOL/USD для отчета старше этого числа слотов (~1 минута) считается устаревшей
+const USDC_ORACLE_MAX_STALE_SLOTS: u64 = 150;
+/// Десятичность USDC
+const USDC_DECIMALS: i32 = 6;
+
+/// Цена для min_profit старше этого числа слотов (~10 секунд) откатывает батч
+const PROFIT_ORACLE_MAX_STALE_SLOTS: u64 = 25;
+/// Десятичность общей единицы min_profit (цена оракула profit_mint при 9 знаках)
+const PROFIT_COMMON_DECIMALS: i32 = 9;
+
+/// wrap_amount = AUTO_WRAP_AMOUNT: обернуть недостающее до wSOL-потребности окна
+pub const AUTO_WRAP_AMOUNT: u64 = u64::MAX;
+
+/// reason_code встроенных ProgramError: BUILTIN_ERROR_CODE_BASE + номер (InvalidArgument = 2, ...)
+pub const BUILTIN_ERROR_CODE_BASE: u32 = 0xFFFF_0000;
+
+/// Проходов по срезу ноги на разбор аккаунтов по умолчанию (max_scan_passes = 0)
+pub const DEFAULT_SCAN_PASSES: u8 = 4;
+
+/// Запас нативных SOL user-а сверх BUY-ов: rent создаваемых ATA и комиссии DEX-ов в SOL
+const USER_LAMPORTS_RESERVE: u64 = 10_000_000;
+
+/// Десятичность quote (wSOL)
+const QUOTE_DECIMALS: u32 = 9;
+/// Фиксированная точка для сравнения цен
+const PRICE_PRECISION_DECIMALS: i32 = 12;
+
+/// 🔮 Цена исполнения BUY (max_sol_cost / tokens_to_buy в целых единицах) не должна отклоняться
+/// от оракула больше max_oracle_deviation_bps. Оракул прайсит token_mint в quote.
+fn check_oracle_deviation(arbitrage: &ArbitrageParams, accounts_slice: &[AccountInfo]) -> Result<()> {
+    let oracle = match arbitrage.oracle {
+        Some(oracle) => oracle,
+        None => return Ok(()),
+    };
+    let oracle_account = find_account(accounts_slice, &oracle).ok_or(MyErrorCode::AccountNotFound)?;
+    let (mantissa, exponent) = read_oracle_price(oracle_account)?;
+    require!(mantissa > 0, MyErrorCode::InvalidAccountData);
+
+    let mint_account = find_account(accounts_slice, &arbitrage.token_mint).ok_or(MyErrorCode::MintAccountNotFound)?;
+    let token_decimals = mint_decimals(mint_account)? as u32;
+
+    // implied = max_sol_cost * 10^token_decimals * 10^P / (tokens_to_buy * 10^QUOTE_DECIMALS)
+    let implied_numerator = 10u128
+        .checked_pow(token_decimals + PRICE_PRECISION_DECIMALS as u32)
+        .and_then(|scale| (arbitrage.max_sol_cost as u128).checked_mul(scale))
+        .ok_or(MyErrorCode::ArithmeticError)?;
+    let implied_denominator = (arbitrage.tokens_to_buy as u128)
+        .checked_mul(10u128.pow(QUOTE_DECIMALS))
+        .filter(|denominator| *denominator > 0)
+        .ok_or(MyErrorCode::ArithmeticError)?;
+    let implied_price = implied_numerator / implied_denominator;
+
+    // oracle = mantissa * 10^exponent в той же фиксированной точке
+    let mantissa = mantissa as u128;
+    let shift = exponent + PRICE_PRECISION_DECIMALS;
+    let oracle_price = if shift >= 0 {
+        10u128
+            .checked_pow(shift as u32)
+            .and_then(|scale| mantissa.checked_mul(scale))
+            .ok_or(MyErrorCode::ArithmeticError)?
+    } else {
+        mantissa / 10u128.checked_pow(shift.unsigned_abs()).ok_or(MyErrorCode::ArithmeticError)?
+    };
+    require!(oracle_price > 0, MyErrorCode::InvalidAccountData);
+
+    let deviation = implied_price.abs_diff(oracle_price);
+    let max_deviation = oracle_price
+        .checked_mul(arbitrage.max_oracle_deviation_bps as u128)
+        .ok_or(MyErrorCode::ArithmeticError)?
+        / 10_000;
+    require!(deviation <= max_deviation, MyErrorCode::OracleDeviation);
+    Ok(())
+}
+
+/// Цена оракула как (mantissa, exponent): price = mantissa * 10^exponent. Формат по владельцу аккаунта.
+fn read_oracle_price(oracle_account: &AccountInfo) -> Result<(i128, i32)> {
+    let data = oracle_account.try_borrow_data()?;
+
+    if oracle_account.owner == &parse_program_id(PYTH_ORACLE_PROGRAM_ID)? {
+        require!(read_u32(&data, 0)? == PYTH_MAGIC, MyErrorCode::InvalidAccountData);
+        // Цена вне статуса Trading (halted / auction) не годится для сравнения
+        require!(
+            read_u32(&data, PYTH_AGG_STATUS_OFFSET)? == PYTH_STATUS_TRADING,
+            MyErrorCode::InvalidAccountData
+        );
+        let exponent = read_u32(&data, PYTH_EXPO_OFFSET)? as i32;
+        let price = read_u64(&data, PYTH_AGG_PRICE_OFFSET)? as i64;
+        return Ok((price as i128, exponent));
+    }
+
+    if oracle_account.owner == &parse_program_id(SWITCHBOARD_V2_PROGRAM_ID)? {
+        let mantissa_bytes: [u8; 16] = data
+            .get(SWITCHBOARD_RESULT_MANTISSA_OFFSET..SWITCHBOARD_RESULT_MANTISSA_OFFSET + 16)
+            .and_then(|slice| slice.try_into().ok())
+            .ok_or(MyErrorCode::InvalidAccountData)?;
+        let scale = read_u32(&data, SWITCHBOARD_RESULT_SCALE_OFFSET)?;
+        return Ok((i128::from_le_bytes(mantissa_bytes), -(scale as i32)));
+    }
+
+    Err(MyErrorCode::InvalidProgramId.into())
+}
+
+/// Слот последнего обновления цены оракула (Pyth agg.pub_slot / Switchboard round_open_slot)
+fn read_oracle_slot(oracle_account: &AccountInfo) -> Result<u64> {
+    let data = oracle_account.try_borrow_data()?;
+    if oracle_account.owner == &parse_program_id(PYTH_ORACLE_PROGRAM_ID)? {
+        return read_u64(&data, PYTH_AGG_PUB_SLOT_OFFSET);
+    }
+    if oracle_account.owner == &parse_program_id(SWITCHBOARD_V2_PROGRAM_ID)? {
+        return read_u64(&data, SWITCHBOARD_ROUND_OPEN_SLOT_OFFSET);
+    }
+    Err(MyErrorCode::InvalidProgramId.into())
+}
+
+/// 💵 profit (lamports quote) в USDC по цене SOL/USD: profit * mantissa * 10^(exponent + 6 - 9).
+/// None - оракул не читается, цена не положительная или старше USDC_ORACLE_MAX_STALE_SLOTS.
+fn profit_in_usdc(profit: u64, oracle_account: &AccountInfo, current_slot: u64) -> Option<u64> {
+    let (mantissa, exponent) = read_oracle_price(oracle_account).ok()?;
+    let published_slot = read_oracle_slot(oracle_account).ok()?;
+    if mantissa <= 0 || current_slot.saturating_sub(published_slot) > USDC_ORACLE_MAX_STALE_SLOTS {
+        return None;
+    }
+
+    let value = (profit as u128).checked_mul(mantissa as u128)?;
+    let shift = exponent + USDC_DECIMALS - QUOTE_DECIMALS as i32;
+    let usdc = if shift >= 0 {
+        value.checked_mul(10u128.checked_pow(shift as u32)?)?
+    } else {
+        value / 10u128.checked_pow(shift.unsigned_abs())?
+    };
+    u64::try_from(usdc).ok()
+}
+
+/// 🎯 profit (единицы profit_mint) в общей единице min_profit:
+/// profit * mantissa * 10^(exponent + PROFIT_COMMON_DECIMALS - profit_decimals).
+/// В отличие от отчетного profit_in_usdc здесь решается судьба батча - устаревшая цена откатывает.
+fn normalize_profit(profit: u64, oracle_account: &AccountInfo, profit_decimals: u8, current_slot: u64) -> Result<u64> {
+    let (mantissa, exponent) = read_oracle_price(oracle_account)?;
+    require!(mantissa > 0, MyErrorCode::InvalidAccountData);
+    let published_slot = read_oracle_slot(oracle_account)?;
+    require!(
+        current_slot.saturating_sub(published_slot) <= PROFIT_ORACLE_MAX_STALE_SLOTS,
+        MyErrorCode::StaleOraclePrice
+    );
+
+    let value = (profit as u128)
+        .checked_mul(mantissa as u128)
+        .ok_or(MyErrorCode::ArithmeticError)?;
+    let shift = exponent + PROFIT_COMMON_DECIMALS - profit_decimals as i32;
+    let normalized = if shift >= 0 {
+        10u128
+            .checked_pow(shift as u32)
+            .and_then(|scale| value.checked_mul(scale))
+            .ok_or(MyErrorCode::ArithmeticError)?
+    } else {
+        value / 10u128.checked_pow(shift.unsigned_abs()).ok_or(MyErrorCode::ArithmeticError)?
+    };
+    u64::try_from(normalized).map_err(|_| MyErrorCode::ArithmeticError.into())
+}
+
+/// min_out за вычетом transfer fee quote-mint-а (Token-2022 TransferFeeConfig) за текущую эпоху.
+/// Без mint-а или без расширения - min_out как есть.
+fn min_out_after_transfer_fee(min_out: u64, quote_mint: Option<&AccountInfo>, epoch: u64) -> Result<u64> {
+    let mint_info = match quote_mint {
+        Some(mint_info) => mint_info,
+        None => return Ok(min_out),
+    };
+    let data = mint_info.try_borrow_data()?;
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    let transfer_fee = match mint.get_extension::<TransferFeeConfig>() {
+        Ok(config) => config
+            .calculate_epoch_fee(epoch, min_out)
+            .ok_or(MyErrorCode::ArithmeticError)?,
+        Err(_) => 0,
+    };
+    Ok(min_out.saturating_sub(transfer_fee))
+}
+
+/// Доля протокола от прибыли: profit * profit_fee_bps / 10000 (0 при нулевой прибыли или ставке)
+fn calculate_profit_fee(profit: u64, profit_fee_bps: u16) -> u64 {
+    (profit as u128 * profit_fee_bps as u128 / 10_000) as u64
+}
+
+// ============================================================================
+// 🔍 ХЕЛПЕРЫ ПОИСКА И ЧТЕНИЯ АККАУНТОВ
+// ============================================================================
+
+/// AccountType::Account в Token-2022 (байт сразу за базовым layout-ом токен-аккаунта)
+const TOKEN_2022_ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+/// Первый аккаунт среза с заданным ключом
+fn find_account<'a, 'info>(accounts_slice: &'a [AccountInfo<'info>], key: &Pubkey) -> Option<&'a AccountInfo<'info>> {
+    accounts_slice.iter().find(|acc_info| acc_info.key == key)
+}
+
+/// ⛽ Бюджет сравнений аккаунтов на разбор ноги: accounts_count * max_scan_passes.
+/// Исчерпан - ошибка, а не продолжение скана: худший CU разбора ограничен детерминированно
+/// (layout-резолвер по позициям тратит один проход)
+struct ScanBudget {
+    remaining: usize,
+}
+
+impl ScanBudget {
+    fn new(accounts_count: usize, scan_passes: usize) -> Self {
+        Self {
+            remaining: accounts_count.saturating_mul(scan_passes),
+        }
+    }
+
+    /// Списать `comparisons` сравнений
+    fn charge(&mut self, comparisons: usize) -> Result<()> {
+        self.remaining = self
+            .remaining
+            .checked_sub(comparisons)
+            .ok_or(MyErrorCode::ScanBudgetExceeded)?;
+        Ok(())
+    }
+
+    /// find_account со списанием каждого сравнения (до совпадения или всего среза)
+    fn find<'a, 'info>(&mut self, accounts_slice: &'a [AccountInfo<'info>], key: &Pubkey) -> Result<Option<&'a AccountInfo<'info>>> {
+        for acc_info in accounts_slice {
+            self.charge(1)?;
+            if acc_info.key == key {
+                return Ok(Some(acc_info));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Первый аккаунт среза, принадлежащий программе и несущий заданный Anchor discriminator
+fn find_program_account<'a, 'info>(
+    accounts_slice: &'a [AccountInfo<'info>],
+    program_id: &Pubkey,
+    discriminator: &[u8; 8],
+    min_len: usize,
+) -> Option<&'a AccountInfo<'info>> {
+    accounts_slice.iter().find(|acc_info| {
+        acc_info.owner == program_id
+            && acc_info.data_len() >= min_len.max(8)
+            && acc_info.data.borrow()[..8] == discriminator[..]
+    })
+}
+
+/// Token account пользователя для заданного mint (SPL Token или Token-2022 с расширениями)
+fn find_user_token_account<'a, 'info>(
+    accounts_slice: &'a [AccountInfo<'info>],
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<&'a AccountInfo<'info>> {
+    // Берем первое совпадение; второй ДРУГОЙ аккаунт того же mint - ошибка бота, а не выбор роутера
+    let mut first: Option<&'a AccountInfo<'info>> = None;
+    for acc_info in accounts_slice {
+        let Some(token_account) = parse_token_account(acc_info)? else {
+            continue;
+        };
+        if token_account.owner != *owner || token_account.mint != *mint {
+            continue;
+        }
+        match first {
+            None => first = Some(acc_info),
+            Some(found) => require!(found.key == acc_info.key, MyErrorCode::InvalidTokenAccount),
+        }
+    }
+    first.ok_or(MyErrorCode::TokenAccountNotFound.into())
+}
+
+/// SPL Token или Token-2022 - единственные токен-программы, которым роутер передает подпись user-а
+fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == anchor_spl::token::ID || *program_id == anchor_spl::token_2022::ID
+}
+
+/// Аккаунт еще не создан: system-owned и без данных
+fn is_uninitialized_account(acc_info: &AccountInfo) -> bool {
+    acc_info.owner == &system_program::ID && acc_info.data_is_empty()
+}
+
+/// Layout token account-а: классический SPL Token - ровно TokenAccount::LEN, Token-2022 - еще и
+/// с расширениями (байт AccountType сразу за базой). Mint-ы и multisig-и сюда не попадают
+fn has_token_account_layout(acc_info: &AccountInfo) -> bool {
+    let data_len = acc_info.data_len();
+    if acc_info.owner == &anchor_spl::token::ID {
+        return data_len == TokenAccount::LEN;
+    }
+    acc_info.owner == &anchor_spl::token_2022::ID
+        && (data_len == TokenAccount::LEN
+            || (data_len > TokenAccount::LEN
+                && acc_info.data.borrow()[TokenAccount::LEN] == TOKEN_2022_ACCOUNT_TYPE_ACCOUNT))
+}
+
+/// Token account из среза: None - не token account (чужой владелец, mint, multisig);
+/// InvalidTokenAccount - layout token account-а, но данные не разбираются (а не молчаливый пропуск)
+fn parse_token_account(acc_info: &AccountInfo) -> Result<Option<InterfaceTokenAccount>> {
+    if !has_token_account_layout(acc_info) {
+        return Ok(None);
+    }
+    InterfaceTokenAccount::try_deserialize(&mut acc_info.data.borrow().as_ref())
+        .map(Some)
+        .map_err(|_| MyErrorCode::InvalidTokenAccount.into())
+}
+
+/// Mint quote-аккаунта user-а
+fn quote_mint_of(build_ctx: &BuildContext) -> Result<Pubkey> {
+    let quote_account = InterfaceTokenAccount::try_deserialize(&mut build_ctx.user_quote_account.data.borrow().as_ref())
+        .map_err(|_| MyErrorCode::InvalidTokenAccount)?;
+    Ok(quote_account.mint)
+}
+
+/// Decimals mint-а (SPL Token или Token-2022)
+fn mint_decimals(mint_account: &AccountInfo) -> Result<u8> {
+    let mint = InterfaceMint::try_deserialize(&mut mint_account.data.borrow().as_ref())
+        .map_err(|_| MyErrorCode::InvalidAccountData)?;
+    Ok(mint.decimals)
+}
+
+/// Адрес программы из константы: опечатка в константе - InvalidProgramId, а не panic без кода
+fn parse_program_id(address: &str) -> Result<Pubkey> {
+    Pubkey::from_str(address).map_err(|_| MyErrorCode::InvalidProgramId.into())
+}
+
+/// Текущий баланс token account (актуален и после CPI)
+fn token_account_amount(acc_info: &AccountInfo) -> Result<u64> {
+    let token_account = InterfaceTokenAccount::try_deserialize(&mut acc_info.data.borrow().as_ref())
+        .map_err(|_| MyErrorCode::InvalidTokenAccount)?;
+    Ok(token_account.amount)
+}
+
+/// Накопленная прибыль + P&L батча, зажатая в [0, u64::MAX]
+fn accumulate_profit(cumulative_profit: u64, batch_profit: i128) -> u64 {
+    (cumulative_profit as i128 + batch_profit).clamp(0, u64::MAX as i128) as u64
+}
+
+/// Discriminator из RouterState; нули (не задан / после migrate_router) -> compiled default
+fn configured_or_default(configured: [u8; 8], default: [u8; 8]) -> [u8; 8] {
+    if configured == [0u8; 8] {
+        default
+    } else {
+        configured
+    }
+}
+
+/// Amount out из return data только что вызванной программы: первые 8 байт как u64 LE.
+/// Данные чужой программы (например, от предыдущего CPI) не подхватываем - сверяем program_id;
+/// None - программа ничего не вернула (большинство DEX-ов) или вернула не u64.
+fn returned_amount(program_id: &Pubkey) -> Option<u64> {
+    let (returning_program, data) = anchor_lang::solana_program::program::get_return_data()?;
+    if returning_program != *program_id {
+        return None;
+    }
+    data.get(..8)?.try_into().ok().map(u64::from_le_bytes)
+}
+
+/// 🐞 Отладочный return data (feature "failure-debug"): пишется ДО шага, который может упасть.
+/// Упавший CPI обрывает транзакцию целиком - управление к нам не вернется, и писать на пути
+/// ошибки бессмысленно. Runtime отдает последний записанный return data транзакции и при
+/// ошибке (returnData в simulateTransaction и в meta), поэтому переживает ровно последняя
+/// запись: наша, если после нее никто не писал. DEX, вернувший свой return data
+/// (programId != роутер), ее затирает. Успешный батч очищает запись в конце.
+#[allow(unused_variables)]
+fn set_failure_debug(index: usize, stage: u8, accounts_slice: &[AccountInfo]) {
+    #[cfg(feature = "failure-debug")]
+    {
+        let payload = LegFailureDebug {
+            index: index as u8,
+            stage,
+            accounts: accounts_slice
+                .iter()
+                .take(MAX_FAILURE_DEBUG_ACCOUNTS)
+                .map(|account| account.key())
+                .collect(),
+        };
+        if let Ok(data) = payload.try_to_vec() {
+            anchor_lang::solana_program::program::set_return_data(&data);
+        }
+    }
+}
+
+/// Очистка отладочного return data после успешного батча (feature "failure-debug")
+fn clear_failure_debug() {
+    #[cfg(feature = "failure-debug")]
+    anchor_lang::solana_program::program::set_return_data(&[]);
+}
+
+/// Числовой код ошибки для событий (для MyErrorCode: 6000 + индекс варианта).
+/// Custom(n) - сам n; встроенные ProgramError (в u64 это n << 32, младшие 32 бита нулевые) -
+/// BUILTIN_ERROR_CODE_BASE + n, чтобы не схлопываться в 0
+fn error_code_of(error: &anchor_lang::error::Error) -> u32 {
+    match error {
+        anchor_lang::error::Error::AnchorError(anchor_error) => anchor_error.error_code_number,
+        anchor_lang::error::Error::ProgramError(program_error) => {
+            let code = u64::from(program_error.program_error.clone());
+            match u32::try_from(code) {
+                Ok(custom_code) => custom_code,
+                Err(_) => BUILTIN_ERROR_CODE_BASE.saturating_add((code >> 32) as u32),
+            }
+        },
+    }
+}
+
+/// Читает Pubkey из сырых данных аккаунта по смещению
+fn read_pubkey(data: &[u8], offset: usize) -> Result<Pubkey> {
+    let bytes: [u8; 32] = data
+        .get(offset..offset + 32)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(MyErrorCode::InvalidAccountData)?;
+    Ok(Pubkey::new_from_array(bytes))
+}
+
+/// Читает little-endian u32 из сырых данных аккаунта по смещению
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes: [u8; 4] = data
+        .get(offset..offset + 4)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(MyErrorCode::InvalidAccountData)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Читает little-endian u64 из сырых данных аккаунта по смещению
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes: [u8; 8] = data
+        .get(offset..offset + 8)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(MyErrorCode::InvalidAccountData)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// ============================================================================
+// 📊 СТРУКТУРЫ ДАННЫХ
+// ============================================================================
+
+/// Текущая версия layout-а RouterState. Новые поля добавляются ТОЛЬКО в конец структуры
+/// (migrate_router дорастит старый аккаунт и заполнит их нулями) с инкрементом версии.
+pub const ROUTER_STATE_VERSION: u8 = 28;
+
+/// Потолок accounts_count на ногу по умолчанию (с запасом для OpenBook/Lifinity + оракулы)
+pub const DEFAULT_MAX_ACCOUNTS_PER_LEG: u8 = 24;
+
+/// Потолок denylist-а mint-ов (размер фиксированного массива denied_mints в RouterState)
+pub const MAX_DENIED_MINTS: usize = 16;
+
+/// Потолок mint-ов в create_user_atas (каждый create_idempotent - отдельный CPI)
+pub const MAX_CREATE_ATAS: usize = 8;
+
+/// Этапы LegFailureDebug: подготовка ноги (pre-flight) и ее исполнение (CPI свопов)
+pub const FAILURE_STAGE_PREFLIGHT: u8 = 0;
+pub const FAILURE_STAGE_EXECUTION: u8 = 1;
+
+/// Ключей в LegFailureDebug: return data ограничен 1024 байтами (1 + 1 + 4 + 31 * 32 = 998)
+pub const MAX_FAILURE_DEBUG_ACCOUNTS: usize = 31;
+
+/// Размер таблицы переопределений discriminator-ов в RouterState
+pub const MAX_DISCRIMINATOR_OVERRIDES: usize = 8;
+
+/// Сколько последних batch_tag помнит роутер (кольцевой буфер в RouterState)
+pub const BATCH_TAG_HISTORY: usize = 8;
+
+/// Размер аккаунта v1 (discriminator + owner + is_paused + bump), до появления version
+const ROUTER_STATE_V1_LEN: usize = 8 + 32 + 1 + 1;
+
+/// Смещение denied_mints в аккаунте (discriminator + поля v1..v16 фиксированного размера)
+const ROUTER_STATE_DENIED_MINTS_OFFSET: usize =
+    8 + 1 + 32 + 1 + 1 + 8 + 2 + 2 + 32 + 8 + 8 + 1 + 8 + 8 + 1 + 1 + 8 + 8 + 8 + 32 + 1 + 1 + 32 + 32 + 1 + 1 + 1 + 32;
+
+/// Состояние роутера (хранится on-chain)
+#[account]
+#[derive(Default)]
+pub struct RouterState {
+    pub version: u8,        // Версия layout-а (первый байт после discriminator)
+    pub owner: Pubkey,      // Владелец для emergency operations
+    pub is_paused: bool,    // Флаг паузы (emergency stop)
+    pub bump: u8,          // Bump для PDA
+    pub last_nonce: u64,   // Последний исполненный nonce батча (защита от повторов)
+    pub max_slippage_bps: u16, // Глобальный cap slippage на ногу (0 = выключен)
+    pub profit_fee_bps: u16, // Доля протокола от прибыли батча (0 = без комиссии)
+    pub treasury: Pubkey,  // Владелец wSOL аккаунта, куда уходит комиссия
+    pub pump_buy_discriminator: [u8; 8],  // v3: discriminator Pump.fun buy (нули = дефолт)
+    pub pump_sell_discriminator: [u8; 8], // v3: discriminator Pump.fun sell (нули = дефолт)
+    pub allow_raw: bool,   // v4: разрешены ли DexType::Raw ноги (по умолчанию нет)
+    pub min_slots_between_batches: u64, // v5: rate limit в слотах (0 = выключен)
+    pub last_batch_slot: u64, // v5: слот последнего успешного батча
+    pub max_accounts_per_leg: u8, // v6: потолок accounts_count на ногу (0 = дефолт)
+    pub enforce_distinct_venues: bool, // v7: отклонять buy_dex == sell_dex без allow_same_venue
+    pub total_batches: u64, // v8: число успешных батчей
+    pub cumulative_profit: u64, // v8: накопленная прибыль в quote (до protocol fee)
+    pub suggested_cu_price_microlamports: u64, // v9: рекомендованный priority fee для ботов (не enforce-ится)
+    pub fee_recipient: Pubkey, // v10: fee recipient Pump.fun по умолчанию (default = PUMP_FEE_RECIPIENT)
+    pub is_shutdown: bool, // v11: необратимый kill-switch
+    pub strict_leg_accounts: bool, // v12: проверять срезы ног на чужие mint / bonding curve
+    pub pending_owner: Pubkey, // v13: предложенный owner до accept_ownership (default = нет)
+    pub quote_mint: Pubkey, // v14: mint quote-аккаунта прибыли (default = канонический wSOL)
+    pub quote_has_transfer_fee: bool, // v15: quote mint - Token-2022 с TransferFeeConfig
+    pub consecutive_failures: u8, // v16: провалы батчей подряд (record_failure)
+    pub failure_threshold: u8, // v16: авто-пауза при достижении (0 = выключено)
+    pub failure_reporter: Pubkey, // v16: кто кроме owner может вызывать record_failure
+    pub denied_mints: [Pubkey; MAX_DENIED_MINTS], // v17: compliance denylist token_mint (v28: массив вместо Vec)
+    pub denied_count: u8, // v28: занятые слоты denied_mints (остальные - нули)
+    pub recent_batch_tags: [[u8; 32]; BATCH_TAG_HISTORY], // v18: последние batch_tag (защита от повторов)
+    pub batch_tag_cursor: u8, // v18: следующая позиция записи в recent_batch_tags
+    pub auto_slippage_relief_bps: u16, // v19: допуск к min_wsol_out ноги (0 = выключен)
+    pub reserved_cu: u32, // v20: резерв CU под финальную проверку (0 = выключен)
+    pub seed: [u8; 8], // v21: seed PDA из reinitialize (нули = основной [b"router_state"])
+    pub min_liquidity: u64, // v22: минимальный SOL-резерв bonding curve ноги (0 = только пустые)
+    pub max_mints_per_batch: u8, // v23: потолок различных token_mint в батче (0 = без ограничения)
+    pub dex_discriminators: [DexDiscriminator; MAX_DISCRIMINATOR_OVERRIDES], // v24: переопределения discriminator-ов DEX-ов
+    pub total_legs_executed: u64, // v25: число исполненных ног (пишется в конце успешного батча)
+    pub profit_mint: Pubkey, // v26: mint, в котором меряется прибыль (default = quote_mint)
+    pub profit_oracle: Pubkey, // v26: оракул цены profit_mint в общей единице (default = без нормализации)
+    pub min_profit: u64, // v26: минимальная прибыль батча в общей единице (0 = только неубыточность)
+    pub max_scan_passes: u8, // v27: проходов по срезу ноги на разбор аккаунтов (0 = DEFAULT_SCAN_PASSES)
+}
+
+impl RouterState {
+    pub const LEN: usize = 8  // discriminator
+        + 1                   // version
+        + 32                  // owner
+        + 1                   // is_paused
+        + 1                   // bump
+        + 8                   // last_nonce
+        + 2                   // max_slippage_bps
+        + 2                   // profit_fee_bps
+        + 32                  // treasury
+        + 8                   // pump_buy_discriminator
+        + 8                   // pump_sell_discriminator
+        + 1                   // allow_raw
+        + 8                   // min_slots_between_batches
+        + 8                   // last_batch_slot
+        + 1                   // max_accounts_per_leg
+        + 1                   // enforce_distinct_venues
+        + 8                   // total_batches
+        + 8                   // cumulative_profit
+        + 8                   // suggested_cu_price_microlamports
+        + 32                  // fee_recipient
+        + 1                   // is_shutdown
+        + 1                   // strict_leg_accounts
+        + 32                  // pending_owner
+        + 32                  // quote_mint
+        + 1                   // quote_has_transfer_fee
+        + 1                   // consecutive_failures
+        + 1                   // failure_threshold
+        + 32                  // failure_reporter
+        + 32 * MAX_DENIED_MINTS // denied_mints
+        + 1                   // denied_count
+        + 32 * BATCH_TAG_HISTORY // recent_batch_tags
+        + 1                   // batch_tag_cursor
+        + 2                   // auto_slippage_relief_bps
+        + 4                   // reserved_cu
+        + 8                   // seed
+        + 8                   // min_liquidity
+        + 1                   // max_mints_per_batch
+        + DexDiscriminator::LEN * MAX_DISCRIMINATOR_OVERRIDES // dex_discriminators
+        + 8                   // total_legs_executed
+        + 32                  // profit_mint
+        + 32                  // profit_oracle
+        + 8                   // min_profit
+        + 1;                  // max_scan_passes
+
+    /// Занятые слоты denylist-а
+    pub fn active_denied_mints(&self) -> &[Pubkey] {
+        &self.denied_mints[..usize::from(self.denied_count).min(MAX_DENIED_MINTS)]
+    }
+
+    /// Суффикс сидов PDA: пусто для основного [b"router_state"], иначе seed из reinitialize.
+    /// Сиды склеиваются без разделителей, поэтому пустой суффикс дает тот же адрес.
+    pub fn seed_suffix(&self) -> &[u8] {
+        if self.seed == [0u8; 8] {
+            &[]
+        } else {
+            &self.seed
+        }
+    }
+
+    /// Mint прибыли: настроенный или mint quote-аккаунта
+    pub fn effective_profit_mint(&self) -> Pubkey {
+        if self.profit_mint == Pubkey::default() {
+            self.effective_quote_mint()
+        } else {
+            self.profit_mint
+        }
+    }
+
+    /// Mint quote-аккаунта: настроенный или канонический wSOL (после migrate_router поле нулевое)
+    pub fn effective_quote_mint(&self) -> Pubkey {
+        if self.quote_mint == Pubkey::default() {
+            anchor_spl::token::spl_token::native_mint::ID
+        } else {
+            self.quote_mint
+        }
+    }
+}
+
+/// v17..v27: denied_mints хранился как Vec (u32 длина + элементы), и поля за ним лежали с
+/// плавающего смещения. Перекладываем данные аккаунта в layout v28: массив + denied_count
+fn widen_legacy_denied_mints(legacy: &[u8]) -> Result<Vec<u8>> {
+    let offset = ROUTER_STATE_DENIED_MINTS_OFFSET;
+    let count_bytes: [u8; 4] = legacy
+        .get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(MyErrorCode::InvalidAccountData)?;
+    let count = u32::from_le_bytes(count_bytes) as usize;
+    require!(count <= MAX_DENIED_MINTS, MyErrorCode::InvalidAccountData);
+    let tail_start = offset + 4 + 32 * count;
+    require!(legacy.len() >= tail_start, MyErrorCode::InvalidAccountData);
+
+    let mut data = Vec::with_capacity(RouterState::LEN);
+    data.extend_from_slice(&legacy[..offset]);
+    data.extend_from_slice(&legacy[offset + 4..tail_start]);
+    data.resize(offset + 32 * MAX_DENIED_MINTS, 0);
+    data.push(count as u8);
+    data.extend_from_slice(&legacy[tail_start..]);
+    Ok(data)
+}
+
+/// Layout v1 (только для migrate_router)
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct RouterStateV1 {
+    pub owner: Pubkey,
+    pub is_paused: bool,
+    pub bump: u8,
+}
+
+/// Переопределение discriminator-а (DEX, сторона). Только u8-поля: после migrate_router
+/// хвост аккаунта может содержать мусор, и такая запись всегда десериализуется до явного сброса
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct DexDiscriminator {
+    pub dex: u8,                // dex_index (порядок объявления DexType)
+    pub side: u8,               // TradeSide as u8
+    pub discriminator: [u8; 8], // Нули = слот свободен
+}
+
+impl DexDiscriminator {
+    pub const LEN: usize = 1 + 1 + 8;
 
-        msg!("🏆 INLINE HFT arbitrage batch completed successfully - MAXIMUM SPEED!");
-        Ok(())
+    pub fn is_set(&self) -> bool {
+        self.discriminator != [0u8; 8]
     }
+}
 
-    /// Emergency stop: только owner может поставить на паузу/снять с паузы
-    pub fn toggle_pause(ctx: Context<TogglePause>) -> Result<()> {
-        let router_state = &mut ctx.accounts.router_state;
-        
-        // Проверяем права владельца
-        require!(
-            ctx.accounts.owner.key() == router_state.owner,
-            MyErrorCode::UnauthorizedAccess
-        );
+/// Оценка CU одной ноги в compute_estimate
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LegCuEstimate {
+    pub index: u8,
+    pub cu_estimate: u32,
+}
 
-        router_state.is_paused = !router_state.is_paused;
-        
-        msg!("🛑 Router pause status changed to: {}", router_state.is_paused);
-        Ok(())
-    }
+/// Результат compute_estimate: total_cu = base_cu + сумма cu_estimate ног
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct BatchCuEstimate {
+    pub base_cu: u32,
+    pub legs: Vec<LegCuEstimate>,
+    pub total_cu: u32,
 }
 
-// ============================================================================
-// 📊 СТРУКТУРЫ ДАННЫХ
-// ============================================================================
+/// Отладочный return data упавшего батча (feature "failure-debug"): нога, этап и ключи ее среза
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LegFailureDebug {
+    /// Индекс ноги в arbitrages
+    pub index: u8,
+    /// FAILURE_STAGE_PREFLIGHT / FAILURE_STAGE_EXECUTION
+    pub stage: u8,
+    /// Ключи среза ноги по порядку (не больше MAX_FAILURE_DEBUG_ACCOUNTS)
+    pub accounts: Vec<Pubkey>,
+}
 
-/// Состояние роутера (хранится on-chain)
-#[account]
-pub struct RouterState {
-    pub owner: Pubkey,      // Владелец для emergency operations
-    pub is_paused: bool,    // Флаг паузы (emergency stop)
-    pub bump: u8,          // Bump для PDA
+/// Снимок RouterState для get_state. Как и RouterState: поля только дописываются в конец,
+/// `version` первым полем - Go-бот декодирует известный ему префикс.
+/// Весь снимок (с полным denylist) обязан влезать в MAX_RETURN_DATA: recent_batch_tags в него
+/// не входят - их бот читает из самого аккаунта.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct RouterStateView {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub is_paused: bool,
+    pub last_nonce: u64,
+    pub max_slippage_bps: u16,
+    pub profit_fee_bps: u16,
+    pub treasury: Pubkey,
+    pub pump_buy_discriminator: [u8; 8],
+    pub pump_sell_discriminator: [u8; 8],
+    pub allow_raw: bool,
+    pub min_slots_between_batches: u64,
+    pub last_batch_slot: u64,
+    pub max_accounts_per_leg: u8,
+    pub enforce_distinct_venues: bool,
+    pub total_batches: u64,
+    pub cumulative_profit: u64,
+    pub suggested_cu_price_microlamports: u64,
+    pub fee_recipient: Pubkey,
+    pub is_shutdown: bool,
+    pub strict_leg_accounts: bool,
+    pub pending_owner: Pubkey,
+    pub quote_mint: Pubkey,
+    pub quote_has_transfer_fee: bool,
+    pub consecutive_failures: u8,
+    pub failure_threshold: u8,
+    pub failure_reporter: Pubkey,
+    pub denied_mints: Vec<Pubkey>,
+    pub batch_tag_cursor: u8,
+    pub auto_slippage_relief_bps: u16,
+    pub reserved_cu: u32,
+    pub seed: [u8; 8],
+    pub min_liquidity: u64,
+    pub max_mints_per_batch: u8,
+    pub dex_discriminators: [DexDiscriminator; MAX_DISCRIMINATOR_OVERRIDES],
+    pub total_legs_executed: u64,
+    pub profit_mint: Pubkey,
+    pub profit_oracle: Pubkey,
+    pub min_profit: u64,
+    pub max_scan_passes: u8,
 }
 
 /// 🧠 Параметры одного арбитража (все рассчитано Go-ботом заранее)
@@ -314,6 +5191,87 @@ pub struct ArbitrageParams {
     pub max_sol_cost: u64,            // Максимум SOL тратим (с учетом slippage)
     pub tokens_to_sell: u64,          // Сколько токенов продаем (Go-бот рассчитал)
     // min_wsol_out уже есть выше - минимум получаем (с учетом slippage)
+
+    // 🔺 ТРЕУГОЛЬНИК: None = классический BUY -> SELL
+    pub bridge: Option<SwapLeg>,      // Промежуточный своп token_mint -> output_mint
+
+    pub token_program: Option<Pubkey>, // Токен-программа ноги (None = token_program из контекста)
+    pub allow_same_venue: bool,       // Намеренный round trip buy_dex == sell_dex (при enforce_distinct_venues)
+    pub reverse: bool,                // SELL -> BUY: продаем имеющиеся токены и откупаем дешевле
+    pub create_ata: bool,             // Создать ATA token_mint перед BUY, если его еще нет
+    pub fee_recipient: Option<Pubkey>, // Fee recipient Pump.fun для ноги (None = router_state.fee_recipient)
+    pub oracle: Option<Pubkey>,       // Pyth / Switchboard цена token_mint в quote (None = без проверки)
+    pub max_oracle_deviation_bps: u16, // Допустимое отклонение цены BUY от оракула
+    pub funding_account: Option<Pubkey>, // Pre-funded quote-аккаунт user-а для ноги (None = user_wsol_account)
+    pub data_prefix: Vec<u8>,         // Префикс данных инструкций ноги для оберток-роутеров (пусто = нет)
+    pub layout: Vec<u8>,              // Роль каждого аккаунта среза (LAYOUT_ROLE_*; пусто = поиск по срезу)
+    pub cu_cap: u32,                  // Потолок CU ноги для бюджета батча (0 = без потолка)
+    pub referral_account: Option<Pubkey>, // Referral / host fee аккаунт для DEX-ов с рибейтом (supports_referral)
+    pub auto_claim_seat: bool,        // Phoenix: занять seat через Seat Manager перед свопом, если он не одобрен
+}
+
+/// 🔺 Промежуточный шаг треугольного арбитража (A -> B -> C -> A)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SwapLeg {
+    pub dex: DexType,                 // Где меняем token_mint -> output_mint (маркет base = token_mint)
+    pub output_mint: Pubkey,          // Что получаем и затем продаем на sell_dex
+    pub min_amount_out: u64,          // Минимум output_mint за весь купленный token_mint
+}
+
+/// 🏦 Flash loan вокруг батча (Kamino Lending): заем amount из reserve на user_wsol_account
+/// перед инструкцией батча и возврат amount + комиссия сразу после нее
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct FlashLoanParams {
+    pub lending_program: Pubkey,      // Программа кредитования (KAMINO_LENDING_PROGRAM_ID)
+    pub reserve: Pubkey,              // Reserve, из которого берется заем (его mint = quote)
+    pub amount: u64,                  // Сумма займа
+    pub max_fee: u64,                 // Комиссия за заем по конфигу reserve (считает бот) - вычитается из прибыли
+}
+
+/// ⚙️ Параметры батча кроме самих ног: одна Borsh-структура для всех execute_batch_N -
+/// новый параметр добавляется сюда, а не в сигнатуру каждой обертки
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct BatchOptions {
+    pub wrap_amount: u64,                     // wSOL для обертки перед батчем (AUTO_WRAP_AMOUNT = недостающее)
+    pub nonce: u64,                           // Строго возрастающий nonce (игнорируется при ненулевом batch_tag)
+    pub max_total_sol: u64,                   // Потолок суммы max_sol_cost окна (0 = без ограничения)
+    pub mode: ExecutionMode,                  // Режим исполнения на весь батч
+    pub start_index: u8,                      // Окно исполнения: arbitrages[start_index..start_index + count]
+    pub count: u8,
+    pub unwrap_all: bool,                     // Закрыть wSOL аккаунт после батча
+    pub settle_native: bool,                  // Нативные SOL user-а входят в результат батча
+    pub batch_tag: [u8; 32],                  // Ненулевой - защита от повтора вместо nonce
+    pub order: Vec<u8>,                       // Порядок исполнения ног окна (пусто = по порядку)
+    pub flash_loan: Option<FlashLoanParams>,  // Заем вокруг батча (проверяется интроспекцией)
+    pub tx_cost_lamports: u64,                // Комиссии транзакции, вычитаемые из прибыли (quote = wSOL)
+}
+
+impl BatchOptions {
+    /// Одна нога без окна, порядка, batch_tag и лимитов (execute_single_arbitrage)
+    pub fn single_leg() -> Self {
+        Self {
+            wrap_amount: 0,
+            nonce: 0,
+            max_total_sol: 0,
+            mode: ExecutionMode::AllOrNothing,
+            start_index: 0,
+            count: 1,
+            unwrap_all: false,
+            settle_native: false,
+            batch_tag: [0u8; 32],
+            order: Vec::new(),
+            flash_loan: None,
+            tx_cost_lamports: 0,
+        }
+    }
+}
+
+/// Режим исполнения батча (выбирается на весь батч)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ExecutionMode {
+    AllOrNothing, // Любая ошибка ноги откатывает всю транзакцию
+    BestEffort,   // Ноги, упавшие до первого CPI, пропускаются (ArbitrageSkipped)
+    PerLegProfit, // Как AllOrNothing, плюс каждая нога по отдельности не в убытке
 }
 
 /// Поддерживаемые DEX-ы
@@ -321,6 +5279,51 @@ pub struct ArbitrageParams {
 pub enum DexType {
     Meteora,    // Meteora DLMM
     PumpFun,    // Pump.fun AMM
+    OpenBookV2, // OpenBook v2 CLOB (take order)
+    Lifinity,   // Lifinity v2 PMM (Pyth oracle)
+    Raw {       // Произвольный CPI (только при RouterState.allow_raw)
+        program_id: Pubkey,
+        data: Vec<u8>,
+        is_writable: Vec<bool>, // Параллельно срезу аккаунтов ноги
+    },
+    Phoenix,    // Phoenix v1 CLOB (IOC swap)
+    RaydiumClmm, // Raydium concentrated liquidity (swap_v2)
+    OrcaV1,     // Orca legacy Token Swap (constant product)
+    StableSwap, // Saber StableSwap (стейбл / LST пулы)
+    Invariant,  // Invariant CLMM (swap с tick-аккаунтами в срезе)
+    Crema,      // Crema Finance CLMM (swap с tick array-ями в срезе)
+    WhirlpoolLike { program_id: Pubkey }, // Orca Whirlpool и его клоны (layout Whirlpool, свой program id; только при allow_raw)
+}
+
+// ============================================================================
+// 📣 СОБЫТИЯ
+// ============================================================================
+
+/// Нога пропущена в BestEffort режиме (до первого CPI)
+#[event]
+pub struct ArbitrageSkipped {
+    pub index: u8,          // Индекс ноги в батче
+    pub reason_code: u32,   // Код ошибки (MyErrorCode -> 6000 + индекс, встроенные -> BUILTIN_ERROR_CODE_BASE + n)
+}
+
+/// Нога исполнена
+#[event]
+pub struct ArbitrageExecuted {
+    pub index: u8,                    // Индекс ноги в батче
+    pub leg_profit: i64,              // Дельта quote-аккаунта за ногу (reverse в агрегат не входит)
+    pub sell_amount_out: Option<u64>, // Amount out из return data SELL-программы (None = не сообщила)
+}
+
+/// Итог батча
+#[event]
+pub struct BatchCompleted {
+    pub executed_count: u8, // Исполненные ноги
+    pub skipped_count: u8,  // Пропущенные ноги
+    pub profit: u64,        // Прирост wSOL за батч
+    pub profit_fee: u64,    // Удержанная комиссия протокола
+    pub cu_consumed: u64,   // Фактический расход CU батча (0 = сборка без "cu-metrics")
+    pub profit_usdc: u64,   // Прибыль в USDC (6 decimals) по оракулу SOL/USD, только для отчета
+    pub usdc_price_stale: bool, // Оракула нет или цена устарела / не торгуется - profit_usdc = 0
 }
 
 // ============================================================================
@@ -332,7 +5335,7 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = owner,
-        space = 8 + 32 + 1 + 1, // discriminator + pubkey + bool + bump
+        space = RouterState::LEN,
         seeds = [b"router_state"],
         bump
     )]
@@ -347,7 +5350,8 @@ pub struct Initialize<'info> {
 #[derive(Accounts)]
 pub struct ExecuteArbitrageBatch<'info> {
     #[account(
-        seeds = [b"router_state"],
+        mut,
+        seeds = [b"router_state", router_state.seed_suffix()],
         bump = router_state.bump
     )]
     pub router_state: Account<'info, RouterState>,
@@ -356,13 +5360,43 @@ pub struct ExecuteArbitrageBatch<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
     
-    /// wSOL аккаунт пользователя (финальная проверка прибыли в конце)
-    #[account(mut)]
-    pub user_wsol_account: Account<'info, TokenAccount>,
+    /// wSOL (quote) аккаунт пользователя, SPL Token или Token-2022 (финальная проверка прибыли в конце)
+    #[account(
+        mut,
+        constraint = user_wsol_account.mint == router_state.effective_quote_mint() @ MyErrorCode::InvalidTokenAccount
+    )]
+    pub user_wsol_account: InterfaceAccount<'info, InterfaceTokenAccount>,
     
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
+
+    /// wSOL аккаунт treasury (нужен только при profit_fee_bps > 0)
+    #[account(
+        mut,
+        constraint = treasury_token_account.owner == router_state.treasury @ MyErrorCode::InvalidTokenAccount,
+        constraint = treasury_token_account.mint == user_wsol_account.mint @ MyErrorCode::InvalidTokenAccount
+    )]
+    pub treasury_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: mint quote-аккаунта (нужен только при quote_has_transfer_fee), сверяется с router_state
+    #[account(
+        constraint = quote_mint.key() == router_state.effective_quote_mint() @ MyErrorCode::InvalidTokenAccount
+    )]
+    pub quote_mint: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: оракул SOL/USD (Pyth / Switchboard) только для отчета profit_usdc; формат по владельцу
+    pub usdc_price_oracle: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: sysvar Instructions (нужен только с flash_loan) - по нему сверяются заем и возврат
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: оракул profit_mint для min_profit (нужен только при заданном profit_oracle), сверяется с router_state
+    #[account(
+        constraint = profit_oracle.key() == router_state.profit_oracle @ MyErrorCode::OracleMismatch
+    )]
+    pub profit_oracle: Option<UncheckedAccount<'info>>,
     
     // 🧠 Гибкая структура remaining_accounts (Go-бот точно знает что передать):
     // Каждый арбитраж использует accounts_count аккаунтов
@@ -376,18 +5410,223 @@ pub struct ExecuteArbitrageBatch<'info> {
 pub struct TogglePause<'info> {
     #[account(
         mut,
-        seeds = [b"router_state"],
-        bump = router_state.bump
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump,
+        has_one = owner @ MyErrorCode::UnauthorizedAccess
     )]
     pub router_state: Account<'info, RouterState>,
     
     pub owner: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct ComputeEstimate<'info> {
+    #[account(
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump
+    )]
+    pub router_state: Account<'info, RouterState>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewBatch<'info> {
+    #[account(
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump
+    )]
+    pub router_state: Account<'info, RouterState>,
+}
+
+#[derive(Accounts)]
+pub struct DerivePumpAccounts<'info> {
+    #[account(
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump
+    )]
+    pub router_state: Account<'info, RouterState>,
+}
+
+#[derive(Accounts)]
+pub struct GetState<'info> {
+    #[account(
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump
+    )]
+    pub router_state: Account<'info, RouterState>,
+}
+
+#[derive(Accounts)]
+pub struct SupportedDexes<'info> {
+    #[account(
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump
+    )]
+    pub router_state: Account<'info, RouterState>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump,
+        has_one = owner @ MyErrorCode::UnauthorizedAccess
+    )]
+    pub router_state: Account<'info, RouterState>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_seed: [u8; 8])]
+pub struct Reinitialize<'info> {
+    #[account(
+        seeds = [b"router_state", old_router_state.seed_suffix()],
+        bump = old_router_state.bump,
+        has_one = owner @ MyErrorCode::UnauthorizedAccess
+    )]
+    pub old_router_state: Account<'info, RouterState>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = RouterState::LEN,
+        seeds = [b"router_state", new_seed.as_ref()],
+        bump
+    )]
+    pub new_router_state: Account<'info, RouterState>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateRouter<'info> {
+    /// CHECK: старый layout не десериализуется как RouterState - разбираем вручную в migrate_router
+    /// (владелец-программа и discriminator). Сиды не фиксируем: re-keyed state тоже мигрирует
+    #[account(mut)]
+    pub router_state: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RescueTokens<'info> {
+    #[account(
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump,
+        has_one = owner @ MyErrorCode::UnauthorizedAccess
+    )]
+    pub router_state: Account<'info, RouterState>,
+
+    pub owner: Signer<'info>,
+
+    /// Токен-аккаунт, которым владеет PDA роутера (откуда спасаем)
+    #[account(
+        mut,
+        constraint = source_token_account.owner == router_state.key() @ MyErrorCode::InvalidTokenAccount
+    )]
+    pub source_token_account: Account<'info, TokenAccount>,
+
+    /// Токен-аккаунт владельца того же mint (куда спасаем)
+    #[account(
+        mut,
+        constraint = destination_token_account.mint == source_token_account.mint @ MyErrorCode::InvalidTokenAccount,
+        constraint = destination_token_account.owner == owner.key() @ MyErrorCode::InvalidTokenAccount
+    )]
+    pub destination_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyWithdrawAll<'info> {
+    #[account(
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump,
+        has_one = owner @ MyErrorCode::UnauthorizedAccess
+    )]
+    pub router_state: Account<'info, RouterState>,
+
+    pub owner: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEmptyAtas<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    /// Нужен только если среди закрываемых есть аккаунты Token-2022
+    pub token_2022_program: Option<Program<'info, anchor_spl::token_2022::Token2022>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateUserAtas<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+    /// Нужен только если среди mint-ов есть Token-2022
+    pub token_2022_program: Option<Program<'info, anchor_spl::token_2022::Token2022>>,
+    pub associated_token_program: Program<'info, anchor_spl::associated_token::AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordFailure<'info> {
+    #[account(
+        mut,
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump
+    )]
+    pub router_state: Account<'info, RouterState>,
+
+    /// Owner или router_state.failure_reporter (Go-бот)
+    pub reporter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOwnership<'info> {
+    #[account(
+        mut,
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump
+    )]
+    pub router_state: Account<'info, RouterState>,
+
+    /// Предложенный owner (кошелек или PDA, подписывающий через invoke_signed)
+    pub new_owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DrainLamports<'info> {
+    #[account(
+        mut,
+        seeds = [b"router_state", router_state.seed_suffix()],
+        bump = router_state.bump,
+        has_one = owner @ MyErrorCode::UnauthorizedAccess
+    )]
+    pub router_state: Account<'info, RouterState>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
 // ============================================================================
 // ⚠️ КАСТОМНЫЕ ОШИБКИ (для детального дебага)
 // ============================================================================
 
+/// Каждый класс сбоя - свой код: резолв аккаунтов -> *NotFound / InsufficientAccounts,
+/// бюджет CU -> InsufficientCompute, прибыльность -> NotProfitable / MinOutNotMet,
+/// DEX без билдера -> NotImplemented, неверная комбинация параметров ноги -> InvalidConfigValue.
 #[error_code]
 pub enum MyErrorCode {
     #[msg("Contract is paused by the admin.")]
@@ -405,7 +5644,7 @@ pub enum MyErrorCode {
     #[msg("Invalid token account mint does not match expected token mint.")]
     InvalidTokenAccount,
 
-    #[msg("Not enough remaining accounts provided.")]
+    #[msg("Not enough accounts: leg accounts_count is below the DEX minimum or exceeds remaining accounts.")]
     InsufficientAccounts,
 
     #[msg("Invalid DEX type specified.")]
@@ -428,4 +5667,121 @@ pub enum MyErrorCode {
 
     #[msg("CPI call failed.")]
     CpiError,
+
+    #[msg("Batch nonce must be greater than the last executed nonce.")]
+    StaleNonce,
+
+    #[msg("Pool or market mint does not match the arbitrage token mint.")]
+    PoolMintMismatch,
+
+    #[msg("Account data is too short or malformed.")]
+    InvalidAccountData,
+
+    #[msg("Operation is only allowed while the router is paused.")]
+    ContractNotPaused,
+
+    #[msg("Leg max_sol_cost or min_wsol_out exceeds the router slippage cap.")]
+    SlippageExceedsCap,
+
+    #[msg("Config value is out of the allowed range.")]
+    InvalidConfigValue,
+
+    #[msg("Oracle account does not match the pool's configured oracle.")]
+    OracleMismatch,
+
+    #[msg("Router state is already at the current version.")]
+    AlreadyMigrated,
+
+    #[msg("Pump.fun bonding curve is complete; trading has migrated.")]
+    BondingCurveComplete,
+
+    #[msg("Sum of max_sol_cost across the batch exceeds max_total_sol.")]
+    BatchSpendExceeded,
+
+    #[msg("Batch window start_index/count does not fit the arbitrages array.")]
+    InvalidBatchWindow,
+
+    #[msg("BUY did not deliver any tokens to the user token account.")]
+    InsufficientTokensReceived,
+
+    #[msg("Raw CPI legs are disabled by the router owner.")]
+    RawDexDisabled,
+
+    #[msg("Raw leg is malformed: writable flags must match the account slice.")]
+    InvalidRawLeg,
+
+    #[msg("Batch rejected by rate limit: too few slots since the last batch.")]
+    RateLimited,
+
+    #[msg("Leg account slice is invalid: too many accounts or accounts of another leg.")]
+    AccountCountMismatch,
+
+    #[msg("Leg buys and sells on the same venue; set allow_same_venue if intentional.")]
+    SameVenue,
+
+    #[msg("Drain would take the router account below rent exemption.")]
+    RentExemptionViolation,
+
+    #[msg("User does not hold enough tokens to sell first in a reverse leg.")]
+    InsufficientTokenBalance,
+
+    #[msg("Pump.fun associated bonding curve account does not exist yet.")]
+    AssociatedBondingCurveMissing,
+
+    #[msg("Router has been permanently shut down.")]
+    RouterShutdown,
+
+    #[msg("Execution price deviates from the oracle price beyond the allowed bps.")]
+    OracleDeviation,
+
+    #[msg("User does not hold enough SOL / wSOL to cover the batch's max_sol_cost.")]
+    InsufficientFunds,
+
+    #[msg("SELL returned less quote than min_wsol_out (net of quote transfer fee).")]
+    MinOutNotMet,
+
+    #[msg("Leg trades a mint that is on the router denylist.")]
+    MintDenied,
+
+    #[msg("DEX is declared but has no instruction builder yet.")]
+    NotImplemented,
+
+    #[msg("Estimated batch compute exceeds the per-transaction CU limit; split the batch.")]
+    InsufficientCompute,
+
+    #[msg("Batch tag was already used by one of the recent batches.")]
+    DuplicateBatchTag,
+
+    #[msg("Leg order must be a permutation of the batch window's leg indices.")]
+    InvalidLegOrder,
+
+    #[msg("Token account still holds tokens and cannot be closed.")]
+    TokenAccountNotEmpty,
+
+    #[msg("Leg account layout does not match the slice: wrong length or account at a role position.")]
+    LayoutMismatch,
+
+    #[msg("Account is owned by an unexpected program.")]
+    InvalidAccountOwner,
+
+    #[msg("Pool has no liquidity on the side the leg trades against (or less than min_liquidity).")]
+    InsufficientLiquidity,
+
+    #[msg("Batch trades more distinct token mints than max_mints_per_batch allows.")]
+    TooManyMints,
+
+    #[msg("Failed to wrap SOL: user_wsol_account is not a native SPL Token account or sync_native failed.")]
+    WsolWrapFailed,
+
+    #[msg("Oracle price is too old to gate execution on.")]
+    StaleOraclePrice,
+
+    #[msg("Leg account resolution exceeded its scan budget (accounts_count * max_scan_passes).")]
+    ScanBudgetExceeded,
+
+    #[msg("user_wsol_account would be left below rent-exemption and the user cannot cover the top-up in native SOL.")]
+    WsolNotRentExempt,
+
+    #[msg("Flash loan borrow/repay instructions do not wrap the batch as described by flash_loan.")]
+    FlashLoanMismatch,
 }
\ No newline at end of file
@@ -0,0 +1,5938 @@
+// tests.rs - тесты роутера: хелперы и билдеры напрямую, execute_batch - через вручную собранный Context.
+// CPI, sysvar-ы, return data и события идут через program_stubs: System, SPL Token / Token-2022 и ATA
+// сымитированы поверх AccountInfo, DEX-ы - мок-программой для Raw ног.
+use super::*;
+use anchor_lang::solana_program::{
+    bpf_loader,
+    clock::Clock,
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE, SUCCESS},
+    program_error::ProgramError,
+    program_option::COption,
+    program::{invoke, invoke_signed},
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    rent::Rent,
+    sysvar,
+};
+use anchor_spl::token::spl_token;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::Once;
+
+// ============================================================================
+// 🧪 РАНТАЙМ: syscall-стабы с состоянием в thread-local (тесты идут параллельно)
+// ============================================================================
+
+/// Мок программы: получает CPI-инструкцию и AccountInfo-ы вызывающего
+type MockProgram = fn(&Instruction, &[AccountInfo]) -> ProgramResult;
+
+/// CU, которые съедает каждый CPI (остаток - в sol_remaining_compute_units)
+const MOCK_CPI_CU: u64 = 10_000;
+/// Эпоха и слот Clock по умолчанию
+const TEST_EPOCH: u64 = 500;
+const TEST_SLOT: u64 = 1_000;
+
+thread_local! {
+    static MOCK_PROGRAMS: RefCell<HashMap<Pubkey, MockProgram>> = RefCell::new(HashMap::new());
+    static INVOCATIONS: RefCell<Vec<Instruction>> = RefCell::new(Vec::new());
+    static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = RefCell::new(None);
+    static EVENTS: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
+    static REMAINING_CU: Cell<u64> = Cell::new(MAX_TRANSACTION_CU as u64);
+    static SLOT: Cell<u64> = Cell::new(TEST_SLOT);
+    /// Программа, от имени которой идет CPI (ее PDA из signers_seeds подписывают)
+    static CALLER_PROGRAM: Cell<Pubkey> = Cell::new(ID);
+}
+
+struct TestSyscalls;
+
+impl SyscallStubs for TestSyscalls {
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let program = MOCK_PROGRAMS
+            .with(|programs| programs.borrow().get(&instruction.program_id).copied())
+            .ok_or(ProgramError::IncorrectProgramId)?;
+        INVOCATIONS.with(|invocations| invocations.borrow_mut().push(instruction.clone()));
+        // Как и runtime: return data сбрасывается перед вызовом
+        RETURN_DATA.with(|return_data| *return_data.borrow_mut() = None);
+        REMAINING_CU.with(|remaining| remaining.set(remaining.get().saturating_sub(MOCK_CPI_CU)));
+
+        // Как и runtime: PDA вызывающей программы из signers_seeds становятся подписантами
+        let caller = CALLER_PROGRAM.with(Cell::get);
+        let pda_signers: Vec<Pubkey> = signers_seeds
+            .iter()
+            .filter_map(|seeds| Pubkey::create_program_address(seeds, &caller).ok())
+            .collect();
+        let account_infos: Vec<AccountInfo> = account_infos
+            .iter()
+            .map(|acc_info| {
+                let mut acc_info = acc_info.clone();
+                acc_info.is_signer |= pda_signers.contains(acc_info.key);
+                acc_info
+            })
+            .collect();
+
+        // Вложенные CPI идут уже от имени вызванной программы
+        CALLER_PROGRAM.with(|caller_program| caller_program.set(instruction.program_id));
+        let result = program(instruction, &account_infos);
+        CALLER_PROGRAM.with(|caller_program| caller_program.set(caller));
+        result
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            slot: SLOT.with(Cell::get),
+            epoch: TEST_EPOCH,
+            ..Clock::default()
+        };
+        unsafe { std::ptr::write(var_addr as *mut Clock, clock) };
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { std::ptr::write(var_addr as *mut Rent, Rent::default()) };
+        SUCCESS
+    }
+
+    fn sol_remaining_compute_units(&self) -> u64 {
+        REMAINING_CU.with(Cell::get)
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        RETURN_DATA.with(|return_data| return_data.borrow().clone())
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        set_mock_return_data(&ID, data);
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        EVENTS.with(|events| events.borrow_mut().push(fields.concat()));
+    }
+}
+
+static INSTALL_STUBS: Once = Once::new();
+
+/// Стабы (один раз на процесс) и чистое состояние рантайма потока со стандартными программами
+fn setup() {
+    INSTALL_STUBS.call_once(|| {
+        program_stubs::set_syscall_stubs(Box::new(TestSyscalls));
+    });
+    MOCK_PROGRAMS.with(|programs| {
+        let mut programs = programs.borrow_mut();
+        programs.clear();
+        programs.insert(system_program::ID, mock_system_program as MockProgram);
+        programs.insert(anchor_spl::token::ID, mock_token_program as MockProgram);
+        programs.insert(anchor_spl::token_2022::ID, mock_token_program as MockProgram);
+        programs.insert(anchor_spl::associated_token::ID, mock_associated_token_program as MockProgram);
+        programs.insert(MOCK_DEX_ID, mock_dex_program as MockProgram);
+    });
+    INVOCATIONS.with(|invocations| invocations.borrow_mut().clear());
+    RETURN_DATA.with(|return_data| *return_data.borrow_mut() = None);
+    EVENTS.with(|events| events.borrow_mut().clear());
+    REMAINING_CU.with(|remaining| remaining.set(MAX_TRANSACTION_CU as u64));
+    SLOT.with(|slot| slot.set(TEST_SLOT));
+    CALLER_PROGRAM.with(|caller_program| caller_program.set(ID));
+}
+
+/// Мок под program id (поверх стандартных из setup)
+fn register_program(program_id: Pubkey, program: MockProgram) {
+    MOCK_PROGRAMS.with(|programs| programs.borrow_mut().insert(program_id, program));
+}
+
+/// Return data от имени программы (runtime: пустые данные = ничего не вернула)
+fn set_mock_return_data(program_id: &Pubkey, data: &[u8]) {
+    let value = if data.is_empty() { None } else { Some((*program_id, data.to_vec())) };
+    RETURN_DATA.with(|return_data| *return_data.borrow_mut() = value);
+}
+
+/// Program id всех CPI по порядку
+fn invoked_programs() -> Vec<Pubkey> {
+    INVOCATIONS.with(|invocations| invocations.borrow().iter().map(|instruction| instruction.program_id).collect())
+}
+
+/// События типа T, выпущенные emit! (sol_log_data: discriminator + Borsh)
+fn emitted<T: AnchorDeserialize + Discriminator>() -> Vec<T> {
+    EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .filter(|data| data.starts_with(&T::DISCRIMINATOR))
+            .map(|data| T::deserialize(&mut &data[8..]).unwrap())
+            .collect()
+    })
+}
+
+// ============================================================================
+// 🧾 АККАУНТЫ: AccountInfo с 'static данными (как у рантайма на время инструкции)
+// ============================================================================
+
+fn leak_account(
+    key: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+    is_signer: bool,
+    executable: bool,
+) -> &'static AccountInfo<'static> {
+    Box::leak(Box::new(AccountInfo::new(
+        Box::leak(Box::new(key)),
+        is_signer,
+        true,
+        Box::leak(Box::new(lamports)),
+        Box::leak(data.into_boxed_slice()),
+        Box::leak(Box::new(owner)),
+        executable,
+        0,
+    )))
+}
+
+/// Аккаунт, который можно realloc-нуть: как в сериализации рантайма, перед key лежит original_data_len (u32),
+/// перед data - ее длина (u64), после data - MAX_PERMITTED_DATA_INCREASE байт запаса
+fn leak_reallocatable_account(key: Pubkey, owner: Pubkey, lamports: u64, data: &[u8]) -> &'static AccountInfo<'static> {
+    #[repr(C)]
+    struct KeyWithOriginalLen {
+        original_data_len: u32,
+        key: Pubkey,
+    }
+    let key = Box::leak(Box::new(KeyWithOriginalLen {
+        original_data_len: data.len() as u32,
+        key,
+    }));
+
+    // u64-слова: длина перед data выровнена так же, как во входном буфере рантайма
+    let words = 1 + (data.len() + MAX_PERMITTED_DATA_INCREASE) / 8 + 1;
+    let buffer = Box::leak(vec![0u64; words].into_boxed_slice());
+    buffer[0] = data.len() as u64;
+    let data_slice = unsafe {
+        let data_ptr = (buffer.as_mut_ptr() as *mut u8).add(8);
+        std::slice::from_raw_parts_mut(data_ptr, data.len())
+    };
+    data_slice.copy_from_slice(data);
+
+    Box::leak(Box::new(AccountInfo::new(
+        &key.key,
+        false,
+        true,
+        Box::leak(Box::new(lamports)),
+        data_slice,
+        Box::leak(Box::new(owner)),
+        false,
+        0,
+    )))
+}
+
+/// Срез remaining_accounts из отдельных аккаунтов (клоны делят lamports и data с исходными)
+fn leak_slice(accounts: &[&'static AccountInfo<'static>]) -> &'static [AccountInfo<'static>] {
+    Box::leak(accounts.iter().map(|acc_info| (*acc_info).clone()).collect::<Vec<_>>().into_boxed_slice())
+}
+
+fn rent_exempt(data_len: usize) -> u64 {
+    Rent::default().minimum_balance(data_len)
+}
+
+fn program_account(program_id: Pubkey) -> &'static AccountInfo<'static> {
+    leak_account(program_id, bpf_loader::ID, 1, Vec::new(), false, true)
+}
+
+fn wallet(lamports: u64) -> &'static AccountInfo<'static> {
+    leak_account(Pubkey::new_unique(), system_program::ID, lamports, Vec::new(), true, false)
+}
+
+/// Произвольный аккаунт программы с заданными данными
+fn data_account(key: Pubkey, owner: Pubkey, data: Vec<u8>) -> &'static AccountInfo<'static> {
+    leak_account(key, owner, rent_exempt(data.len()), data, false, false)
+}
+
+fn pack_token_account(mint: Pubkey, owner: Pubkey, amount: u64, is_native: Option<u64>) -> Vec<u8> {
+    let mut data = vec![0u8; spl_token::state::Account::LEN];
+    let account = spl_token::state::Account {
+        mint,
+        owner,
+        amount,
+        delegate: COption::None,
+        state: spl_token::state::AccountState::Initialized,
+        is_native: is_native.map_or(COption::None, COption::Some),
+        delegated_amount: 0,
+        close_authority: COption::None,
+    };
+    spl_token::state::Account::pack(account, &mut data).unwrap();
+    data
+}
+
+fn token_account_at(key: Pubkey, mint: Pubkey, owner: Pubkey, amount: u64, token_program: Pubkey) -> &'static AccountInfo<'static> {
+    let data = pack_token_account(mint, owner, amount, None);
+    leak_account(key, token_program, rent_exempt(data.len()), data, false, false)
+}
+
+fn mint_account(key: Pubkey, decimals: u8, token_program: Pubkey) -> &'static AccountInfo<'static> {
+    let mint = spl_token::state::Mint {
+        mint_authority: COption::None,
+        supply: POOL_LIQUIDITY,
+        decimals,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    let mut data = vec![0u8; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(mint, &mut data).unwrap();
+    leak_account(key, token_program, rent_exempt(data.len()), data, false, false)
+}
+
+/// Классический SPL Token аккаунт со случайным адресом
+fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> &'static AccountInfo<'static> {
+    token_account_at(Pubkey::new_unique(), mint, owner, amount, anchor_spl::token::ID)
+}
+
+/// Native wSOL аккаунт: lamports = rent-резерв + баланс
+fn wsol_account(owner: Pubkey, amount: u64) -> &'static AccountInfo<'static> {
+    let reserve = rent_exempt(spl_token::state::Account::LEN);
+    let data = pack_token_account(spl_token::native_mint::ID, owner, amount, Some(reserve));
+    leak_account(Pubkey::new_unique(), anchor_spl::token::ID, reserve + amount, data, false, false)
+}
+
+/// Пополнить native wSOL аккаунт (баланс и lamports вместе, как после wrap)
+fn fund_wsol(acc_info: &AccountInfo, amount: u64) {
+    update_token_account(acc_info, |account| {
+        account.amount += amount;
+        Ok(())
+    })
+    .unwrap();
+    **acc_info.lamports.borrow_mut() += amount;
+}
+
+/// Баланс token account-а (базовый layout, в том числе у Token-2022)
+fn token_amount(acc_info: &AccountInfo) -> u64 {
+    spl_token::state::Account::unpack(&acc_info.data.borrow()[..spl_token::state::Account::LEN])
+        .unwrap()
+        .amount
+}
+
+/// Данные аккаунта: discriminator и поля по смещениям
+fn put_pubkey(data: &mut [u8], offset: usize, key: &Pubkey) {
+    data[offset..offset + 32].copy_from_slice(key.as_ref());
+}
+
+fn put_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_data_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_data_i64(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+// ============================================================================
+// 🎭 МОКИ ПРОГРАММ
+// ============================================================================
+
+/// AccountInfo аккаунта инструкции по позиции в ее AccountMeta
+fn account_at<'a, 'info>(
+    instruction: &Instruction,
+    account_infos: &'a [AccountInfo<'info>],
+    position: usize,
+) -> std::result::Result<&'a AccountInfo<'info>, ProgramError> {
+    let key = instruction.accounts.get(position).ok_or(ProgramError::NotEnoughAccountKeys)?.pubkey;
+    account_infos
+        .iter()
+        .find(|acc_info| *acc_info.key == key)
+        .ok_or(ProgramError::NotEnoughAccountKeys)
+}
+
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    let from_lamports = from.lamports().checked_sub(lamports).ok_or(ProgramError::InsufficientFunds)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? += lamports;
+    Ok(())
+}
+
+fn update_token_account(
+    acc_info: &AccountInfo,
+    update: impl FnOnce(&mut spl_token::state::Account) -> ProgramResult,
+) -> ProgramResult {
+    let mut data = acc_info.try_borrow_mut_data()?;
+    let base = &mut data[..spl_token::state::Account::LEN];
+    let mut account = spl_token::state::Account::unpack(base)?;
+    update(&mut account)?;
+    spl_token::state::Account::pack(account, base)
+}
+
+/// Перевод токенов; у native wSOL вместе с балансом переезжают и lamports
+fn transfer_tokens(source: &AccountInfo, destination: &AccountInfo, amount: u64) -> ProgramResult {
+    let mut native = false;
+    update_token_account(source, |account| {
+        account.amount = account.amount.checked_sub(amount).ok_or(ProgramError::InsufficientFunds)?;
+        native = account.is_native.is_some();
+        Ok(())
+    })?;
+    update_token_account(destination, |account| {
+        account.amount = account.amount.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    })?;
+    if native {
+        move_lamports(source, destination, amount)?;
+    }
+    Ok(())
+}
+
+/// System program: только Transfer (tag 2)
+fn mock_system_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    match instruction.data.get(..4) {
+        Some([2, 0, 0, 0]) => {
+            let lamports = instruction
+                .data
+                .get(4..12)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or(ProgramError::InvalidInstructionData)?;
+            move_lamports(account_at(instruction, account_infos, 0)?, account_at(instruction, account_infos, 1)?, lamports)
+        },
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+/// SPL Token / Token-2022: Transfer, TransferChecked, SyncNative, CloseAccount
+fn mock_token_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    use spl_token::instruction::TokenInstruction;
+
+    match TokenInstruction::unpack(&instruction.data)? {
+        TokenInstruction::Transfer { amount } => transfer_tokens(
+            account_at(instruction, account_infos, 0)?,
+            account_at(instruction, account_infos, 1)?,
+            amount,
+        ),
+        TokenInstruction::TransferChecked { amount, .. } => transfer_tokens(
+            account_at(instruction, account_infos, 0)?,
+            account_at(instruction, account_infos, 2)?,
+            amount,
+        ),
+        TokenInstruction::SyncNative => {
+            let acc_info = account_at(instruction, account_infos, 0)?;
+            let lamports = acc_info.lamports();
+            update_token_account(acc_info, |account| match account.is_native {
+                COption::Some(reserve) => {
+                    account.amount = lamports.checked_sub(reserve).ok_or(ProgramError::InsufficientFunds)?;
+                    Ok(())
+                },
+                COption::None => Err(ProgramError::InvalidAccountData),
+            })
+        },
+        TokenInstruction::CloseAccount => {
+            let acc_info = account_at(instruction, account_infos, 0)?;
+            let destination = account_at(instruction, account_infos, 1)?;
+            let account = spl_token::state::Account::unpack(&acc_info.try_borrow_data()?[..spl_token::state::Account::LEN])?;
+            if account.is_native.is_none() && account.amount != 0 {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            move_lamports(acc_info, destination, acc_info.lamports())?;
+            acc_info.try_borrow_mut_data()?.fill(0);
+            Ok(())
+        },
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+/// Associated Token Account: create / create_idempotent - [payer, ata, wallet, mint, system, token program]
+fn mock_associated_token_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let payer = account_at(instruction, account_infos, 0)?;
+    let ata = account_at(instruction, account_infos, 1)?;
+    let wallet = account_at(instruction, account_infos, 2)?;
+    let mint = account_at(instruction, account_infos, 3)?;
+    let token_program = account_at(instruction, account_infos, 5)?;
+
+    if ata.owner != &system_program::ID {
+        return Ok(());
+    }
+    move_lamports(payer, ata, rent_exempt(spl_token::state::Account::LEN))?;
+    *ata.try_borrow_mut_data()? = Box::leak(pack_token_account(*mint.key, *wallet.key, 0, None).into_boxed_slice());
+    ata.assign(token_program.key);
+    Ok(())
+}
+
+/// Мок DEX-а для Raw ног
+const MOCK_DEX_ID: Pubkey = Pubkey::new_from_array([7u8; 32]);
+
+/// Позиции аккаунтов свопа в инструкции: [источник user-а, приемник user-а, приемник пула, источник пула]
+type SwapPositions = [u8; 4];
+
+/// data = [вход (u64), выход (u64), SwapPositions]: вход уходит от user-а в пул, выход - из пула user-у.
+/// Выход сообщается через return data (как u64 LE)
+fn mock_dex_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let data = &instruction.data;
+    let read_amount = |offset: usize| -> std::result::Result<u64, ProgramError> {
+        data.get(offset..offset + 8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidInstructionData)
+    };
+    let (amount_in, amount_out) = (read_amount(0)?, read_amount(8)?);
+    let positions = data.get(16..20).ok_or(ProgramError::InvalidInstructionData)?;
+    let account = |index: usize| account_at(instruction, account_infos, positions[index] as usize);
+
+    transfer_tokens(account(0)?, account(2)?, amount_in)?;
+    transfer_tokens(account(3)?, account(1)?, amount_out)?;
+    set_mock_return_data(&instruction.program_id, &amount_out.to_le_bytes());
+    Ok(())
+}
+
+fn mock_swap_data(amount_in: u64, amount_out: u64, positions: SwapPositions) -> Vec<u8> {
+    let mut data = amount_in.to_le_bytes().to_vec();
+    data.extend_from_slice(&amount_out.to_le_bytes());
+    data.extend_from_slice(&positions);
+    data
+}
+
+/// Raw сторона ноги через мок DEX
+fn mock_swap(amount_in: u64, amount_out: u64, positions: SwapPositions, accounts_count: usize) -> DexType {
+    let mut is_writable = vec![true; accounts_count];
+    is_writable[0] = false;
+    DexType::Raw {
+        program_id: MOCK_DEX_ID,
+        data: mock_swap_data(amount_in, amount_out, positions),
+        is_writable,
+    }
+}
+
+// ============================================================================
+// 🏗️ ФИКСТУРЫ
+// ============================================================================
+
+/// Параметры ноги без опций: все Option - None, флаги выключены
+fn leg_params(token_mint: Pubkey, buy_dex: DexType, sell_dex: DexType, accounts_count: u8) -> ArbitrageParams {
+    ArbitrageParams {
+        token_mint,
+        amount_in: 0,
+        min_wsol_out: 0,
+        buy_dex,
+        sell_dex,
+        accounts_count,
+        tokens_to_buy: 0,
+        max_sol_cost: 0,
+        tokens_to_sell: 0,
+        bridge: None,
+        token_program: None,
+        allow_same_venue: false,
+        reverse: false,
+        create_ata: false,
+        fee_recipient: None,
+        oracle: None,
+        max_oracle_deviation_bps: 0,
+        funding_account: None,
+        data_prefix: Vec::new(),
+        layout: Vec::new(),
+        cu_cap: 0,
+        referral_account: None,
+        auto_claim_seat: false,
+    }
+}
+
+/// RouterState как после initialize (quote = wSOL)
+fn initialized_state(owner: Pubkey, bump: u8) -> RouterState {
+    RouterState {
+        version: ROUTER_STATE_VERSION,
+        owner,
+        bump,
+        pump_buy_discriminator: PUMP_BUY_DISCRIMINATOR,
+        pump_sell_discriminator: PUMP_SELL_DISCRIMINATOR,
+        max_accounts_per_leg: DEFAULT_MAX_ACCOUNTS_PER_LEG,
+        fee_recipient: Pubkey::from_str(PUMP_FEE_RECIPIENT).unwrap(),
+        quote_mint: spl_token::native_mint::ID,
+        ..RouterState::default()
+    }
+}
+
+fn router_state_account(key: Pubkey, state: &RouterState) -> &'static AccountInfo<'static> {
+    let mut data = vec![0u8; RouterState::LEN];
+    state.try_serialize(&mut data.as_mut_slice()).unwrap();
+    data_account(key, ID, data)
+}
+
+/// Sysvar Rent в bincode-layout-е: lamports_per_byte_year, exemption_threshold, burn_percent
+fn rent_sysvar_account() -> &'static AccountInfo<'static> {
+    let rent = Rent::default();
+    let mut data = rent.lamports_per_byte_year.to_le_bytes().to_vec();
+    data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+    data.push(rent.burn_percent);
+    leak_account(sysvar::rent::ID, sysvar::ID, 1, data, false, false)
+}
+
+/// Аккаунты execute_batch: основной router_state с owner-ом, user со 100 SOL и пустой native wSOL аккаунт
+struct BatchFixture {
+    owner: &'static AccountInfo<'static>,
+    router_state: &'static AccountInfo<'static>,
+    user: &'static AccountInfo<'static>,
+    user_wsol_account: &'static AccountInfo<'static>,
+    token_program: &'static AccountInfo<'static>,
+    system_program: &'static AccountInfo<'static>,
+    rent: &'static AccountInfo<'static>,
+}
+
+const USER_LAMPORTS: u64 = 100_000_000_000;
+
+impl BatchFixture {
+    fn new(configure: impl FnOnce(&mut RouterState)) -> Self {
+        setup();
+        let owner = wallet(USER_LAMPORTS);
+        let (router_state_key, bump) = Pubkey::find_program_address(&[b"router_state"], &ID);
+        let mut state = initialized_state(*owner.key, bump);
+        configure(&mut state);
+        let user = wallet(USER_LAMPORTS);
+        Self {
+            owner,
+            router_state: router_state_account(router_state_key, &state),
+            user,
+            user_wsol_account: wsol_account(*user.key, 0),
+            token_program: program_account(anchor_spl::token::ID),
+            system_program: program_account(system_program::ID),
+            rent: rent_sysvar_account(),
+        }
+    }
+
+    fn accounts(&self) -> ExecuteArbitrageBatch<'static> {
+        ExecuteArbitrageBatch {
+            router_state: Account::try_from(self.router_state).unwrap(),
+            user: Signer::try_from(self.user).unwrap(),
+            user_wsol_account: InterfaceAccount::try_from(self.user_wsol_account).unwrap(),
+            token_program: Program::try_from(self.token_program).unwrap(),
+            system_program: Program::try_from(self.system_program).unwrap(),
+            rent: Sysvar::from_account_info(self.rent).unwrap(),
+            treasury_token_account: None,
+            quote_mint: None,
+            usdc_price_oracle: None,
+            instructions_sysvar: None,
+            profit_oracle: None,
+        }
+    }
+}
+
+impl BatchFixture {
+    /// BuildContext, который execute_batch собрал бы для этого router_state
+    fn build_ctx(&self) -> BuildContext<'static> {
+        BuildContext {
+            user: self.user.clone(),
+            user_quote_account: self.user_wsol_account.clone(),
+            system_program: self.system_program.clone(),
+            token_program: self.token_program.clone(),
+            rent: self.rent.clone(),
+            pump_program_id: parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap(),
+            fee_recipient: Pubkey::from_str(PUMP_FEE_RECIPIENT).unwrap(),
+            pump_buy_discriminator: PUMP_BUY_DISCRIMINATOR,
+            pump_sell_discriminator: PUMP_SELL_DISCRIMINATOR,
+            allow_raw: false,
+            enforce_distinct_venues: false,
+            min_liquidity: 0,
+            dex_discriminators: [DexDiscriminator::default(); MAX_DISCRIMINATOR_OVERRIDES],
+            scan_passes: DEFAULT_SCAN_PASSES as usize,
+        }
+    }
+}
+
+fn execute<const N: usize>(
+    accounts: &mut ExecuteArbitrageBatch<'static>,
+    remaining_accounts: &[&'static AccountInfo<'static>],
+    arbitrages: [ArbitrageParams; N],
+    options: BatchOptions,
+) -> Result<()> {
+    let bumps = ExecuteArbitrageBatchBumps {
+        router_state: accounts.router_state.bump,
+    };
+    execute_batch(Context::new(&ID, accounts, leak_slice(remaining_accounts), bumps), arbitrages, options)
+}
+
+fn batch_options(nonce: u64) -> BatchOptions {
+    BatchOptions {
+        nonce,
+        ..BatchOptions::single_leg()
+    }
+}
+
+/// Ошибка с кодом expected (источник ошибки в require! не сравнивается)
+#[track_caller]
+fn assert_error<T>(result: Result<T>, expected: MyErrorCode) {
+    match result {
+        Ok(_) => panic!("expected an error, got Ok"),
+        Err(error) => assert_eq!(error_code_of(&error), error_code_of(&anchor_lang::error::Error::from(expected))),
+    }
+}
+
+/// Raw нога BUY -> SELL через мок DEX: cost wSOL -> tokens -> proceeds wSOL
+struct MockLeg {
+    params: ArbitrageParams,
+    accounts: Vec<&'static AccountInfo<'static>>,
+    user_token: &'static AccountInfo<'static>,
+    pool_quote: &'static AccountInfo<'static>,
+}
+
+const POOL_LIQUIDITY: u64 = 1_000_000_000_000;
+
+impl MockLeg {
+    fn new(fixture: &BatchFixture, cost: u64, tokens: u64, proceeds: u64) -> Self {
+        let token_mint = Pubkey::new_unique();
+        let pool_authority = Pubkey::new_unique();
+        let user_token = token_account(token_mint, *fixture.user.key, 0);
+        let pool_quote = wsol_account(pool_authority, POOL_LIQUIDITY);
+        let pool_token = token_account(token_mint, pool_authority, POOL_LIQUIDITY);
+        let accounts = vec![
+            program_account(MOCK_DEX_ID),
+            fixture.user,
+            fixture.user_wsol_account,
+            user_token,
+            pool_quote,
+            pool_token,
+        ];
+        // [программа, user, wSOL user-а, token user-а, wSOL пула, token пула]
+        let params = ArbitrageParams {
+            amount_in: cost,
+            max_sol_cost: cost,
+            min_wsol_out: proceeds,
+            tokens_to_buy: tokens,
+            tokens_to_sell: tokens,
+            ..leg_params(
+                token_mint,
+                mock_swap(cost, tokens, [2, 3, 4, 5], accounts.len()),
+                mock_swap(tokens, proceeds, [3, 2, 5, 4], accounts.len()),
+                accounts.len() as u8,
+            )
+        };
+        Self {
+            params,
+            accounts,
+            user_token,
+            pool_quote,
+        }
+    }
+}
+
+// ============================================================================
+// ✅ ТЕСТЫ
+// ============================================================================
+
+#[test]
+fn wrap_trade_unwrap_round_trip_returns_profit_as_native_sol() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000_000, 500, 1_200_000);
+    let user_lamports_before = fixture.user.lamports();
+    let wsol_rent = fixture.user_wsol_account.lamports();
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        wrap_amount: 1_000_000,
+        unwrap_all: true,
+        ..batch_options(1)
+    };
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], options).unwrap();
+
+    // Обернули 1_000_000, наторговали +200_000, закрытие вернуло баланс и rent wSOL аккаунта
+    assert_eq!(fixture.user.lamports(), user_lamports_before + wsol_rent + 200_000);
+    assert_eq!(fixture.user_wsol_account.lamports(), 0);
+    assert_eq!(token_amount(leg.user_token), 0);
+    assert_eq!(token_amount(leg.pool_quote), POOL_LIQUIDITY - 200_000);
+    assert_eq!(
+        invoked_programs(),
+        vec![system_program::ID, anchor_spl::token::ID, MOCK_DEX_ID, MOCK_DEX_ID, anchor_spl::token::ID]
+    );
+}
+
+#[test]
+fn replayed_nonce_is_rejected_and_increasing_nonces_execute() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 10_000);
+
+    let mut accounts = fixture.accounts();
+    for nonce in [1, 2, 5] {
+        execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(nonce)).unwrap();
+        assert_eq!(accounts.router_state.last_nonce, nonce);
+    }
+
+    // Повтор последнего и любой меньший nonce отклоняются до исполнения ног
+    for nonce in [5, 3] {
+        let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(nonce));
+        assert_error(result, MyErrorCode::StaleNonce);
+    }
+    assert_eq!(accounts.router_state.last_nonce, 5);
+    assert_eq!(accounts.router_state.total_batches, 3);
+}
+
+/// Срез OpenBook v2 ноги: маркет base_mint / wSOL (base lot 100, quote lot 10) и token account user-а
+fn openbook_slice(fixture: &BatchFixture, token_mint: Pubkey, base_mint: Pubkey) -> Vec<&'static AccountInfo<'static>> {
+    let openbook_program_id = parse_program_id(OPENBOOK_V2_PROGRAM_ID).unwrap();
+    let [authority, bids, asks, event_heap, base_vault, quote_vault] = [(); 6].map(|_| Pubkey::new_unique());
+
+    let mut data = vec![0u8; OPENBOOK_MARKET_QUOTE_VAULT_OFFSET + 32];
+    data[..8].copy_from_slice(&OPENBOOK_MARKET_DISCRIMINATOR);
+    put_pubkey(&mut data, OPENBOOK_MARKET_AUTHORITY_OFFSET, &authority);
+    put_pubkey(&mut data, OPENBOOK_MARKET_BIDS_OFFSET, &bids);
+    put_pubkey(&mut data, OPENBOOK_MARKET_ASKS_OFFSET, &asks);
+    put_pubkey(&mut data, OPENBOOK_MARKET_EVENT_HEAP_OFFSET, &event_heap);
+    put_pubkey(&mut data, OPENBOOK_MARKET_BASE_VAULT_OFFSET, &base_vault);
+    put_pubkey(&mut data, OPENBOOK_MARKET_QUOTE_VAULT_OFFSET, &quote_vault);
+    put_pubkey(&mut data, OPENBOOK_MARKET_BASE_MINT_OFFSET, &base_mint);
+    put_u64(&mut data, OPENBOOK_MARKET_QUOTE_LOT_SIZE_OFFSET, 10);
+    put_u64(&mut data, OPENBOOK_MARKET_BASE_LOT_SIZE_OFFSET, 100);
+
+    let mut slice = vec![
+        program_account(openbook_program_id),
+        data_account(Pubkey::new_unique(), openbook_program_id, data),
+    ];
+    slice.extend([authority, bids, asks, event_heap].map(|key| data_account(key, openbook_program_id, vec![0u8; 8])));
+    slice.push(token_account_at(base_vault, base_mint, authority, POOL_LIQUIDITY, anchor_spl::token::ID));
+    slice.push(token_account_at(quote_vault, spl_token::native_mint::ID, authority, POOL_LIQUIDITY, anchor_spl::token::ID));
+    slice.push(token_account(token_mint, *fixture.user.key, 0));
+    slice
+}
+
+#[test]
+fn openbook_take_order_layout_and_side() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&openbook_slice(&fixture, token_mint, token_mint));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 5_000,
+        min_wsol_out: 900_000,
+        tokens_to_sell: 5_000,
+        ..leg_params(token_mint, DexType::OpenBookV2, DexType::OpenBookV2, slice.len() as u8)
+    };
+    let build_ctx = fixture.build_ctx();
+
+    let (buy, buy_accounts) = build_dex_instruction(&DexType::OpenBookV2, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    let (sell, _) = build_dex_instruction(&DexType::OpenBookV2, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+    // discriminator, side, price_lots, max_base_lots, max_quote_lots, order_type, limit
+    assert_eq!(buy.data.len(), 35);
+    assert_eq!(buy.data[..8], [0x03, 0x2c, 0x47, 0x03, 0x1a, 0xc7, 0xcb, 0x55]);
+    assert_eq!(buy.data[8], 0); // Bid
+    assert_eq!(read_data_i64(&buy.data, 9), 2_000); // 1_000_000 * 100 / (5_000 * 10)
+    assert_eq!(read_data_i64(&buy.data, 17), 50); // 5_000 / 100
+    assert_eq!(read_data_i64(&buy.data, 25), 100_000); // 1_000_000 / 10
+    assert_eq!(buy.data[33..], [1, 10]);
+
+    assert_eq!(sell.data[8], 1); // Ask
+    assert_eq!(read_data_i64(&sell.data, 9), 1_800); // 900_000 * 100 / (5_000 * 10)
+    assert_eq!(read_data_i64(&sell.data, 17), 50);
+    assert_eq!(read_data_i64(&sell.data, 25), i64::MAX);
+
+    // signer, penalty_payer, market, authority, bids, asks, vaults, event heap, user accounts, 2 оракула (None), программы
+    assert_eq!(buy.accounts.len(), 16);
+    assert_eq!(buy.accounts[0].pubkey, *fixture.user.key);
+    assert!(buy.accounts[0].is_signer);
+    assert_eq!(buy.accounts[2].pubkey, *slice[1].key);
+    assert_eq!(buy.accounts[9].pubkey, *slice[8].key);
+    assert_eq!(buy.accounts[10].pubkey, *fixture.user_wsol_account.key);
+    assert_eq!(buy.accounts[11].pubkey, buy.program_id);
+    assert_eq!(buy.accounts[12].pubkey, buy.program_id);
+    assert_eq!(buy_accounts.len(), 13);
+}
+
+#[test]
+fn openbook_market_of_another_mint_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&openbook_slice(&fixture, token_mint, Pubkey::new_unique()));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 5_000,
+        ..leg_params(token_mint, DexType::OpenBookV2, DexType::OpenBookV2, slice.len() as u8)
+    };
+
+    let result = build_dex_instruction(&DexType::OpenBookV2, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::PoolMintMismatch);
+}
+
+fn rescue(
+    fixture: &BatchFixture,
+    source: &'static AccountInfo<'static>,
+    destination: &'static AccountInfo<'static>,
+    amount: u64,
+) -> Result<()> {
+    let mut accounts = RescueTokens {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+        owner: Signer::try_from(fixture.owner).unwrap(),
+        source_token_account: Account::try_from(source).unwrap(),
+        destination_token_account: Account::try_from(destination).unwrap(),
+        token_program: Program::try_from(fixture.token_program).unwrap(),
+    };
+    let bumps = RescueTokensBumps {
+        router_state: accounts.router_state.bump,
+    };
+    dex_arbitrage_router::rescue_tokens(Context::new(&ID, &mut accounts, &[], bumps), amount)
+}
+
+#[test]
+fn rescue_tokens_moves_router_balance_to_owner_when_paused() {
+    let fixture = BatchFixture::new(|state| state.is_paused = true);
+    let mint = Pubkey::new_unique();
+    let source = token_account(mint, *fixture.router_state.key, 700);
+    let destination = token_account(mint, *fixture.owner.key, 0);
+
+    rescue(&fixture, source, destination, 700).unwrap();
+
+    assert_eq!(token_amount(source), 0);
+    assert_eq!(token_amount(destination), 700);
+}
+
+#[test]
+fn rescue_tokens_rejects_when_not_paused() {
+    let fixture = BatchFixture::new(|_| {});
+    let mint = Pubkey::new_unique();
+    let source = token_account(mint, *fixture.router_state.key, 700);
+    let destination = token_account(mint, *fixture.owner.key, 0);
+
+    assert_error(rescue(&fixture, source, destination, 700), MyErrorCode::ContractNotPaused);
+    assert_eq!(token_amount(source), 700);
+    assert!(invoked_programs().is_empty());
+}
+
+fn slippage_leg(amount_in: u64, max_sol_cost: u64, min_wsol_out: u64) -> ArbitrageParams {
+    ArbitrageParams {
+        amount_in,
+        max_sol_cost,
+        min_wsol_out,
+        ..leg_params(Pubkey::new_unique(), DexType::PumpFun, DexType::PumpFun, 8)
+    }
+}
+
+#[test]
+fn slippage_cap_accepts_legs_exactly_at_the_cap() {
+    // 1% от 1_000_000: max_sol_cost до 1_010_000, min_wsol_out от 990_000
+    assert!(check_slippage_cap(&slippage_leg(1_000_000, 1_010_000, 990_000), 100).is_ok());
+    assert!(check_slippage_cap(&slippage_leg(1_000_000, 1_000_000, 1_000_000), 100).is_ok());
+}
+
+#[test]
+fn slippage_cap_rejects_legs_beyond_the_cap() {
+    assert_error(check_slippage_cap(&slippage_leg(1_000_000, 1_010_001, 990_000), 100), MyErrorCode::SlippageExceedsCap);
+    assert_error(check_slippage_cap(&slippage_leg(1_000_000, 1_010_000, 989_999), 100), MyErrorCode::SlippageExceedsCap);
+    // Выключенный cap ничего не ограничивает
+    assert!(check_slippage_cap(&slippage_leg(1_000_000, u64::MAX, 0), 0).is_ok());
+}
+
+#[test]
+fn batch_leg_beyond_slippage_cap_is_rejected_before_any_cpi() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.max_slippage_bps = 100;
+    });
+    let leg = MockLeg::new(&fixture, 1_000_000, 500, 1_200_000);
+    fund_wsol(fixture.user_wsol_account, 2_000_000);
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_010_001,
+        ..leg.params.clone()
+    };
+
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &leg.accounts, [arbitrage], batch_options(1)), MyErrorCode::SlippageExceedsCap);
+    assert!(invoked_programs().is_empty());
+}
+
+/// Треугольник wSOL -> A -> B -> wSOL через мок DEX: 1_000_000 -> 500 A -> 2_000 B -> proceeds
+fn triangle_leg(fixture: &BatchFixture, proceeds: u64) -> (ArbitrageParams, Vec<&'static AccountInfo<'static>>) {
+    let (token_a, token_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let pool_authority = Pubkey::new_unique();
+    // [программа, user, wSOL user-а, A user-а, B user-а, wSOL пула, A пула, B пула]
+    let slice = vec![
+        program_account(MOCK_DEX_ID),
+        fixture.user,
+        fixture.user_wsol_account,
+        token_account(token_a, *fixture.user.key, 0),
+        token_account(token_b, *fixture.user.key, 0),
+        wsol_account(pool_authority, POOL_LIQUIDITY),
+        token_account(token_a, pool_authority, POOL_LIQUIDITY),
+        token_account(token_b, pool_authority, POOL_LIQUIDITY),
+    ];
+    let arbitrage = ArbitrageParams {
+        amount_in: 1_000_000,
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 500,
+        tokens_to_sell: 500,
+        bridge: Some(SwapLeg {
+            dex: mock_swap(500, 2_000, [3, 4, 6, 7], slice.len()),
+            output_mint: token_b,
+            min_amount_out: 2_000,
+        }),
+        ..leg_params(
+            token_a,
+            mock_swap(1_000_000, 500, [2, 3, 5, 6], slice.len()),
+            mock_swap(2_000, proceeds, [4, 2, 7, 5], slice.len()),
+            slice.len() as u8,
+        )
+    };
+    (arbitrage, slice)
+}
+
+#[test]
+fn triangle_feeds_each_hop_into_the_next() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+    let (arbitrage, slice) = triangle_leg(&fixture, 1_100_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage], batch_options(1)).unwrap();
+
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100_000);
+    assert_eq!(token_amount(slice[3]), 0);
+    assert_eq!(token_amount(slice[4]), 0);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID; 3]);
+    assert_eq!(accounts.router_state.cumulative_profit, 100_000);
+}
+
+#[test]
+fn triangle_ending_below_the_starting_quote_balance_fails() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+    let (arbitrage, slice) = triangle_leg(&fixture, 900_000);
+
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &slice, [arbitrage], batch_options(1)), MyErrorCode::NotProfitable);
+}
+
+fn estimate(fixture: &BatchFixture, arbitrages: [ArbitrageParams; 4]) -> Result<BatchCuEstimate> {
+    let mut accounts = ComputeEstimate {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+    };
+    let bumps = ComputeEstimateBumps {
+        router_state: accounts.router_state.bump,
+    };
+    dex_arbitrage_router::compute_estimate(Context::new(&ID, &mut accounts, &[], bumps), arbitrages)
+}
+
+#[test]
+fn two_pump_fun_legs_estimate_scan_and_two_swaps_each() {
+    let fixture = BatchFixture::new(|_| {});
+    let pump_accounts = min_accounts(&DexType::PumpFun);
+    let pump_leg = leg_params(Pubkey::new_unique(), DexType::PumpFun, DexType::PumpFun, pump_accounts);
+    let empty_slot = leg_params(Pubkey::default(), DexType::Meteora, DexType::Meteora, 0);
+
+    let leg_cu = pump_accounts as u32 * ACCOUNT_SCAN_CU + 2 * PUMP_FUN_SWAP_CU;
+    assert_eq!(leg_cu_estimate(&pump_leg).unwrap(), leg_cu);
+
+    let estimate = estimate(&fixture, [pump_leg.clone(), pump_leg, empty_slot.clone(), empty_slot]).unwrap();
+    assert_eq!(estimate.base_cu, BATCH_BASE_CU);
+    assert_eq!(estimate.legs.iter().map(|leg| (leg.index, leg.cu_estimate)).collect::<Vec<_>>(), vec![(0, leg_cu), (1, leg_cu)]);
+    assert_eq!(estimate.total_cu, BATCH_BASE_CU + 2 * leg_cu);
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn leg_without_a_builder_cannot_be_estimated() {
+    let leg = leg_params(Pubkey::new_unique(), DexType::Meteora, DexType::PumpFun, 8);
+    assert_error(leg_cu_estimate(&leg), MyErrorCode::NotImplemented);
+}
+
+/// Срез Lifinity v2 ноги: пул token_mint (A) / wSOL (B) с оракулами [main, sub, pc] и token account user-а.
+/// Оракулы в срезе - slice_oracles (могут отличаться от сконфигурированных в пуле)
+fn lifinity_slice(
+    fixture: &BatchFixture,
+    token_mint: Pubkey,
+    pool_oracles: [Pubkey; 3],
+    slice_oracles: [Pubkey; 3],
+) -> Vec<&'static AccountInfo<'static>> {
+    let lifinity_program_id = parse_program_id(LIFINITY_V2_PROGRAM_ID).unwrap();
+    let amm = Pubkey::new_unique();
+    let (authority, _) = Pubkey::find_program_address(&[amm.as_ref()], &lifinity_program_id);
+    let [token_vault, quote_vault, pool_mint, fee_account] = [(); 4].map(|_| Pubkey::new_unique());
+
+    let mut data = vec![0u8; LIFINITY_AMM_ORACLE_PC_OFFSET + 32];
+    data[..8].copy_from_slice(&LIFINITY_AMM_DISCRIMINATOR);
+    put_pubkey(&mut data, LIFINITY_AMM_TOKEN_A_ACCOUNT_OFFSET, &token_vault);
+    put_pubkey(&mut data, LIFINITY_AMM_TOKEN_B_ACCOUNT_OFFSET, &quote_vault);
+    put_pubkey(&mut data, LIFINITY_AMM_POOL_MINT_OFFSET, &pool_mint);
+    put_pubkey(&mut data, LIFINITY_AMM_TOKEN_A_MINT_OFFSET, &token_mint);
+    put_pubkey(&mut data, LIFINITY_AMM_TOKEN_B_MINT_OFFSET, &spl_token::native_mint::ID);
+    put_pubkey(&mut data, LIFINITY_AMM_FEE_ACCOUNT_OFFSET, &fee_account);
+    put_pubkey(&mut data, LIFINITY_AMM_ORACLE_MAIN_OFFSET, &pool_oracles[0]);
+    put_pubkey(&mut data, LIFINITY_AMM_ORACLE_SUB_OFFSET, &pool_oracles[1]);
+    put_pubkey(&mut data, LIFINITY_AMM_ORACLE_PC_OFFSET, &pool_oracles[2]);
+
+    let mut slice = vec![
+        program_account(lifinity_program_id),
+        data_account(amm, lifinity_program_id, data),
+        data_account(authority, system_program::ID, Vec::new()),
+        token_account_at(token_vault, token_mint, authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(quote_vault, spl_token::native_mint::ID, authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        data_account(pool_mint, anchor_spl::token::ID, vec![0u8; 82]),
+        data_account(fee_account, anchor_spl::token::ID, vec![0u8; 165]),
+    ];
+    slice.extend(slice_oracles.map(|key| data_account(key, Pubkey::new_unique(), vec![0u8; 8])));
+    slice.push(token_account(token_mint, *fixture.user.key, 0));
+    slice
+}
+
+#[test]
+fn lifinity_swap_layout_and_side() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    let slice = leak_slice(&lifinity_slice(&fixture, token_mint, oracles, oracles));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 5_000,
+        min_wsol_out: 900_000,
+        tokens_to_sell: 5_000,
+        ..leg_params(token_mint, DexType::Lifinity, DexType::Lifinity, slice.len() as u8)
+    };
+    let build_ctx = fixture.build_ctx();
+
+    let (buy, buy_accounts) = build_dex_instruction(&DexType::Lifinity, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    let (sell, _) = build_dex_instruction(&DexType::Lifinity, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+    // discriminator, amount_in, minimum_amount_out
+    assert_eq!(buy.data.len(), 24);
+    assert_eq!(buy.data[..8], [0xf8, 0xc6, 0x9e, 0x91, 0xe1, 0x75, 0x87, 0xc8]);
+    assert_eq!(read_data_u64(&buy.data, 8), 1_000_000);
+    assert_eq!(read_data_u64(&buy.data, 16), 5_000);
+    assert_eq!(read_data_u64(&sell.data, 8), 5_000);
+    assert_eq!(read_data_u64(&sell.data, 16), 900_000);
+
+    // authority, amm, user, source, destination, swap source/destination, pool mint, fee, token program, 3 оракула
+    assert_eq!(buy.accounts.len(), 13);
+    assert_eq!(buy.accounts[0].pubkey, *slice[2].key);
+    assert!(buy.accounts[2].is_signer);
+    // BUY: wSOL user-а -> token user-а, из quote vault в token vault
+    assert_eq!(buy.accounts[3].pubkey, *fixture.user_wsol_account.key);
+    assert_eq!(buy.accounts[4].pubkey, *slice[10].key);
+    assert_eq!(buy.accounts[5].pubkey, *slice[4].key);
+    assert_eq!(buy.accounts[6].pubkey, *slice[3].key);
+    // SELL: зеркально
+    assert_eq!(sell.accounts[3].pubkey, *slice[10].key);
+    assert_eq!(sell.accounts[5].pubkey, *slice[3].key);
+    assert_eq!(buy.accounts[10..].iter().map(|meta| meta.pubkey).collect::<Vec<_>>(), oracles.to_vec());
+    assert_eq!(buy_accounts.len(), 14);
+}
+
+#[test]
+fn lifinity_oracle_not_configured_in_the_pool_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    // Вместо pc-оракула пула бот подсунул чужой аккаунт
+    let spoofed = [oracles[0], oracles[1], Pubkey::new_unique()];
+    let slice = leak_slice(&lifinity_slice(&fixture, token_mint, oracles, spoofed));
+    let arbitrage = leg_params(token_mint, DexType::Lifinity, DexType::Lifinity, slice.len() as u8);
+
+    let result = build_dex_instruction(&DexType::Lifinity, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::OracleMismatch);
+}
+
+#[test]
+fn profit_fee_splits_profit_by_bps() {
+    assert_eq!(calculate_profit_fee(200_000, 1_000), 20_000);
+    assert_eq!(calculate_profit_fee(199, 2_500), 49); // округление вниз в пользу user-а
+    assert_eq!(calculate_profit_fee(u64::MAX, 10_000), u64::MAX);
+    assert_eq!(calculate_profit_fee(200_000, 0), 0);
+    assert_eq!(calculate_profit_fee(0, 1_000), 0);
+}
+
+#[test]
+fn profit_fee_goes_to_treasury_and_the_rest_stays_with_user() {
+    let treasury = Pubkey::new_unique();
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.profit_fee_bps = 1_000;
+        state.treasury = treasury;
+    });
+    let leg = MockLeg::new(&fixture, 1_000_000, 500, 1_200_000);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+    let treasury_wsol = wsol_account(treasury, 0);
+
+    let mut accounts = ExecuteArbitrageBatch {
+        treasury_token_account: Some(Account::try_from(treasury_wsol).unwrap()),
+        ..fixture.accounts()
+    };
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    assert_eq!(token_amount(treasury_wsol), 20_000);
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_180_000);
+}
+
+#[test]
+fn zero_profit_batch_sends_no_fee_and_needs_no_treasury() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.profit_fee_bps = 1_000;
+        state.treasury = Pubkey::new_unique();
+    });
+    let leg = MockLeg::new(&fixture, 1_000_000, 500, 1_000_000);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_000_000);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID, MOCK_DEX_ID]);
+}
+
+fn migrate(fixture: &BatchFixture, router_state: &'static AccountInfo<'static>) -> Result<()> {
+    let mut accounts = MigrateRouter {
+        router_state: UncheckedAccount::try_from(router_state),
+        owner: Signer::try_from(fixture.owner).unwrap(),
+        system_program: Program::try_from(fixture.system_program).unwrap(),
+    };
+    dex_arbitrage_router::migrate_router(Context::new(&ID, &mut accounts, &[], MigrateRouterBumps {}))
+}
+
+#[test]
+fn migrate_v1_account_preserves_owner_and_pause_flag() {
+    let fixture = BatchFixture::new(|_| {});
+    let legacy = RouterStateV1 {
+        owner: *fixture.owner.key,
+        is_paused: true,
+        bump: 254,
+    };
+    let mut data = RouterState::DISCRIMINATOR.to_vec();
+    legacy.serialize(&mut data).unwrap();
+    assert_eq!(data.len(), ROUTER_STATE_V1_LEN);
+    let router_state = leak_reallocatable_account(Pubkey::new_unique(), ID, rent_exempt(ROUTER_STATE_V1_LEN), &data);
+    let owner_lamports_before = fixture.owner.lamports();
+
+    migrate(&fixture, router_state).unwrap();
+
+    assert_eq!(router_state.data_len(), RouterState::LEN);
+    let migrated = RouterState::try_deserialize(&mut &router_state.data.borrow()[..]).unwrap();
+    assert_eq!(migrated.version, ROUTER_STATE_VERSION);
+    assert_eq!(migrated.owner, *fixture.owner.key);
+    assert!(migrated.is_paused);
+    assert_eq!(migrated.bump, 254);
+    assert_eq!(migrated.total_batches, 0);
+    // Rent за новый размер доплатил owner
+    assert_eq!(router_state.lamports(), rent_exempt(RouterState::LEN));
+    assert_eq!(
+        fixture.owner.lamports(),
+        owner_lamports_before - (rent_exempt(RouterState::LEN) - rent_exempt(ROUTER_STATE_V1_LEN))
+    );
+
+    // Повторная миграция уже актуального layout-а
+    assert_error(migrate(&fixture, router_state), MyErrorCode::AlreadyMigrated);
+}
+
+#[test]
+fn migrate_v1_account_rejects_foreign_signer() {
+    let fixture = BatchFixture::new(|_| {});
+    let legacy = RouterStateV1 {
+        owner: Pubkey::new_unique(),
+        is_paused: false,
+        bump: 254,
+    };
+    let mut data = RouterState::DISCRIMINATOR.to_vec();
+    legacy.serialize(&mut data).unwrap();
+    let router_state = leak_reallocatable_account(Pubkey::new_unique(), ID, rent_exempt(ROUTER_STATE_V1_LEN), &data);
+
+    assert_error(migrate(&fixture, router_state), MyErrorCode::UnauthorizedAccess);
+    assert_eq!(router_state.data_len(), ROUTER_STATE_V1_LEN);
+}
+
+/// RouterState в layout-е v17..v27: denied_mints - Vec из `denied`, резерв под MAX_DENIED_MINTS
+/// добит байтами `stale` (удаленные mint-ы). Без `with_tail` аккаунт кончается на denylist-е (v17)
+fn legacy_router_state_data(state: &RouterState, denied: &[Pubkey], stale: u8, with_tail: bool) -> Vec<u8> {
+    let mut current = Vec::new();
+    state.try_serialize(&mut current).unwrap();
+    let offset = ROUTER_STATE_DENIED_MINTS_OFFSET;
+    let tail = if with_tail { &current[offset + 32 * MAX_DENIED_MINTS + 1..] } else { &[][..] };
+
+    let mut data = current[..offset].to_vec();
+    data.extend_from_slice(&(denied.len() as u32).to_le_bytes());
+    denied.iter().for_each(|mint| data.extend_from_slice(mint.as_ref()));
+    data.extend_from_slice(tail);
+    data.resize(offset + 4 + 32 * MAX_DENIED_MINTS + tail.len(), stale);
+    data
+}
+
+#[test]
+fn migrate_v17_account_with_shrunk_denylist_zeroes_every_later_field() {
+    let fixture = BatchFixture::new(|_| {});
+    let kept = Pubkey::new_unique();
+    let legacy_state = RouterState {
+        version: 17,
+        failure_threshold: 3,
+        ..initialized_state(*fixture.owner.key, 254)
+    };
+    // Было два mint-а, один удален: за концом Vec остались его байты и мусор резерва
+    let data = legacy_router_state_data(&legacy_state, &[kept], 0xff, false);
+    let router_state = leak_reallocatable_account(Pubkey::new_unique(), ID, rent_exempt(data.len()), &data);
+
+    migrate(&fixture, router_state).unwrap();
+
+    assert_eq!(router_state.data_len(), RouterState::LEN);
+    let migrated = RouterState::try_deserialize(&mut &router_state.data.borrow()[..]).unwrap();
+    assert_eq!(migrated.version, ROUTER_STATE_VERSION);
+    assert_eq!(migrated.failure_threshold, 3);
+    assert_eq!(migrated.active_denied_mints(), [kept]);
+    assert!(migrated.denied_mints[1..].iter().all(|mint| *mint == Pubkey::default()));
+    // Все поля после denylist - нули, а не байты удаленного mint-а
+    assert_eq!(migrated.recent_batch_tags, [[0; 32]; BATCH_TAG_HISTORY]);
+    assert_eq!(migrated.batch_tag_cursor, 0);
+    assert_eq!(migrated.auto_slippage_relief_bps, 0);
+    assert_eq!(migrated.reserved_cu, 0);
+    assert_eq!(migrated.seed, [0; 8]);
+    assert_eq!(migrated.min_liquidity, 0);
+    assert_eq!(migrated.max_mints_per_batch, 0);
+    assert_eq!(migrated.dex_discriminators, [DexDiscriminator::default(); MAX_DISCRIMINATOR_OVERRIDES]);
+    assert_eq!(migrated.total_legs_executed, 0);
+    assert_eq!(migrated.profit_mint, Pubkey::default());
+    assert_eq!(migrated.profit_oracle, Pubkey::default());
+    assert_eq!(migrated.min_profit, 0);
+    assert_eq!(migrated.max_scan_passes, 0);
+}
+
+#[test]
+fn migrate_v27_account_moves_a_full_denylist_into_the_fixed_array() {
+    let fixture = BatchFixture::new(|_| {});
+    let denied = [(); MAX_DENIED_MINTS].map(|_| Pubkey::new_unique());
+    let legacy_state = RouterState {
+        version: 27,
+        batch_tag_cursor: 2,
+        reserved_cu: 40_000,
+        min_profit: 5_000,
+        max_scan_passes: 3,
+        ..initialized_state(*fixture.owner.key, 254)
+    };
+    let data = legacy_router_state_data(&legacy_state, &denied, 0, true);
+    assert_eq!(data.len(), RouterState::LEN + 3);
+    let router_state = leak_reallocatable_account(Pubkey::new_unique(), ID, rent_exempt(data.len()), &data);
+
+    migrate(&fixture, router_state).unwrap();
+
+    assert_eq!(router_state.data_len(), RouterState::LEN);
+    let migrated = RouterState::try_deserialize(&mut &router_state.data.borrow()[..]).unwrap();
+    assert_eq!(migrated.active_denied_mints(), denied);
+    // Поля v18..v27 за Vec-ом прочитаны со сдвига, а не обнулены
+    assert_eq!(migrated.batch_tag_cursor, 2);
+    assert_eq!(migrated.reserved_cu, 40_000);
+    assert_eq!(migrated.min_profit, 5_000);
+    assert_eq!(migrated.max_scan_passes, 3);
+
+    // В layout-е v28 denylist лежит с фиксированного смещения
+    let stored = router_state.data.borrow();
+    assert_eq!(stored[ROUTER_STATE_DENIED_MINTS_OFFSET..ROUTER_STATE_DENIED_MINTS_OFFSET + 32], denied[0].to_bytes());
+    assert_eq!(stored[ROUTER_STATE_DENIED_MINTS_OFFSET + 32 * MAX_DENIED_MINTS], MAX_DENIED_MINTS as u8);
+}
+
+#[test]
+fn classic_and_token_2022_legs_in_one_batch_use_their_own_token_programs() {
+    let fixture = BatchFixture::new(|_| {});
+    let build_ctx = fixture.build_ctx();
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+
+    let classic_mint = Pubkey::new_unique();
+    let classic_slice = leak_slice(&lifinity_slice(&fixture, classic_mint, oracles, oracles));
+    let classic = leg_params(classic_mint, DexType::Lifinity, DexType::Lifinity, classic_slice.len() as u8);
+
+    // Token-2022 нога: токен-аккаунт user-а под Token-2022, программа в срезе ноги
+    let token_2022_mint = Pubkey::new_unique();
+    let mut slice = lifinity_slice(&fixture, token_2022_mint, oracles, oracles);
+    slice.pop();
+    slice.push(token_account_at(Pubkey::new_unique(), token_2022_mint, *fixture.user.key, 0, anchor_spl::token_2022::ID));
+    slice.push(program_account(anchor_spl::token_2022::ID));
+    let token_2022_slice = leak_slice(&slice);
+    let token_2022 = ArbitrageParams {
+        token_program: Some(anchor_spl::token_2022::ID),
+        ..leg_params(token_2022_mint, DexType::Lifinity, DexType::Lifinity, token_2022_slice.len() as u8)
+    };
+
+    let classic_leg = prepare_leg(&classic, classic_slice, &build_ctx, 0).unwrap();
+    let token_2022_leg = prepare_leg(&token_2022, token_2022_slice, &build_ctx, 0).unwrap();
+
+    // token_program мета Lifinity swap-а - десятая
+    assert_eq!(classic_leg.buy.0.accounts[9].pubkey, anchor_spl::token::ID);
+    assert_eq!(classic_leg.sell.unwrap().0.accounts[9].pubkey, anchor_spl::token::ID);
+    assert_eq!(token_2022_leg.buy.0.accounts[9].pubkey, anchor_spl::token_2022::ID);
+    assert_eq!(token_2022_leg.sell.unwrap().0.accounts[9].pubkey, anchor_spl::token_2022::ID);
+    assert_eq!(*token_2022_leg.build_ctx.token_program.key, anchor_spl::token_2022::ID);
+}
+
+#[test]
+fn leg_token_program_missing_from_the_slice_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    let slice = leak_slice(&lifinity_slice(&fixture, token_mint, oracles, oracles));
+    let arbitrage = ArbitrageParams {
+        token_program: Some(anchor_spl::token_2022::ID),
+        ..leg_params(token_mint, DexType::Lifinity, DexType::Lifinity, slice.len() as u8)
+    };
+
+    assert_error(prepare_leg(&arbitrage, slice, &fixture.build_ctx(), 0).map(|_| ()), MyErrorCode::AccountNotFound);
+}
+
+/// Bonding curve Pump.fun в тестовом срезе
+#[derive(Clone, Copy)]
+struct PumpCurve {
+    virtual_token_reserves: u64,
+    virtual_sol_reserves: u64,
+    real_token_reserves: u64,
+    real_sol_reserves: u64,
+    complete: bool,
+}
+
+/// Кривая свежего токена (стартовые резервы Pump.fun) с 1 SOL реальной ликвидности
+const FRESH_PUMP_CURVE: PumpCurve = PumpCurve {
+    virtual_token_reserves: 1_073_000_000_000_000,
+    virtual_sol_reserves: 30_000_000_000,
+    real_token_reserves: 793_100_000_000_000,
+    real_sol_reserves: 1_000_000_000,
+    complete: false,
+};
+
+/// Комиссия Pump.fun в Global
+const PUMP_FEE_BPS: u64 = 100;
+
+fn pump_curve_data(curve: PumpCurve) -> Vec<u8> {
+    let mut data = vec![0u8; PUMP_BONDING_CURVE_COMPLETE_OFFSET + 1];
+    put_u64(&mut data, PUMP_BONDING_CURVE_VIRTUAL_TOKEN_OFFSET, curve.virtual_token_reserves);
+    put_u64(&mut data, PUMP_BONDING_CURVE_VIRTUAL_SOL_OFFSET, curve.virtual_sol_reserves);
+    put_u64(&mut data, PUMP_BONDING_CURVE_REAL_TOKEN_OFFSET, curve.real_token_reserves);
+    put_u64(&mut data, PUMP_BONDING_CURVE_REAL_SOL_OFFSET, curve.real_sol_reserves);
+    data[PUMP_BONDING_CURVE_COMPLETE_OFFSET] = curve.complete as u8;
+    data
+}
+
+/// Срез Pump.fun ноги: [program, global, fee recipient, mint, bonding curve, associated bonding curve,
+/// event authority, token account user-а]
+fn pump_slice(fixture: &BatchFixture, token_mint: Pubkey, curve: PumpCurve) -> Vec<&'static AccountInfo<'static>> {
+    let pump_program_id = parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap();
+    let [global, bonding_curve, associated_bonding_curve, event_authority] =
+        derive_pump_fun_pdas(&token_mint, &pump_program_id, &anchor_spl::token::ID);
+
+    let mut global_data = vec![0u8; PUMP_GLOBAL_FEE_BPS_OFFSET + 8];
+    put_u64(&mut global_data, PUMP_GLOBAL_FEE_BPS_OFFSET, PUMP_FEE_BPS);
+
+    vec![
+        program_account(pump_program_id),
+        data_account(global, pump_program_id, global_data),
+        data_account(Pubkey::from_str(PUMP_FEE_RECIPIENT).unwrap(), system_program::ID, Vec::new()),
+        mint_account(token_mint, 6, anchor_spl::token::ID),
+        data_account(bonding_curve, pump_program_id, pump_curve_data(curve)),
+        token_account_at(associated_bonding_curve, token_mint, bonding_curve, curve.real_token_reserves, anchor_spl::token::ID),
+        data_account(event_authority, pump_program_id, Vec::new()),
+        token_account(token_mint, *fixture.user.key, 0),
+    ]
+}
+
+#[test]
+fn pump_fun_leg_on_complete_curve_fails_early() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let complete = PumpCurve {
+        complete: true,
+        ..FRESH_PUMP_CURVE
+    };
+    let slice = leak_slice(&pump_slice(&fixture, token_mint, complete));
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8);
+    let build_ctx = fixture.build_ctx();
+
+    for side in [TradeSide::Buy, TradeSide::Sell] {
+        let result = build_dex_instruction(&DexType::PumpFun, side, slice, &arbitrage, &build_ctx);
+        assert_error(result, MyErrorCode::BondingCurveComplete);
+    }
+
+    // Та же кривая до миграции собирается
+    let slice = leak_slice(&pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE));
+    build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+}
+
+#[test]
+fn best_effort_batch_skips_complete_curve_leg_and_runs_the_rest() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let token_mint = Pubkey::new_unique();
+    let complete = PumpCurve {
+        complete: true,
+        ..FRESH_PUMP_CURVE
+    };
+    let pump_accounts = pump_slice(&fixture, token_mint, complete);
+    let pump_leg = ArbitrageParams {
+        amount_in: 1_000_000,
+        max_sol_cost: 1_000_000,
+        min_wsol_out: 1_000_000,
+        tokens_to_buy: 1_000,
+        tokens_to_sell: 1_000,
+        ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, pump_accounts.len() as u8)
+    };
+    let mock_leg = MockLeg::new(&fixture, 1_000_000, 500, 1_100_000);
+    fund_wsol(fixture.user_wsol_account, 2_000_000);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        count: 2,
+        ..batch_options(1)
+    };
+    let remaining = [&pump_accounts[..], &mock_leg.accounts[..]].concat();
+    execute(&mut accounts, &remaining, [pump_leg, mock_leg.params.clone()], options).unwrap();
+
+    let skipped = emitted::<ArbitrageSkipped>();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].index, 0);
+    assert_eq!(
+        skipped[0].reason_code,
+        error_code_of(&anchor_lang::error::Error::from(MyErrorCode::BondingCurveComplete))
+    );
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID, MOCK_DEX_ID]);
+    assert_eq!(token_amount(fixture.user_wsol_account), 2_100_000);
+}
+
+/// Четыре Raw ноги по 250_000 wSOL с прибылью 10_000 каждая
+fn four_mock_legs(fixture: &BatchFixture) -> ([ArbitrageParams; 4], Vec<&'static AccountInfo<'static>>) {
+    let legs = [(); 4].map(|_| MockLeg::new(fixture, 250_000, 100, 260_000));
+    let remaining = legs.iter().flat_map(|leg| leg.accounts.iter().copied()).collect();
+    (legs.map(|leg| leg.params), remaining)
+}
+
+#[test]
+fn four_legs_summing_exactly_to_max_total_sol_execute() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+    let (legs, remaining) = four_mock_legs(&fixture);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        max_total_sol: 1_000_000,
+        count: 4,
+        ..batch_options(1)
+    };
+    execute(&mut accounts, &remaining, legs, options).unwrap();
+
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_040_000);
+}
+
+#[test]
+fn four_legs_summing_past_max_total_sol_are_rejected_before_any_cpi() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+    let (legs, remaining) = four_mock_legs(&fixture);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        max_total_sol: 999_999,
+        count: 4,
+        ..batch_options(1)
+    };
+    assert_error(execute(&mut accounts, &remaining, legs, options), MyErrorCode::BatchSpendExceeded);
+    assert!(invoked_programs().is_empty());
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_000_000);
+}
+
+#[test]
+fn error_code_of_maps_router_custom_and_builtin_errors() {
+    let router_error = anchor_lang::error::Error::from(MyErrorCode::BondingCurveComplete);
+    assert_eq!(error_code_of(&router_error), 6000 + MyErrorCode::BondingCurveComplete as u32);
+
+    let custom_error = anchor_lang::error::Error::from(ProgramError::Custom(42));
+    assert_eq!(error_code_of(&custom_error), 42);
+
+    // Встроенные ProgramError не схлопываются в 0 и различаются между собой
+    let invalid_data = anchor_lang::error::Error::from(ProgramError::InvalidAccountData);
+    let missing_signature = anchor_lang::error::Error::from(ProgramError::MissingRequiredSignature);
+    assert_eq!(error_code_of(&invalid_data), BUILTIN_ERROR_CODE_BASE + 4);
+    assert_ne!(error_code_of(&invalid_data), error_code_of(&missing_signature));
+    assert!(error_code_of(&missing_signature) > BUILTIN_ERROR_CODE_BASE);
+}
+
+#[test]
+fn skipped_leg_event_carries_the_guard_error_and_batch_counts_it() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.max_slippage_bps = 100;
+    });
+    let mut guarded = MockLeg::new(&fixture, 1_000_000, 500, 1_100_000);
+    guarded.params.max_sol_cost = 1_020_000; // 2% сверху при cap 1%
+    let healthy = MockLeg::new(&fixture, 1_000_000, 500, 1_100_000);
+    fund_wsol(fixture.user_wsol_account, 2_020_000);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        count: 2,
+        ..batch_options(1)
+    };
+    let remaining = [&guarded.accounts[..], &healthy.accounts[..]].concat();
+    execute(&mut accounts, &remaining, [guarded.params.clone(), healthy.params.clone()], options).unwrap();
+
+    let skipped = emitted::<ArbitrageSkipped>();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].index, 0);
+    assert_eq!(
+        skipped[0].reason_code,
+        error_code_of(&anchor_lang::error::Error::from(MyErrorCode::SlippageExceedsCap))
+    );
+    let completed = emitted::<BatchCompleted>();
+    assert_eq!(completed.len(), 1);
+    assert_eq!(completed[0].executed_count, 1);
+    assert_eq!(completed[0].skipped_count, 1);
+    assert_eq!(completed[0].profit, 100_000);
+    assert_eq!(token_amount(guarded.user_token), 0);
+}
+
+#[test]
+fn derive_pump_accounts_matches_independent_derivation() {
+    let fixture = BatchFixture::new(|_| {});
+    let mint = Pubkey::new_unique();
+    let pump_program_id = Pubkey::from_str("6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P").unwrap();
+
+    let mut accounts = DerivePumpAccounts {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+    };
+    let bumps = DerivePumpAccountsBumps {
+        router_state: accounts.router_state.bump,
+    };
+    let derived = dex_arbitrage_router::derive_pump_accounts(Context::new(&ID, &mut accounts, &[], bumps), mint).unwrap();
+
+    let (bonding_curve, _) = Pubkey::find_program_address(&[b"bonding-curve", mint.as_ref()], &pump_program_id);
+    let (associated_bonding_curve, _) = Pubkey::find_program_address(
+        &[bonding_curve.as_ref(), anchor_spl::token::ID.as_ref(), mint.as_ref()],
+        &anchor_spl::associated_token::ID,
+    );
+    assert_eq!(
+        derived,
+        [
+            Pubkey::find_program_address(&[b"global"], &pump_program_id).0,
+            bonding_curve,
+            associated_bonding_curve,
+            Pubkey::find_program_address(&[b"__event_authority"], &pump_program_id).0,
+        ]
+    );
+}
+
+#[test]
+fn middle_window_executes_only_its_legs_with_their_accounts_from_offset_zero() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+    let legs = [(); 4].map(|_| MockLeg::new(&fixture, 250_000, 100, 260_000));
+    // Срезы только ног окна [1, 3)
+    let remaining = [&legs[1].accounts[..], &legs[2].accounts[..]].concat();
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        start_index: 1,
+        count: 2,
+        ..batch_options(1)
+    };
+    let arbitrages = [0, 1, 2, 3].map(|index| legs[index].params.clone());
+    execute(&mut accounts, &remaining, arbitrages, options).unwrap();
+
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_020_000);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID; 4]);
+    for leg in [&legs[0], &legs[3]] {
+        assert_eq!(token_amount(leg.pool_quote), POOL_LIQUIDITY);
+    }
+    for leg in [&legs[1], &legs[2]] {
+        assert_eq!(token_amount(leg.pool_quote), POOL_LIQUIDITY - 10_000);
+    }
+}
+
+#[test]
+fn window_past_the_end_of_the_batch_is_rejected() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let legs = [(); 4].map(|_| MockLeg::new(&fixture, 250_000, 100, 260_000));
+    let remaining = [&legs[3].accounts[..]].concat();
+
+    let mut accounts = fixture.accounts();
+    for (start_index, count) in [(3, 2), (0, 0), (4, 1)] {
+        let options = BatchOptions {
+            start_index,
+            count,
+            ..batch_options(1)
+        };
+        let arbitrages = [0, 1, 2, 3].map(|index| legs[index].params.clone());
+        assert_error(execute(&mut accounts, &remaining, arbitrages, options), MyErrorCode::InvalidBatchWindow);
+    }
+    assert!(invoked_programs().is_empty());
+}
+
+/// Discriminator-ы Pump.fun после воображаемого апгрейда программы
+const UPGRADED_PUMP_BUY_DISCRIMINATOR: [u8; 8] = [0xb1; 8];
+const UPGRADED_PUMP_SELL_DISCRIMINATOR: [u8; 8] = [0x5e; 8];
+
+/// Мок Pump.fun (позиции как в build_pump_fun_instruction): buy - max_sol_cost лампортов user-а в кривую
+/// и amount токенов из associated bonding curve, sell - токены обратно и min_sol_output лампортов user-у.
+/// Неизвестный discriminator отклоняется, как настоящей программой
+fn mock_pump_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let data = &instruction.data;
+    let read_amount = |offset: usize| -> std::result::Result<u64, ProgramError> {
+        data.get(offset..offset + 8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidInstructionData)
+    };
+    let (amount, sol_amount) = (read_amount(8)?, read_amount(16)?);
+    let bonding_curve = account_at(instruction, account_infos, 3)?;
+    let associated_bonding_curve = account_at(instruction, account_infos, 4)?;
+    let user_token = account_at(instruction, account_infos, 5)?;
+    let user = account_at(instruction, account_infos, 6)?;
+
+    let discriminator = &data[..8];
+    if discriminator == PUMP_BUY_DISCRIMINATOR || discriminator == UPGRADED_PUMP_BUY_DISCRIMINATOR {
+        move_lamports(user, bonding_curve, sol_amount)?;
+        transfer_tokens(associated_bonding_curve, user_token, amount)
+    } else if discriminator == PUMP_SELL_DISCRIMINATOR || discriminator == UPGRADED_PUMP_SELL_DISCRIMINATOR {
+        transfer_tokens(user_token, associated_bonding_curve, amount)?;
+        move_lamports(bonding_curve, user, sol_amount)
+    } else {
+        Err(ProgramError::InvalidInstructionData)
+    }
+}
+
+/// Pump.fun -> Pump.fun нога на кривой с исполняющим моком
+fn pump_leg(fixture: &BatchFixture, tokens: u64, sol_cost: u64, sol_out: u64) -> (ArbitrageParams, Vec<&'static AccountInfo<'static>>) {
+    register_program(parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap(), mock_pump_program);
+    let token_mint = Pubkey::new_unique();
+    let slice = pump_slice(fixture, token_mint, FRESH_PUMP_CURVE);
+    // Кривой нужны лампорты, чтобы оплачивать sell
+    **slice[4].lamports.borrow_mut() += FRESH_PUMP_CURVE.real_sol_reserves;
+    let arbitrage = ArbitrageParams {
+        amount_in: sol_cost,
+        max_sol_cost: sol_cost,
+        min_wsol_out: sol_out,
+        tokens_to_buy: tokens,
+        tokens_to_sell: tokens,
+        ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8)
+    };
+    (arbitrage, slice)
+}
+
+/// Инструкция owner-а с UpdateConfig; изменения RouterState сохраняются в аккаунт (как exit после инструкции)
+fn update_config(
+    fixture: &BatchFixture,
+    instruction: impl FnOnce(Context<'_, '_, '_, 'static, UpdateConfig<'static>>) -> Result<()>,
+) -> Result<()> {
+    let mut accounts = UpdateConfig {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+        owner: Signer::try_from(fixture.owner).unwrap(),
+    };
+    let bumps = UpdateConfigBumps {
+        router_state: accounts.router_state.bump,
+    };
+    instruction(Context::new(&ID, &mut accounts, &[], bumps))?;
+    accounts.exit(&ID)
+}
+
+#[test]
+fn upgraded_pump_discriminators_are_used_by_the_built_instructions() {
+    let fixture = BatchFixture::new(|_| {});
+    update_config(&fixture, |ctx| {
+        dex_arbitrage_router::set_pump_discriminators(ctx, UPGRADED_PUMP_BUY_DISCRIMINATOR, UPGRADED_PUMP_SELL_DISCRIMINATOR)
+    })
+    .unwrap();
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 1_000_000);
+
+    let mut accounts = fixture.accounts();
+    assert_eq!(accounts.router_state.pump_buy_discriminator, UPGRADED_PUMP_BUY_DISCRIMINATOR);
+    execute(&mut accounts, &slice, [arbitrage], batch_options(1)).unwrap();
+
+    let invocations = INVOCATIONS.with(|invocations| invocations.borrow().clone());
+    assert_eq!(invocations.len(), 2);
+    assert_eq!(invocations[0].data[..8], UPGRADED_PUMP_BUY_DISCRIMINATOR);
+    assert_eq!(invocations[1].data[..8], UPGRADED_PUMP_SELL_DISCRIMINATOR);
+    assert_eq!(token_amount(slice[7]), 0);
+}
+
+#[test]
+fn zero_pump_discriminators_fall_back_to_compiled_defaults() {
+    let fixture = BatchFixture::new(|state| {
+        state.pump_buy_discriminator = [0u8; 8];
+        state.pump_sell_discriminator = [0u8; 8];
+    });
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 1_000_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage], batch_options(1)).unwrap();
+
+    let invocations = INVOCATIONS.with(|invocations| invocations.borrow().clone());
+    assert_eq!(invocations[0].data[..8], PUMP_BUY_DISCRIMINATOR);
+    assert_eq!(invocations[1].data[..8], PUMP_SELL_DISCRIMINATOR);
+}
+
+/// Pump.fun с частичным исполнением BUY: user-у приходит только половина купленного
+fn mock_pump_program_half_fill(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    if instruction.data[..8] != PUMP_BUY_DISCRIMINATOR {
+        return mock_pump_program(instruction, account_infos);
+    }
+    let mut half_fill = instruction.clone();
+    let tokens = u64::from_le_bytes(instruction.data[8..16].try_into().unwrap());
+    half_fill.data[8..16].copy_from_slice(&(tokens / 2).to_le_bytes());
+    mock_pump_program(&half_fill, account_infos)
+}
+
+#[test]
+fn partial_buy_clamps_the_sell_to_tokens_actually_received() {
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 600_000);
+    register_program(parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap(), mock_pump_program_half_fill);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage], batch_options(1)).unwrap();
+
+    let invocations = INVOCATIONS.with(|invocations| invocations.borrow().clone());
+    assert_eq!(invocations.len(), 2);
+    assert_eq!(read_data_u64(&invocations[0].data, 8), 1_000);
+    // SELL пересобран на пришедшие 500 токенов, min_sol_output прежний
+    assert_eq!(invocations[1].data[..8], PUMP_SELL_DISCRIMINATOR);
+    assert_eq!(read_data_u64(&invocations[1].data, 8), 500);
+    assert_eq!(read_data_u64(&invocations[1].data, 16), 600_000);
+    assert_eq!(token_amount(slice[7]), 0);
+}
+
+#[test]
+fn buy_that_delivers_nothing_fails_before_the_sell() {
+    fn mock_pump_program_no_fill(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+        let mut no_fill = instruction.clone();
+        no_fill.data[8..16].copy_from_slice(&0u64.to_le_bytes());
+        mock_pump_program(&no_fill, account_infos)
+    }
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 600_000);
+    register_program(parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap(), mock_pump_program_no_fill);
+
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &slice, [arbitrage], batch_options(1)), MyErrorCode::InsufficientTokensReceived);
+    assert_eq!(invoked_programs().len(), 1);
+}
+
+#[test]
+fn raw_leg_passes_slice_in_order_with_writable_flags() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let slice = leak_slice(&leg.accounts);
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+
+    let (instruction, accounts) =
+        build_dex_instruction(&leg.params.buy_dex, TradeSide::Buy, slice, &leg.params, &build_ctx).unwrap();
+
+    assert_eq!(instruction.program_id, MOCK_DEX_ID);
+    assert_eq!(instruction.data, mock_swap_data(1_000, 10, [2, 3, 4, 5]));
+    let metas: Vec<Pubkey> = instruction.accounts.iter().map(|meta| meta.pubkey).collect();
+    assert_eq!(metas, leg.accounts.iter().map(|acc_info| *acc_info.key).collect::<Vec<_>>());
+    assert!(!instruction.accounts[0].is_writable);
+    assert!(instruction.accounts[1..].iter().all(|meta| meta.is_writable));
+    // Подпись - только у user-а, как в самом срезе
+    let signers: Vec<Pubkey> = instruction.accounts.iter().filter(|meta| meta.is_signer).map(|meta| meta.pubkey).collect();
+    assert_eq!(signers, vec![*fixture.user.key]);
+    assert_eq!(accounts.len(), slice.len());
+}
+
+#[test]
+fn raw_leg_executes_against_a_mock_program() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+    assert_eq!(token_amount(leg.user_token), 0);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID, MOCK_DEX_ID]);
+}
+
+#[test]
+fn raw_leg_is_rejected_when_disabled_or_malformed() {
+    let fixture = BatchFixture::new(|_| {});
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let slice = leak_slice(&leg.accounts);
+    let raw = |program_id: Pubkey, is_writable: Vec<bool>| DexType::Raw {
+        program_id,
+        data: Vec::new(),
+        is_writable,
+    };
+    let writable = vec![true; slice.len()];
+
+    // allow_raw выключен по умолчанию
+    let result = build_dex_instruction(&leg.params.buy_dex, TradeSide::Buy, slice, &leg.params, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::RawDexDisabled);
+
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+    let cases = [
+        (raw(MOCK_DEX_ID, vec![true; slice.len() - 1]), MyErrorCode::InvalidRawLeg),
+        (raw(ID, writable.clone()), MyErrorCode::InvalidRawLeg),
+        (raw(Pubkey::new_unique(), writable), MyErrorCode::AccountNotFound),
+    ];
+    for (dex, expected) in cases {
+        assert_error(build_dex_instruction(&dex, TradeSide::Buy, slice, &leg.params, &build_ctx), expected);
+    }
+}
+
+/// Три Raw ноги по 1_000 wSOL (+100 каждая); средняя ссылается на программу, которой нет в ее срезе
+fn legs_with_broken_middle(fixture: &BatchFixture) -> ([MockLeg; 3], Vec<&'static AccountInfo<'static>>) {
+    let mut legs = [(); 3].map(|_| MockLeg::new(fixture, 1_000, 10, 1_100));
+    legs[1].params.buy_dex = DexType::Raw {
+        program_id: Pubkey::new_unique(),
+        data: Vec::new(),
+        is_writable: vec![true; legs[1].accounts.len()],
+    };
+    let remaining = legs.iter().flat_map(|leg| leg.accounts.iter().copied()).collect();
+    (legs, remaining)
+}
+
+#[test]
+fn all_or_nothing_batch_with_failing_middle_leg_executes_nothing() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 3_000);
+    let (legs, remaining) = legs_with_broken_middle(&fixture);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        mode: ExecutionMode::AllOrNothing,
+        count: 3,
+        ..batch_options(1)
+    };
+    let arbitrages = [0, 1, 2].map(|index| legs[index].params.clone());
+    assert_error(execute(&mut accounts, &remaining, arbitrages, options), MyErrorCode::AccountNotFound);
+
+    assert!(invoked_programs().is_empty());
+    assert_eq!(token_amount(fixture.user_wsol_account), 3_000);
+}
+
+#[test]
+fn best_effort_batch_with_failing_middle_leg_runs_the_outer_legs() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 3_000);
+    let (legs, remaining) = legs_with_broken_middle(&fixture);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        count: 3,
+        ..batch_options(1)
+    };
+    let arbitrages = [0, 1, 2].map(|index| legs[index].params.clone());
+    execute(&mut accounts, &remaining, arbitrages, options).unwrap();
+
+    assert_eq!(token_amount(fixture.user_wsol_account), 3_200);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID; 4]);
+    assert_eq!(token_amount(legs[1].pool_quote), POOL_LIQUIDITY);
+    let executed: Vec<u8> = emitted::<ArbitrageExecuted>().iter().map(|event| event.index).collect();
+    assert_eq!(executed, vec![0, 2]);
+    assert_eq!(emitted::<ArbitrageSkipped>().iter().map(|event| event.index).collect::<Vec<_>>(), vec![1]);
+}
+
+#[test]
+fn best_effort_does_not_swallow_a_failure_after_the_first_cpi() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 3_000);
+    let mut legs = [(); 3].map(|_| MockLeg::new(&fixture, 1_000, 10, 1_100));
+    // Средняя нога продает дешевле своего min_wsol_out - это видно только после CPI
+    legs[1].params.sell_dex = mock_swap(10, 900, [3, 2, 5, 4], legs[1].accounts.len());
+    let remaining: Vec<_> = legs.iter().flat_map(|leg| leg.accounts.iter().copied()).collect();
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        count: 3,
+        ..batch_options(1)
+    };
+    let arbitrages = [0, 1, 2].map(|index| legs[index].params.clone());
+    assert_error(execute(&mut accounts, &remaining, arbitrages, options), MyErrorCode::MinOutNotMet);
+}
+
+#[test]
+fn back_to_back_batches_within_the_rate_limit_window_are_rejected() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.min_slots_between_batches = 10;
+    });
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+    assert_eq!(accounts.router_state.last_batch_slot, TEST_SLOT);
+
+    // Тот же слот и на слот раньше границы окна
+    for slot in [TEST_SLOT, TEST_SLOT + 9] {
+        SLOT.with(|current| current.set(slot));
+        let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(slot));
+        assert_error(result, MyErrorCode::RateLimited);
+    }
+
+    // Ровно min_slots_between_batches спустя - снова можно
+    SLOT.with(|current| current.set(TEST_SLOT + 10));
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(TEST_SLOT + 10)).unwrap();
+    assert_eq!(accounts.router_state.last_batch_slot, TEST_SLOT + 10);
+    assert_eq!(accounts.router_state.total_batches, 2);
+}
+
+#[test]
+fn zero_min_slots_disables_the_rate_limit() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    let mut accounts = fixture.accounts();
+    for nonce in [1, 2] {
+        execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(nonce)).unwrap();
+    }
+    assert_eq!(accounts.router_state.total_batches, 2);
+}
+
+/// Срез Phoenix ноги: маркет token_mint / wSOL (base lot 100, quote lot 10), log authority, vault-ы, token user-а
+fn phoenix_slice(fixture: &BatchFixture, token_mint: Pubkey) -> Vec<&'static AccountInfo<'static>> {
+    let phoenix_program_id = parse_program_id(PHOENIX_PROGRAM_ID).unwrap();
+    let market = Pubkey::new_unique();
+    let (log_authority, _) = Pubkey::find_program_address(&[b"log"], &phoenix_program_id);
+    let [base_vault, quote_vault] = [(); 2].map(|_| Pubkey::new_unique());
+
+    let mut data = vec![0u8; PHOENIX_MARKET_HEADER_LEN];
+    put_pubkey(&mut data, PHOENIX_MARKET_BASE_MINT_OFFSET, &token_mint);
+    put_pubkey(&mut data, PHOENIX_MARKET_BASE_VAULT_OFFSET, &base_vault);
+    put_u64(&mut data, PHOENIX_MARKET_BASE_LOT_SIZE_OFFSET, 100);
+    put_pubkey(&mut data, PHOENIX_MARKET_QUOTE_MINT_OFFSET, &spl_token::native_mint::ID);
+    put_pubkey(&mut data, PHOENIX_MARKET_QUOTE_VAULT_OFFSET, &quote_vault);
+    put_u64(&mut data, PHOENIX_MARKET_QUOTE_LOT_SIZE_OFFSET, 10);
+
+    vec![
+        program_account(phoenix_program_id),
+        data_account(log_authority, system_program::ID, Vec::new()),
+        data_account(market, phoenix_program_id, data),
+        token_account_at(base_vault, token_mint, market, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(quote_vault, spl_token::native_mint::ID, market, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account(token_mint, *fixture.user.key, 0),
+    ]
+}
+
+#[test]
+fn phoenix_ioc_packet_converts_amounts_to_lots() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&phoenix_slice(&fixture, token_mint));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_005,
+        tokens_to_buy: 5_001,
+        tokens_to_sell: 5_050,
+        min_wsol_out: 900_001,
+        ..leg_params(token_mint, DexType::Phoenix, DexType::Phoenix, slice.len() as u8)
+    };
+    let build_ctx = fixture.build_ctx();
+
+    let (buy, buy_accounts) = build_dex_instruction(&DexType::Phoenix, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    let (sell, _) = build_dex_instruction(&DexType::Phoenix, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+    // Swap, ImmediateOrCancel, side, price None, 4 x u64 лотов, хвост пакета
+    assert_eq!(buy.data.len(), 57);
+    assert_eq!(buy.data[..4], [0, 2, 0, 0]);
+    assert_eq!(read_data_u64(&buy.data, 4), 0);
+    assert_eq!(read_data_u64(&buy.data, 12), 100_000); // 1_000_005 / 10 вниз: потолок не превышаем
+    assert_eq!(read_data_u64(&buy.data, 20), 51); // 5_001 / 100 вверх: минимум не ослабляем
+    assert_eq!(read_data_u64(&buy.data, 28), 0);
+    assert!(buy.data[36..].iter().all(|&byte| byte == 0));
+
+    assert_eq!(sell.data[..4], [0, 2, 1, 0]);
+    assert_eq!(read_data_u64(&sell.data, 4), 50); // 5_050 / 100 вниз
+    assert_eq!(read_data_u64(&sell.data, 12), 0);
+    assert_eq!(read_data_u64(&sell.data, 20), 0);
+    assert_eq!(read_data_u64(&sell.data, 28), 90_001); // 900_001 / 10 вверх
+
+    // program, log authority, market, trader, base / quote user-а, vault-ы, token program
+    assert_eq!(buy.accounts.len(), 9);
+    assert_eq!(buy.accounts[2].pubkey, *slice[2].key);
+    assert!(buy.accounts[3].is_signer);
+    assert_eq!(buy.accounts[4].pubkey, *slice[5].key);
+    assert_eq!(buy.accounts[5].pubkey, *fixture.user_wsol_account.key);
+    assert_eq!(buy_accounts.len(), 9);
+}
+
+#[test]
+fn phoenix_sell_below_one_lot_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&phoenix_slice(&fixture, token_mint));
+    let arbitrage = ArbitrageParams {
+        tokens_to_sell: 99,
+        ..leg_params(token_mint, DexType::Phoenix, DexType::Phoenix, slice.len() as u8)
+    };
+
+    let result = build_dex_instruction(&DexType::Phoenix, TradeSide::Sell, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::ArithmeticError);
+}
+
+#[test]
+fn leg_at_max_accounts_per_leg_executes() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.max_accounts_per_leg = 6;
+    });
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    assert_eq!(leg.params.accounts_count, 6);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+}
+
+#[test]
+fn leg_beyond_max_accounts_per_leg_is_rejected_before_slicing() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.max_accounts_per_leg = 5;
+    });
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    let mut accounts = fixture.accounts();
+    let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1));
+    assert_error(result, MyErrorCode::AccountCountMismatch);
+    assert!(invoked_programs().is_empty());
+
+    // 255 при дефолтном потолке (0 после миграции -> DEFAULT_MAX_ACCOUNTS_PER_LEG)
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.max_accounts_per_leg = 0;
+    });
+    let mut accounts = fixture.accounts();
+    let arbitrage = ArbitrageParams {
+        accounts_count: u8::MAX,
+        ..leg.params.clone()
+    };
+    assert_error(execute(&mut accounts, &leg.accounts, [arbitrage], batch_options(1)), MyErrorCode::AccountCountMismatch);
+}
+
+#[test]
+fn unwrap_all_closes_prefunded_wsol_account_into_native_sol() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000_000, 500, 1_050_000);
+    fund_wsol(fixture.user_wsol_account, 3_000_000);
+    let wsol_lamports = fixture.user_wsol_account.lamports();
+    let user_lamports_before = fixture.user.lamports();
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        unwrap_all: true,
+        ..batch_options(1)
+    };
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], options).unwrap();
+
+    // Весь wSOL (остаток + прибыль) и rent аккаунта - нативными SOL тем же батчем
+    assert_eq!(fixture.user.lamports(), user_lamports_before + wsol_lamports + 50_000);
+    assert_eq!(fixture.user_wsol_account.lamports(), 0);
+    assert!(fixture.user_wsol_account.data.borrow().iter().all(|&byte| byte == 0));
+    assert_eq!(invoked_programs().last(), Some(&anchor_spl::token::ID));
+}
+
+#[test]
+fn without_unwrap_all_wsol_account_stays_open_for_next_batch() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000_000, 500, 1_050_000);
+    fund_wsol(fixture.user_wsol_account, 3_000_000);
+    let user_lamports_before = fixture.user.lamports();
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    assert_eq!(fixture.user.lamports(), user_lamports_before);
+    assert_eq!(token_amount(fixture.user_wsol_account), 3_050_000);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID, MOCK_DEX_ID]);
+}
+
+#[test]
+fn same_venue_round_trip_is_rejected_only_when_enforced_and_not_allowed() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    let slice = leak_slice(&lifinity_slice(&fixture, token_mint, oracles, oracles));
+    let same_venue = leg_params(token_mint, DexType::Lifinity, DexType::Lifinity, slice.len() as u8);
+    let enforcing = BuildContext {
+        enforce_distinct_venues: true,
+        ..fixture.build_ctx()
+    };
+
+    // Дефолт для совместимости - только предупреждение
+    prepare_leg(&same_venue, slice, &fixture.build_ctx(), 0).unwrap();
+    assert_error(prepare_leg(&same_venue, slice, &enforcing, 0).map(|_| ()), MyErrorCode::SameVenue);
+
+    let intentional = ArbitrageParams {
+        allow_same_venue: true,
+        ..same_venue
+    };
+    prepare_leg(&intentional, slice, &enforcing, 0).unwrap();
+}
+
+#[test]
+fn cross_venue_leg_passes_enforced_distinct_venues() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    // Lifinity (вместе с token account user-а) + маркет Phoenix без второго token account-а
+    let mut slice = lifinity_slice(&fixture, token_mint, oracles, oracles);
+    slice.extend_from_slice(&phoenix_slice(&fixture, token_mint)[..5]);
+    let slice = leak_slice(&slice);
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 5_000,
+        tokens_to_sell: 5_000,
+        min_wsol_out: 900_000,
+        ..leg_params(token_mint, DexType::Lifinity, DexType::Phoenix, slice.len() as u8)
+    };
+    let enforcing = BuildContext {
+        enforce_distinct_venues: true,
+        ..fixture.build_ctx()
+    };
+
+    let prepared = prepare_leg(&arbitrage, slice, &enforcing, 0).unwrap();
+    assert_eq!(prepared.buy.0.program_id, parse_program_id(LIFINITY_V2_PROGRAM_ID).unwrap());
+    assert_eq!(prepared.sell.unwrap().0.program_id, parse_program_id(PHOENIX_PROGRAM_ID).unwrap());
+}
+
+#[test]
+fn get_state_return_data_decodes_as_stable_borsh_layout() {
+    let treasury = Pubkey::new_unique();
+    let fixture = BatchFixture::new(|state| {
+        state.is_paused = true;
+        state.last_nonce = 77;
+        state.treasury = treasury;
+        state.total_batches = 12;
+        state.cumulative_profit = 3_400_000;
+    });
+    let mut accounts = GetState {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+    };
+    let bumps = GetStateBumps {
+        router_state: accounts.router_state.bump,
+    };
+    let view = dex_arbitrage_router::get_state(Context::new(&ID, &mut accounts, &[], bumps)).unwrap();
+
+    // Return data инструкции = Borsh RouterStateView без Anchor discriminator-а
+    let return_data = view.try_to_vec().unwrap();
+    assert_eq!(return_data[0], ROUTER_STATE_VERSION);
+    assert_eq!(return_data[1..33], fixture.owner.key.to_bytes());
+    assert_eq!(return_data[33], 1);
+    assert_eq!(read_data_u64(&return_data, 34), 77);
+
+    let decoded = RouterStateView::try_from_slice(&return_data).unwrap();
+    assert_eq!(decoded.version, ROUTER_STATE_VERSION);
+    assert_eq!(decoded.owner, *fixture.owner.key);
+    assert!(decoded.is_paused);
+    assert_eq!(decoded.treasury, treasury);
+    assert_eq!(decoded.fee_recipient, Pubkey::from_str(PUMP_FEE_RECIPIENT).unwrap());
+    assert_eq!(decoded.total_batches, 12);
+    assert_eq!(decoded.cumulative_profit, 3_400_000);
+    assert_eq!(decoded.pump_buy_discriminator, PUMP_BUY_DISCRIMINATOR);
+}
+
+#[test]
+fn cumulative_profit_accumulates_signed_and_clamps_at_the_bounds() {
+    assert_eq!(accumulate_profit(1_000, 200), 1_200);
+    assert_eq!(accumulate_profit(1_000, -200), 800);
+    assert_eq!(accumulate_profit(100, -500), 0);
+    assert_eq!(accumulate_profit(u64::MAX, 1), u64::MAX);
+}
+
+/// Выигрышная (+300) и проигрышная (loss) ноги по 1_000 wSOL
+fn win_and_loss_legs(fixture: &BatchFixture, loss: u64) -> ([ArbitrageParams; 2], Vec<&'static AccountInfo<'static>>) {
+    let win = MockLeg::new(fixture, 1_000, 10, 1_300);
+    let lose = MockLeg::new(fixture, 1_000, 10, 1_000 - loss);
+    let remaining = [&win.accounts[..], &lose.accounts[..]].concat();
+    ([win.params, lose.params], remaining)
+}
+
+#[test]
+fn mixed_win_loss_batch_settles_on_the_aggregate() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.cumulative_profit = 50;
+    });
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let (legs, remaining) = win_and_loss_legs(&fixture, 100);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+    execute(&mut accounts, &remaining, legs, options).unwrap();
+
+    let leg_profits: Vec<i64> = emitted::<ArbitrageExecuted>().iter().map(|event| event.leg_profit).collect();
+    assert_eq!(leg_profits, vec![300, -100]);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 200);
+    assert_eq!(accounts.router_state.cumulative_profit, 250);
+    assert_eq!(token_amount(fixture.user_wsol_account), 2_200);
+}
+
+#[test]
+fn mixed_batch_with_losing_aggregate_is_not_profitable() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let (legs, remaining) = win_and_loss_legs(&fixture, 301);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+    assert_error(execute(&mut accounts, &remaining, legs, options), MyErrorCode::NotProfitable);
+}
+
+#[test]
+fn event_authority_is_derived_from_each_dex_program_id() {
+    let pump_program_id = parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap();
+    let meteora_program_id = parse_program_id(METEORA_DLMM_PROGRAM_ID).unwrap();
+
+    let pump_authority = derive_event_authority(&pump_program_id);
+    let meteora_authority = derive_event_authority(&meteora_program_id);
+
+    assert_eq!(pump_authority, Pubkey::find_program_address(&[b"__event_authority"], &pump_program_id).0);
+    assert_eq!(meteora_authority, Pubkey::find_program_address(&[b"__event_authority"], &meteora_program_id).0);
+    assert_ne!(pump_authority, meteora_authority);
+
+    // В срезе находится authority именно запрошенной программы
+    let slice = leak_slice(&[
+        data_account(pump_authority, pump_program_id, Vec::new()),
+        data_account(meteora_authority, meteora_program_id, Vec::new()),
+    ]);
+    assert_eq!(*find_event_authority(slice, &pump_program_id).unwrap().key, pump_authority);
+    assert_eq!(*find_event_authority(slice, &meteora_program_id).unwrap().key, meteora_authority);
+    assert_error(find_event_authority(&slice[..1], &meteora_program_id), MyErrorCode::PDAAccountNotFound);
+}
+
+fn drain(fixture: &BatchFixture, amount: u64) -> Result<()> {
+    let mut accounts = DrainLamports {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+        owner: Signer::try_from(fixture.owner).unwrap(),
+    };
+    let bumps = DrainLamportsBumps {
+        router_state: accounts.router_state.bump,
+    };
+    dex_arbitrage_router::drain_lamports(Context::new(&ID, &mut accounts, &[], bumps), amount)
+}
+
+#[test]
+fn drain_returns_stray_lamports_and_keeps_router_rent_exempt() {
+    let fixture = BatchFixture::new(|_| {});
+    let rent_minimum = rent_exempt(RouterState::LEN);
+    **fixture.router_state.lamports.borrow_mut() = rent_minimum + 5_000_000;
+    let owner_lamports_before = fixture.owner.lamports();
+
+    drain(&fixture, 5_000_000).unwrap();
+    assert_eq!(fixture.router_state.lamports(), rent_minimum);
+    assert_eq!(fixture.owner.lamports(), owner_lamports_before + 5_000_000);
+
+    // Ни лампорта ниже rent-exemption, и без переполнения на абсурдной сумме
+    for amount in [1, u64::MAX] {
+        assert_error(drain(&fixture, amount), MyErrorCode::RentExemptionViolation);
+    }
+    assert_eq!(fixture.router_state.lamports(), rent_minimum);
+}
+
+/// Reverse нога: продаем 500 имеющихся токенов за 1_100_000 wSOL и откупаем bought_back за 1_000_000
+fn reverse_leg(fixture: &BatchFixture, tokens_held: u64, bought_back: u64) -> MockLeg {
+    let mut leg = MockLeg::new(fixture, 1_000_000, 500, 1_100_000);
+    leg.params.reverse = true;
+    leg.params.buy_dex = mock_swap(1_000_000, bought_back, [2, 3, 4, 5], leg.accounts.len());
+    update_token_account(leg.user_token, |account| {
+        account.amount = tokens_held;
+        Ok(())
+    })
+    .unwrap();
+    leg
+}
+
+#[test]
+fn reverse_leg_sells_first_and_accumulates_the_token() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = reverse_leg(&fixture, 500, 520);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    let invocations = INVOCATIONS.with(|invocations| invocations.borrow().clone());
+    // SELL (500 токенов) идет раньше BUY
+    assert_eq!(invocations[0].data, mock_swap_data(500, 1_100_000, [3, 2, 5, 4]));
+    assert_eq!(invocations[1].data, mock_swap_data(1_000_000, 520, [2, 3, 4, 5]));
+    assert_eq!(token_amount(leg.user_token), 520);
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100_000);
+}
+
+#[test]
+fn reverse_leg_that_ends_with_fewer_tokens_is_not_profitable() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = reverse_leg(&fixture, 500, 480);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)), MyErrorCode::NotProfitable);
+}
+
+#[test]
+fn reverse_leg_without_tokens_to_sell_is_rejected_before_any_cpi() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = reverse_leg(&fixture, 499, 520);
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+
+    let mut accounts = fixture.accounts();
+    let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1));
+    assert_error(result, MyErrorCode::InsufficientTokenBalance);
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn dex_cu_cost_has_an_entry_per_variant_and_a_sentinel_for_unimplemented() {
+    let whirlpool = DexType::WhirlpoolLike {
+        program_id: Pubkey::new_unique(),
+    };
+    let raw = DexType::Raw {
+        program_id: MOCK_DEX_ID,
+        data: Vec::new(),
+        is_writable: Vec::new(),
+    };
+    let table = [
+        (DexType::PumpFun, PUMP_FUN_SWAP_CU),
+        (DexType::OpenBookV2, OPENBOOK_V2_SWAP_CU),
+        (DexType::Lifinity, LIFINITY_SWAP_CU),
+        (DexType::Phoenix, PHOENIX_SWAP_CU),
+        (DexType::RaydiumClmm, RAYDIUM_CLMM_SWAP_CU),
+        (DexType::OrcaV1, ORCA_V1_SWAP_CU),
+        (DexType::StableSwap, STABLE_SWAP_CU),
+        (DexType::Invariant, INVARIANT_SWAP_CU),
+        (DexType::Crema, CREMA_SWAP_CU),
+        (whirlpool, WHIRLPOOL_SWAP_CU),
+        (raw, RAW_CPI_CU),
+    ];
+    for (dex, expected) in table.iter() {
+        assert_eq!(dex_cu_cost(dex), *expected, "{:?}", dex);
+        assert_ne!(dex_cu_cost(dex), UNSUPPORTED_DEX_CU, "{:?}", dex);
+        // Один CPI любого DEX-а помещается в транзакцию с запасом под батч
+        assert!(dex_cu_cost(dex) + BATCH_BASE_CU < MAX_TRANSACTION_CU);
+    }
+    // Каждому варианту (кроме Meteora) - своя строка таблицы
+    let mut indexes: Vec<u8> = table.iter().map(|(dex, _)| dex_index(dex)).collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+    assert_eq!(indexes.len(), table.len());
+
+    assert_eq!(dex_cu_cost(&DexType::Meteora), UNSUPPORTED_DEX_CU);
+}
+
+#[test]
+fn non_executable_account_at_pump_program_id_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+    // Ключ программы верный, но это обычный (не исполняемый) аккаунт
+    slice[0] = leak_account(*slice[0].key, bpf_loader::ID, rent_exempt(0), Vec::new(), false, false);
+    let slice = leak_slice(&slice);
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8);
+
+    let result = build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::InvalidProgramId);
+}
+
+/// Raw нога, чей token account user-а - еще не созданный ATA (в срезе также mint и ATA program)
+fn leg_with_missing_ata(fixture: &BatchFixture) -> (ArbitrageParams, Vec<&'static AccountInfo<'static>>) {
+    let token_mint = Pubkey::new_unique();
+    let pool_authority = Pubkey::new_unique();
+    let ata = get_associated_token_address_with_program_id(fixture.user.key, &token_mint, &anchor_spl::token::ID);
+    let slice = vec![
+        program_account(MOCK_DEX_ID),
+        fixture.user,
+        fixture.user_wsol_account,
+        leak_account(ata, system_program::ID, 0, Vec::new(), false, false),
+        wsol_account(pool_authority, POOL_LIQUIDITY),
+        token_account(token_mint, pool_authority, POOL_LIQUIDITY),
+        mint_account(token_mint, 6, anchor_spl::token::ID),
+        program_account(anchor_spl::associated_token::ID),
+    ];
+    let arbitrage = ArbitrageParams {
+        amount_in: 1_000,
+        max_sol_cost: 1_000,
+        min_wsol_out: 1_100,
+        tokens_to_buy: 10,
+        tokens_to_sell: 10,
+        create_ata: true,
+        ..leg_params(
+            token_mint,
+            mock_swap(1_000, 10, [2, 3, 4, 5], slice.len()),
+            mock_swap(10, 1_100, [3, 2, 5, 4], slice.len()),
+            slice.len() as u8,
+        )
+    };
+    (arbitrage, slice)
+}
+
+#[test]
+fn missing_ata_is_created_then_used_by_the_buy() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (arbitrage, slice) = leg_with_missing_ata(&fixture);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let user_lamports_before = fixture.user.lamports();
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage.clone()], batch_options(1)).unwrap();
+
+    assert_eq!(invoked_programs(), vec![anchor_spl::associated_token::ID, MOCK_DEX_ID, MOCK_DEX_ID]);
+    let ata = slice[3];
+    assert_eq!(ata.owner, &anchor_spl::token::ID);
+    assert_eq!(token_amount(ata), 0);
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+    // Rent ATA платит user
+    assert_eq!(fixture.user.lamports(), user_lamports_before - rent_exempt(spl_token::state::Account::LEN));
+
+    // Следующий батч: ATA уже есть - создавать нечего
+    execute(&mut accounts, &slice, [arbitrage], batch_options(2)).unwrap();
+    assert_eq!(invoked_programs()[3..], [MOCK_DEX_ID, MOCK_DEX_ID]);
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_200);
+}
+
+/// RouterStateView, который вернул бы get_state для router_state фикстуры
+fn state_view(fixture: &BatchFixture) -> RouterStateView {
+    let mut accounts = GetState {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+    };
+    let bumps = GetStateBumps {
+        router_state: accounts.router_state.bump,
+    };
+    dex_arbitrage_router::get_state(Context::new(&ID, &mut accounts, &[], bumps)).unwrap()
+}
+
+#[test]
+fn suggested_cu_price_is_stored_and_read_back_through_get_state() {
+    let fixture = BatchFixture::new(|_| {});
+    assert_eq!(state_view(&fixture).suggested_cu_price_microlamports, 0);
+
+    update_config(&fixture, |ctx| dex_arbitrage_router::set_suggested_cu_price(ctx, 25_000)).unwrap();
+
+    assert_eq!(state_view(&fixture).suggested_cu_price_microlamports, 25_000);
+}
+
+#[test]
+fn missing_associated_bonding_curve_has_its_own_error() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+    let associated_bonding_curve = *slice[5].key;
+    let build_ctx = fixture.build_ctx();
+
+    // Bonding curve есть, а ATA кривой в срезе нет
+    let mut without_ata = slice.clone();
+    without_ata.remove(5);
+    let without_ata = leak_slice(&without_ata);
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, without_ata.len() as u8);
+    let result = build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, without_ata, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::AssociatedBondingCurveMissing);
+
+    // Ключ передан, но аккаунт еще не создан - то же самое, пока не разрешен create_ata
+    slice[5] = leak_account(associated_bonding_curve, system_program::ID, 0, Vec::new(), false, false);
+    let slice = leak_slice(&slice);
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::AssociatedBondingCurveMissing);
+
+    let creating = ArbitrageParams {
+        create_ata: true,
+        ..arbitrage
+    };
+    let (instruction, _) = build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &creating, &build_ctx).unwrap();
+    assert_eq!(instruction.accounts[4].pubkey, associated_bonding_curve);
+}
+
+#[test]
+fn per_leg_fee_recipient_overrides_the_router_default() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let rotated = Pubkey::new_unique();
+    let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+    // В срезе только ротированный recipient, дефолтного роутера нет
+    slice[2] = data_account(rotated, system_program::ID, Vec::new());
+    let slice = leak_slice(&slice);
+    let default_leg = ArbitrageParams {
+        amount_in: 1_000_000,
+        max_sol_cost: 1_000_000,
+        min_wsol_out: 900_000,
+        tokens_to_buy: 10_000,
+        tokens_to_sell: 10_000,
+        ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8)
+    };
+    let build_ctx = fixture.build_ctx();
+    assert_ne!(build_ctx.fee_recipient, rotated);
+
+    let result = prepare_leg(&default_leg, slice, &build_ctx, 0).map(|_| ());
+    assert_error(result, MyErrorCode::AccountNotFound);
+
+    let overridden = ArbitrageParams {
+        fee_recipient: Some(rotated),
+        ..default_leg.clone()
+    };
+    let prepared = prepare_leg(&overridden, slice, &build_ctx, 0).unwrap();
+    assert_eq!(prepared.buy.0.accounts[1].pubkey, rotated);
+    assert_eq!(prepared.sell.unwrap().0.accounts[1].pubkey, rotated);
+
+    // Override, которого нет в срезе, отклоняется
+    let absent = ArbitrageParams {
+        fee_recipient: Some(Pubkey::new_unique()),
+        ..default_leg
+    };
+    let result = prepare_leg(&absent, slice, &build_ctx, 0).map(|_| ());
+    assert_error(result, MyErrorCode::AccountNotFound);
+}
+
+#[test]
+fn shutdown_is_permanent_and_rejects_every_later_batch() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 10_000);
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    update_config(&fixture, dex_arbitrage_router::shutdown).unwrap();
+    assert!(state_view(&fixture).is_shutdown);
+
+    let mut accounts = fixture.accounts();
+    let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(2));
+    assert_error(result, MyErrorCode::RouterShutdown);
+
+    // Пауза - отдельный флаг: toggle_pause туда-обратно shutdown не снимает, повторный shutdown - no-op
+    for _ in 0..2 {
+        let mut toggle = TogglePause {
+            router_state: Account::try_from(fixture.router_state).unwrap(),
+            owner: Signer::try_from(fixture.owner).unwrap(),
+        };
+        let bumps = TogglePauseBumps {
+            router_state: toggle.router_state.bump,
+        };
+        dex_arbitrage_router::toggle_pause(Context::new(&ID, &mut toggle, &[], bumps)).unwrap();
+        toggle.exit(&ID).unwrap();
+    }
+    update_config(&fixture, dex_arbitrage_router::shutdown).unwrap();
+
+    let view = state_view(&fixture);
+    assert!(view.is_shutdown);
+    assert!(!view.is_paused);
+    let mut accounts = fixture.accounts();
+    let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(3));
+    assert_error(result, MyErrorCode::RouterShutdown);
+    assert_eq!(accounts.router_state.total_batches, 1);
+}
+
+/// Цена ушла: мок Pump.fun отклоняет buy с max_sol_cost ниже этой суммы (TooMuchSolRequired)
+const PRICE_MOVED_SOL_COST: u64 = 1_050_000;
+const PUMP_TOO_MUCH_SOL_REQUIRED: u32 = 6002;
+
+fn mock_pump_program_price_moved(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let max_sol_cost = u64::from_le_bytes(instruction.data[16..24].try_into().unwrap());
+    if instruction.data[..8] == PUMP_BUY_DISCRIMINATOR && max_sol_cost < PRICE_MOVED_SOL_COST {
+        return Err(ProgramError::Custom(PUMP_TOO_MUCH_SOL_REQUIRED));
+    }
+    mock_pump_program(instruction, account_infos)
+}
+
+#[test]
+fn failed_buy_cpi_is_not_retried_and_the_widest_cost_within_the_cap_executes() {
+    let fixture = BatchFixture::new(|state| state.max_slippage_bps = 500);
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 1_100_000);
+    register_program(parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap(), mock_pump_program_price_moved);
+    let best_effort = |nonce| BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        ..batch_options(nonce)
+    };
+
+    // Упавший CPI роняет инструкцию целиком: повторить buy с другой ценой роутер не может
+    let mut accounts = fixture.accounts();
+    let error = execute(&mut accounts, &slice, [arbitrage.clone()], best_effort(1)).unwrap_err();
+    assert_eq!(error_code_of(&error), PUMP_TOO_MUCH_SOL_REQUIRED);
+    assert_eq!(invoked_programs().len(), 1);
+
+    // Вместо лестницы - сразу верхняя ступень, ограниченная max_slippage_bps
+    let widest = ArbitrageParams {
+        max_sol_cost: PRICE_MOVED_SOL_COST,
+        ..arbitrage
+    };
+    execute(&mut accounts, &slice, [widest], best_effort(2)).unwrap();
+    let invocations = INVOCATIONS.with(|invocations| invocations.borrow().clone());
+    assert_eq!(read_data_u64(&invocations[1].data, 16), PRICE_MOVED_SOL_COST);
+    assert_eq!(emitted::<ArbitrageExecuted>().len(), 1);
+}
+
+/// Две Raw ноги на разные mint-ы; срез второй по ошибке захватил bonding curve первой
+fn legs_sharing_a_bonding_curve(fixture: &BatchFixture) -> ([ArbitrageParams; 2], Vec<&'static AccountInfo<'static>>) {
+    let first = MockLeg::new(fixture, 1_000, 10, 1_100);
+    let mut second = MockLeg::new(fixture, 1_000, 10, 1_100);
+    let (first_curve, _) = Pubkey::find_program_address(
+        &[b"bonding-curve", first.params.token_mint.as_ref()],
+        &parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap(),
+    );
+    second.accounts.push(data_account(first_curve, system_program::ID, Vec::new()));
+    let accounts_count = second.accounts.len();
+    second.params = ArbitrageParams {
+        buy_dex: mock_swap(1_000, 10, [2, 3, 4, 5], accounts_count),
+        sell_dex: mock_swap(10, 1_100, [3, 2, 5, 4], accounts_count),
+        accounts_count: accounts_count as u8,
+        ..second.params
+    };
+    let remaining = first.accounts.iter().chain(second.accounts.iter()).copied().collect();
+    ([first.params, second.params], remaining)
+}
+
+#[test]
+fn strict_leg_accounts_rejects_a_slice_holding_another_legs_bonding_curve() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.strict_leg_accounts = true;
+    });
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let (arbitrages, remaining) = legs_sharing_a_bonding_curve(&fixture);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+    assert_error(execute(&mut accounts, &remaining, arbitrages, options), MyErrorCode::AccountCountMismatch);
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn without_strict_leg_accounts_a_shared_bonding_curve_is_not_checked() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let (arbitrages, remaining) = legs_sharing_a_bonding_curve(&fixture);
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+    execute(&mut accounts, &remaining, arbitrages, options).unwrap();
+    assert_eq!(token_amount(fixture.user_wsol_account), 2_200);
+}
+
+/// Pyth PriceAccount в статусе Trading: price * 10^expo, опубликована в pub_slot
+fn pyth_price_account(price: i64, expo: i32, pub_slot: u64) -> &'static AccountInfo<'static> {
+    let mut data = vec![0u8; PYTH_AGG_PUB_SLOT_OFFSET + 8];
+    data[..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+    data[PYTH_EXPO_OFFSET..PYTH_EXPO_OFFSET + 4].copy_from_slice(&expo.to_le_bytes());
+    put_u64(&mut data, PYTH_AGG_PRICE_OFFSET, price as u64);
+    data[PYTH_AGG_STATUS_OFFSET..PYTH_AGG_STATUS_OFFSET + 4].copy_from_slice(&PYTH_STATUS_TRADING.to_le_bytes());
+    put_u64(&mut data, PYTH_AGG_PUB_SLOT_OFFSET, pub_slot);
+    data_account(Pubkey::new_unique(), parse_program_id(PYTH_ORACLE_PROGRAM_ID).unwrap(), data)
+}
+
+/// Switchboard v2 aggregator: последний раунд = mantissa * 10^-scale, открыт в round_open_slot
+fn switchboard_aggregator_account(mantissa: i128, scale: u32, round_open_slot: u64) -> &'static AccountInfo<'static> {
+    let mut data = vec![0u8; SWITCHBOARD_RESULT_SCALE_OFFSET + 4];
+    put_u64(&mut data, SWITCHBOARD_ROUND_OPEN_SLOT_OFFSET, round_open_slot);
+    data[SWITCHBOARD_RESULT_MANTISSA_OFFSET..SWITCHBOARD_RESULT_MANTISSA_OFFSET + 16]
+        .copy_from_slice(&mantissa.to_le_bytes());
+    data[SWITCHBOARD_RESULT_SCALE_OFFSET..SWITCHBOARD_RESULT_SCALE_OFFSET + 4].copy_from_slice(&scale.to_le_bytes());
+    data_account(Pubkey::new_unique(), parse_program_id(SWITCHBOARD_V2_PROGRAM_ID).unwrap(), data)
+}
+
+#[test]
+fn oracle_deviation_accepts_prices_in_range_and_rejects_outside_for_pyth_and_switchboard() {
+    let token_mint = Pubkey::new_unique();
+    let mint = mint_account(token_mint, 6, anchor_spl::token::ID);
+    // Оба оракула: 0.001 SOL за целый токен
+    for oracle in [pyth_price_account(100_000, -8, TEST_SLOT), switchboard_aggregator_account(1, 3, TEST_SLOT)] {
+        let slice = leak_slice(&[oracle, mint]);
+        // 1 токен (6 знаков) за max_sol_cost лампортов, допуск 5%
+        let leg = |max_sol_cost: u64| ArbitrageParams {
+            max_sol_cost,
+            tokens_to_buy: 1_000_000,
+            oracle: Some(*oracle.key),
+            max_oracle_deviation_bps: 500,
+            ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, 2)
+        };
+
+        for in_range in [1_000_000, 1_040_000, 1_050_000, 950_000] {
+            check_oracle_deviation(&leg(in_range), slice).unwrap();
+        }
+        for out_of_range in [1_060_000, 940_000, 2_000_000] {
+            assert_error(check_oracle_deviation(&leg(out_of_range), slice), MyErrorCode::OracleDeviation);
+        }
+    }
+}
+
+#[test]
+fn oracle_of_an_unknown_program_or_missing_from_the_slice_is_rejected() {
+    let token_mint = Pubkey::new_unique();
+    let mint = mint_account(token_mint, 6, anchor_spl::token::ID);
+    let forged = data_account(Pubkey::new_unique(), Pubkey::new_unique(), vec![0u8; 512]);
+    let leg = |oracle: Pubkey| ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 1_000_000,
+        oracle: Some(oracle),
+        max_oracle_deviation_bps: 500,
+        ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, 2)
+    };
+
+    let slice = leak_slice(&[forged, mint]);
+    assert_error(check_oracle_deviation(&leg(*forged.key), slice), MyErrorCode::InvalidProgramId);
+    assert_error(check_oracle_deviation(&leg(Pubkey::new_unique()), slice), MyErrorCode::AccountNotFound);
+}
+
+/// Squads-подобный multisig: его vault - PDA [b"vault"], подписывающий CPI в роутер
+const MOCK_MULTISIG_ID: Pubkey = Pubkey::new_from_array([9u8; 32]);
+
+/// Роутер как цель CPI: полный Anchor dispatch (constraints аккаунтов, Signer, exit)
+fn router_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let accounts: Vec<AccountInfo> = instruction
+        .accounts
+        .iter()
+        .map(|meta| {
+            account_infos
+                .iter()
+                .find(|acc_info| acc_info.key == &meta.pubkey)
+                .cloned()
+                .ok_or(ProgramError::NotEnoughAccountKeys)
+        })
+        .collect::<std::result::Result<_, _>>()?;
+    entry(&instruction.program_id, Box::leak(accounts.into_boxed_slice()), &instruction.data)
+}
+
+/// CPI в роутер от имени multisig-а; vault подписывает через invoke_signed
+fn invoke_from_multisig(instruction: &Instruction, account_infos: &[AccountInfo], vault_bump: u8) -> ProgramResult {
+    CALLER_PROGRAM.with(|caller_program| caller_program.set(MOCK_MULTISIG_ID));
+    let result = invoke_signed(instruction, account_infos, &[&[b"vault", &[vault_bump]]]);
+    CALLER_PROGRAM.with(|caller_program| caller_program.set(ID));
+    result
+}
+
+#[test]
+fn pda_owner_accepts_ownership_and_runs_owner_only_instructions_via_invoke_signed() {
+    let fixture = BatchFixture::new(|_| {});
+    register_program(ID, router_program);
+    let (vault, vault_bump) = Pubkey::find_program_address(&[b"vault"], &MOCK_MULTISIG_ID);
+    let vault_info = leak_account(vault, system_program::ID, 0, Vec::new(), false, false);
+    let account_infos = [fixture.router_state.clone(), vault_info.clone(), fixture.owner.clone()];
+
+    update_config(&fixture, |ctx| dex_arbitrage_router::propose_new_owner(ctx, vault)).unwrap();
+
+    let accept = Instruction {
+        program_id: ID,
+        accounts: accounts::AcceptOwnership {
+            router_state: *fixture.router_state.key,
+            new_owner: vault,
+        }
+        .to_account_metas(None),
+        data: instruction::AcceptOwnership {}.data(),
+    };
+    // Без подписи vault-а (invoke без seeds) Signer не проходит
+    let not_signed = ProgramError::from(anchor_lang::error::Error::from(anchor_lang::error::ErrorCode::AccountNotSigner));
+    assert_eq!(invoke(&accept, &account_infos), Err(not_signed));
+    invoke_from_multisig(&accept, &account_infos, vault_bump).unwrap();
+    assert_eq!(state_view(&fixture).owner, vault);
+
+    // Owner-only инструкция от multisig-а проходит has_one, от прежнего owner-а - нет
+    let set_cu_price = |owner: Pubkey| Instruction {
+        program_id: ID,
+        accounts: accounts::UpdateConfig {
+            router_state: *fixture.router_state.key,
+            owner,
+        }
+        .to_account_metas(None),
+        data: instruction::SetSuggestedCuPrice {
+            suggested_cu_price_microlamports: 7_500,
+        }
+        .data(),
+    };
+    invoke_from_multisig(&set_cu_price(vault), &account_infos, vault_bump).unwrap();
+    assert_eq!(state_view(&fixture).suggested_cu_price_microlamports, 7_500);
+
+    let unauthorized = ProgramError::from(anchor_lang::error::Error::from(MyErrorCode::UnauthorizedAccess));
+    assert_eq!(invoke(&set_cu_price(*fixture.owner.key), &account_infos), Err(unauthorized));
+}
+
+/// initialize на пустом аккаунте router_state (как после init) с заданным quote mint
+fn initialize_router(quote_mint: Pubkey) -> Account<'static, RouterState> {
+    setup();
+    let (router_state_key, bump) = Pubkey::find_program_address(&[b"router_state"], &ID);
+    let router_state = data_account(router_state_key, ID, vec![0u8; RouterState::LEN]);
+    let mut accounts = Initialize {
+        router_state: Account::try_from_unchecked(router_state).unwrap(),
+        owner: Signer::try_from(wallet(USER_LAMPORTS)).unwrap(),
+        system_program: Program::try_from(program_account(system_program::ID)).unwrap(),
+    };
+    let bumps = InitializeBumps { router_state: bump };
+    dex_arbitrage_router::initialize(Context::new(&ID, &mut accounts, &[], bumps), quote_mint).unwrap();
+    accounts.exit(&ID).unwrap();
+    Account::try_from(router_state).unwrap()
+}
+
+#[test]
+fn initialize_stores_a_custom_quote_mint() {
+    let local_quote_mint = Pubkey::new_unique();
+    assert_eq!(initialize_router(local_quote_mint).effective_quote_mint(), local_quote_mint);
+    // Нулевой mint - канонический wSOL
+    assert_eq!(initialize_router(Pubkey::default()).effective_quote_mint(), spl_token::native_mint::ID);
+}
+
+#[test]
+fn batch_settles_profit_in_a_custom_quote_mint() {
+    let local_quote_mint = Pubkey::new_unique();
+    let default_fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.quote_mint = local_quote_mint;
+    });
+    let fixture = BatchFixture {
+        user_wsol_account: token_account(local_quote_mint, *default_fixture.user.key, 1_000),
+        ..default_fixture
+    };
+    let mut leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    // Пул котируется в том же локальном mint-е
+    leg.pool_quote = token_account(local_quote_mint, Pubkey::new_unique(), POOL_LIQUIDITY);
+    leg.accounts[4] = leg.pool_quote;
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+    assert_eq!(token_amount(leg.pool_quote), POOL_LIQUIDITY - 100);
+    let completed = emitted::<BatchCompleted>();
+    assert_eq!(completed[0].profit, 100);
+}
+
+#[test]
+fn underfunded_user_is_rejected_before_any_cpi() {
+    // Pump.fun BUY платится нативными SOL: нужно max_sol_cost + USER_LAMPORTS_RESERVE
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 1_100_000);
+    **fixture.user.lamports.borrow_mut() = 1_000_000 + USER_LAMPORTS_RESERVE - 1;
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &slice, [arbitrage], batch_options(1)), MyErrorCode::InsufficientFunds);
+    assert!(invoked_programs().is_empty());
+
+    // Ровно на границе хватает
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 1_100_000);
+    **fixture.user.lamports.borrow_mut() = 1_000_000 + USER_LAMPORTS_RESERVE;
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage], batch_options(1)).unwrap();
+}
+
+#[test]
+fn underfunded_wsol_or_wrap_is_rejected_before_any_cpi() {
+    // wSOL ноги: баланс user_wsol_account меньше суммы max_sol_cost
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 999);
+    let mut accounts = fixture.accounts();
+    assert_error(
+        execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)),
+        MyErrorCode::InsufficientFunds,
+    );
+    assert!(invoked_programs().is_empty());
+
+    // Обернуть больше, чем есть нативных SOL (с запасом), тоже нельзя
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let options = BatchOptions {
+        wrap_amount: USER_LAMPORTS - USER_LAMPORTS_RESERVE + 1,
+        ..batch_options(1)
+    };
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &leg.accounts, [leg.params.clone()], options), MyErrorCode::InsufficientFunds);
+    assert!(invoked_programs().is_empty());
+}
+
+/// Срез Raydium CLMM ноги: пул wSOL (mint_0) / token_mint (mint_1), tick_arrays tick array-ев и token user-а
+fn raydium_clmm_slice(fixture: &BatchFixture, token_mint: Pubkey, tick_arrays: usize) -> Vec<&'static AccountInfo<'static>> {
+    let clmm_program_id = parse_program_id(RAYDIUM_CLMM_PROGRAM_ID).unwrap();
+    let [pool, amm_config, quote_vault, token_vault, observation] = [(); 5].map(|_| Pubkey::new_unique());
+
+    let mut data = vec![0u8; RAYDIUM_CLMM_POOL_OBSERVATION_OFFSET + 32];
+    data[..8].copy_from_slice(&RAYDIUM_CLMM_POOL_DISCRIMINATOR);
+    put_pubkey(&mut data, RAYDIUM_CLMM_POOL_AMM_CONFIG_OFFSET, &amm_config);
+    put_pubkey(&mut data, RAYDIUM_CLMM_POOL_TOKEN_MINT_0_OFFSET, &spl_token::native_mint::ID);
+    put_pubkey(&mut data, RAYDIUM_CLMM_POOL_TOKEN_MINT_1_OFFSET, &token_mint);
+    put_pubkey(&mut data, RAYDIUM_CLMM_POOL_TOKEN_VAULT_0_OFFSET, &quote_vault);
+    put_pubkey(&mut data, RAYDIUM_CLMM_POOL_TOKEN_VAULT_1_OFFSET, &token_vault);
+    put_pubkey(&mut data, RAYDIUM_CLMM_POOL_OBSERVATION_OFFSET, &observation);
+
+    let mut slice = vec![
+        program_account(clmm_program_id),
+        data_account(amm_config, clmm_program_id, vec![0u8; 8]),
+        data_account(pool, clmm_program_id, data),
+        token_account_at(quote_vault, spl_token::native_mint::ID, pool, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(token_vault, token_mint, pool, POOL_LIQUIDITY, anchor_spl::token::ID),
+        data_account(observation, clmm_program_id, vec![0u8; 8]),
+        program_account(anchor_spl::token_2022::ID),
+        program_account(parse_program_id(MEMO_PROGRAM_ID).unwrap()),
+        mint_account(token_mint, 6, anchor_spl::token::ID),
+        mint_account(spl_token::native_mint::ID, 9, anchor_spl::token::ID),
+    ];
+    for _ in 0..tick_arrays {
+        let mut tick_array = vec![0u8; 64];
+        tick_array[..8].copy_from_slice(&RAYDIUM_CLMM_TICK_ARRAY_DISCRIMINATOR);
+        slice.push(data_account(Pubkey::new_unique(), clmm_program_id, tick_array));
+    }
+    slice.push(token_account(token_mint, *fixture.user.key, 0));
+    slice
+}
+
+#[test]
+fn raydium_clmm_swap_v2_layout_and_data() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&raydium_clmm_slice(&fixture, token_mint, 2));
+    let user_token = *slice[12].key;
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 5_000,
+        min_wsol_out: 900_000,
+        tokens_to_sell: 5_000,
+        ..leg_params(token_mint, DexType::RaydiumClmm, DexType::RaydiumClmm, slice.len() as u8)
+    };
+    let build_ctx = fixture.build_ctx();
+
+    let (buy, buy_accounts) = build_dex_instruction(&DexType::RaydiumClmm, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    let (sell, _) = build_dex_instruction(&DexType::RaydiumClmm, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+    // discriminator, amount, other_amount_threshold, sqrt_price_limit_x64 (0), is_base_input
+    assert_eq!(buy.data.len(), 41);
+    assert_eq!(buy.data[..8], [0x2b, 0x04, 0xed, 0x0b, 0x1a, 0xc9, 0x1e, 0x62]);
+    assert_eq!(read_data_u64(&buy.data, 8), 1_000_000);
+    assert_eq!(read_data_u64(&buy.data, 16), 5_000);
+    assert_eq!(buy.data[24..40], [0u8; 16]);
+    assert_eq!(buy.data[40], 1);
+    assert_eq!(read_data_u64(&sell.data, 8), 5_000);
+    assert_eq!(read_data_u64(&sell.data, 16), 900_000);
+
+    // payer, amm config, pool, input/output, input/output vault, observation, 2 токен-программы, memo,
+    // input/output mint, tick array-и
+    assert_eq!(buy.accounts.len(), 13 + 2);
+    assert!(buy.accounts[0].is_signer);
+    assert_eq!(buy.accounts[1].pubkey, *slice[1].key);
+    assert_eq!(buy.accounts[2].pubkey, *slice[2].key);
+    // BUY: quote -> token (token - mint_1 пула)
+    assert_eq!(buy.accounts[3].pubkey, *fixture.user_wsol_account.key);
+    assert_eq!(buy.accounts[4].pubkey, user_token);
+    assert_eq!(buy.accounts[5].pubkey, *slice[3].key);
+    assert_eq!(buy.accounts[6].pubkey, *slice[4].key);
+    assert_eq!(buy.accounts[7].pubkey, *slice[5].key);
+    assert_eq!(buy.accounts[11].pubkey, spl_token::native_mint::ID);
+    assert_eq!(buy.accounts[12].pubkey, token_mint);
+    assert_eq!(buy.accounts[13..].iter().map(|meta| meta.pubkey).collect::<Vec<_>>(), vec![*slice[10].key, *slice[11].key]);
+    // SELL: зеркально
+    assert_eq!(sell.accounts[3].pubkey, user_token);
+    assert_eq!(sell.accounts[4].pubkey, *fixture.user_wsol_account.key);
+    assert_eq!(sell.accounts[5].pubkey, *slice[4].key);
+    assert_eq!(sell.accounts[11].pubkey, token_mint);
+    assert_eq!(buy_accounts.len(), 14 + 2);
+}
+
+#[test]
+fn raydium_clmm_takes_at_most_three_tick_arrays_and_the_bitmap_extension() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let mut slice = raydium_clmm_slice(&fixture, token_mint, 4);
+    let clmm_program_id = parse_program_id(RAYDIUM_CLMM_PROGRAM_ID).unwrap();
+    let (bitmap_extension, _) = Pubkey::find_program_address(
+        &[b"pool_tick_array_bitmap_extension", slice[2].key.as_ref()],
+        &clmm_program_id,
+    );
+    slice.push(data_account(bitmap_extension, clmm_program_id, vec![0u8; 8]));
+    let slice = leak_slice(&slice);
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 5_000,
+        ..leg_params(token_mint, DexType::RaydiumClmm, DexType::RaydiumClmm, slice.len() as u8)
+    };
+
+    let (buy, _) = build_dex_instruction(&DexType::RaydiumClmm, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx()).unwrap();
+    assert_eq!(buy.accounts[13].pubkey, bitmap_extension);
+    assert!(!buy.accounts[13].is_writable);
+    let tick_arrays: Vec<Pubkey> = buy.accounts[14..].iter().map(|meta| meta.pubkey).collect();
+    assert_eq!(tick_arrays, slice[10..13].iter().map(|acc_info| *acc_info.key).collect::<Vec<_>>());
+}
+
+#[test]
+fn raydium_clmm_pool_of_another_mint_or_without_tick_arrays_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let build_ctx = fixture.build_ctx();
+
+    let slice = leak_slice(&raydium_clmm_slice(&fixture, Pubkey::new_unique(), 1));
+    let arbitrage = leg_params(token_mint, DexType::RaydiumClmm, DexType::RaydiumClmm, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::RaydiumClmm, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::PoolMintMismatch);
+
+    let slice = leak_slice(&raydium_clmm_slice(&fixture, token_mint, 0));
+    let arbitrage = leg_params(token_mint, DexType::RaydiumClmm, DexType::RaydiumClmm, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::RaydiumClmm, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::AccountNotFound);
+}
+
+/// Token-2022 mint (9 знаков) с TransferFeeConfig: fee_bps с потолком maximum_fee с эпохи 0
+fn transfer_fee_mint(key: Pubkey, fee_bps: u16, maximum_fee: u64) -> &'static AccountInfo<'static> {
+    use anchor_spl::token_2022::spl_token_2022::extension::{ExtensionType, StateWithExtensionsMut};
+
+    let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[ExtensionType::TransferFeeConfig])
+        .unwrap();
+    let mut data = vec![0u8; len];
+    let mut mint = StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(&mut data).unwrap();
+    let config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+    for transfer_fee in [&mut config.older_transfer_fee, &mut config.newer_transfer_fee] {
+        transfer_fee.transfer_fee_basis_points = fee_bps.into();
+        transfer_fee.maximum_fee = maximum_fee.into();
+    }
+    mint.base = spl_token_2022::state::Mint {
+        decimals: 9,
+        is_initialized: true,
+        ..spl_token_2022::state::Mint::default()
+    };
+    mint.pack_base();
+    mint.init_account_type().unwrap();
+    data_account(key, anchor_spl::token_2022::ID, data)
+}
+
+#[test]
+fn min_out_is_reduced_by_the_quote_transfer_fee() {
+    let mint = transfer_fee_mint(Pubkey::new_unique(), 100, u64::MAX);
+    // 1% от 1_100 с округлением вверх
+    assert_eq!(min_out_after_transfer_fee(1_100, Some(mint), TEST_EPOCH).unwrap(), 1_089);
+    assert_eq!(min_out_after_transfer_fee(1_100, None, TEST_EPOCH).unwrap(), 1_100);
+
+    let capped = transfer_fee_mint(Pubkey::new_unique(), 100, 5);
+    assert_eq!(min_out_after_transfer_fee(1_100, Some(capped), TEST_EPOCH).unwrap(), 1_095);
+
+    // Mint без расширения - без вычета
+    let plain = mint_account(Pubkey::new_unique(), 9, anchor_spl::token_2022::ID);
+    assert_eq!(min_out_after_transfer_fee(1_100, Some(plain), TEST_EPOCH).unwrap(), 1_100);
+}
+
+#[test]
+fn sell_net_of_the_quote_transfer_fee_passes_only_with_the_flag() {
+    for quote_has_transfer_fee in [false, true] {
+        let fee_mint = transfer_fee_mint(Pubkey::new_unique(), 100, u64::MAX);
+        let default_fixture = BatchFixture::new(|state| {
+            state.allow_raw = true;
+            state.quote_mint = *fee_mint.key;
+            state.quote_has_transfer_fee = quote_has_transfer_fee;
+        });
+        let user_quote = token_account_at(
+            Pubkey::new_unique(),
+            *fee_mint.key,
+            *default_fixture.user.key,
+            1_000,
+            anchor_spl::token_2022::ID,
+        );
+        let fixture = BatchFixture {
+            user_wsol_account: user_quote,
+            ..default_fixture
+        };
+        // min_wsol_out 1_100 до комиссии, пришло 1_089 после
+        let mut leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+        leg.params.sell_dex = mock_swap(10, 1_089, [3, 2, 5, 4], leg.accounts.len());
+        leg.pool_quote = token_account_at(
+            Pubkey::new_unique(),
+            *fee_mint.key,
+            Pubkey::new_unique(),
+            POOL_LIQUIDITY,
+            anchor_spl::token_2022::ID,
+        );
+        leg.accounts[4] = leg.pool_quote;
+
+        let mut accounts = ExecuteArbitrageBatch {
+            quote_mint: Some(UncheckedAccount::try_from(fee_mint)),
+            ..fixture.accounts()
+        };
+        let result = execute(&mut accounts, &leg.accounts, [leg.params], batch_options(1));
+        if quote_has_transfer_fee {
+            result.unwrap();
+            assert_eq!(token_amount(fixture.user_wsol_account), 1_089);
+        } else {
+            assert_error(result, MyErrorCode::MinOutNotMet);
+        }
+    }
+}
+
+fn record_failure(fixture: &BatchFixture, reporter: &'static AccountInfo<'static>) -> Result<()> {
+    let mut accounts = RecordFailure {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+        reporter: Signer::try_from(reporter).unwrap(),
+    };
+    let bumps = RecordFailureBumps {
+        router_state: accounts.router_state.bump,
+    };
+    dex_arbitrage_router::record_failure(Context::new(&ID, &mut accounts, &[], bumps))?;
+    accounts.exit(&ID)
+}
+
+#[test]
+fn circuit_breaker_pauses_after_threshold_failures_and_success_resets_the_count() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let bot = wallet(USER_LAMPORTS);
+    let bot_key = *bot.key;
+    update_config(&fixture, |ctx| dex_arbitrage_router::set_failure_threshold(ctx, 3, bot_key)).unwrap();
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 10_000);
+
+    // Два провала, затем успешный батч сбрасывает счетчик
+    record_failure(&fixture, bot).unwrap();
+    record_failure(&fixture, fixture.owner).unwrap();
+    assert_eq!(state_view(&fixture).consecutive_failures, 2);
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+    accounts.exit(&ID).unwrap();
+    assert_eq!(state_view(&fixture).consecutive_failures, 0);
+
+    // Посторонний не может накручивать счетчик
+    assert_error(record_failure(&fixture, wallet(USER_LAMPORTS)), MyErrorCode::UnauthorizedAccess);
+
+    // Три провала подряд - пауза, батчи отклоняются
+    for failures in 1..=3 {
+        assert!(!state_view(&fixture).is_paused);
+        record_failure(&fixture, bot).unwrap();
+        assert_eq!(state_view(&fixture).consecutive_failures, failures);
+    }
+    assert!(state_view(&fixture).is_paused);
+    let mut accounts = fixture.accounts();
+    let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(2));
+    assert_error(result, MyErrorCode::ContractIsPaused);
+}
+
+#[test]
+fn zero_failure_threshold_never_pauses() {
+    let fixture = BatchFixture::new(|_| {});
+    for _ in 0..10 {
+        record_failure(&fixture, fixture.owner).unwrap();
+    }
+    let view = state_view(&fixture);
+    assert_eq!(view.consecutive_failures, 10);
+    assert!(!view.is_paused);
+}
+
+fn native_settled(nonce: u64) -> BatchOptions {
+    BatchOptions {
+        settle_native: true,
+        ..batch_options(nonce)
+    }
+}
+
+#[test]
+fn native_settled_batch_counts_the_lamport_delta_as_profit() {
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 1_100_000);
+    let user_lamports_before = fixture.user.lamports();
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage], native_settled(1)).unwrap();
+
+    assert_eq!(fixture.user.lamports(), user_lamports_before + 100_000);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 100_000);
+    assert_eq!(accounts.router_state.cumulative_profit, 100_000);
+}
+
+#[test]
+fn native_settled_batch_losing_lamports_is_not_profitable() {
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 900_000);
+
+    // Без settle_native нативная потеря не видна - wSOL не менялся
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage.clone()], batch_options(1)).unwrap();
+
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &slice, [arbitrage], native_settled(2)), MyErrorCode::NotProfitable);
+}
+
+#[test]
+fn native_settlement_excludes_rent_of_created_atas() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (arbitrage, slice) = leg_with_missing_ata(&fixture);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &slice, [arbitrage], native_settled(1)).unwrap();
+
+    // Rent нового ATA возвратный: прибыль - только +100 wSOL
+    assert_eq!(invoked_programs()[0], anchor_spl::associated_token::ID);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 100);
+}
+
+#[test]
+fn leg_three_missing_an_account_fails_pre_flight_before_legs_one_and_two_execute() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let legs = [(); 2].map(|_| MockLeg::new(&fixture, 1_000, 10, 1_100));
+    let (pump_arbitrage, mut pump_accounts) = pump_leg(&fixture, 1_000, 1_000_000, 1_100_000);
+    // Вместо Global PDA Pump.fun - посторонний аккаунт (accounts_count тот же)
+    pump_accounts[1] = data_account(Pubkey::new_unique(), system_program::ID, Vec::new());
+    let remaining: Vec<_> = legs
+        .iter()
+        .flat_map(|leg| leg.accounts.iter().copied())
+        .chain(pump_accounts)
+        .collect();
+    let user_lamports_before = fixture.user.lamports();
+
+    let mut accounts = fixture.accounts();
+    let options = BatchOptions {
+        count: 3,
+        ..batch_options(1)
+    };
+    let arbitrages = [legs[0].params.clone(), legs[1].params.clone(), pump_arbitrage];
+    assert_error(execute(&mut accounts, &remaining, arbitrages, options), MyErrorCode::PDAAccountNotFound);
+
+    // Ни одного CPI: первые две ноги не тронуты
+    assert!(invoked_programs().is_empty());
+    assert!(emitted::<ArbitrageExecuted>().is_empty());
+    assert_eq!(token_amount(fixture.user_wsol_account), 2_000);
+    assert_eq!(legs.iter().map(|leg| token_amount(leg.user_token)).sum::<u64>(), 0);
+    assert_eq!(fixture.user.lamports(), user_lamports_before);
+}
+
+#[test]
+fn min_accounts_per_dex_type() {
+    let whirlpool = DexType::WhirlpoolLike {
+        program_id: Pubkey::new_unique(),
+    };
+    let raw = DexType::Raw {
+        program_id: MOCK_DEX_ID,
+        data: Vec::new(),
+        is_writable: Vec::new(),
+    };
+    let table = [
+        (DexType::PumpFun, 8),
+        (DexType::OpenBookV2, 9),
+        (DexType::Lifinity, 11),
+        (DexType::Phoenix, 6),
+        (DexType::RaydiumClmm, 12),
+        (DexType::OrcaV1, 8),
+        (DexType::StableSwap, 8),
+        (DexType::Invariant, 10),
+        (DexType::Crema, 10),
+        (whirlpool, 7),
+        (raw, 1),
+        (DexType::Meteora, 0),
+    ];
+    for (dex, expected) in table.iter() {
+        assert_eq!(min_accounts(dex), *expected, "{:?}", dex);
+    }
+
+    // Минимальные срезы фикстур ровно на границе
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    assert_eq!(pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE).len(), min_accounts(&DexType::PumpFun) as usize);
+    assert_eq!(phoenix_slice(&fixture, token_mint).len(), min_accounts(&DexType::Phoenix) as usize);
+    assert_eq!(raydium_clmm_slice(&fixture, token_mint, 1).len(), min_accounts(&DexType::RaydiumClmm) as usize);
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    assert_eq!(lifinity_slice(&fixture, token_mint, oracles, oracles).len(), min_accounts(&DexType::Lifinity) as usize);
+}
+
+#[test]
+fn leg_below_the_buy_dex_minimum_is_rejected_before_slicing() {
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, slice) = pump_leg(&fixture, 1_000, 1_000_000, 1_100_000);
+    let undercounted = ArbitrageParams {
+        accounts_count: min_accounts(&DexType::PumpFun) - 1,
+        ..arbitrage
+    };
+
+    let mut accounts = fixture.accounts();
+    let result = execute(&mut accounts, &slice, [undercounted], batch_options(1));
+    assert_error(result, MyErrorCode::InsufficientAccounts);
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn denied_mint_is_rejected_and_allowed_mint_executes() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let denied = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let allowed = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let denied_mint = denied.params.token_mint;
+    update_config(&fixture, |ctx| dex_arbitrage_router::add_denied_mint(ctx, denied_mint)).unwrap();
+
+    let mut accounts = fixture.accounts();
+    let result = execute(&mut accounts, &denied.accounts, [denied.params.clone()], batch_options(1));
+    assert_error(result, MyErrorCode::MintDenied);
+    assert!(invoked_programs().is_empty());
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &allowed.accounts, [allowed.params.clone()], batch_options(1)).unwrap();
+    assert_eq!(token_amount(fixture.user_wsol_account), 2_100);
+
+    // После удаления из denylist mint снова торгуется
+    update_config(&fixture, |ctx| dex_arbitrage_router::remove_denied_mint(ctx, denied_mint)).unwrap();
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &denied.accounts, [denied.params.clone()], batch_options(2)).unwrap();
+}
+
+#[test]
+fn denylist_is_bounded_and_ignores_duplicates() {
+    let fixture = BatchFixture::new(|_| {});
+    let first = Pubkey::new_unique();
+    update_config(&fixture, |ctx| dex_arbitrage_router::add_denied_mint(ctx, first)).unwrap();
+    update_config(&fixture, |ctx| dex_arbitrage_router::add_denied_mint(ctx, first)).unwrap();
+    assert_eq!(state_view(&fixture).denied_mints, vec![first]);
+
+    for _ in 1..MAX_DENIED_MINTS {
+        update_config(&fixture, |ctx| dex_arbitrage_router::add_denied_mint(ctx, Pubkey::new_unique())).unwrap();
+    }
+    let result = update_config(&fixture, |ctx| dex_arbitrage_router::add_denied_mint(ctx, Pubkey::new_unique()));
+    assert_error(result, MyErrorCode::InvalidConfigValue);
+    assert_eq!(state_view(&fixture).denied_mints.len(), MAX_DENIED_MINTS);
+
+    let result = update_config(&fixture, |ctx| dex_arbitrage_router::remove_denied_mint(ctx, Pubkey::new_unique()));
+    assert_error(result, MyErrorCode::InvalidConfigValue);
+
+    // Удаление переносит последний слот на место удаленного и обнуляет освободившийся
+    update_config(&fixture, |ctx| dex_arbitrage_router::remove_denied_mint(ctx, first)).unwrap();
+    let state = Account::<RouterState>::try_from(fixture.router_state).unwrap();
+    assert_eq!(state.denied_count as usize, MAX_DENIED_MINTS - 1);
+    assert!(!state.active_denied_mints().contains(&first));
+    assert_eq!(state.denied_mints[MAX_DENIED_MINTS - 1], Pubkey::default());
+}
+
+#[test]
+fn state_view_with_full_denylist_fits_in_return_data() {
+    let fixture = BatchFixture::new(|_| {});
+    for _ in 0..MAX_DENIED_MINTS {
+        update_config(&fixture, |ctx| dex_arbitrage_router::add_denied_mint(ctx, Pubkey::new_unique())).unwrap();
+    }
+
+    // Denylist - единственное поле переменной длины: это самый большой снимок get_state
+    let return_data = state_view(&fixture).try_to_vec().unwrap();
+    assert!(return_data.len() <= anchor_lang::solana_program::program::MAX_RETURN_DATA);
+}
+
+#[test]
+fn sell_amount_out_comes_from_the_sell_programs_return_data() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    let executed = emitted::<ArbitrageExecuted>();
+    assert_eq!(executed[0].sell_amount_out, Some(1_100));
+    assert_eq!(executed[0].leg_profit, 100);
+}
+
+#[test]
+fn dex_without_return_data_reports_no_sell_amount_out() {
+    fn mock_dex_program_silent(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+        mock_dex_program(instruction, account_infos)?;
+        set_mock_return_data(&instruction.program_id, &[]);
+        Ok(())
+    }
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    register_program(MOCK_DEX_ID, mock_dex_program_silent);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    // Прибыль все равно меряется по балансу
+    let executed = emitted::<ArbitrageExecuted>();
+    assert_eq!(executed[0].sell_amount_out, None);
+    assert_eq!(executed[0].leg_profit, 100);
+}
+
+#[test]
+fn return_data_of_another_program_is_ignored() {
+    setup();
+    set_mock_return_data(&MOCK_DEX_ID, &1_100u64.to_le_bytes());
+    assert_eq!(returned_amount(&MOCK_DEX_ID), Some(1_100));
+    assert_eq!(returned_amount(&Pubkey::new_unique()), None);
+
+    // Короче u64 - не сумма
+    set_mock_return_data(&MOCK_DEX_ID, &[1, 2, 3]);
+    assert_eq!(returned_amount(&MOCK_DEX_ID), None);
+}
+
+#[test]
+fn account_resolution_failures_name_the_missing_kind_of_account() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let build_ctx = fixture.build_ctx();
+    let build = |missing: usize| {
+        let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+        slice[missing] = data_account(Pubkey::new_unique(), system_program::ID, Vec::new());
+        let slice = leak_slice(&slice);
+        let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8);
+        build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &build_ctx)
+    };
+
+    // [program, global, fee recipient, mint, bonding curve, associated bonding curve, event authority, user token]
+    assert_error(build(0), MyErrorCode::AccountNotFound);
+    assert_error(build(1), MyErrorCode::PDAAccountNotFound);
+    assert_error(build(3), MyErrorCode::MintAccountNotFound);
+    assert_error(build(4), MyErrorCode::PDAAccountNotFound);
+    assert_error(build(7), MyErrorCode::TokenAccountNotFound);
+}
+
+#[test]
+fn compute_failures_are_insufficient_compute() {
+    // Оценка: 4 тяжелые ноги не влезают в лимит транзакции
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let heavy = mock_swap(1_000, 10, [2, 3, 4, 5], 64);
+    let heavy_leg = leg_params(Pubkey::new_unique(), heavy.clone(), heavy, 64);
+    assert_error(estimate(&fixture, [(); 4].map(|_| heavy_leg.clone())), MyErrorCode::InsufficientCompute);
+
+    // Исполнение: cu_cap ноги больше остатка CU (AllOrNothing)
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let capped = ArbitrageParams {
+        cu_cap: 100_000,
+        ..leg.params.clone()
+    };
+    REMAINING_CU.with(|remaining| remaining.set(50_000));
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &leg.accounts, [capped], batch_options(1)), MyErrorCode::InsufficientCompute);
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn profitability_and_unimplemented_venue_failures_have_their_own_codes() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let losing = MockLeg::new(&fixture, 1_000, 10, 900);
+    let losing_params = ArbitrageParams {
+        min_wsol_out: 900,
+        ..losing.params.clone()
+    };
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let mut accounts = fixture.accounts();
+    assert_error(execute(&mut accounts, &losing.accounts, [losing_params], batch_options(1)), MyErrorCode::NotProfitable);
+
+    // Meteora - не "неверный DEX", а еще не реализованный
+    let token_mint = Pubkey::new_unique();
+    let meteora_program_id = parse_program_id(METEORA_DLMM_PROGRAM_ID).unwrap();
+    let slice = leak_slice(&[
+        program_account(meteora_program_id),
+        data_account(derive_event_authority(&meteora_program_id), meteora_program_id, Vec::new()),
+    ]);
+    let arbitrage = leg_params(token_mint, DexType::Meteora, DexType::Meteora, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::Meteora, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::NotImplemented);
+}
+
+fn tagged(tag: u8) -> BatchOptions {
+    BatchOptions {
+        batch_tag: [tag; 32],
+        ..BatchOptions::single_leg()
+    }
+}
+
+#[test]
+fn repeated_batch_tag_within_the_window_is_rejected() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    // Теги не обязаны возрастать, nonce не используется
+    let mut accounts = fixture.accounts();
+    for tag in [5, 2, 9] {
+        execute(&mut accounts, &leg.accounts, [leg.params.clone()], tagged(tag)).unwrap();
+    }
+    assert_eq!(accounts.router_state.last_nonce, 0);
+
+    let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], tagged(2));
+    assert_error(result, MyErrorCode::DuplicateBatchTag);
+    assert_eq!(accounts.router_state.total_batches, 3);
+}
+
+#[test]
+fn batch_tag_aged_out_of_the_ring_buffer_is_accepted_again() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    let mut accounts = fixture.accounts();
+    // Первый тег и еще BATCH_TAG_HISTORY новых - первый вытеснен из кольца
+    for tag in 1..=BATCH_TAG_HISTORY as u8 + 1 {
+        execute(&mut accounts, &leg.accounts, [leg.params.clone()], tagged(tag)).unwrap();
+    }
+    assert!(!accounts.router_state.recent_batch_tags.contains(&[1; 32]));
+    execute(&mut accounts, &leg.accounts, [leg.params.clone()], tagged(1)).unwrap();
+
+    // Последний записанный все еще в окне
+    let last = BATCH_TAG_HISTORY as u8 + 1;
+    let result = execute(&mut accounts, &leg.accounts, [leg.params.clone()], tagged(last));
+    assert_error(result, MyErrorCode::DuplicateBatchTag);
+}
+
+/// Пул Orca Token Swap token_mint (A) / wSOL (B): [program, swap, authority, vault A, vault B, pool mint, fee, user token]
+fn orca_v1_slice(fixture: &BatchFixture, token_mint: Pubkey, pool_token_mint: Pubkey) -> Vec<&'static AccountInfo<'static>> {
+    let orca_program_id = parse_program_id(ORCA_TOKEN_SWAP_PROGRAM_ID).unwrap();
+    let swap = Pubkey::new_unique();
+    let (authority, bump) = Pubkey::find_program_address(&[swap.as_ref()], &orca_program_id);
+    let [token_vault, quote_vault, pool_mint, fee_account] = [(); 4].map(|_| Pubkey::new_unique());
+
+    let mut data = vec![0u8; ORCA_SWAP_LEN];
+    data[ORCA_SWAP_IS_INITIALIZED_OFFSET] = 1;
+    data[ORCA_SWAP_BUMP_SEED_OFFSET] = bump;
+    put_pubkey(&mut data, ORCA_SWAP_TOKEN_PROGRAM_OFFSET, &anchor_spl::token::ID);
+    put_pubkey(&mut data, ORCA_SWAP_TOKEN_A_OFFSET, &token_vault);
+    put_pubkey(&mut data, ORCA_SWAP_TOKEN_B_OFFSET, &quote_vault);
+    put_pubkey(&mut data, ORCA_SWAP_POOL_MINT_OFFSET, &pool_mint);
+    put_pubkey(&mut data, ORCA_SWAP_TOKEN_A_MINT_OFFSET, &pool_token_mint);
+    put_pubkey(&mut data, ORCA_SWAP_TOKEN_B_MINT_OFFSET, &spl_token::native_mint::ID);
+    put_pubkey(&mut data, ORCA_SWAP_POOL_FEE_ACCOUNT_OFFSET, &fee_account);
+
+    vec![
+        program_account(orca_program_id),
+        data_account(swap, orca_program_id, data),
+        data_account(authority, system_program::ID, Vec::new()),
+        token_account_at(token_vault, pool_token_mint, authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(quote_vault, spl_token::native_mint::ID, authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        data_account(pool_mint, anchor_spl::token::ID, vec![0u8; 82]),
+        data_account(fee_account, anchor_spl::token::ID, vec![0u8; 165]),
+        token_account(token_mint, *fixture.user.key, 0),
+    ]
+}
+
+#[test]
+fn orca_v1_swap_layout_and_side() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&orca_v1_slice(&fixture, token_mint, token_mint));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 5_000,
+        min_wsol_out: 900_000,
+        tokens_to_sell: 4_000,
+        ..leg_params(token_mint, DexType::OrcaV1, DexType::OrcaV1, slice.len() as u8)
+    };
+    let build_ctx = fixture.build_ctx();
+
+    let (buy, buy_accounts) = build_dex_instruction(&DexType::OrcaV1, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    let (sell, _) = build_dex_instruction(&DexType::OrcaV1, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+    // tag 1, amount_in, minimum_amount_out
+    assert_eq!(buy.program_id, parse_program_id(ORCA_TOKEN_SWAP_PROGRAM_ID).unwrap());
+    assert_eq!(buy.data.len(), 17);
+    assert_eq!(buy.data[0], ORCA_SWAP_INSTRUCTION_TAG);
+    assert_eq!(read_data_u64(&buy.data, 1), 1_000_000);
+    assert_eq!(read_data_u64(&buy.data, 9), 5_000);
+    assert_eq!(read_data_u64(&sell.data, 1), 4_000);
+    assert_eq!(read_data_u64(&sell.data, 9), 900_000);
+
+    // swap, authority, user, source, swap source, swap destination, destination, pool mint, fee, token program
+    let keys = |ix: &Instruction| ix.accounts.iter().map(|meta| meta.pubkey).collect::<Vec<_>>();
+    assert_eq!(
+        keys(&buy),
+        vec![
+            *slice[1].key,
+            *slice[2].key,
+            *fixture.user.key,
+            *fixture.user_wsol_account.key,
+            *slice[4].key,
+            *slice[3].key,
+            *slice[7].key,
+            *slice[5].key,
+            *slice[6].key,
+            anchor_spl::token::ID,
+        ]
+    );
+    assert!(buy.accounts[2].is_signer);
+    assert!(!buy.accounts[1].is_writable);
+    // SELL: token user-а -> wSOL user-а, из vault A в vault B
+    assert_eq!(sell.accounts[3].pubkey, *slice[7].key);
+    assert_eq!(sell.accounts[4].pubkey, *slice[3].key);
+    assert_eq!(sell.accounts[5].pubkey, *slice[4].key);
+    assert_eq!(sell.accounts[6].pubkey, *fixture.user_wsol_account.key);
+    assert_eq!(buy_accounts.len(), 11);
+}
+
+#[test]
+fn orca_v1_pool_without_the_leg_token_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&orca_v1_slice(&fixture, token_mint, Pubkey::new_unique()));
+    let arbitrage = leg_params(token_mint, DexType::OrcaV1, DexType::OrcaV1, slice.len() as u8);
+
+    let result = build_dex_instruction(&DexType::OrcaV1, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::PoolMintMismatch);
+}
+
+/// Нога, пришедшая на 50 lamports ниже min_wsol_out Go-бота (1_100), но все равно в плюсе
+fn leg_short_of_min_out(fixture: &BatchFixture) -> MockLeg {
+    let mut leg = MockLeg::new(fixture, 1_000, 10, 1_050);
+    leg.params.min_wsol_out = 1_100;
+    leg
+}
+
+#[test]
+fn leg_short_of_min_out_passes_only_with_slippage_relief() {
+    let strict = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = leg_short_of_min_out(&strict);
+    fund_wsol(strict.user_wsol_account, 1_000);
+    let result = execute(&mut strict.accounts(), &leg.accounts, [leg.params.clone()], batch_options(1));
+    assert_error(result, MyErrorCode::MinOutNotMet);
+
+    // 5%: min_wsol_out 1_100 -> 1_045
+    let relieved = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.auto_slippage_relief_bps = 500;
+    });
+    let leg = leg_short_of_min_out(&relieved);
+    fund_wsol(relieved.user_wsol_account, 1_000);
+    execute(&mut relieved.accounts(), &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    // Общая прибыль батча по-прежнему проверяется и положительна
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 50);
+    assert_eq!(token_amount(relieved.user_wsol_account), 1_050);
+}
+
+#[test]
+fn slippage_relief_never_drops_min_out_below_amount_in() {
+    assert_eq!(apply_slippage_relief(1_100, 1_000, 0), 1_100);
+    assert_eq!(apply_slippage_relief(1_100, 1_000, 500), 1_045);
+    // Пол: нога может уйти в ноль, но не в убыток
+    assert_eq!(apply_slippage_relief(1_100, 1_000, 5_000), 1_000);
+    assert_eq!(apply_slippage_relief(1_100, 1_000, 10_000), 1_000);
+    // min_wsol_out ниже amount_in (бот сам заложил убыток ноги) не трогаем
+    assert_eq!(apply_slippage_relief(900, 1_000, 5_000), 900);
+}
+
+fn supported_dexes_of(fixture: &BatchFixture) -> u32 {
+    let mut accounts = SupportedDexes {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+    };
+    let bumps = SupportedDexesBumps {
+        router_state: accounts.router_state.bump,
+    };
+    dex_arbitrage_router::supported_dexes(Context::new(&ID, &mut accounts, &[], bumps)).unwrap()
+}
+
+#[test]
+fn supported_dexes_marks_pump_fun_and_not_meteora() {
+    let fixture = BatchFixture::new(|_| {});
+    let mask = supported_dexes_of(&fixture);
+
+    let bit = |dex: DexType| mask & (1 << dex_index(&dex)) != 0;
+    assert!(bit(DexType::PumpFun));
+    assert!(bit(DexType::Phoenix));
+    assert!(!bit(DexType::Meteora));
+    // Raw и WhirlpoolLike (CPI в program id от бота) без allow_raw не исполнить
+    let whirlpool = DexType::WhirlpoolLike {
+        program_id: Pubkey::new_unique(),
+    };
+    assert!(!bit(DexType::Raw { program_id: MOCK_DEX_ID, data: Vec::new(), is_writable: Vec::new() }));
+    assert!(!bit(whirlpool.clone()));
+
+    let raw_allowed = BatchFixture::new(|state| state.allow_raw = true);
+    let raw_bits = (1 << dex_index(&mock_swap(0, 0, [0; 4], 1))) | (1 << dex_index(&whirlpool));
+    assert_eq!(supported_dexes_of(&raw_allowed), mask | raw_bits);
+}
+
+#[test]
+fn supported_dex_mask_matches_the_cu_table() {
+    // Бит выставлен ровно у вариантов с билдером: нога с неподдержанным DEX-ом падает на оценке CU
+    let mask = supported_dex_mask(true);
+    assert_eq!(mask & 1, 0);
+    assert_eq!(mask.count_ones(), 11);
+    assert_eq!(mask >> 12, 0);
+
+    let meteora_leg = leg_params(Pubkey::new_unique(), DexType::Meteora, DexType::PumpFun, 10);
+    assert_error(leg_cu_estimate(&meteora_leg), MyErrorCode::NotImplemented);
+}
+
+/// Жирная нога (+500) и убыточная (-100, но в пределах своего min_wsol_out): агрегат +400
+fn fat_and_losing_legs(fixture: &BatchFixture) -> (Vec<&'static AccountInfo<'static>>, [ArbitrageParams; 2]) {
+    let fat = MockLeg::new(fixture, 1_000, 10, 1_500);
+    let losing = MockLeg::new(fixture, 1_000, 10, 900);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let remaining = [&fat.accounts[..], &losing.accounts[..]].concat();
+    (remaining, [fat.params, losing.params])
+}
+
+#[test]
+fn per_leg_profit_rejects_a_losing_leg_masked_by_a_fat_one() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (remaining, legs) = fat_and_losing_legs(&fixture);
+    let options = BatchOptions {
+        mode: ExecutionMode::PerLegProfit,
+        count: 2,
+        ..batch_options(1)
+    };
+
+    let result = execute(&mut fixture.accounts(), &remaining, legs, options);
+    assert_error(result, MyErrorCode::NotProfitable);
+    // Жирная нога успела исполниться, откат - дело рантайма
+    assert_eq!(emitted::<ArbitrageExecuted>().len(), 1);
+}
+
+#[test]
+fn all_or_nothing_accepts_the_same_legs_on_aggregate_profit() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (remaining, legs) = fat_and_losing_legs(&fixture);
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+
+    execute(&mut fixture.accounts(), &remaining, legs, options).unwrap();
+    let executed = emitted::<ArbitrageExecuted>();
+    assert_eq!(executed.iter().map(|event| event.leg_profit).collect::<Vec<_>>(), vec![500, -100]);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 400);
+}
+
+/// Нога, которая платит и получает quote через собственный pre-funded wSOL-аккаунт user-а
+fn leg_funded_from(fixture: &BatchFixture, funding: &'static AccountInfo<'static>, proceeds: u64) -> MockLeg {
+    let mut leg = MockLeg::new(fixture, 1_000, 10, proceeds);
+    leg.accounts[2] = funding;
+    leg.params.funding_account = Some(*funding.key);
+    leg
+}
+
+#[test]
+fn two_legs_draw_from_two_funding_accounts() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let first_funding = wsol_account(*fixture.user.key, 1_000);
+    let second_funding = wsol_account(*fixture.user.key, 1_000);
+    let first = leg_funded_from(&fixture, first_funding, 1_100);
+    let second = leg_funded_from(&fixture, second_funding, 1_200);
+    let remaining = [&first.accounts[..], &second.accounts[..]].concat();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+
+    // Основной wSOL-аккаунт пуст: его потребность окна - ноль
+    execute(&mut fixture.accounts(), &remaining, [first.params, second.params], options).unwrap();
+
+    assert_eq!(token_amount(first_funding), 1_100);
+    assert_eq!(token_amount(second_funding), 1_200);
+    assert_eq!(token_amount(fixture.user_wsol_account), 0);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 300);
+}
+
+#[test]
+fn funding_account_of_another_owner_or_underfunded_is_rejected() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let foreign = leg_funded_from(&fixture, wsol_account(Pubkey::new_unique(), 1_000), 1_100);
+    let result = execute(&mut fixture.accounts(), &foreign.accounts, [foreign.params], batch_options(1));
+    assert_error(result, MyErrorCode::InvalidTokenAccount);
+
+    let underfunded = leg_funded_from(&fixture, wsol_account(*fixture.user.key, 999), 1_100);
+    let result = execute(&mut fixture.accounts(), &underfunded.accounts, [underfunded.params], batch_options(1));
+    assert_error(result, MyErrorCode::InsufficientFunds);
+}
+
+/// Stable пул token_mint / quote_mint (token - сторона A или B):
+/// [program, swap info, authority, clock, token reserves, quote reserves, token admin fees, quote admin fees, user token]
+fn stable_swap_slice(
+    fixture: &BatchFixture,
+    token_mint: Pubkey,
+    quote_mint: Pubkey,
+    token_is_a: bool,
+) -> Vec<&'static AccountInfo<'static>> {
+    let stable_swap_program_id = parse_program_id(STABLE_SWAP_PROGRAM_ID).unwrap();
+    let swap_info = Pubkey::new_unique();
+    let (authority, nonce) = Pubkey::find_program_address(&[swap_info.as_ref()], &stable_swap_program_id);
+    let [token_reserves, quote_reserves, token_admin_fees, quote_admin_fees] = [(); 4].map(|_| Pubkey::new_unique());
+
+    let mut data = vec![0u8; STABLE_SWAP_INFO_LEN];
+    data[STABLE_SWAP_IS_INITIALIZED_OFFSET] = 1;
+    data[STABLE_SWAP_NONCE_OFFSET] = nonce;
+    put_u64(&mut data, STABLE_SWAP_TARGET_AMP_OFFSET, 100);
+    let (a, b) = if token_is_a {
+        ((token_reserves, token_mint, token_admin_fees), (quote_reserves, quote_mint, quote_admin_fees))
+    } else {
+        ((quote_reserves, quote_mint, quote_admin_fees), (token_reserves, token_mint, token_admin_fees))
+    };
+    put_pubkey(&mut data, STABLE_SWAP_TOKEN_A_RESERVES_OFFSET, &a.0);
+    put_pubkey(&mut data, STABLE_SWAP_TOKEN_A_MINT_OFFSET, &a.1);
+    put_pubkey(&mut data, STABLE_SWAP_TOKEN_A_ADMIN_FEES_OFFSET, &a.2);
+    put_pubkey(&mut data, STABLE_SWAP_TOKEN_B_RESERVES_OFFSET, &b.0);
+    put_pubkey(&mut data, STABLE_SWAP_TOKEN_B_MINT_OFFSET, &b.1);
+    put_pubkey(&mut data, STABLE_SWAP_TOKEN_B_ADMIN_FEES_OFFSET, &b.2);
+
+    vec![
+        program_account(stable_swap_program_id),
+        data_account(swap_info, stable_swap_program_id, data),
+        data_account(authority, system_program::ID, Vec::new()),
+        data_account(sysvar::clock::ID, sysvar::ID, vec![0u8; 40]),
+        token_account_at(token_reserves, token_mint, authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(quote_reserves, quote_mint, authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(token_admin_fees, token_mint, Pubkey::new_unique(), 0, anchor_spl::token::ID),
+        token_account_at(quote_admin_fees, quote_mint, Pubkey::new_unique(), 0, anchor_spl::token::ID),
+        token_account(token_mint, *fixture.user.key, 0),
+    ]
+}
+
+#[test]
+fn stable_swap_resolves_pool_accounts_and_builds_swap_data() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 990_000,
+        min_wsol_out: 1_001_000,
+        tokens_to_sell: 990_000,
+        ..leg_params(token_mint, DexType::StableSwap, DexType::StableSwap, 9)
+    };
+    let build_ctx = fixture.build_ctx();
+
+    // Порядок сторон пула не важен: vault-ы и admin fee выбираются по mint-ам
+    for token_is_a in [true, false] {
+        let slice = leak_slice(&stable_swap_slice(&fixture, token_mint, spl_token::native_mint::ID, token_is_a));
+        let (buy, buy_accounts) =
+            build_dex_instruction(&DexType::StableSwap, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+        let (sell, _) = build_dex_instruction(&DexType::StableSwap, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+        assert_eq!(buy.program_id, parse_program_id(STABLE_SWAP_PROGRAM_ID).unwrap());
+        assert_eq!(buy.data.len(), 17);
+        assert_eq!(buy.data[0], STABLE_SWAP_INSTRUCTION_TAG);
+        assert_eq!(read_data_u64(&buy.data, 1), 1_000_000);
+        assert_eq!(read_data_u64(&buy.data, 9), 990_000);
+        assert_eq!(read_data_u64(&sell.data, 1), 990_000);
+        assert_eq!(read_data_u64(&sell.data, 9), 1_001_000);
+
+        // swap info, authority, user, clock, source, swap source, swap destination, destination, admin fees, token program
+        let keys = |ix: &Instruction| ix.accounts.iter().map(|meta| meta.pubkey).collect::<Vec<_>>();
+        assert_eq!(
+            keys(&buy),
+            vec![
+                *slice[1].key,
+                *slice[2].key,
+                *fixture.user.key,
+                *slice[3].key,
+                *fixture.user_wsol_account.key,
+                *slice[5].key,
+                *slice[4].key,
+                *slice[8].key,
+                *slice[6].key,
+                anchor_spl::token::ID,
+            ]
+        );
+        assert!(buy.accounts[2].is_signer);
+        // SELL: admin fee берется с выходной стороны - quote
+        assert_eq!(sell.accounts[4].pubkey, *slice[8].key);
+        assert_eq!(sell.accounts[5].pubkey, *slice[4].key);
+        assert_eq!(sell.accounts[6].pubkey, *slice[5].key);
+        assert_eq!(sell.accounts[7].pubkey, *fixture.user_wsol_account.key);
+        assert_eq!(sell.accounts[8].pubkey, *slice[7].key);
+        assert_eq!(buy_accounts.len(), 11);
+    }
+}
+
+#[test]
+fn stable_swap_pool_of_other_mints_or_paused_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let arbitrage = leg_params(token_mint, DexType::StableSwap, DexType::StableSwap, 9);
+    let build_ctx = fixture.build_ctx();
+
+    // Вторая сторона пула - не quote mint user-а (LST / другой stablecoin)
+    let slice = leak_slice(&stable_swap_slice(&fixture, token_mint, Pubkey::new_unique(), true));
+    let result = build_dex_instruction(&DexType::StableSwap, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::PoolMintMismatch);
+
+    let slice = leak_slice(&stable_swap_slice(&fixture, token_mint, spl_token::native_mint::ID, true));
+    slice[1].data.borrow_mut()[STABLE_SWAP_IS_PAUSED_OFFSET] = 1;
+    let result = build_dex_instruction(&DexType::StableSwap, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::InvalidAccountData);
+}
+
+#[test]
+fn bogus_leg_token_program_is_rejected_before_any_cpi() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let mut leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    // Подменная "токен-программа" в срезе получила бы подпись user-а на transfer-ах
+    let bogus_token_program = program_account(Pubkey::new_unique());
+    leg.accounts.push(bogus_token_program);
+    leg.params.accounts_count += 1;
+    leg.params.token_program = Some(*bogus_token_program.key);
+
+    let result = execute(&mut fixture.accounts(), &leg.accounts, [leg.params], batch_options(1));
+    assert_error(result, MyErrorCode::InvalidProgramId);
+    assert!(invoked_programs().is_empty());
+    assert!(is_token_program(&anchor_spl::token::ID) && is_token_program(&anchor_spl::token_2022::ID));
+}
+
+const WRAPPER_DISCRIMINATOR: [u8; 8] = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, 0x03, 0x04];
+
+#[test]
+fn data_prefix_is_prepended_to_raw_and_builtin_instructions() {
+    let fixture = BatchFixture::new(|_| {});
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+
+    // Raw: префикс перед data от бота
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let slice = leak_slice(&leg.accounts);
+    let unprefixed = build_dex_instruction(&leg.params.buy_dex, TradeSide::Buy, slice, &leg.params, &build_ctx).unwrap().0;
+    let arbitrage = ArbitrageParams {
+        data_prefix: WRAPPER_DISCRIMINATOR.to_vec(),
+        ..leg.params.clone()
+    };
+    let (prefixed, _) = build_dex_instruction(&arbitrage.buy_dex, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    assert_eq!(prefixed.data[..8], WRAPPER_DISCRIMINATOR);
+    assert_eq!(prefixed.data[8..], unprefixed.data[..]);
+
+    // Встроенный билдер: tag Orca остается сразу после префикса
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&orca_v1_slice(&fixture, token_mint, token_mint));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000,
+        data_prefix: WRAPPER_DISCRIMINATOR.to_vec(),
+        ..leg_params(token_mint, DexType::OrcaV1, DexType::OrcaV1, slice.len() as u8)
+    };
+    let (orca, _) = build_dex_instruction(&DexType::OrcaV1, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    assert_eq!(orca.data.len(), 8 + 17);
+    assert_eq!(orca.data[..8], WRAPPER_DISCRIMINATOR);
+    assert_eq!(orca.data[8], ORCA_SWAP_INSTRUCTION_TAG);
+    assert_eq!(read_data_u64(&orca.data, 9), 1_000);
+}
+
+#[test]
+fn data_prefix_is_prepended_to_inline_pump_fun_sell() {
+    let fixture = BatchFixture::new(|_| {});
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+    let (leg, slice) = pump_leg(&fixture, 10, 1_000, 1_100);
+    let slice = leak_slice(&slice);
+    let arbitrage = ArbitrageParams {
+        data_prefix: WRAPPER_DISCRIMINATOR.to_vec(),
+        ..leg
+    };
+
+    // BUY и inline SELL (Pump.fun -> Pump.fun) получают один и тот же префикс
+    let buy = build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    assert_eq!(buy.0.data[..8], WRAPPER_DISCRIMINATOR);
+    let (sell, _) = build_sell_instruction(&arbitrage, slice, &build_ctx, &buy).unwrap();
+    assert_eq!(sell.data.len(), 8 + 24);
+    assert_eq!(sell.data[..8], WRAPPER_DISCRIMINATOR);
+    assert_eq!(sell.data[8..16], build_ctx.pump_sell_discriminator);
+    assert_eq!(read_data_u64(&sell.data, 16), 10);
+
+    // Clamped SELL после partial fill пересобирается тем же путем
+    let clamped_params = ArbitrageParams {
+        tokens_to_sell: 4,
+        ..arbitrage.clone()
+    };
+    let (clamped, _) = build_sell_instruction(&clamped_params, slice, &build_ctx, &buy).unwrap();
+    assert_eq!(clamped.data[..8], WRAPPER_DISCRIMINATOR);
+    assert_eq!(read_data_u64(&clamped.data, 16), 4);
+
+    // Без allow_raw префикс отклоняется и на inline SELL
+    let result = build_sell_instruction(&arbitrage, slice, &fixture.build_ctx(), &buy);
+    assert_error(result, MyErrorCode::RawDexDisabled);
+}
+
+#[test]
+fn data_prefix_requires_allow_raw_and_a_bounded_length() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&orca_v1_slice(&fixture, token_mint, token_mint));
+    let arbitrage = ArbitrageParams {
+        data_prefix: WRAPPER_DISCRIMINATOR.to_vec(),
+        ..leg_params(token_mint, DexType::OrcaV1, DexType::OrcaV1, slice.len() as u8)
+    };
+    let result = build_dex_instruction(&DexType::OrcaV1, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::RawDexDisabled);
+
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+    let arbitrage = ArbitrageParams {
+        data_prefix: vec![0; MAX_DATA_PREFIX_LEN + 1],
+        ..arbitrage
+    };
+    let result = build_dex_instruction(&DexType::OrcaV1, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::InvalidRawLeg);
+}
+
+#[test]
+fn batch_completed_reports_cu_consumed_between_entry_and_exit() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let legs = [(); 2].map(|_| MockLeg::new(&fixture, 1_000, 10, 1_100));
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let remaining = [&legs[0].accounts[..], &legs[1].accounts[..]].concat();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+
+    execute(&mut fixture.accounts(), &remaining, [legs[0].params.clone(), legs[1].params.clone()], options).unwrap();
+
+    // Стаб списывает MOCK_CPI_CU за каждый CPI: BUY + SELL на ногу
+    let expected = if cfg!(feature = "cu-metrics") { 4 * MOCK_CPI_CU } else { 0 };
+    assert_eq!(emitted::<BatchCompleted>()[0].cu_consumed, expected);
+}
+
+fn two_leg_batch(fixture: &BatchFixture) -> (Vec<&'static AccountInfo<'static>>, [ArbitrageParams; 2]) {
+    let first = MockLeg::new(fixture, 1_000, 10, 1_100);
+    let second = MockLeg::new(fixture, 1_000, 20, 1_200);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let remaining = [&first.accounts[..], &second.accounts[..]].concat();
+    (remaining, [first.params, second.params])
+}
+
+#[test]
+fn reversed_order_executes_legs_backwards_with_their_own_slices() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (remaining, legs) = two_leg_batch(&fixture);
+    let options = BatchOptions {
+        count: 2,
+        order: vec![1, 0],
+        ..batch_options(1)
+    };
+
+    execute(&mut fixture.accounts(), &remaining, legs, options).unwrap();
+
+    // Срезы остались по исходным индексам: нога 1 купила свои 20 токенов и продала за 1_200
+    let executed = emitted::<ArbitrageExecuted>();
+    assert_eq!(executed.iter().map(|event| (event.index, event.leg_profit)).collect::<Vec<_>>(), vec![(1, 200), (0, 100)]);
+    assert_eq!(token_amount(fixture.user_wsol_account), 2_300);
+}
+
+#[test]
+fn order_that_is_not_a_permutation_of_the_window_is_rejected() {
+    for order in [vec![0, 0], vec![1], vec![0, 2], vec![1, 0, 1]] {
+        let fixture = BatchFixture::new(|state| state.allow_raw = true);
+        let (remaining, legs) = two_leg_batch(&fixture);
+        let options = BatchOptions {
+            count: 2,
+            order,
+            ..batch_options(1)
+        };
+        let result = execute(&mut fixture.accounts(), &remaining, legs, options);
+        assert_error(result, MyErrorCode::InvalidLegOrder);
+        assert!(invoked_programs().is_empty());
+    }
+}
+
+fn close_empty_atas(fixture: &BatchFixture, token_accounts: &[&'static AccountInfo<'static>]) -> Result<()> {
+    let mut accounts = CloseEmptyAtas {
+        user: Signer::try_from(fixture.user).unwrap(),
+        token_program: Program::try_from(fixture.token_program).unwrap(),
+        token_2022_program: None,
+    };
+    dex_arbitrage_router::close_empty_atas(Context::new(
+        &ID,
+        &mut accounts,
+        leak_slice(token_accounts),
+        CloseEmptyAtasBumps {},
+    ))
+}
+
+#[test]
+fn close_empty_atas_reclaims_rent_of_each_account() {
+    let fixture = BatchFixture::new(|_| {});
+    let empty = [(); 2].map(|_| token_account(Pubkey::new_unique(), *fixture.user.key, 0));
+    let user_lamports = fixture.user.lamports();
+
+    close_empty_atas(&fixture, &empty).unwrap();
+
+    let rent = rent_exempt(spl_token::state::Account::LEN);
+    assert_eq!(fixture.user.lamports(), user_lamports + 2 * rent);
+    assert!(empty.iter().all(|acc_info| acc_info.lamports() == 0));
+    assert_eq!(invoked_programs(), vec![anchor_spl::token::ID; 2]);
+}
+
+#[test]
+fn close_empty_atas_rejects_non_empty_and_foreign_accounts() {
+    let fixture = BatchFixture::new(|_| {});
+    let non_empty = token_account(Pubkey::new_unique(), *fixture.user.key, 1);
+    assert_error(close_empty_atas(&fixture, &[non_empty]), MyErrorCode::TokenAccountNotEmpty);
+
+    let foreign = token_account(Pubkey::new_unique(), Pubkey::new_unique(), 0);
+    assert_error(close_empty_atas(&fixture, &[foreign]), MyErrorCode::InvalidTokenAccount);
+    assert!(invoked_programs().is_empty());
+}
+
+/// Layout среза pump_slice по ролям
+const PUMP_SLICE_LAYOUT: [u8; 8] = [
+    LAYOUT_ROLE_PUMP_PROGRAM,
+    LAYOUT_ROLE_PUMP_GLOBAL,
+    LAYOUT_ROLE_PUMP_FEE_RECIPIENT,
+    LAYOUT_ROLE_TOKEN_MINT,
+    LAYOUT_ROLE_PUMP_BONDING_CURVE,
+    LAYOUT_ROLE_PUMP_ASSOCIATED_BONDING_CURVE,
+    LAYOUT_ROLE_PUMP_EVENT_AUTHORITY,
+    LAYOUT_ROLE_USER_TOKEN_ACCOUNT,
+];
+
+#[test]
+fn correct_layout_builds_the_same_pump_instruction_as_the_scan() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE));
+    let scanned = ArbitrageParams {
+        tokens_to_buy: 1_000,
+        max_sol_cost: 50_000,
+        ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8)
+    };
+    let laid_out = ArbitrageParams {
+        layout: PUMP_SLICE_LAYOUT.to_vec(),
+        ..scanned.clone()
+    };
+    let build_ctx = fixture.build_ctx();
+
+    validate_leg_layout(&laid_out, slice, &build_ctx).unwrap();
+    for side in [TradeSide::Buy, TradeSide::Sell] {
+        let (expected, _) = build_dex_instruction(&DexType::PumpFun, side, slice, &scanned, &build_ctx).unwrap();
+        let (actual, _) = build_dex_instruction(&DexType::PumpFun, side, slice, &laid_out, &build_ctx).unwrap();
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn mislabeled_layout_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE));
+    let build_ctx = fixture.build_ctx();
+
+    // Bonding curve и associated bonding curve перепутаны местами
+    let mut swapped = PUMP_SLICE_LAYOUT;
+    swapped.swap(4, 5);
+    // Layout короче среза, неизвестная роль
+    let mut unknown = PUMP_SLICE_LAYOUT;
+    unknown[2] = 0x42;
+    for layout in [swapped.to_vec(), PUMP_SLICE_LAYOUT[..7].to_vec(), unknown.to_vec()] {
+        let arbitrage = ArbitrageParams {
+            layout,
+            ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8)
+        };
+        assert_error(validate_leg_layout(&arbitrage, slice, &build_ctx), MyErrorCode::LayoutMismatch);
+    }
+
+    // LAYOUT_ROLE_OTHER подходит к любой позиции
+    let arbitrage = ArbitrageParams {
+        layout: vec![LAYOUT_ROLE_OTHER; 8],
+        ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8)
+    };
+    validate_leg_layout(&arbitrage, slice, &build_ctx).unwrap();
+}
+
+fn emergency_withdraw_all(fixture: &BatchFixture, token_accounts: &[&'static AccountInfo<'static>]) -> Result<()> {
+    let mut accounts = EmergencyWithdrawAll {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+        owner: Signer::try_from(fixture.owner).unwrap(),
+        token_program: Program::try_from(fixture.token_program).unwrap(),
+    };
+    let bumps = EmergencyWithdrawAllBumps {
+        router_state: accounts.router_state.bump,
+    };
+    dex_arbitrage_router::emergency_withdraw_all(Context::new(&ID, &mut accounts, leak_slice(token_accounts), bumps))
+}
+
+#[test]
+fn emergency_withdraw_all_sweeps_every_router_held_token_account() {
+    let fixture = BatchFixture::new(|state| state.is_paused = true);
+    let router_key = *fixture.router_state.key;
+    let [first_mint, second_mint] = [(); 2].map(|_| Pubkey::new_unique());
+    let router_held = [
+        token_account(first_mint, router_key, 500),
+        token_account(second_mint, router_key, 700),
+        wsol_account(router_key, 900),
+    ];
+    let owner_accounts = [
+        token_account(first_mint, *fixture.owner.key, 1),
+        token_account(second_mint, *fixture.owner.key, 0),
+        wsol_account(*fixture.owner.key, 0),
+    ];
+    // Чужой аккаунт и пустой аккаунт роутера пропускаются
+    let unrelated = token_account(first_mint, Pubkey::new_unique(), 300);
+    let empty = token_account(second_mint, router_key, 0);
+    let remaining = [&router_held[..], &owner_accounts[..], &[unrelated, empty]].concat();
+
+    emergency_withdraw_all(&fixture, &remaining).unwrap();
+
+    assert!(router_held.iter().all(|acc_info| token_amount(acc_info) == 0));
+    assert_eq!(owner_accounts.map(token_amount), [501, 700, 900]);
+    assert_eq!(token_amount(unrelated), 300);
+    assert_eq!(invoked_programs(), vec![anchor_spl::token::ID; 3]);
+}
+
+#[test]
+fn emergency_withdraw_all_requires_a_paused_router() {
+    let fixture = BatchFixture::new(|_| {});
+    let router_held = token_account(Pubkey::new_unique(), *fixture.router_state.key, 500);
+    let result = emergency_withdraw_all(&fixture, &[router_held]);
+    assert_error(result, MyErrorCode::ContractNotPaused);
+    assert_eq!(token_amount(router_held), 500);
+}
+
+#[test]
+fn reserved_cu_stops_the_loop_early_and_still_runs_the_profit_check() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.reserved_cu = 90_000;
+    });
+    let legs = [(); 3].map(|_| MockLeg::new(&fixture, 1_000, 10, 1_100));
+    fund_wsol(fixture.user_wsol_account, 3_000);
+    let remaining = [&legs[0].accounts[..], &legs[1].accounts[..], &legs[2].accounts[..]].concat();
+    let options = BatchOptions {
+        count: 3,
+        ..batch_options(1)
+    };
+    // Первая нога (два CPI по MOCK_CPI_CU) оставляет 80_000 - меньше резерва
+    REMAINING_CU.with(|remaining| remaining.set(100_000));
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &remaining, legs.map(|leg| leg.params), options).unwrap();
+
+    let skipped = emitted::<ArbitrageSkipped>();
+    let insufficient_compute = error_code_of(&anchor_lang::error::Error::from(MyErrorCode::InsufficientCompute));
+    assert_eq!(skipped.iter().map(|event| (event.index, event.reason_code)).collect::<Vec<_>>(), vec![
+        (1, insufficient_compute),
+        (2, insufficient_compute),
+    ]);
+    let completed = &emitted::<BatchCompleted>()[0];
+    assert_eq!((completed.executed_count, completed.skipped_count, completed.profit), (1, 2, 100));
+    assert_eq!(accounts.router_state.total_batches, 1);
+    assert_eq!(invoked_programs().len(), 2);
+}
+
+/// MockLeg, чей token_mint живет под Token-2022: токен-аккаунты user-а и пула - Token-2022, программа в срезе
+fn token_2022_mock_leg(fixture: &BatchFixture, cost: u64, tokens: u64, proceeds: u64) -> MockLeg {
+    let mut leg = MockLeg::new(fixture, cost, tokens, proceeds);
+    let token_mint = leg.params.token_mint;
+    leg.user_token = token_account_at(Pubkey::new_unique(), token_mint, *fixture.user.key, 0, anchor_spl::token_2022::ID);
+    leg.accounts[3] = leg.user_token;
+    leg.accounts[5] = token_account_at(Pubkey::new_unique(), token_mint, Pubkey::new_unique(), POOL_LIQUIDITY, anchor_spl::token_2022::ID);
+    leg.accounts.push(program_account(anchor_spl::token_2022::ID));
+    let accounts_count = leg.accounts.len();
+    leg.params.accounts_count = accounts_count as u8;
+    leg.params.buy_dex = mock_swap(cost, tokens, [2, 3, 4, 5], accounts_count);
+    leg.params.sell_dex = mock_swap(tokens, proceeds, [3, 2, 5, 4], accounts_count);
+    leg
+}
+
+#[test]
+fn classic_and_token_2022_legs_both_execute_in_one_batch() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let classic = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let token_2022 = token_2022_mock_leg(&fixture, 1_000, 20, 1_150);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+
+    // Программа ноги выводится из владельца ее токен-аккаунта, без явного token_program
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+    let prepared = prepare_leg(&classic.params, leak_slice(&classic.accounts), &build_ctx, 0).unwrap();
+    assert_eq!(*prepared.build_ctx.token_program.key, anchor_spl::token::ID);
+    let prepared = prepare_leg(&token_2022.params, leak_slice(&token_2022.accounts), &build_ctx, 0).unwrap();
+    assert_eq!(*prepared.build_ctx.token_program.key, anchor_spl::token_2022::ID);
+
+    let remaining = [&classic.accounts[..], &token_2022.accounts[..]].concat();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+    execute(&mut fixture.accounts(), &remaining, [classic.params, token_2022.params], options).unwrap();
+
+    assert_eq!(emitted::<ArbitrageExecuted>().len(), 2);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 250);
+    assert_eq!(token_amount(fixture.user_wsol_account), 2_250);
+    assert_eq!(token_amount(token_2022.user_token), 0);
+}
+
+/// Батч с прибылью 0.5 SOL и оракулом SOL/USD (или без него)
+fn batch_completed_with_usdc_oracle(oracle: Option<&'static AccountInfo<'static>>) -> BatchCompleted {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000_000_000, 10, 1_500_000_000);
+    fund_wsol(fixture.user_wsol_account, 1_000_000_000);
+    let mut accounts = ExecuteArbitrageBatch {
+        usdc_price_oracle: oracle.map(UncheckedAccount::try_from),
+        ..fixture.accounts()
+    };
+    execute(&mut accounts, &leg.accounts, [leg.params], batch_options(1)).unwrap();
+    emitted::<BatchCompleted>().remove(0)
+}
+
+#[test]
+fn batch_completed_reports_profit_in_usdc_from_the_oracle() {
+    // $150.00000000 за SOL: 0.5 SOL = 75 USDC
+    let completed = batch_completed_with_usdc_oracle(Some(pyth_price_account(15_000_000_000, -8, TEST_SLOT)));
+    assert_eq!(completed.profit, 500_000_000);
+    assert_eq!(completed.profit_usdc, 75_000_000);
+    assert!(!completed.usdc_price_stale);
+
+    let completed = batch_completed_with_usdc_oracle(Some(switchboard_aggregator_account(150_250, 3, TEST_SLOT)));
+    assert_eq!(completed.profit_usdc, 75_125_000);
+}
+
+#[test]
+fn missing_or_stale_usdc_oracle_reports_zero_with_the_stale_flag() {
+    let stale_slot = TEST_SLOT - USDC_ORACLE_MAX_STALE_SLOTS - 1;
+    for oracle in [None, Some(pyth_price_account(15_000_000_000, -8, stale_slot))] {
+        let completed = batch_completed_with_usdc_oracle(oracle);
+        // Отчет не влияет на исполнение
+        assert_eq!(completed.profit, 500_000_000);
+        assert_eq!(completed.profit_usdc, 0);
+        assert!(completed.usdc_price_stale);
+    }
+}
+
+const NEW_ROUTER_SEED: [u8; 8] = *b"green-01";
+
+fn reinitialize(fixture: &BatchFixture, new_seed: [u8; 8]) -> Result<Account<'static, RouterState>> {
+    let (new_router_state_key, bump) = Pubkey::find_program_address(&[b"router_state", new_seed.as_ref()], &ID);
+    let new_router_state = data_account(new_router_state_key, ID, vec![0u8; RouterState::LEN]);
+    let mut accounts = Reinitialize {
+        old_router_state: Account::try_from(fixture.router_state).unwrap(),
+        new_router_state: Account::try_from_unchecked(new_router_state).unwrap(),
+        owner: Signer::try_from(fixture.owner).unwrap(),
+        system_program: Program::try_from(fixture.system_program).unwrap(),
+    };
+    let bumps = ReinitializeBumps {
+        old_router_state: accounts.old_router_state.bump,
+        new_router_state: bump,
+    };
+    dex_arbitrage_router::reinitialize(Context::new(&ID, &mut accounts, &[], bumps), new_seed)?;
+    accounts.exit(&ID)?;
+    Ok(Account::try_from(new_router_state).unwrap())
+}
+
+#[test]
+fn reinitialize_copies_config_to_a_router_state_under_the_new_seed() {
+    let fixture = BatchFixture::new(|state| {
+        state.is_paused = true;
+        state.allow_raw = true;
+        state.max_slippage_bps = 250;
+        state.reserved_cu = 40_000;
+        state.last_nonce = 77;
+        state.total_batches = 12;
+        state.cumulative_profit = 5_000;
+        state.recent_batch_tags[0] = [1; 32];
+    });
+
+    let new_state = reinitialize(&fixture, NEW_ROUTER_SEED).unwrap();
+
+    assert_eq!(new_state.seed, NEW_ROUTER_SEED);
+    assert_eq!(new_state.seed_suffix(), NEW_ROUTER_SEED.as_ref());
+    assert_eq!(
+        Pubkey::create_program_address(&[b"router_state", new_state.seed_suffix(), &[new_state.bump]], &ID).unwrap(),
+        new_state.key()
+    );
+    // Конфиг перенесен, новый state сразу работает
+    assert_eq!(new_state.owner, *fixture.owner.key);
+    assert!(new_state.allow_raw && !new_state.is_paused);
+    assert_eq!((new_state.max_slippage_bps, new_state.reserved_cu), (250, 40_000));
+    // Счетчики и история - с нуля
+    assert_eq!((new_state.last_nonce, new_state.total_batches, new_state.cumulative_profit), (0, 0, 0));
+    assert_eq!(new_state.recent_batch_tags, [[0; 32]; BATCH_TAG_HISTORY]);
+    // Старый остался на паузе
+    assert!(Account::<RouterState>::try_from(fixture.router_state).unwrap().is_paused);
+}
+
+#[test]
+fn reinitialize_requires_a_paused_router_and_a_non_zero_seed() {
+    let running = BatchFixture::new(|_| {});
+    assert_error(reinitialize(&running, NEW_ROUTER_SEED).map(|_| ()), MyErrorCode::ContractNotPaused);
+
+    let paused = BatchFixture::new(|state| state.is_paused = true);
+    assert_error(reinitialize(&paused, [0; 8]).map(|_| ()), MyErrorCode::InvalidConfigValue);
+}
+
+/// Дорогая нога (cu_cap 80_000) перед двумя дешевыми (по 20_000) при остатке 100_000 CU
+fn expensive_leg_first(fixture: &BatchFixture) -> (Vec<&'static AccountInfo<'static>>, [ArbitrageParams; 3]) {
+    let legs = [(); 3].map(|_| MockLeg::new(fixture, 1_000, 10, 1_100));
+    fund_wsol(fixture.user_wsol_account, 3_000);
+    let remaining = [&legs[0].accounts[..], &legs[1].accounts[..], &legs[2].accounts[..]].concat();
+    let mut params = legs.map(|leg| leg.params);
+    for (params, cu_cap) in params.iter_mut().zip([80_000, 20_000, 20_000]) {
+        params.cu_cap = cu_cap;
+    }
+    REMAINING_CU.with(|remaining| remaining.set(100_000));
+    (remaining, params)
+}
+
+#[test]
+fn expensive_leg_is_skipped_to_preserve_later_legs_in_best_effort() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (remaining, legs) = expensive_leg_first(&fixture);
+    let options = BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        count: 3,
+        ..batch_options(1)
+    };
+
+    execute(&mut fixture.accounts(), &remaining, legs, options).unwrap();
+
+    // 80_000 + 40_000 на следующие ноги не влезают в 100_000
+    let skipped = emitted::<ArbitrageSkipped>();
+    assert_eq!(skipped.iter().map(|event| event.index).collect::<Vec<_>>(), vec![0]);
+    assert_eq!(emitted::<ArbitrageExecuted>().iter().map(|event| event.index).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 200);
+}
+
+#[test]
+fn expensive_leg_reverts_the_batch_in_all_or_nothing() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (remaining, legs) = expensive_leg_first(&fixture);
+    let options = BatchOptions {
+        count: 3,
+        ..batch_options(1)
+    };
+
+    assert_error(execute(&mut fixture.accounts(), &remaining, legs, options), MyErrorCode::InsufficientCompute);
+    assert!(invoked_programs().is_empty());
+}
+
+/// Тот же ключ и данные, но другой владелец - подмена аккаунта
+fn reowned(acc_info: &AccountInfo, owner: Pubkey) -> &'static AccountInfo<'static> {
+    leak_account(*acc_info.key, owner, acc_info.lamports(), acc_info.data.borrow().to_vec(), false, false)
+}
+
+#[test]
+fn pump_fun_accounts_with_the_wrong_owner_are_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, 8);
+    let build_ctx = fixture.build_ctx();
+    let pump_program_id = parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap();
+
+    // global, mint, bonding curve, associated bonding curve, token account user-а
+    for (position, owner) in [
+        (1, system_program::ID),
+        (3, anchor_spl::token_2022::ID),
+        (4, Pubkey::new_unique()),
+        (5, pump_program_id),
+        (7, Pubkey::new_unique()),
+    ] {
+        let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+        slice[position] = reowned(slice[position], owner);
+        let slice = leak_slice(&slice);
+        for side in [TradeSide::Buy, TradeSide::Sell] {
+            let result = build_dex_instruction(&DexType::PumpFun, side, slice, &arbitrage, &build_ctx);
+            assert_error(result, MyErrorCode::InvalidAccountOwner);
+        }
+    }
+
+    // Кривая для предпросмотра цены читается с той же проверкой
+    let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+    slice[4] = reowned(slice[4], Pubkey::new_unique());
+    let result = read_pump_curve(leak_slice(&slice), &token_mint, &pump_program_id);
+    assert_error(result, MyErrorCode::InvalidAccountOwner);
+}
+
+/// Маленькая кривая для счета вручную: 1_000_000 / 1_000_000
+const SMALL_PUMP_CURVE: PumpCurve = PumpCurve {
+    virtual_token_reserves: 1_000_000,
+    virtual_sol_reserves: 1_000_000,
+    real_token_reserves: 800_000,
+    real_sol_reserves: 0,
+    complete: false,
+};
+
+#[test]
+fn pump_curve_reserves_price_buys_and_sells_like_pump_fun() {
+    let reserves = PumpCurveReserves {
+        virtual_token_reserves: 1_000_000,
+        virtual_sol_reserves: 1_000_000,
+    };
+    // 1_000 * 1_000_000 / 999_000 + 1 = 1_002, комиссия 1% - 10
+    assert_eq!(reserves.buy_cost(1_000, 0), Some(1_002));
+    assert_eq!(reserves.buy_cost(1_000, PUMP_FEE_BPS), Some(1_012));
+    // 1_000 * 1_000_000 / 1_001_000 = 999, комиссия 9
+    assert_eq!(reserves.sell_out(1_000, PUMP_FEE_BPS), Some(990));
+    // Всю кривую не купить
+    assert_eq!(reserves.buy_cost(1_000_000, 0), None);
+
+    // Комиссия в кривую не идет
+    let after = reserves.after_buy(1_000).unwrap();
+    assert_eq!(after, PumpCurveReserves {
+        virtual_token_reserves: 999_000,
+        virtual_sol_reserves: 1_001_002,
+    });
+    // 1_000 * 1_001_002 / 1_000_000 = 1_001, комиссия 10
+    assert_eq!(after.sell_out(1_000, PUMP_FEE_BPS), Some(991));
+}
+
+#[test]
+fn preview_batch_predicts_profit_from_the_known_curve_state() {
+    let fixture = BatchFixture::new(|_| {});
+    // Нога 0: BUY на Pump.fun, SELL на другом venue по min_wsol_out бота
+    let first_mint = Pubkey::new_unique();
+    let first_slice = pump_slice(&fixture, first_mint, SMALL_PUMP_CURVE);
+    let pump_buy = ArbitrageParams {
+        tokens_to_buy: 1_000,
+        tokens_to_sell: 1_000,
+        min_wsol_out: 1_100,
+        ..leg_params(first_mint, DexType::PumpFun, DexType::Phoenix, first_slice.len() as u8)
+    };
+    // Нога 1: round trip по одной кривой - SELL по резервам после BUY
+    let second_mint = Pubkey::new_unique();
+    let second_slice = pump_slice(&fixture, second_mint, SMALL_PUMP_CURVE);
+    let round_trip = ArbitrageParams {
+        tokens_to_buy: 1_000,
+        tokens_to_sell: 1_000,
+        ..leg_params(second_mint, DexType::PumpFun, DexType::PumpFun, second_slice.len() as u8)
+    };
+    // Нога 2: без Pump.fun - худший случай бота
+    let other_slice = vec![data_account(Pubkey::new_unique(), Pubkey::new_unique(), Vec::new())];
+    let other = ArbitrageParams {
+        max_sol_cost: 1_000,
+        min_wsol_out: 1_050,
+        ..leg_params(Pubkey::new_unique(), DexType::Phoenix, DexType::Phoenix, 1)
+    };
+    // Нога 3 пустая
+    let empty = leg_params(Pubkey::new_unique(), DexType::PumpFun, DexType::PumpFun, 0);
+
+    let remaining = [&first_slice[..], &second_slice[..], &other_slice[..]].concat();
+    let mut accounts = PreviewBatch {
+        router_state: Account::try_from(fixture.router_state).unwrap(),
+    };
+    let bumps = PreviewBatchBumps {
+        router_state: accounts.router_state.bump,
+    };
+    let predicted = dex_arbitrage_router::preview_batch(
+        Context::new(&ID, &mut accounts, leak_slice(&remaining), bumps),
+        [pump_buy, round_trip, other, empty],
+    )
+    .unwrap();
+
+    // (1_100 - 1_012) + (991 - 1_012) + (1_050 - 1_000)
+    assert_eq!(predicted, 88 - 21 + 50);
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn execute_batch_1_runs_a_single_leg_array() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let mut accounts = fixture.accounts();
+    let bumps = ExecuteArbitrageBatchBumps {
+        router_state: accounts.router_state.bump,
+    };
+
+    dex_arbitrage_router::execute_batch_1(
+        Context::new(&ID, &mut accounts, leak_slice(&leg.accounts), bumps),
+        [leg.params],
+        batch_options(1),
+    )
+    .unwrap();
+
+    assert_eq!(emitted::<BatchCompleted>()[0].executed_count, 1);
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+}
+
+#[test]
+fn execute_batch_8_runs_all_eight_legs() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let legs = [(); 8].map(|_| MockLeg::new(&fixture, 1_000, 10, 1_100));
+    fund_wsol(fixture.user_wsol_account, 8_000);
+    let remaining = legs.iter().flat_map(|leg| leg.accounts.iter().copied()).collect::<Vec<_>>();
+    let mut accounts = fixture.accounts();
+    let bumps = ExecuteArbitrageBatchBumps {
+        router_state: accounts.router_state.bump,
+    };
+    let options = BatchOptions {
+        count: 8,
+        ..batch_options(1)
+    };
+
+    dex_arbitrage_router::execute_batch_8(
+        Context::new(&ID, &mut accounts, leak_slice(&remaining), bumps),
+        legs.map(|leg| leg.params),
+        options,
+    )
+    .unwrap();
+
+    let executed = emitted::<ArbitrageExecuted>();
+    assert_eq!(executed.iter().map(|event| event.index).collect::<Vec<_>>(), (0..8).collect::<Vec<u8>>());
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 800);
+    assert_eq!(token_amount(fixture.user_wsol_account), 8_800);
+}
+
+#[test]
+fn leg_on_a_zero_reserve_pool_is_skipped_in_best_effort() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let drained_mint = Pubkey::new_unique();
+    let drained_curve = PumpCurve {
+        real_token_reserves: 0,
+        real_sol_reserves: 0,
+        ..FRESH_PUMP_CURVE
+    };
+    let drained_slice = pump_slice(&fixture, drained_mint, drained_curve);
+    let drained = ArbitrageParams {
+        tokens_to_buy: 1_000,
+        max_sol_cost: 1_000_000,
+        tokens_to_sell: 1_000,
+        ..leg_params(drained_mint, DexType::PumpFun, DexType::PumpFun, drained_slice.len() as u8)
+    };
+    let healthy = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let remaining = [&drained_slice[..], &healthy.accounts[..]].concat();
+    let options = BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        count: 2,
+        ..batch_options(1)
+    };
+
+    execute(&mut fixture.accounts(), &remaining, [drained, healthy.params], options).unwrap();
+
+    let skipped = emitted::<ArbitrageSkipped>();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].index, 0);
+    assert_eq!(skipped[0].reason_code, error_code_of(&anchor_lang::error::Error::from(MyErrorCode::InsufficientLiquidity)));
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 100);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID; 2]);
+}
+
+#[test]
+fn pool_below_min_liquidity_is_reported_as_insufficient_liquidity() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE));
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8);
+
+    let build_ctx = BuildContext {
+        min_liquidity: FRESH_PUMP_CURVE.real_sol_reserves,
+        ..fixture.build_ctx()
+    };
+    check_pool_liquidity(&arbitrage, slice, &build_ctx).unwrap();
+
+    let build_ctx = BuildContext {
+        min_liquidity: FRESH_PUMP_CURVE.real_sol_reserves + 1,
+        ..fixture.build_ctx()
+    };
+    assert_error(check_pool_liquidity(&arbitrage, slice, &build_ctx), MyErrorCode::InsufficientLiquidity);
+}
+
+#[test]
+fn legs_whose_max_sol_cost_sum_overflows_u64_are_rejected_by_the_spend_cap() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let (legs, remaining) = four_mock_legs(&fixture);
+    // Сумма 2^65: в u64 она бы обернулась в 0 и прошла любой потолок
+    let legs = legs.map(|leg| ArbitrageParams {
+        max_sol_cost: u64::MAX / 2 + 1,
+        ..leg
+    });
+    assert_eq!(legs.iter().fold(0u64, |total, leg| total.wrapping_add(leg.max_sol_cost)), 0);
+
+    for max_total_sol in [1_000_000, u64::MAX] {
+        let options = BatchOptions {
+            max_total_sol,
+            count: 4,
+            ..batch_options(1)
+        };
+        let result = execute(&mut fixture.accounts(), &remaining, legs.clone(), options);
+        assert_error(result, MyErrorCode::BatchSpendExceeded);
+    }
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn distinct_mints_at_the_cap_execute_and_one_more_is_rejected() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.max_mints_per_batch = 3;
+    });
+    fund_wsol(fixture.user_wsol_account, 1_000_000);
+    let options = BatchOptions {
+        count: 4,
+        ..batch_options(1)
+    };
+
+    // Четыре ноги, три различных mint-а: повтор того же рынка не считается
+    let (legs, remaining) = four_mock_legs(&fixture);
+    let repeated = [legs[0].clone(), legs[0].clone(), legs[1].clone(), legs[2].clone()];
+    let remaining = [&remaining[..6], &remaining[..6], &remaining[6..18]].concat();
+    execute(&mut fixture.accounts(), &remaining, repeated, options.clone()).unwrap();
+    assert_eq!(emitted::<BatchCompleted>()[0].executed_count, 4);
+
+    let (legs, remaining) = four_mock_legs(&fixture);
+    let options = BatchOptions {
+        nonce: 2,
+        ..options
+    };
+    let result = execute(&mut fixture.accounts(), &remaining, legs, options);
+    assert_error(result, MyErrorCode::TooManyMints);
+}
+
+#[test]
+fn per_leg_cu_breakdown_sums_to_the_total() {
+    let fixture = BatchFixture::new(|_| {});
+    let pump_accounts = min_accounts(&DexType::PumpFun);
+    let pump_leg = leg_params(Pubkey::new_unique(), DexType::PumpFun, DexType::PumpFun, pump_accounts);
+    // CLMM нога с тремя tick array-ями сверх минимума
+    let clmm_accounts = min_accounts(&DexType::RaydiumClmm) + 3;
+    let clmm_leg = leg_params(Pubkey::new_unique(), DexType::RaydiumClmm, DexType::RaydiumClmm, clmm_accounts);
+    let empty_slot = leg_params(Pubkey::default(), DexType::Meteora, DexType::Meteora, 0);
+    let mixed_accounts = min_accounts(&DexType::Phoenix) + min_accounts(&DexType::Lifinity);
+    let mixed_leg = leg_params(Pubkey::new_unique(), DexType::Phoenix, DexType::Lifinity, mixed_accounts);
+
+    let estimate = estimate(&fixture, [pump_leg, clmm_leg, empty_slot, mixed_leg]).unwrap();
+
+    let clmm_cu = clmm_accounts as u32 * ACCOUNT_SCAN_CU + 2 * RAYDIUM_CLMM_SWAP_CU + 3 * TICK_ARRAY_CROSSING_CU;
+    let mixed_cu = mixed_accounts as u32 * ACCOUNT_SCAN_CU + PHOENIX_SWAP_CU + LIFINITY_SWAP_CU;
+    assert_eq!(
+        estimate.legs.iter().map(|leg| (leg.index, leg.cu_estimate)).collect::<Vec<_>>(),
+        vec![(0, pump_accounts as u32 * ACCOUNT_SCAN_CU + 2 * PUMP_FUN_SWAP_CU), (1, clmm_cu), (3, mixed_cu)]
+    );
+    assert_eq!(
+        estimate.base_cu + estimate.legs.iter().map(|leg| leg.cu_estimate).sum::<u32>(),
+        estimate.total_cu
+    );
+}
+
+/// Пул Invariant token_mint / wSOL (token - X или Y) с `ticks` tick-аккаунтами:
+/// [program, state, pool, tickmap, mint X, mint Y, reserve X, reserve Y, program authority, ticks..., user token]
+fn invariant_slice(
+    fixture: &BatchFixture,
+    token_mint: Pubkey,
+    token_is_x: bool,
+    ticks: usize,
+) -> Vec<&'static AccountInfo<'static>> {
+    let invariant_program_id = parse_program_id(INVARIANT_PROGRAM_ID).unwrap();
+    let (state, _) = Pubkey::find_program_address(&[b"statev1"], &invariant_program_id);
+    let (program_authority, _) = Pubkey::find_program_address(&[b"Invariant"], &invariant_program_id);
+    let [pool, tickmap, token_reserve, quote_reserve] = [(); 4].map(|_| Pubkey::new_unique());
+    let quote_mint = spl_token::native_mint::ID;
+    let (mint_x, mint_y, reserve_x, reserve_y) = if token_is_x {
+        (token_mint, quote_mint, token_reserve, quote_reserve)
+    } else {
+        (quote_mint, token_mint, quote_reserve, token_reserve)
+    };
+
+    let mut data = vec![0u8; INVARIANT_POOL_TICKMAP_OFFSET + 32];
+    data[..8].copy_from_slice(&INVARIANT_POOL_DISCRIMINATOR);
+    put_pubkey(&mut data, INVARIANT_POOL_TOKEN_X_OFFSET, &mint_x);
+    put_pubkey(&mut data, INVARIANT_POOL_TOKEN_Y_OFFSET, &mint_y);
+    put_pubkey(&mut data, INVARIANT_POOL_RESERVE_X_OFFSET, &reserve_x);
+    put_pubkey(&mut data, INVARIANT_POOL_RESERVE_Y_OFFSET, &reserve_y);
+    put_pubkey(&mut data, INVARIANT_POOL_TICKMAP_OFFSET, &tickmap);
+
+    let mut slice = vec![
+        program_account(invariant_program_id),
+        data_account(state, invariant_program_id, vec![0u8; 8]),
+        data_account(pool, invariant_program_id, data),
+        data_account(tickmap, invariant_program_id, vec![0u8; 8]),
+        mint_account(mint_x, 9, anchor_spl::token::ID),
+        mint_account(mint_y, 9, anchor_spl::token::ID),
+        token_account_at(reserve_x, mint_x, program_authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(reserve_y, mint_y, program_authority, POOL_LIQUIDITY, anchor_spl::token::ID),
+        data_account(program_authority, system_program::ID, Vec::new()),
+    ];
+    slice.extend((0..ticks).map(|_| {
+        let mut tick = vec![0u8; 16];
+        tick[..8].copy_from_slice(&INVARIANT_TICK_DISCRIMINATOR);
+        data_account(Pubkey::new_unique(), invariant_program_id, tick)
+    }));
+    slice.push(token_account(token_mint, *fixture.user.key, 0));
+    slice
+}
+
+#[test]
+fn invariant_swap_layout_and_direction() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let build_ctx = fixture.build_ctx();
+
+    for token_is_x in [true, false] {
+        let slice = leak_slice(&invariant_slice(&fixture, token_mint, token_is_x, 2));
+        let arbitrage = ArbitrageParams {
+            max_sol_cost: 1_000_000,
+            tokens_to_sell: 5_000,
+            ..leg_params(token_mint, DexType::Invariant, DexType::Invariant, slice.len() as u8)
+        };
+        let (buy, buy_accounts) = build_dex_instruction(&DexType::Invariant, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+        let (sell, _) = build_dex_instruction(&DexType::Invariant, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+        // discriminator, x_to_y, amount, by_amount_in, sqrt_price_limit (u128)
+        assert_eq!(buy.data.len(), 34);
+        assert_eq!(buy.data[..8], [0xf8, 0xc6, 0x9e, 0x91, 0xe1, 0x75, 0x87, 0xc8]);
+        assert_eq!(read_data_u64(&buy.data, 9), 1_000_000);
+        assert_eq!(read_data_u64(&sell.data, 9), 5_000);
+        assert_eq!((buy.data[17], sell.data[17]), (1, 1));
+        let sqrt_price_limit = |data: &[u8]| u128::from_le_bytes(data[18..34].try_into().unwrap());
+
+        // BUY отдает quote: x -> y, только если quote - это X
+        let buy_x_to_y = !token_is_x;
+        assert_eq!(buy.data[8], buy_x_to_y as u8);
+        assert_eq!(sell.data[8], !buy_x_to_y as u8);
+        let (buy_limit, sell_limit) = if buy_x_to_y {
+            (INVARIANT_MIN_SQRT_PRICE, INVARIANT_MAX_SQRT_PRICE)
+        } else {
+            (INVARIANT_MAX_SQRT_PRICE, INVARIANT_MIN_SQRT_PRICE)
+        };
+        assert_eq!((sqrt_price_limit(&buy.data), sqrt_price_limit(&sell.data)), (buy_limit, sell_limit));
+
+        // account_x / account_y - токен-аккаунты user-а по сторонам пула
+        let user_token = *slice[slice.len() - 1].key;
+        let (account_x, account_y) = if token_is_x {
+            (user_token, *fixture.user_wsol_account.key)
+        } else {
+            (*fixture.user_wsol_account.key, user_token)
+        };
+        assert_eq!(buy.accounts[1].pubkey, *slice[2].key);
+        assert_eq!(buy.accounts[2].pubkey, *slice[3].key);
+        assert_eq!((buy.accounts[5].pubkey, buy.accounts[6].pubkey), (account_x, account_y));
+        assert_eq!((buy.accounts[7].pubkey, buy.accounts[8].pubkey), (*slice[6].key, *slice[7].key));
+        assert!(buy.accounts[9].is_signer);
+        // Пересекаемые tick-и - в хвосте в порядке среза
+        assert_eq!(buy.accounts.len(), 13 + 2);
+        assert_eq!((buy.accounts[13].pubkey, buy.accounts[14].pubkey), (*slice[9].key, *slice[10].key));
+        assert_eq!(buy_accounts.len(), 14 + 2);
+    }
+}
+
+#[test]
+fn invariant_pool_of_other_mints_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let slice = leak_slice(&invariant_slice(&fixture, Pubkey::new_unique(), true, 0));
+    let arbitrage = leg_params(Pubkey::new_unique(), DexType::Invariant, DexType::Invariant, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::Invariant, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::PoolMintMismatch);
+}
+
+#[test]
+fn non_native_wsol_mint_account_fails_the_wrap_with_a_clear_error() {
+    let default_fixture = BatchFixture::new(|state| state.allow_raw = true);
+    // Mint - wSOL, но аккаунт не native: sync_native его не пересчитал бы
+    let fixture = BatchFixture {
+        user_wsol_account: token_account(spl_token::native_mint::ID, *default_fixture.user.key, 0),
+        ..default_fixture
+    };
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let user_lamports = fixture.user.lamports();
+    let options = BatchOptions {
+        wrap_amount: 1_000,
+        ..batch_options(1)
+    };
+
+    let result = execute(&mut fixture.accounts(), &leg.accounts, [leg.params], options);
+    assert_error(result, MyErrorCode::WsolWrapFailed);
+    // До перевода лампортов дело не дошло
+    assert!(invoked_programs().is_empty());
+    assert_eq!(fixture.user.lamports(), user_lamports);
+}
+
+#[test]
+fn second_distinct_user_token_account_of_the_mint_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let owner = *fixture.user.key;
+    let first = token_account(token_mint, owner, 0);
+    let second = token_account(token_mint, owner, 0);
+    let other_mint = token_account(Pubkey::new_unique(), owner, 0);
+
+    // Первое совпадение детерминированно, тот же аккаунт дважды - не неоднозначность
+    let slice = leak_slice(&[other_mint, first, first]);
+    assert_eq!(find_user_token_account(slice, &owner, &token_mint).unwrap().key, first.key);
+
+    let slice = leak_slice(&[first, other_mint, second]);
+    assert_error(find_user_token_account(slice, &owner, &token_mint), MyErrorCode::InvalidTokenAccount);
+
+    // Через сборку ноги: тот же выход для билдера
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    let mut slice = lifinity_slice(&fixture, token_mint, oracles, oracles);
+    slice.push(token_account(token_mint, owner, 0));
+    let slice = leak_slice(&slice);
+    let arbitrage = leg_params(token_mint, DexType::Lifinity, DexType::Lifinity, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::Lifinity, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::InvalidTokenAccount);
+}
+
+#[test]
+fn multi_leg_batch_wraps_the_window_total_once_and_unwraps_once() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let legs = [1_000, 2_000, 3_000].map(|cost| MockLeg::new(&fixture, cost, 10, cost + 100));
+    let remaining = legs.iter().flat_map(|leg| leg.accounts.iter().copied()).collect::<Vec<_>>();
+    let user_lamports_before = fixture.user.lamports();
+    let wsol_rent = fixture.user_wsol_account.lamports();
+    let options = BatchOptions {
+        wrap_amount: AUTO_WRAP_AMOUNT,
+        unwrap_all: true,
+        count: 3,
+        ..batch_options(1)
+    };
+
+    execute(&mut fixture.accounts(), &remaining, legs.map(|leg| leg.params), options).unwrap();
+
+    // Один transfer + sync_native на сумму max_sol_cost окна (6_000), одно закрытие в конце
+    assert_eq!(
+        invoked_programs(),
+        [
+            vec![system_program::ID, anchor_spl::token::ID],
+            vec![MOCK_DEX_ID; 6],
+            vec![anchor_spl::token::ID],
+        ]
+        .concat()
+    );
+    let wrap = INVOCATIONS.with(|invocations| invocations.borrow()[0].clone());
+    assert_eq!(u64::from_le_bytes(wrap.data[4..12].try_into().unwrap()), 6_000);
+    assert_eq!(fixture.user.lamports(), user_lamports_before + wsol_rent + 300);
+    assert_eq!(fixture.user_wsol_account.lamports(), 0);
+}
+
+#[test]
+fn referral_account_in_a_pump_fun_leg_is_rejected() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+    let referral = token_account(spl_token::native_mint::ID, Pubkey::new_unique(), 0);
+    slice.push(referral);
+    let slice = leak_slice(&slice);
+    let arbitrage = ArbitrageParams {
+        referral_account: Some(*referral.key),
+        tokens_to_buy: 1_000,
+        max_sol_cost: 1_000_000,
+        tokens_to_sell: 1_000,
+        ..leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8)
+    };
+
+    // Bonding curve referral-а не принимает: отказ вместо молча потерянного рибейта
+    let result = prepare_leg(&arbitrage, slice, &fixture.build_ctx(), 0).map(|_| ());
+    assert_error(result, MyErrorCode::InvalidConfigValue);
+
+    let without_referral = ArbitrageParams {
+        referral_account: None,
+        ..arbitrage
+    };
+    prepare_leg(&without_referral, slice, &fixture.build_ctx(), 0).unwrap();
+}
+
+#[test]
+fn referral_account_is_appended_to_the_orca_swap() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let mut slice = orca_v1_slice(&fixture, token_mint, token_mint);
+    // Host fee - LP-токены пула
+    let referral = token_account(*slice[5].key, Pubkey::new_unique(), 0);
+    slice.push(referral);
+    let slice = leak_slice(&slice);
+    let arbitrage = ArbitrageParams {
+        referral_account: Some(*referral.key),
+        ..leg_params(token_mint, DexType::OrcaV1, DexType::OrcaV1, slice.len() as u8)
+    };
+
+    let (buy, buy_accounts) = build_dex_instruction(&DexType::OrcaV1, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx()).unwrap();
+    assert_eq!(buy.accounts.len(), 11);
+    assert_eq!(buy.accounts[10].pubkey, *referral.key);
+    assert!(buy.accounts[10].is_writable);
+    assert_eq!(buy_accounts.last().unwrap().key, referral.key);
+}
+
+#[test]
+fn saber_lst_pool_resolves_through_the_stable_swap_leg() {
+    // Saber: SwapInfo на 395 байт у программы SSwpk...
+    assert_eq!(STABLE_SWAP_PROGRAM_ID, "SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg1UZ");
+    assert_eq!(STABLE_SWAP_INFO_LEN, 395);
+
+    // LST пул wSOL (A) / mSOL (B): полная подготовка ноги, BUY и SELL через один SwapInfo
+    let fixture = BatchFixture::new(|_| {});
+    let lst_mint = Pubkey::new_unique();
+    let slice = leak_slice(&stable_swap_slice(&fixture, lst_mint, spl_token::native_mint::ID, false));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000_000,
+        tokens_to_buy: 950_000,
+        tokens_to_sell: 950_000,
+        min_wsol_out: 1_000_500,
+        ..leg_params(lst_mint, DexType::StableSwap, DexType::StableSwap, slice.len() as u8)
+    };
+
+    let prepared = prepare_leg(&arbitrage, slice, &fixture.build_ctx(), 0).unwrap();
+    let (buy, _) = &prepared.buy;
+    let (sell, _) = prepared.sell.as_ref().unwrap();
+    assert_eq!(buy.accounts[0].pubkey, *slice[1].key);
+    assert_eq!(sell.accounts[0].pubkey, *slice[1].key);
+    // swap source / destination: BUY wSOL -> mSOL, SELL обратно
+    assert_eq!((buy.accounts[5].pubkey, buy.accounts[6].pubkey), (*slice[5].key, *slice[4].key));
+    assert_eq!((sell.accounts[5].pubkey, sell.accounts[6].pubkey), (*slice[4].key, *slice[5].key));
+    assert_eq!(prepared.token_account.key, slice[8].key);
+}
+
+const RENAMED_DISCRIMINATOR: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+
+fn set_dex_discriminator(fixture: &BatchFixture, dex: DexType, side: TradeSide, discriminator: [u8; 8]) -> Result<()> {
+    update_config(fixture, |ctx| dex_arbitrage_router::set_dex_discriminator(ctx, dex, side, discriminator))
+}
+
+#[test]
+fn meteora_buy_discriminator_override_is_stored_per_dex_and_side() {
+    let fixture = BatchFixture::new(|_| {});
+    set_dex_discriminator(&fixture, DexType::Meteora, TradeSide::Buy, [0xaa; 8]).unwrap();
+    // Повторная установка той же пары занимает тот же слот
+    set_dex_discriminator(&fixture, DexType::Meteora, TradeSide::Buy, RENAMED_DISCRIMINATOR).unwrap();
+
+    let state = Account::<RouterState>::try_from(fixture.router_state).unwrap();
+    let overrides = state.dex_discriminators.iter().filter(|entry| entry.is_set()).collect::<Vec<_>>();
+    assert_eq!(overrides.len(), 1);
+    assert_eq!(overrides[0].dex, dex_index(&DexType::Meteora));
+    assert_eq!(overrides[0].side, TradeSide::Buy as u8);
+    assert_eq!(overrides[0].discriminator, RENAMED_DISCRIMINATOR);
+
+    // Билдера Meteora пока нет: override хранится, но нога по-прежнему не исполняется
+    let build_ctx = BuildContext {
+        dex_discriminators: state.dex_discriminators,
+        ..fixture.build_ctx()
+    };
+    let meteora_program_id = parse_program_id(METEORA_DLMM_PROGRAM_ID).unwrap();
+    let slice = leak_slice(&[
+        program_account(meteora_program_id),
+        data_account(derive_event_authority(&meteora_program_id), meteora_program_id, Vec::new()),
+    ]);
+    let arbitrage = leg_params(Pubkey::new_unique(), DexType::Meteora, DexType::Meteora, 2);
+    let result = build_dex_instruction(&DexType::Meteora, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::NotImplemented);
+}
+
+#[test]
+fn discriminator_override_replaces_the_compiled_default_of_its_side_only() {
+    let fixture = BatchFixture::new(|_| {});
+    set_dex_discriminator(&fixture, DexType::Lifinity, TradeSide::Buy, RENAMED_DISCRIMINATOR).unwrap();
+    // Без 8-байтного Anchor discriminator-а переопределять нечего
+    let result = set_dex_discriminator(&fixture, DexType::Phoenix, TradeSide::Buy, RENAMED_DISCRIMINATOR);
+    assert_error(result, MyErrorCode::InvalidDexType);
+    // У Pump.fun один источник discriminator-ов - set_pump_discriminators
+    let result = set_dex_discriminator(&fixture, DexType::PumpFun, TradeSide::Sell, RENAMED_DISCRIMINATOR);
+    assert_error(result, MyErrorCode::InvalidDexType);
+    // Одна запись на dex_index переопределила бы discriminator всем клонам Whirlpool
+    let whirlpool_like = DexType::WhirlpoolLike {
+        program_id: Pubkey::new_unique(),
+    };
+    let result = set_dex_discriminator(&fixture, whirlpool_like, TradeSide::Buy, RENAMED_DISCRIMINATOR);
+    assert_error(result, MyErrorCode::InvalidDexType);
+
+    let state = Account::<RouterState>::try_from(fixture.router_state).unwrap();
+    let build_ctx = BuildContext {
+        dex_discriminators: state.dex_discriminators,
+        ..fixture.build_ctx()
+    };
+    let token_mint = Pubkey::new_unique();
+    let oracles = [(); 3].map(|_| Pubkey::new_unique());
+    let slice = leak_slice(&lifinity_slice(&fixture, token_mint, oracles, oracles));
+    let arbitrage = leg_params(token_mint, DexType::Lifinity, DexType::Lifinity, slice.len() as u8);
+
+    let (buy, _) = build_dex_instruction(&DexType::Lifinity, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+    let (sell, _) = build_dex_instruction(&DexType::Lifinity, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+    assert_eq!(buy.data[..8], RENAMED_DISCRIMINATOR);
+    assert_eq!(sell.data[..8], [0xf8, 0xc6, 0x9e, 0x91, 0xe1, 0x75, 0x87, 0xc8]);
+    assert_eq!(buy.data[8..], sell.data[8..]);
+}
+
+#[test]
+fn partially_executed_best_effort_batch_accounts_only_executed_legs() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.total_legs_executed = 5;
+        state.cumulative_profit = 1_000;
+    });
+    let first = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    // Нога 1 отсеивается на pre-flight (подменная токен-программа)
+    let mut skipped = MockLeg::new(&fixture, 1_000, 10, 5_000);
+    let bogus_token_program = program_account(Pubkey::new_unique());
+    skipped.accounts.push(bogus_token_program);
+    skipped.params.accounts_count += 1;
+    skipped.params.token_program = Some(*bogus_token_program.key);
+    let third = MockLeg::new(&fixture, 1_000, 10, 1_250);
+    fund_wsol(fixture.user_wsol_account, 3_000);
+    let remaining = [&first.accounts[..], &skipped.accounts[..], &third.accounts[..]].concat();
+    let options = BatchOptions {
+        mode: ExecutionMode::BestEffort,
+        count: 3,
+        ..batch_options(1)
+    };
+
+    let mut accounts = fixture.accounts();
+    execute(&mut accounts, &remaining, [first.params, skipped.params, third.params], options).unwrap();
+    accounts.exit(&ID).unwrap();
+
+    let state = fixture.accounts().router_state;
+    assert_eq!(state.total_batches, 1);
+    assert_eq!(state.total_legs_executed, 5 + 2);
+    assert_eq!(state.cumulative_profit, 1_000 + 100 + 250);
+}
+
+#[test]
+fn reverted_batch_leaves_persisted_stats_untouched() {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.cumulative_profit = 1_000;
+    });
+    let profitable = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let losing = MockLeg::new(&fixture, 1_000, 10, 800);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let remaining = [&profitable.accounts[..], &losing.accounts[..]].concat();
+    let losing_params = ArbitrageParams {
+        min_wsol_out: 800,
+        ..losing.params
+    };
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+
+    // Первая нога исполнилась, но батч в минусе - откат отбрасывает и ее учет
+    let result = execute(&mut fixture.accounts(), &remaining, [profitable.params, losing_params], options);
+    assert_error(result, MyErrorCode::NotProfitable);
+    let state = fixture.accounts().router_state;
+    assert_eq!((state.total_batches, state.total_legs_executed, state.cumulative_profit), (0, 0, 1_000));
+    // P&L исполненной ноги остается в событии
+    assert_eq!(emitted::<ArbitrageExecuted>()[0].leg_profit, 100);
+}
+
+#[test]
+fn cumulative_profit_is_clamped_to_the_u64_range() {
+    assert_eq!(accumulate_profit(1_000, 250), 1_250);
+    assert_eq!(accumulate_profit(100, -250), 0);
+    assert_eq!(accumulate_profit(u64::MAX - 1, 10), u64::MAX);
+}
+
+fn create_user_atas(fixture: &BatchFixture, mints: Vec<Pubkey>, remaining: &[&'static AccountInfo<'static>]) -> Result<()> {
+    let mut accounts = CreateUserAtas {
+        user: Signer::try_from(fixture.user).unwrap(),
+        token_program: Program::try_from(fixture.token_program).unwrap(),
+        token_2022_program: None,
+        associated_token_program: Program::try_from(program_account(anchor_spl::associated_token::ID)).unwrap(),
+        system_program: Program::try_from(fixture.system_program).unwrap(),
+    };
+    dex_arbitrage_router::create_user_atas(
+        Context::new(&ID, &mut accounts, leak_slice(remaining), CreateUserAtasBumps {}),
+        mints,
+    )
+}
+
+#[test]
+fn create_user_atas_creates_only_missing_accounts() {
+    let fixture = BatchFixture::new(|_| {});
+    let mints = [(); 3].map(|_| Pubkey::new_unique());
+    let atas = mints.map(|mint| get_associated_token_address_with_program_id(fixture.user.key, &mint, &anchor_spl::token::ID));
+    // Вторая ATA уже существует - ее CPI пропускается
+    let ata_accounts = [
+        leak_account(atas[0], system_program::ID, 0, Vec::new(), false, false),
+        token_account_at(atas[1], mints[1], *fixture.user.key, 5, anchor_spl::token::ID),
+        leak_account(atas[2], system_program::ID, 0, Vec::new(), false, false),
+    ];
+    let mut remaining = mints.map(|mint| mint_account(mint, 6, anchor_spl::token::ID)).to_vec();
+    remaining.extend(ata_accounts);
+
+    create_user_atas(&fixture, mints.to_vec(), &remaining).unwrap();
+
+    assert_eq!(invoked_programs(), vec![anchor_spl::associated_token::ID; 2]);
+    assert!(ata_accounts.iter().all(|ata| ata.owner == &anchor_spl::token::ID));
+    assert_eq!(token_amount(ata_accounts[1]), 5);
+}
+
+#[test]
+fn create_user_atas_rejects_bad_mint_lists() {
+    let fixture = BatchFixture::new(|_| {});
+    assert_error(create_user_atas(&fixture, Vec::new(), &[]), MyErrorCode::InvalidConfigValue);
+    let too_many = vec![Pubkey::new_unique(); MAX_CREATE_ATAS + 1];
+    assert_error(create_user_atas(&fixture, too_many, &[]), MyErrorCode::InvalidConfigValue);
+    assert_error(
+        create_user_atas(&fixture, vec![Pubkey::new_unique()], &[]),
+        MyErrorCode::MintAccountNotFound,
+    );
+    assert!(invoked_programs().is_empty());
+}
+
+#[cfg(feature = "failure-debug")]
+#[test]
+fn failed_leg_leaves_debug_payload_in_return_data() {
+    /// Мок DEX-а, падающий на покупке FAILING_TOKENS (до нее не пишет return data никто, кроме роутера)
+    fn mock_dex_program_failing(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+        if instruction.data.get(8..16) == Some(&FAILING_TOKENS.to_le_bytes()[..]) {
+            return Err(ProgramError::Custom(1));
+        }
+        mock_dex_program(instruction, account_infos)
+    }
+    const FAILING_TOKENS: u64 = 7;
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    register_program(MOCK_DEX_ID, mock_dex_program_failing);
+    let first = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    // Вторая нога падает на свопе покупки - после записи отладочного payload
+    let failing = MockLeg::new(&fixture, 1_000, FAILING_TOKENS, 1_100);
+    fund_wsol(fixture.user_wsol_account, 2_000);
+    let remaining = [&first.accounts[..], &failing.accounts[..]].concat();
+    let options = BatchOptions {
+        count: 2,
+        ..batch_options(1)
+    };
+
+    assert!(execute(&mut fixture.accounts(), &remaining, [first.params, failing.params], options).is_err());
+
+    let (program_id, data) = RETURN_DATA.with(|return_data| return_data.borrow().clone()).unwrap();
+    assert_eq!(program_id, ID);
+    let payload = LegFailureDebug::try_from_slice(&data).unwrap();
+    assert_eq!((payload.index, payload.stage), (1, FAILURE_STAGE_EXECUTION));
+    let expected: Vec<Pubkey> = failing.accounts.iter().map(|acc_info| *acc_info.key).collect();
+    assert_eq!(payload.accounts, expected);
+}
+
+#[cfg(feature = "failure-debug")]
+#[test]
+fn successful_batch_clears_debug_payload() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    execute(&mut fixture.accounts(), &leg.accounts, [leg.params.clone()], batch_options(1)).unwrap();
+
+    assert!(RETURN_DATA.with(|return_data| return_data.borrow().is_none()));
+}
+
+/// Батч с прибылью 100 lamports при profit_mint / profit_oracle / min_profit из router_state
+fn execute_against_min_profit(
+    profit_mint: Pubkey,
+    oracle: Option<&'static AccountInfo<'static>>,
+    min_profit: u64,
+) -> Result<()> {
+    let fixture = BatchFixture::new(|state| {
+        state.allow_raw = true;
+        state.profit_mint = profit_mint;
+        state.profit_oracle = oracle.map(|acc_info| *acc_info.key).unwrap_or_default();
+        state.min_profit = min_profit;
+    });
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let mut accounts = ExecuteArbitrageBatch {
+        quote_mint: Some(UncheckedAccount::try_from(mint_account(spl_token::native_mint::ID, 9, anchor_spl::token::ID))),
+        profit_oracle: oracle.map(UncheckedAccount::try_from),
+        ..fixture.accounts()
+    };
+    execute(&mut accounts, &leg.accounts, [leg.params], batch_options(1))
+}
+
+#[test]
+fn min_profit_without_oracle_compares_raw_profit_mint_units() {
+    execute_against_min_profit(Pubkey::default(), None, 100).unwrap();
+    assert_error(execute_against_min_profit(Pubkey::default(), None, 101), MyErrorCode::NotProfitable);
+    // profit_mint обязан совпадать с mint quote-аккаунта
+    assert_error(
+        execute_against_min_profit(Pubkey::new_unique(), None, 100),
+        MyErrorCode::InvalidTokenAccount,
+    );
+}
+
+#[test]
+fn min_profit_with_oracle_compares_normalized_profit() {
+    // $150 за SOL: 100 lamports = 15_000 единиц по 1e-9
+    let oracle = || Some(pyth_price_account(15_000_000_000, -8, TEST_SLOT));
+    let wsol = spl_token::native_mint::ID;
+    execute_against_min_profit(wsol, oracle(), 15_000).unwrap();
+    assert_error(execute_against_min_profit(wsol, oracle(), 15_001), MyErrorCode::NotProfitable);
+
+    let stale_slot = TEST_SLOT - PROFIT_ORACLE_MAX_STALE_SLOTS - 1;
+    let stale = Some(pyth_price_account(15_000_000_000, -8, stale_slot));
+    assert_error(execute_against_min_profit(wsol, stale, 15_000), MyErrorCode::StaleOraclePrice);
+}
+
+/// Phoenix Swap (IOC): лоты из instruction data, размеры лотов из маркета; исполняет ровно минимум
+/// fill-а (BUY - за весь quote, SELL - весь base)
+fn mock_phoenix_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let account = |position: usize| account_at(instruction, account_infos, position);
+    let (base_lot_size, quote_lot_size) = {
+        let market_data = account(2)?.try_borrow_data()?;
+        (
+            read_data_u64(&market_data, PHOENIX_MARKET_BASE_LOT_SIZE_OFFSET),
+            read_data_u64(&market_data, PHOENIX_MARKET_QUOTE_LOT_SIZE_OFFSET),
+        )
+    };
+    let data = &instruction.data;
+    let (user_base, user_quote, base_vault, quote_vault) = (account(4)?, account(5)?, account(6)?, account(7)?);
+    if data[2] == 0 {
+        transfer_tokens(user_quote, quote_vault, read_data_u64(data, 12) * quote_lot_size)?;
+        transfer_tokens(base_vault, user_base, read_data_u64(data, 20) * base_lot_size)
+    } else {
+        transfer_tokens(user_base, base_vault, read_data_u64(data, 4) * base_lot_size)?;
+        transfer_tokens(quote_vault, user_quote, read_data_u64(data, 28) * quote_lot_size)
+    }
+}
+
+/// Seat Manager ClaimSeat: seat [payer, ..., seat (7)] создается программой Phoenix сразу в статусе Approved
+fn mock_phoenix_seat_manager_program(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let seat = account_at(instruction, account_infos, 7)?;
+    let mut data = vec![0u8; 128];
+    put_u64(&mut data, PHOENIX_SEAT_APPROVAL_STATUS_OFFSET, PHOENIX_SEAT_STATUS_APPROVED);
+    *seat.try_borrow_mut_data()? = Box::leak(data.into_boxed_slice());
+    seat.assign(&parse_program_id(PHOENIX_PROGRAM_ID).unwrap());
+    Ok(())
+}
+
+/// Phoenix нога 1_000 -> 1_000 токенов -> 1_100 с auto_claim_seat и seat-ом трейдера в заданном состоянии
+fn phoenix_seat_leg(fixture: &BatchFixture, seat_approved: bool) -> (ArbitrageParams, Vec<&'static AccountInfo<'static>>) {
+    let phoenix_program_id = parse_program_id(PHOENIX_PROGRAM_ID).unwrap();
+    let seat_manager_program_id = parse_program_id(PHOENIX_SEAT_MANAGER_PROGRAM_ID).unwrap();
+    register_program(phoenix_program_id, mock_phoenix_program);
+    register_program(seat_manager_program_id, mock_phoenix_seat_manager_program);
+
+    let token_mint = Pubkey::new_unique();
+    let mut slice = phoenix_slice(fixture, token_mint);
+    let market = *slice[2].key;
+    let (seat, _) = Pubkey::find_program_address(&[b"seat", market.as_ref(), fixture.user.key.as_ref()], &phoenix_program_id);
+    let seat_account = if seat_approved {
+        let mut data = vec![0u8; 128];
+        put_u64(&mut data, PHOENIX_SEAT_APPROVAL_STATUS_OFFSET, PHOENIX_SEAT_STATUS_APPROVED);
+        data_account(seat, phoenix_program_id, data)
+    } else {
+        leak_account(seat, system_program::ID, 0, Vec::new(), false, false)
+    };
+    let (seat_manager, _) = Pubkey::find_program_address(&[market.as_ref()], &seat_manager_program_id);
+    let (deposit_collector, _) = Pubkey::find_program_address(&[market.as_ref(), b"deposit"], &seat_manager_program_id);
+    slice.extend([
+        seat_account,
+        program_account(seat_manager_program_id),
+        data_account(seat_manager, seat_manager_program_id, Vec::new()),
+        data_account(deposit_collector, system_program::ID, Vec::new()),
+    ]);
+
+    let arbitrage = ArbitrageParams {
+        amount_in: 1_000,
+        max_sol_cost: 1_000,
+        min_wsol_out: 1_100,
+        tokens_to_buy: 1_000,
+        tokens_to_sell: 1_000,
+        auto_claim_seat: true,
+        ..leg_params(token_mint, DexType::Phoenix, DexType::Phoenix, slice.len() as u8)
+    };
+    (arbitrage, slice)
+}
+
+#[test]
+fn phoenix_leg_claims_the_seat_before_its_swaps() {
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, remaining) = phoenix_seat_leg(&fixture, false);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    execute(&mut fixture.accounts(), &remaining, [arbitrage], batch_options(1)).unwrap();
+
+    let seat_manager_program_id = parse_program_id(PHOENIX_SEAT_MANAGER_PROGRAM_ID).unwrap();
+    let phoenix_program_id = parse_program_id(PHOENIX_PROGRAM_ID).unwrap();
+    let sequence: Vec<Pubkey> = invoked_programs()
+        .into_iter()
+        .filter(|program_id| [seat_manager_program_id, phoenix_program_id].contains(program_id))
+        .collect();
+    assert_eq!(sequence, vec![seat_manager_program_id, phoenix_program_id, phoenix_program_id]);
+    assert_eq!(remaining[6].owner, &phoenix_program_id);
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+}
+
+#[test]
+fn approved_phoenix_seat_is_not_claimed_again() {
+    let fixture = BatchFixture::new(|_| {});
+    let (arbitrage, remaining) = phoenix_seat_leg(&fixture, true);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+
+    execute(&mut fixture.accounts(), &remaining, [arbitrage], batch_options(1)).unwrap();
+
+    let seat_manager_program_id = parse_program_id(PHOENIX_SEAT_MANAGER_PROGRAM_ID).unwrap();
+    assert!(!invoked_programs().contains(&seat_manager_program_id));
+}
+
+#[test]
+fn auto_claim_seat_requires_a_phoenix_side() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let mut leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    leg.params.auto_claim_seat = true;
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let result = execute(&mut fixture.accounts(), &leg.accounts, [leg.params], batch_options(1));
+    assert_error(result, MyErrorCode::InvalidConfigValue);
+}
+
+#[test]
+fn scan_budget_allows_exactly_accounts_count_times_passes() {
+    let mut budget = ScanBudget::new(8, 2);
+    budget.charge(8).unwrap();
+    budget.charge(7).unwrap();
+    budget.charge(1).unwrap();
+    assert_error(budget.charge(1), MyErrorCode::ScanBudgetExceeded);
+
+    // find списывает сравнения до совпадения; не найдено в пределах бюджета - ошибка, а не None
+    let slice = leak_slice(&[(); 3].map(|_| program_account(Pubkey::new_unique())));
+    let mut budget = ScanBudget::new(3, 1);
+    assert_eq!(budget.find(slice, slice[1].key).unwrap().map(|acc_info| acc_info.key), Some(slice[1].key));
+    assert!(budget.find(slice, slice[0].key).unwrap().is_some());
+    assert_error(budget.find(slice, slice[2].key).map(|_| ()), MyErrorCode::ScanBudgetExceeded);
+}
+
+#[test]
+fn pump_resolution_fails_once_its_scan_exceeds_the_budget() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE));
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8);
+
+    // Полный проход (8) + поиск associated bonding curve (6-я позиция) = 14 сравнений
+    let one_pass = BuildContext {
+        scan_passes: 1,
+        ..fixture.build_ctx()
+    };
+    let result = build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &one_pass);
+    assert_error(result, MyErrorCode::ScanBudgetExceeded);
+
+    let two_passes = BuildContext {
+        scan_passes: 2,
+        ..fixture.build_ctx()
+    };
+    build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &two_passes).unwrap();
+}
+
+#[test]
+fn single_arbitrage_invokes_the_same_instructions_as_a_one_leg_batch() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    let mut accounts = fixture.accounts();
+    let bumps = ExecuteArbitrageBatchBumps {
+        router_state: accounts.router_state.bump,
+    };
+    let take_invocations = || INVOCATIONS.with(|invocations| std::mem::take(&mut *invocations.borrow_mut()));
+
+    dex_arbitrage_router::execute_single_arbitrage(
+        Context::new(&ID, &mut accounts, leak_slice(&leg.accounts), bumps),
+        leg.params.clone(),
+        1,
+        0,
+        0,
+    )
+    .unwrap();
+    let single = take_invocations();
+    execute(&mut accounts, &leg.accounts, [leg.params], batch_options(2)).unwrap();
+    let batch = take_invocations();
+
+    assert_eq!(single.len(), 2);
+    assert_eq!(single, batch);
+    let profits: Vec<u64> = emitted::<BatchCompleted>().iter().map(|completed| completed.profit).collect();
+    assert_eq!(profits, vec![100, 100]);
+}
+
+#[test]
+fn meteora_leg_rejects_a_pump_derived_event_authority() {
+    let fixture = BatchFixture::new(|_| {});
+    let meteora_program_id = parse_program_id(METEORA_DLMM_PROGRAM_ID).unwrap();
+    let pump_program_id = parse_program_id(PUMP_FUN_PROGRAM_ID).unwrap();
+    let token_mint = Pubkey::new_unique();
+    // Authority Pump.fun в срезе Meteora ноги не подходит: PDA выводится от программы ноги
+    let slice = leak_slice(&[
+        program_account(meteora_program_id),
+        data_account(derive_event_authority(&pump_program_id), pump_program_id, Vec::new()),
+    ]);
+    let arbitrage = leg_params(token_mint, DexType::Meteora, DexType::Meteora, slice.len() as u8);
+
+    for side in [TradeSide::Buy, TradeSide::Sell] {
+        let result = build_dex_instruction(&DexType::Meteora, side, slice, &arbitrage, &fixture.build_ctx());
+        assert_error(result, MyErrorCode::PDAAccountNotFound);
+    }
+}
+
+/// Мок-нога 1_000 -> 1_100 с wSOL аккаунтом, у которого комиссии съели 1_500 lamports:
+/// после батча ему не хватает 400 lamports до rent-exemption
+fn leg_with_wsol_below_rent(fixture: &BatchFixture) -> MockLeg {
+    let leg = MockLeg::new(fixture, 1_000, 10, 1_100);
+    fund_wsol(fixture.user_wsol_account, 1_000);
+    **fixture.user_wsol_account.lamports.borrow_mut() -= 1_500;
+    leg
+}
+
+#[test]
+fn wsol_account_below_rent_is_topped_up_from_user_lamports() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = leg_with_wsol_below_rent(&fixture);
+    let user_lamports = fixture.user.lamports();
+
+    execute(&mut fixture.accounts(), &leg.accounts, [leg.params], batch_options(1)).unwrap();
+
+    let rent = rent_exempt(spl_token::state::Account::LEN);
+    assert_eq!(fixture.user_wsol_account.lamports(), rent);
+    assert_eq!(fixture.user.lamports(), user_lamports - 400);
+    // Доплата идет в rent-резерв, не в баланс
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+    assert_eq!(invoked_programs(), vec![MOCK_DEX_ID, MOCK_DEX_ID, system_program::ID]);
+}
+
+#[test]
+fn wsol_account_below_rent_reverts_when_user_cannot_top_it_up() {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = leg_with_wsol_below_rent(&fixture);
+    // User сам должен остаться rent-exempt: на доплату 400 сверх своего резерва у него только 399
+    **fixture.user.lamports.borrow_mut() = rent_exempt(0) + 399;
+
+    let result = execute(&mut fixture.accounts(), &leg.accounts, [leg.params], batch_options(1));
+    assert_error(result, MyErrorCode::WsolNotRentExempt);
+}
+
+/// Срез Crema ноги: config, пул token_mint / wSOL, tick array map, mint-ы и vault-ы сторон, `tick_arrays`
+/// tick array-ев и token account user-а
+fn crema_slice(
+    fixture: &BatchFixture,
+    token_mint: Pubkey,
+    token_is_a: bool,
+    tick_arrays: usize,
+) -> Vec<&'static AccountInfo<'static>> {
+    let crema_program_id = parse_program_id(CREMA_PROGRAM_ID).unwrap();
+    let [clmm_config, pool, token_vault, quote_vault] = [(); 4].map(|_| Pubkey::new_unique());
+    let (tick_array_map, _) = Pubkey::find_program_address(&[b"tick_array_map", pool.as_ref()], &crema_program_id);
+    let quote_mint = spl_token::native_mint::ID;
+    let (mint_a, mint_b, vault_a, vault_b) = if token_is_a {
+        (token_mint, quote_mint, token_vault, quote_vault)
+    } else {
+        (quote_mint, token_mint, quote_vault, token_vault)
+    };
+
+    let mut data = vec![0u8; CREMA_POOL_TOKEN_B_VAULT_OFFSET + 32];
+    data[..8].copy_from_slice(&CREMA_POOL_DISCRIMINATOR);
+    put_pubkey(&mut data, CREMA_POOL_CLMM_CONFIG_OFFSET, &clmm_config);
+    put_pubkey(&mut data, CREMA_POOL_TOKEN_A_OFFSET, &mint_a);
+    put_pubkey(&mut data, CREMA_POOL_TOKEN_B_OFFSET, &mint_b);
+    put_pubkey(&mut data, CREMA_POOL_TOKEN_A_VAULT_OFFSET, &vault_a);
+    put_pubkey(&mut data, CREMA_POOL_TOKEN_B_VAULT_OFFSET, &vault_b);
+
+    let mut slice = vec![
+        program_account(crema_program_id),
+        data_account(clmm_config, crema_program_id, vec![0u8; 8]),
+        data_account(pool, crema_program_id, data),
+        data_account(tick_array_map, crema_program_id, vec![0u8; 8]),
+        mint_account(mint_a, 9, anchor_spl::token::ID),
+        mint_account(mint_b, 9, anchor_spl::token::ID),
+        token_account_at(vault_a, mint_a, pool, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(vault_b, mint_b, pool, POOL_LIQUIDITY, anchor_spl::token::ID),
+    ];
+    slice.extend((0..tick_arrays).map(|_| {
+        let mut tick_array = vec![0u8; 16];
+        tick_array[..8].copy_from_slice(&CREMA_TICK_ARRAY_DISCRIMINATOR);
+        data_account(Pubkey::new_unique(), crema_program_id, tick_array)
+    }));
+    slice.push(token_account(token_mint, *fixture.user.key, 0));
+    slice
+}
+
+#[test]
+fn crema_swap_resolves_pool_accounts_and_crossed_tick_arrays() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let build_ctx = fixture.build_ctx();
+
+    for token_is_a in [true, false] {
+        let slice = leak_slice(&crema_slice(&fixture, token_mint, token_is_a, 3));
+        let arbitrage = ArbitrageParams {
+            max_sol_cost: 1_000_000,
+            tokens_to_sell: 5_000,
+            ..leg_params(token_mint, DexType::Crema, DexType::Crema, slice.len() as u8)
+        };
+        let (buy, buy_accounts) = build_dex_instruction(&DexType::Crema, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+        let (sell, _) = build_dex_instruction(&DexType::Crema, TradeSide::Sell, slice, &arbitrage, &build_ctx).unwrap();
+
+        // discriminator, amount, amount_specified_is_input, a_to_b, sqrt_price_limit (u128)
+        assert_eq!(buy.data.len(), 34);
+        assert_eq!(buy.data[..8], CREMA_SWAP_DISCRIMINATOR);
+        assert_eq!(read_data_u64(&buy.data, 8), 1_000_000);
+        assert_eq!(read_data_u64(&sell.data, 8), 5_000);
+        assert_eq!((buy.data[16], sell.data[16]), (1, 1));
+        // BUY отдает quote: a -> b, только если quote - это A
+        assert_eq!((buy.data[17], sell.data[17]), (!token_is_a as u8, token_is_a as u8));
+        let sqrt_price_limit = u128::from_le_bytes(buy.data[18..34].try_into().unwrap());
+        let buy_limit = if token_is_a { CREMA_MAX_SQRT_PRICE } else { CREMA_MIN_SQRT_PRICE };
+        assert_eq!(sqrt_price_limit, buy_limit);
+
+        // config, пул, mint-ы, account_a / account_b user-а, vault-ы, tick array map, owner
+        let user_token = *slice[slice.len() - 1].key;
+        let (account_a, account_b) = if token_is_a {
+            (user_token, *fixture.user_wsol_account.key)
+        } else {
+            (*fixture.user_wsol_account.key, user_token)
+        };
+        let keys: Vec<Pubkey> = buy.accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(keys[..4], [*slice[1].key, *slice[2].key, *slice[4].key, *slice[5].key]);
+        assert_eq!(keys[4..9], [account_a, account_b, *slice[6].key, *slice[7].key, *slice[3].key]);
+        assert!(buy.accounts[9].is_signer);
+        assert_eq!(keys[10..12], [anchor_spl::token::ID; 2]);
+        // Пересекаемые tick array-и - в хвосте в порядке среза
+        assert_eq!(keys[12..], [*slice[8].key, *slice[9].key, *slice[10].key]);
+        assert_eq!(buy_accounts.len(), 13 + 3);
+    }
+}
+
+#[test]
+fn crema_swap_rejects_foreign_pools_and_missing_tick_arrays() {
+    let fixture = BatchFixture::new(|_| {});
+    let build_ctx = fixture.build_ctx();
+
+    let slice = leak_slice(&crema_slice(&fixture, Pubkey::new_unique(), true, 1));
+    let arbitrage = leg_params(Pubkey::new_unique(), DexType::Crema, DexType::Crema, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::Crema, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::PoolMintMismatch);
+
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&crema_slice(&fixture, token_mint, true, 0));
+    let arbitrage = leg_params(token_mint, DexType::Crema, DexType::Crema, slice.len() as u8);
+    let result = build_dex_instruction(&DexType::Crema, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::AccountNotFound);
+}
+
+/// sysvar Instructions транзакции с текущей инструкцией current_index
+fn instructions_sysvar_account(instructions: &[Instruction], current_index: u16) -> &'static AccountInfo<'static> {
+    use sysvar::instructions::{construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction};
+    let borrowed: Vec<BorrowedInstruction> = instructions
+        .iter()
+        .map(|instruction| BorrowedInstruction {
+            program_id: &instruction.program_id,
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|meta| BorrowedAccountMeta {
+                    pubkey: &meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: &instruction.data,
+        })
+        .collect();
+    let mut data = construct_instructions_data(&borrowed);
+    store_current_index(&mut data, current_index);
+    leak_account(sysvar::instructions::ID, sysvar::ID, 0, data, false, false)
+}
+
+/// Kamino flash borrow / repay займа flash_loan на user_wsol_account (repay ссылается на borrow с индексом 0)
+fn kamino_flash_instruction(fixture: &BatchFixture, flash_loan: &FlashLoanParams, discriminator: [u8; 8]) -> Instruction {
+    let mut accounts: Vec<AccountMeta> = (0..7).map(|_| AccountMeta::new(Pubkey::new_unique(), false)).collect();
+    accounts[KAMINO_FLASH_USER_AUTHORITY_INDEX] = AccountMeta::new_readonly(*fixture.user.key, true);
+    accounts[KAMINO_FLASH_RESERVE_INDEX] = AccountMeta::new(flash_loan.reserve, false);
+    accounts[KAMINO_FLASH_USER_LIQUIDITY_INDEX] = AccountMeta::new(*fixture.user_wsol_account.key, false);
+    let mut data = discriminator.to_vec();
+    data.extend_from_slice(&flash_loan.amount.to_le_bytes());
+    if discriminator == KAMINO_FLASH_REPAY_DISCRIMINATOR {
+        data.push(0);
+    }
+    Instruction {
+        program_id: flash_loan.lending_program,
+        accounts,
+        data,
+    }
+}
+
+/// Батч 1_000 -> 1_100 целиком на занятые 1_000 wSOL: [borrow, батч, repay?] в транзакции
+fn execute_flash_loaned_batch(max_fee: u64, with_repay: bool) -> (BatchFixture, Result<()>) {
+    let fixture = BatchFixture::new(|state| state.allow_raw = true);
+    let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+    let flash_loan = FlashLoanParams {
+        lending_program: parse_program_id(KAMINO_LENDING_PROGRAM_ID).unwrap(),
+        reserve: Pubkey::new_unique(),
+        amount: 1_000,
+        max_fee,
+    };
+    let batch_instruction = Instruction {
+        program_id: ID,
+        accounts: Vec::new(),
+        data: Vec::new(),
+    };
+    let mut instructions = vec![
+        kamino_flash_instruction(&fixture, &flash_loan, KAMINO_FLASH_BORROW_DISCRIMINATOR),
+        batch_instruction,
+    ];
+    if with_repay {
+        instructions.push(kamino_flash_instruction(&fixture, &flash_loan, KAMINO_FLASH_REPAY_DISCRIMINATOR));
+    }
+    // Заем пришел на user_wsol_account инструкцией перед батчем
+    fund_wsol(fixture.user_wsol_account, flash_loan.amount);
+    let mut accounts = ExecuteArbitrageBatch {
+        instructions_sysvar: Some(UncheckedAccount::try_from(instructions_sysvar_account(&instructions, 1))),
+        ..fixture.accounts()
+    };
+    let options = BatchOptions {
+        flash_loan: Some(flash_loan),
+        ..batch_options(1)
+    };
+    let result = execute(&mut accounts, &leg.accounts, [leg.params], options);
+    (fixture, result)
+}
+
+#[test]
+fn flash_loaned_batch_profits_after_the_loan_fee() {
+    let (fixture, result) = execute_flash_loaned_batch(10, true);
+    result.unwrap();
+
+    // Возврат 1_000 + 10 уйдет следующей инструкцией - прибыль батча уже за вычетом комиссии
+    assert_eq!(token_amount(fixture.user_wsol_account), 1_100);
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 90);
+}
+
+#[test]
+fn flash_loaned_batch_reverts_when_the_fee_eats_the_profit() {
+    let (_, result) = execute_flash_loaned_batch(101, true);
+    assert_error(result, MyErrorCode::NotProfitable);
+}
+
+#[test]
+fn flash_loaned_batch_without_repay_is_rejected_before_any_cpi() {
+    let (_, result) = execute_flash_loaned_batch(10, false);
+    assert_error(result, MyErrorCode::FlashLoanMismatch);
+    assert!(invoked_programs().is_empty());
+}
+
+#[test]
+fn batch_clearing_min_wsol_out_gross_but_not_net_of_tx_cost_reverts() {
+    let with_tx_cost = |tx_cost_lamports: u64| {
+        let fixture = BatchFixture::new(|state| state.allow_raw = true);
+        // min_wsol_out = 1_100: сама нога проходит, прибыль до комиссий 100
+        let leg = MockLeg::new(&fixture, 1_000, 10, 1_100);
+        fund_wsol(fixture.user_wsol_account, 1_000);
+        let options = BatchOptions {
+            tx_cost_lamports,
+            ..batch_options(1)
+        };
+        execute(&mut fixture.accounts(), &leg.accounts, [leg.params], options)
+    };
+
+    assert_error(with_tx_cost(101), MyErrorCode::NotProfitable);
+    assert!(emitted::<BatchCompleted>().is_empty());
+
+    with_tx_cost(60).unwrap();
+    assert_eq!(emitted::<BatchCompleted>()[0].profit, 40);
+}
+
+/// Token account с layout-ом SPL Token, но битым state (валидны только 0..=2)
+fn malformed_token_account(mint: Pubkey, owner: Pubkey) -> &'static AccountInfo<'static> {
+    let mut data = pack_token_account(mint, owner, 0, None);
+    data[108] = 7;
+    data_account(Pubkey::new_unique(), anchor_spl::token::ID, data)
+}
+
+#[test]
+fn malformed_conversions_map_to_specific_errors() {
+    assert_error(parse_program_id("not-a-program-id"), MyErrorCode::InvalidProgramId);
+
+    let malformed = malformed_token_account(Pubkey::new_unique(), Pubkey::new_unique());
+    assert_error(parse_token_account(malformed), MyErrorCode::InvalidTokenAccount);
+    assert_error(token_account_amount(malformed), MyErrorCode::InvalidTokenAccount);
+    // Не token account (mint, чужой владелец) - не ошибка, а None
+    assert!(parse_token_account(mint_account(Pubkey::new_unique(), 6, anchor_spl::token::ID)).unwrap().is_none());
+    assert!(parse_token_account(data_account(Pubkey::new_unique(), Pubkey::new_unique(), vec![0u8; 165])).unwrap().is_none());
+
+    let garbage_mint = data_account(Pubkey::new_unique(), anchor_spl::token::ID, vec![0xff; 82]);
+    assert_error(mint_decimals(garbage_mint), MyErrorCode::InvalidAccountData);
+
+    assert_error(read_pubkey(&[0u8; 40], 16), MyErrorCode::InvalidAccountData);
+    assert_error(read_u64(&[0u8; 8], 4), MyErrorCode::InvalidAccountData);
+    assert_error(read_u32(&[0u8; 3], 0), MyErrorCode::InvalidAccountData);
+}
+
+#[test]
+fn malformed_quote_account_fails_the_builder_with_invalid_token_account() {
+    let fixture = BatchFixture::new(|_| {});
+    let build_ctx = BuildContext {
+        user_quote_account: malformed_token_account(spl_token::native_mint::ID, *fixture.user.key).clone(),
+        ..fixture.build_ctx()
+    };
+    assert_error(quote_mint_of(&build_ctx), MyErrorCode::InvalidTokenAccount);
+
+    let token_mint = Pubkey::new_unique();
+    let slice = leak_slice(&phoenix_slice(&fixture, token_mint));
+    let arbitrage = ArbitrageParams {
+        max_sol_cost: 1_000,
+        tokens_to_buy: 100,
+        ..leg_params(token_mint, DexType::Phoenix, DexType::Phoenix, slice.len() as u8)
+    };
+    let result = build_dex_instruction(&DexType::Phoenix, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::InvalidTokenAccount);
+}
+
+#[test]
+fn malformed_user_token_account_in_pump_slice_is_reported_not_skipped() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let mut slice = pump_slice(&fixture, token_mint, FRESH_PUMP_CURVE);
+    *slice.last_mut().unwrap() = malformed_token_account(token_mint, *fixture.user.key);
+    let slice = leak_slice(&slice);
+    let arbitrage = leg_params(token_mint, DexType::PumpFun, DexType::PumpFun, slice.len() as u8);
+
+    let result = build_dex_instruction(&DexType::PumpFun, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::InvalidTokenAccount);
+}
+
+/// Срез Whirlpool-клона program_id: пул token_mint (A) / wSOL (B), vault-ы, один tick array, oracle и token user-а
+fn whirlpool_like_slice(fixture: &BatchFixture, program_id: Pubkey, token_mint: Pubkey) -> Vec<&'static AccountInfo<'static>> {
+    let [whirlpool, vault_a, vault_b, tick_array] = [(); 4].map(|_| Pubkey::new_unique());
+    let (oracle, _) = Pubkey::find_program_address(&[b"oracle", whirlpool.as_ref()], &program_id);
+
+    let mut data = vec![0u8; WHIRLPOOL_TOKEN_VAULT_B_OFFSET + 32];
+    data[..8].copy_from_slice(&WHIRLPOOL_DISCRIMINATOR);
+    put_pubkey(&mut data, WHIRLPOOL_TOKEN_MINT_A_OFFSET, &token_mint);
+    put_pubkey(&mut data, WHIRLPOOL_TOKEN_VAULT_A_OFFSET, &vault_a);
+    put_pubkey(&mut data, WHIRLPOOL_TOKEN_MINT_B_OFFSET, &spl_token::native_mint::ID);
+    put_pubkey(&mut data, WHIRLPOOL_TOKEN_VAULT_B_OFFSET, &vault_b);
+    let mut tick_array_data = vec![0u8; 16];
+    tick_array_data[..8].copy_from_slice(&WHIRLPOOL_TICK_ARRAY_DISCRIMINATOR);
+
+    vec![
+        program_account(program_id),
+        data_account(whirlpool, program_id, data),
+        token_account_at(vault_a, token_mint, whirlpool, POOL_LIQUIDITY, anchor_spl::token::ID),
+        token_account_at(vault_b, spl_token::native_mint::ID, whirlpool, POOL_LIQUIDITY, anchor_spl::token::ID),
+        data_account(tick_array, program_id, tick_array_data),
+        data_account(oracle, program_id, Vec::new()),
+        token_account(token_mint, *fixture.user.key, 0),
+    ]
+}
+
+#[test]
+fn whirlpool_like_builder_targets_each_clone_program_id() {
+    let fixture = BatchFixture::new(|_| {});
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+    let token_mint = Pubkey::new_unique();
+
+    for program_id in [Pubkey::new_unique(), Pubkey::new_unique()] {
+        let dex = DexType::WhirlpoolLike { program_id };
+        let slice = leak_slice(&whirlpool_like_slice(&fixture, program_id, token_mint));
+        let arbitrage = ArbitrageParams {
+            max_sol_cost: 1_000,
+            tokens_to_buy: 10,
+            ..leg_params(token_mint, dex.clone(), dex.clone(), slice.len() as u8)
+        };
+        let (buy, buy_accounts) = build_dex_instruction(&dex, TradeSide::Buy, slice, &arbitrage, &build_ctx).unwrap();
+
+        // Цель CPI и oracle PDA - от program_id ноги
+        assert_eq!(buy.program_id, program_id);
+        assert_eq!(*buy_accounts[0].key, program_id);
+        let keys: Vec<Pubkey> = buy.accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(keys[2], *slice[1].key);
+        // Единственный tick array повторен до трех
+        assert_eq!(keys[7..10], [*slice[4].key; 3]);
+        let (oracle, _) = Pubkey::find_program_address(&[b"oracle", slice[1].key.as_ref()], &program_id);
+        assert_eq!(keys[10], oracle);
+        assert_eq!(read_data_u64(&buy.data, 8), 1_000);
+        assert_eq!(read_data_u64(&buy.data, 16), 10);
+    }
+}
+
+#[test]
+fn whirlpool_like_leg_ignores_pools_of_another_clone() {
+    let fixture = BatchFixture::new(|_| {});
+    let build_ctx = BuildContext {
+        allow_raw: true,
+        ..fixture.build_ctx()
+    };
+    let token_mint = Pubkey::new_unique();
+    let [clone_a, clone_b] = [(); 2].map(|_| Pubkey::new_unique());
+    // Программа clone_b в срезе есть, но пул и tick array-и принадлежат clone_a
+    let mut slice = whirlpool_like_slice(&fixture, clone_a, token_mint);
+    slice.push(program_account(clone_b));
+    let slice = leak_slice(&slice);
+    let dex = DexType::WhirlpoolLike { program_id: clone_b };
+    let arbitrage = leg_params(token_mint, dex.clone(), dex.clone(), slice.len() as u8);
+    let result = build_dex_instruction(&dex, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::AccountNotFound);
+
+    // Подпись user-а не уходит в сам роутер
+    let dex = DexType::WhirlpoolLike { program_id: ID };
+    let result = build_dex_instruction(&dex, TradeSide::Buy, slice, &arbitrage, &build_ctx);
+    assert_error(result, MyErrorCode::InvalidProgramId);
+}
+
+#[test]
+fn whirlpool_like_leg_requires_allow_raw() {
+    let fixture = BatchFixture::new(|_| {});
+    let token_mint = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+    let slice = leak_slice(&whirlpool_like_slice(&fixture, program_id, token_mint));
+    let dex = DexType::WhirlpoolLike { program_id };
+    let arbitrage = leg_params(token_mint, dex.clone(), dex.clone(), slice.len() as u8);
+
+    // Валидный пул клона, но program id от бота - это CPI в произвольную программу
+    let result = build_dex_instruction(&dex, TradeSide::Buy, slice, &arbitrage, &fixture.build_ctx());
+    assert_error(result, MyErrorCode::RawDexDisabled);
+    assert!(invoked_programs().is_empty());
+}